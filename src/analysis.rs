@@ -0,0 +1,864 @@
+//! Static analysis of a source file, independent of how the results are
+//! presented (terminal reports or the language server).
+
+use crate::ast::{Expr, ExprKind, Span};
+use crate::builtins;
+use crate::lint::{self, Lint, LintLevels};
+use crate::parser::{blank_comments, check_nesting, error_message, program};
+use crate::visit::Visitor;
+use chumsky::Parser;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+    /// The lint that reported this, if any.
+    pub lint: Option<Lint>,
+    /// Another place the diagnostic refers to, with a label.
+    pub secondary: Option<(Span, String)>,
+}
+
+/// A function declared with `fn`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnSymbol {
+    pub name: String,
+    pub params: Vec<String>,
+    /// The span of the declaration.
+    pub span: Span,
+}
+
+/// A use of a name, linked to the declaration it refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+    pub definition: Span,
+}
+
+/// Everything known about a source file after parsing and name resolution.
+#[derive(Debug)]
+pub struct Analysis {
+    /// The independent declaration chains making up the program, possibly
+    /// containing `ExprKind::Error` nodes where the parser recovered from
+    /// mistakes. Empty if recovery was impossible.
+    pub program: Vec<Expr>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub functions: Vec<FnSymbol>,
+    pub references: Vec<Reference>,
+}
+
+impl Analysis {
+    /// Finds the reference under the given character offset.
+    pub fn reference_at(&self, offset: usize) -> Option<&Reference> {
+        self.references
+            .iter()
+            .find(|r| r.span.start <= offset && offset <= r.span.end)
+    }
+
+    /// Finds the declaration of the name used at the given offset.
+    pub fn definition_at(&self, offset: usize) -> Option<Span> {
+        self.reference_at(offset).map(|r| r.definition.clone())
+    }
+
+    /// Finds the function called or declared at the given offset.
+    pub fn function_at(&self, offset: usize) -> Option<&FnSymbol> {
+        match self.reference_at(offset) {
+            Some(r) => self.functions.iter().find(|f| f.span == r.definition),
+            None => self
+                .functions
+                .iter()
+                .find(|f| f.span.start <= offset && offset <= f.span.end),
+        }
+    }
+}
+
+/// Parses `src`, recovering from syntax errors where possible, and resolves
+/// every variable and function name to its declaration.
+pub fn analyze(src: &str) -> Analysis {
+    let (program, errs) = {
+        #[cfg(feature = "trace")]
+        let _span = tracing::info_span!("parse", len = src.chars().count()).entered();
+        let blanked = blank_comments(src);
+        let (program, errs) = match check_nesting(&blanked) {
+            Ok(()) => program().parse_recovery(&*blanked),
+            Err(e) => (None, vec![e]),
+        };
+        #[cfg(feature = "trace")]
+        for e in &errs {
+            tracing::debug!(span = ?e.span(), "recovered parse error");
+        }
+        (program.unwrap_or_default(), errs)
+    };
+    #[cfg(feature = "trace")]
+    if tracing::enabled!(tracing::Level::DEBUG) {
+        let nodes: usize = program.iter().map(Expr::node_count).sum();
+        tracing::debug!(nodes, "parsed");
+    }
+
+    let mut diagnostics: Vec<_> = errs
+        .into_iter()
+        .map(|e| Diagnostic {
+            span: e.span(),
+            severity: Severity::Error,
+            message: error_message(&e),
+            lint: None,
+            secondary: None,
+        })
+        .collect();
+
+    let mut resolver = Resolver::default();
+    {
+        #[cfg(feature = "trace")]
+        let _span = tracing::info_span!("resolve").entered();
+        for decl in &program {
+            resolver.visit_expr(decl);
+        }
+    }
+    diagnostics.append(&mut resolver.diagnostics);
+    diagnostics.extend(lint::check(&program, &LintLevels::default()));
+
+    Analysis {
+        program,
+        diagnostics,
+        functions: resolver.functions,
+        references: resolver.references,
+    }
+}
+
+/// Resolves names lexically: a function body sees its parameters, the
+/// variables declared before the function, and every function declared before
+/// it (including itself, so it can recurse).
+#[derive(Default)]
+struct Resolver {
+    vars: Vec<(String, Span)>,
+    fns: Vec<(String, Span)>,
+    diagnostics: Vec<Diagnostic>,
+    functions: Vec<FnSymbol>,
+    references: Vec<Reference>,
+}
+
+impl Resolver {
+    fn lookup(
+        &mut self,
+        scope: fn(&Self) -> &[(String, Span)],
+        what: &str,
+        name: &str,
+        span: Span,
+    ) {
+        match scope(self).iter().rev().find(|(n, _)| n == name) {
+            Some((_, definition)) => {
+                let definition = definition.clone();
+                self.references.push(Reference {
+                    name: name.to_string(),
+                    span,
+                    definition,
+                });
+            }
+            None => self.diagnostics.push(Diagnostic {
+                span,
+                severity: Severity::Error,
+                message: format!("Cannot find {what} `{name}` in scope"),
+                lint: None,
+                secondary: None,
+            }),
+        }
+    }
+}
+
+impl Visitor<'_> for Resolver {
+    // A name that isn't a variable can refer to a function, as a value.
+    fn visit_var(&mut self, expr: &Expr, name: &str) {
+        let span = expr.span.clone();
+        let is_var = self.vars.iter().any(|(n, _)| n == name);
+        if !is_var && self.fns.iter().any(|(n, _)| n == name) {
+            self.lookup(|r| &r.fns, "function", name, span);
+        } else if is_var || builtins::lookup(name).is_none() {
+            self.lookup(|r| &r.vars, "variable", name, span);
+        }
+    }
+
+    fn visit_call(&mut self, expr: &Expr, name: &str, args: &[Expr]) {
+        // Only the function name refers to the declaration, not the whole
+        // call.
+        let span = expr.span.start..expr.span.start + name.chars().count();
+        let declared = self.fns.iter().any(|(n, _)| n == name);
+        if declared || builtins::lookup(name).is_none() {
+            // A parameter can hold a function value to call.
+            let scope: fn(&Self) -> &[(String, Span)] =
+                if !declared && self.vars.iter().any(|(n, _)| n == name) {
+                    |r| &r.vars
+                } else {
+                    |r| &r.fns
+                };
+            self.lookup(scope, "function", name, span);
+        }
+        args.iter().for_each(|arg| self.visit_expr(arg));
+    }
+
+    fn visit_block(&mut self, _expr: &Expr, chain: &Expr) {
+        // Functions are hoisted to the start of the block.
+        let depth = self.fns.len();
+        for decl in chain.declarations() {
+            if let ExprKind::Fn { name, .. } = &decl.kind {
+                self.fns.push((name.clone(), decl.span.clone()));
+            }
+        }
+        self.visit_expr(chain);
+        self.fns.truncate(depth);
+    }
+
+    fn visit_let(&mut self, expr: &Expr, name: &str, rhs: &Expr, then: &Expr) {
+        self.visit_expr(rhs);
+        self.vars.push((name.to_string(), expr.span.clone()));
+        self.visit_expr(then);
+        self.vars.pop();
+    }
+
+    fn visit_fn(&mut self, expr: &Expr, name: &str, params: &[String], body: &Expr, then: &Expr) {
+        self.functions.push(FnSymbol {
+            name: name.to_string(),
+            params: params.to_vec(),
+            span: expr.span.clone(),
+        });
+        self.fns.push((name.to_string(), expr.span.clone()));
+
+        let depth = self.vars.len();
+        self.vars.extend(
+            params
+                .iter()
+                .map(|param| (param.clone(), expr.span.clone())),
+        );
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+
+        self.visit_expr(then);
+        self.fns.pop();
+    }
+
+    fn visit_lambda(&mut self, expr: &Expr, params: &[String], body: &Expr) {
+        let depth = self.vars.len();
+        self.vars.extend(
+            params
+                .iter()
+                .map(|param| (param.clone(), expr.span.clone())),
+        );
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+
+    fn visit_for(&mut self, expr: &Expr, var: &str, list: &Expr, body: &Expr) {
+        self.visit_expr(list);
+        self.vars.push((var.to_string(), expr.span.clone()));
+        self.visit_expr(body);
+        self.vars.pop();
+    }
+
+    fn visit_if_let(
+        &mut self,
+        expr: &Expr,
+        name: &str,
+        value: &Expr,
+        then: &Expr,
+        otherwise: &Expr,
+    ) {
+        self.visit_expr(value);
+        self.vars.push((name.to_string(), expr.span.clone()));
+        self.visit_expr(then);
+        self.vars.pop();
+        self.visit_expr(otherwise);
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        expr: &Expr,
+        body: &Expr,
+        generators: &[(String, Expr)],
+        filter: Option<&Expr>,
+    ) {
+        let depth = self.vars.len();
+        for (var, list) in generators {
+            self.visit_expr(list);
+            self.vars.push((var.clone(), expr.span.clone()));
+        }
+        if let Some(filter) = filter {
+            self.visit_expr(filter);
+        }
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+}
+
+/// The names of variables used in `expr` without being bound by an enclosing
+/// `let` or parameter.
+///
+/// Scoping follows evaluation, which is lexical for variables: a function
+/// body sees its parameters and the variables in scope where it is declared.
+pub fn free_variables(expr: &Expr) -> BTreeSet<String> {
+    let mut finder = FreeVariables::default();
+    finder.visit_expr(expr);
+    finder.free
+}
+
+/// The `let` declarations in `expr` whose variable is never read.
+pub fn unused_variables(expr: &Expr) -> Vec<&Expr> {
+    let mut finder = FreeVariables::default();
+    finder.visit_expr(expr);
+    let used = finder.used;
+    finder
+        .declared
+        .retain(|decl| !used.iter().any(|u| std::ptr::eq(*u, *decl)));
+    finder.declared
+}
+
+/// The uses of variables and functions declared with a `@deprecated`
+/// attribute: the span of each use, with the declaration it refers to. A
+/// call's span is just the function name, and a variable's the whole name.
+pub fn deprecated_uses(expr: &Expr) -> Vec<(Span, &Expr)> {
+    let mut finder = DeprecatedUses::default();
+    finder.visit_expr(expr);
+    finder.uses
+}
+
+/// Pairs of `let` declarations of the same variable in the same declaration
+/// chain: the later one, and the earlier one it shadows. Declarations in a
+/// block only shadow each other, not those outside.
+pub fn shadowed_variables(expr: &Expr) -> Vec<(&Expr, &Expr)> {
+    redeclarations(expr, |kind| match kind {
+        ExprKind::Let { name, .. } => Some(name),
+        _ => None,
+    })
+}
+
+/// Like [`shadowed_variables`], for functions declared with `fn`.
+pub fn redefined_functions(expr: &Expr) -> Vec<(&Expr, &Expr)> {
+    redeclarations(expr, |kind| match kind {
+        ExprKind::Fn { name, .. } => Some(name),
+        _ => None,
+    })
+}
+
+/// Pairs of declarations in the same declaration chain that `declared`
+/// gives the same name.
+fn redeclarations(expr: &Expr, declared: fn(&ExprKind) -> Option<&String>) -> Vec<(&Expr, &Expr)> {
+    let blocks = expr.iter().filter_map(|e| match &e.kind {
+        ExprKind::Block(chain) => Some(&**chain),
+        _ => None,
+    });
+    let mut pairs = Vec::new();
+    for chain in std::iter::once(expr).chain(blocks) {
+        let mut seen: Vec<(&String, &Expr)> = Vec::new();
+        for decl in chain.declarations() {
+            if let Some(name) = declared(&decl.kind) {
+                if let Some((_, earlier)) = seen.iter().rev().find(|(n, _)| *n == name) {
+                    pairs.push((decl, *earlier));
+                }
+                seen.push((name, decl));
+            }
+        }
+    }
+    pairs
+}
+
+/// Whether `body` ends with a call to the function `fn_name`, whose result
+/// is then the result of `body`, as in `fn f n = { let m = n - 1; f(m) }`.
+///
+/// A call is in tail position when it is all of `body`, the expression
+/// ending a declaration chain or block in tail position, or either branch of
+/// an `if let` in tail position. Operands, arguments, conditions of `and`
+/// and `or`, `let` right-hand sides and the value matched by `if let` are
+/// not, and neither is a call to an inner function of the same name
+/// shadowing `fn_name`. Pipes are expected to be desugared.
+///
+/// ```
+/// use kyomusky::analysis::is_tail_call;
+/// use kyomusky::ast::ExprKind;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let tail = |body: &str| {
+///     let program = parse_program(&format!("fn f n = {body}; 0"), ParseOptions::default()).unwrap();
+///     let ExprKind::Fn { body, .. } = &program[0].kind else {
+///         unreachable!();
+///     };
+///     is_tail_call(body, "f")
+/// };
+///
+/// assert!(tail("f(n - 1)"));
+/// assert!(tail("{ let m = n - 1; f(m) }"));
+/// assert!(tail("{ fn g x = x; { let y = g(n); f(y) } }"));
+/// assert!(!tail("g(n)"));
+/// assert!(!tail("1 + f(n - 1)"));
+/// assert!(!tail("g(f(n))"));
+/// assert!(!tail("n < 1 or f(n - 1)"));
+/// assert!(!tail("{ let m = f(n); m }"));
+/// assert!(!tail("{ fn f x = x; f(n) }"));
+/// assert!(!tail("[f(n)]"));
+/// assert!(tail("if let some(m) = find(\\x -> x > 0, [n]) then f(m - 1) else 0"));
+/// assert!(tail("if let some(m) = g(n) then m else f(n + 1)"));
+/// assert!(!tail("if let some(m) = f(n) then m else 0"));
+/// ```
+pub fn is_tail_call(body: &Expr, fn_name: &str) -> bool {
+    match &body.kind {
+        ExprKind::Call(name, _) => name == fn_name,
+        // Calls look functions up before variables, so `let` cannot shadow.
+        ExprKind::Let { then, .. } => is_tail_call(then, fn_name),
+        ExprKind::Fn { name, then, .. } => name != fn_name && is_tail_call(then, fn_name),
+        ExprKind::IfLet {
+            then, otherwise, ..
+        } => is_tail_call(then, fn_name) || is_tail_call(otherwise, fn_name),
+        // Every function of a block is in scope from its start.
+        ExprKind::Block(chain) => {
+            let shadowed = chain
+                .declarations()
+                .iter()
+                .any(|decl| matches!(&decl.kind, ExprKind::Fn { name, .. } if name == fn_name));
+            !shadowed && is_tail_call(chain, fn_name)
+        }
+        _ => false,
+    }
+}
+
+/// The calls in `expr` in tail position of the body of a function or
+/// lambda, whichever function they call, in source order. See
+/// [`is_tail_call`] for what is in tail position.
+///
+/// ```
+/// use kyomusky::analysis::tail_calls;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "fn f n = { let m = g(n); h(m) }; fn k x = 1 + f(x); map(\\x -> k(x), [1])";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let calls: Vec<_> = tail_calls(&program[0]).iter().map(|c| &src[c.span.clone()]).collect();
+/// assert_eq!(calls, ["h(m)", "k(x)"]);
+///
+/// let src = "fn f n = if let some(m) = g(n) then h(m) else k(n); f(1)";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let calls: Vec<_> = tail_calls(&program[0]).iter().map(|c| &src[c.span.clone()]).collect();
+/// assert_eq!(calls, ["h(m)", "k(n)"]);
+/// ```
+pub fn tail_calls(expr: &Expr) -> Vec<&Expr> {
+    fn collect<'a>(expr: &'a Expr, calls: &mut Vec<&'a Expr>) {
+        match &expr.kind {
+            ExprKind::Call(..) | ExprKind::Apply(..) => calls.push(expr),
+            ExprKind::Let { then, .. } | ExprKind::Fn { then, .. } => collect(then, calls),
+            ExprKind::Block(chain) => collect(chain, calls),
+            ExprKind::IfLet {
+                then, otherwise, ..
+            } => {
+                collect(then, calls);
+                collect(otherwise, calls);
+            }
+            _ => {}
+        }
+    }
+
+    let mut calls = Vec::new();
+    for node in expr.iter() {
+        if let ExprKind::Fn { body, .. } | ExprKind::Lambda { body, .. } = &node.kind {
+            collect(body, &mut calls);
+        }
+    }
+    calls.sort_by_key(|call| call.span.start);
+    calls
+}
+
+#[derive(Default)]
+struct FreeVariables<'a> {
+    /// Variables in scope, with the `let` declaring them (`None` for
+    /// parameters).
+    vars: Vec<(&'a str, Option<&'a Expr>)>,
+    free: BTreeSet<String>,
+    /// `let` declarations visited, and those whose variable was read.
+    declared: Vec<&'a Expr>,
+    used: Vec<&'a Expr>,
+}
+
+impl<'a> FreeVariables<'a> {
+    /// Marks the variable declared by `decl` as read.
+    fn read(&mut self, decl: &'a Expr) {
+        if !self.used.iter().any(|u| std::ptr::eq(*u, decl)) {
+            self.used.push(decl);
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for FreeVariables<'a> {
+    fn visit_var(&mut self, _expr: &'a Expr, name: &'a str) {
+        match self.vars.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, Some(decl))) => self.read(decl),
+            Some((_, None)) => {}
+            None => {
+                self.free.insert(name.to_string());
+            }
+        }
+    }
+
+    // Calling a variable holding a function value reads it.
+    fn visit_call(&mut self, _expr: &'a Expr, name: &'a str, args: &'a [Expr]) {
+        if let Some((_, Some(decl))) = self.vars.iter().rev().find(|(n, _)| *n == name) {
+            self.read(decl);
+        }
+        args.iter().for_each(|arg| self.visit_expr(arg));
+    }
+
+    fn visit_let(&mut self, expr: &'a Expr, name: &'a str, rhs: &'a Expr, then: &'a Expr) {
+        self.visit_expr(rhs);
+        self.declared.push(expr);
+        self.vars.push((name, Some(expr)));
+        self.visit_expr(then);
+        self.vars.pop();
+    }
+
+    fn visit_fn(
+        &mut self,
+        expr: &'a Expr,
+        _name: &'a str,
+        params: &'a [String],
+        body: &'a Expr,
+        then: &'a Expr,
+    ) {
+        self.visit_lambda(expr, params, body);
+        self.visit_expr(then);
+    }
+
+    fn visit_lambda(&mut self, _expr: &'a Expr, params: &'a [String], body: &'a Expr) {
+        let depth = self.vars.len();
+        self.vars
+            .extend(params.iter().map(|param| (param.as_str(), None)));
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+
+    fn visit_for(&mut self, _expr: &'a Expr, var: &'a str, list: &'a Expr, body: &'a Expr) {
+        self.visit_expr(list);
+        self.vars.push((var, None));
+        self.visit_expr(body);
+        self.vars.pop();
+    }
+
+    fn visit_if_let(
+        &mut self,
+        _expr: &'a Expr,
+        name: &'a str,
+        value: &'a Expr,
+        then: &'a Expr,
+        otherwise: &'a Expr,
+    ) {
+        self.visit_expr(value);
+        self.vars.push((name, None));
+        self.visit_expr(then);
+        self.vars.pop();
+        self.visit_expr(otherwise);
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        _expr: &'a Expr,
+        body: &'a Expr,
+        generators: &'a [(String, Expr)],
+        filter: Option<&'a Expr>,
+    ) {
+        let depth = self.vars.len();
+        for (var, list) in generators {
+            self.visit_expr(list);
+            self.vars.push((var, None));
+        }
+        if let Some(filter) = filter {
+            self.visit_expr(filter);
+        }
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+}
+
+/// Resolves names like [`Resolver`], keeping the declarations they refer to.
+#[derive(Default)]
+struct DeprecatedUses<'a> {
+    /// Variables in scope, with the `let` declaring them (`None` for
+    /// parameters).
+    vars: Vec<(&'a str, Option<&'a Expr>)>,
+    fns: Vec<(&'a str, &'a Expr)>,
+    uses: Vec<(Span, &'a Expr)>,
+}
+
+impl<'a> DeprecatedUses<'a> {
+    fn var(&self, name: &str) -> Option<Option<&'a Expr>> {
+        self.vars
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, decl)| *decl)
+    }
+
+    fn function(&self, name: &str) -> Option<&'a Expr> {
+        self.fns
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, decl)| *decl)
+    }
+
+    fn used(&mut self, span: Span, decl: Option<&'a Expr>) {
+        if let Some(decl) = decl {
+            if let ExprKind::Let {
+                deprecated: Some(_),
+                ..
+            }
+            | ExprKind::Fn {
+                deprecated: Some(_),
+                ..
+            } = decl.kind
+            {
+                self.uses.push((span, decl));
+            }
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for DeprecatedUses<'a> {
+    fn visit_var(&mut self, expr: &'a Expr, name: &'a str) {
+        let decl = match self.var(name) {
+            Some(decl) => decl,
+            None => self.function(name),
+        };
+        self.used(expr.span.clone(), decl);
+    }
+
+    fn visit_call(&mut self, expr: &'a Expr, name: &'a str, args: &'a [Expr]) {
+        let span = expr.span.start..expr.span.start + name.chars().count();
+        let decl = match self.function(name) {
+            Some(decl) => Some(decl),
+            None => self.var(name).flatten(),
+        };
+        self.used(span, decl);
+        args.iter().for_each(|arg| self.visit_expr(arg));
+    }
+
+    fn visit_block(&mut self, _expr: &'a Expr, chain: &'a Expr) {
+        let depth = self.fns.len();
+        for decl in chain.declarations() {
+            if let ExprKind::Fn { name, .. } = &decl.kind {
+                self.fns.push((name, decl));
+            }
+        }
+        self.visit_expr(chain);
+        self.fns.truncate(depth);
+    }
+
+    fn visit_let(&mut self, expr: &'a Expr, name: &'a str, rhs: &'a Expr, then: &'a Expr) {
+        self.visit_expr(rhs);
+        self.vars.push((name, Some(expr)));
+        self.visit_expr(then);
+        self.vars.pop();
+    }
+
+    fn visit_fn(
+        &mut self,
+        expr: &'a Expr,
+        name: &'a str,
+        params: &'a [String],
+        body: &'a Expr,
+        then: &'a Expr,
+    ) {
+        self.fns.push((name, expr));
+        self.visit_lambda(expr, params, body);
+        self.visit_expr(then);
+        self.fns.pop();
+    }
+
+    fn visit_lambda(&mut self, _expr: &'a Expr, params: &'a [String], body: &'a Expr) {
+        let depth = self.vars.len();
+        self.vars
+            .extend(params.iter().map(|param| (param.as_str(), None)));
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+
+    fn visit_for(&mut self, _expr: &'a Expr, var: &'a str, list: &'a Expr, body: &'a Expr) {
+        self.visit_expr(list);
+        self.vars.push((var, None));
+        self.visit_expr(body);
+        self.vars.pop();
+    }
+
+    fn visit_if_let(
+        &mut self,
+        _expr: &'a Expr,
+        name: &'a str,
+        value: &'a Expr,
+        then: &'a Expr,
+        otherwise: &'a Expr,
+    ) {
+        self.visit_expr(value);
+        self.vars.push((name, None));
+        self.visit_expr(then);
+        self.vars.pop();
+        self.visit_expr(otherwise);
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        _expr: &'a Expr,
+        body: &'a Expr,
+        generators: &'a [(String, Expr)],
+        filter: Option<&'a Expr>,
+    ) {
+        let depth = self.vars.len();
+        for (var, list) in generators {
+            self.visit_expr(list);
+            self.vars.push((var, None));
+        }
+        if let Some(filter) = filter {
+            self.visit_expr(filter);
+        }
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+}
+
+/// Which functions each declared function may call, see [`call_graph`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallGraph {
+    /// For each function declared with `fn`, including in nested blocks, the
+    /// declared and built-in functions called in its body. A function
+    /// calling itself has an edge to itself.
+    pub calls: BTreeMap<String, BTreeSet<String>>,
+    /// Names called anywhere that are neither declared, built-in, nor a
+    /// variable in scope, which would hold a function value.
+    pub unknown: BTreeSet<String>,
+}
+
+/// Builds the call graph of `expr`.
+///
+/// Variables are scoped lexically, but a call looks its function up among
+/// those declared by the blocks being evaluated when it runs, the caller's
+/// included. So a call may reach a function declared anywhere in `expr`,
+/// after the caller or nested in another function. A declared function wins
+/// over a parameter of the same name, as in evaluation; calling a parameter
+/// or another variable otherwise calls the function value it holds, which
+/// adds no edge.
+pub fn call_graph(expr: &Expr) -> CallGraph {
+    let mut builder = CallGraphBuilder {
+        declared: expr
+            .iter()
+            .filter_map(|e| match &e.kind {
+                ExprKind::Fn { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect(),
+        ..CallGraphBuilder::default()
+    };
+    builder.visit_expr(expr);
+    builder.graph
+}
+
+#[derive(Default)]
+struct CallGraphBuilder<'a> {
+    /// Every function declared in the tree.
+    declared: BTreeSet<&'a str>,
+    /// Variables in scope.
+    vars: Vec<&'a str>,
+    /// The functions whose body is being visited, innermost last.
+    callers: Vec<&'a str>,
+    graph: CallGraph,
+}
+
+impl<'a> Visitor<'a> for CallGraphBuilder<'a> {
+    fn visit_call(&mut self, _expr: &'a Expr, name: &'a str, args: &'a [Expr]) {
+        if self.declared.contains(name) || builtins::lookup(name).is_some() {
+            if let Some(caller) = self.callers.last() {
+                let calls = self.graph.calls.entry(caller.to_string()).or_default();
+                calls.insert(name.to_string());
+            }
+        } else if !self.vars.contains(&name) {
+            self.graph.unknown.insert(name.to_string());
+        }
+        args.iter().for_each(|arg| self.visit_expr(arg));
+    }
+
+    fn visit_let(&mut self, _expr: &'a Expr, name: &'a str, rhs: &'a Expr, then: &'a Expr) {
+        self.visit_expr(rhs);
+        self.vars.push(name);
+        self.visit_expr(then);
+        self.vars.pop();
+    }
+
+    fn visit_fn(
+        &mut self,
+        expr: &'a Expr,
+        name: &'a str,
+        params: &'a [String],
+        body: &'a Expr,
+        then: &'a Expr,
+    ) {
+        self.graph.calls.entry(name.to_string()).or_default();
+        self.callers.push(name);
+        self.visit_lambda(expr, params, body);
+        self.callers.pop();
+        self.visit_expr(then);
+    }
+
+    fn visit_lambda(&mut self, _expr: &'a Expr, params: &'a [String], body: &'a Expr) {
+        let depth = self.vars.len();
+        self.vars.extend(params.iter().map(String::as_str));
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+
+    fn visit_for(&mut self, _expr: &'a Expr, var: &'a str, list: &'a Expr, body: &'a Expr) {
+        self.visit_expr(list);
+        self.vars.push(var);
+        self.visit_expr(body);
+        self.vars.pop();
+    }
+
+    fn visit_if_let(
+        &mut self,
+        _expr: &'a Expr,
+        name: &'a str,
+        value: &'a Expr,
+        then: &'a Expr,
+        otherwise: &'a Expr,
+    ) {
+        self.visit_expr(value);
+        self.vars.push(name);
+        self.visit_expr(then);
+        self.vars.pop();
+        self.visit_expr(otherwise);
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        _expr: &'a Expr,
+        body: &'a Expr,
+        generators: &'a [(String, Expr)],
+        filter: Option<&'a Expr>,
+    ) {
+        let depth = self.vars.len();
+        for (var, list) in generators {
+            self.visit_expr(list);
+            self.vars.push(var);
+        }
+        if let Some(filter) = filter {
+            self.visit_expr(filter);
+        }
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+}
@@ -0,0 +1,1318 @@
+/// A range of character offsets into the source text.
+pub type Span = std::ops::Range<usize>;
+
+/// A node of the syntax tree together with the source range it was parsed
+/// from.
+///
+/// Expressions span their whole extent, e.g. `1 + 2` covers both operands.
+/// `let` and `fn` declarations are the exception: they span the declaration
+/// itself, up to and including its `;`, but not the expression following it.
+///
+/// ```
+/// use kyomusky::ast::ExprKind;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "1 + 2 * 3";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// assert_eq!(program[0].span, 0..src.len());
+/// let ExprKind::Binary { rhs, .. } = &program[0].kind else {
+///     panic!("expected an addition");
+/// };
+/// assert_eq!(&src[rhs.span.clone()], "2 * 3");
+/// ```
+///
+/// Two expressions compare equal when they have the same shape, regardless of
+/// where they appeared in the source, and hash accordingly. Likewise the
+/// `Debug` output leaves spans out to keep dumped trees readable.
+///
+/// Number literals compare by their bit pattern, so that equality is an
+/// equivalence relation (`Eq`) consistent with `Hash`: a `NaN` literal equals
+/// itself, while `0` and `-0` are different literals.
+///
+/// `Display` renders the expression back into source code, with only the
+/// parentheses needed to keep its structure when parsed again.
+#[derive(Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Error,
+    Num(f64),
+    Str(String),
+    /// `"value is ${expr}"`, a string with at least one embedded expression.
+    /// Evaluates to the parts stringified and concatenated.
+    InterpolatedStr(Vec<StrPart>),
+    Bool(bool),
+    /// `none`, the optional value holding nothing. `some(x)` holds `x`.
+    None,
+    Var(String),
+
+    /// A prefix operator applied to `operand`.
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    /// An infix operator applied to `lhs` and `rhs`.
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+    /// `target[index]`: an element of a list or string, or for a map `some`
+    /// of the value stored under a key, and `none` if there is no such key.
+    ///
+    /// ```
+    /// use kyomusky::run::eval_str;
+    ///
+    /// assert_eq!(eval_str(r#"let xs = [1, 2]; xs[1]"#).unwrap(), "2");
+    /// assert_eq!(eval_str(r#"let m = {"a": 1}; [m["a"], m["b"]]"#).unwrap(), "[some(1), none]");
+    /// let src = r#"let m = {"a": 1}; if let some(a) = m["a"] then a + 1 else 0"#;
+    /// assert_eq!(eval_str(src).unwrap(), "2");
+    /// ```
+    Index(Box<Expr>, Box<Expr>),
+    IndexAssign {
+        target: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+
+    /// Arguments are positional, or `NamedArg`s binding a parameter by name.
+    Call(String, Vec<Expr>),
+    /// Calls the result of an expression rather than a function by name, as
+    /// in `fs[0](1)` or `f(1)(2)`.
+    Apply(Box<Expr>, Vec<Expr>),
+    /// `name = value`, only found among the arguments of a `Call`.
+    NamedArg(String, Box<Expr>),
+    /// `...list`, only found among the arguments of a `Call` or `Apply`,
+    /// passing the elements of the list as positional arguments. The call
+    /// is checked for arity once they are known:
+    ///
+    /// ```
+    /// use kyomusky::eval::ErrorKind;
+    /// use kyomusky::run::eval_str;
+    ///
+    /// let run = |call: &str| eval_str(&format!("fn add3 a b c = a * 100 + b * 10 + c; {call}"));
+    ///
+    /// assert_eq!(run("let xs = [1, 2, 3]; add3(...xs)").unwrap(), "123");
+    /// assert_eq!(run("add3(1, ...[2], 3)").unwrap(), "123");
+    /// assert_eq!(run("add3(...[1, 2], ...[], 3)").unwrap(), "123");
+    /// assert_eq!(run("max(...[3, 1, 2])").unwrap(), "3");
+    ///
+    /// let err = run("add3(...[1, 2])").unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::Arity);
+    /// let err = run("add3(1, 2, ...3)").unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::Type);
+    /// assert_eq!(err.message, "Type error: expected list, found number");
+    /// ```
+    Spread(Box<Expr>),
+    /// A declaration chain between braces. Its declarations are only visible
+    /// inside, and its functions can all call each other, even before being
+    /// declared.
+    Block(Box<Expr>),
+    /// `let name = rhs; then`. `rhs` is evaluated before `then`, so effects
+    /// happen in source order, nested declarations included:
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "let x = print(1); let y = { let z = print(2); print(3) }; x + y + print(4)";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let mut out = Vec::new();
+    /// let mut env = Env::new();
+    /// env.output = Box::new(&mut out);
+    /// assert_eq!(eval(&program[0], &mut env).unwrap().to_string(), "8");
+    /// drop(env);
+    /// assert_eq!(String::from_utf8(out).unwrap(), "1\n2\n3\n4\n");
+    /// ```
+    ///
+    /// With `let lazy name = rhs; then`, `rhs` is only evaluated when `name`
+    /// is first used, in the scope of the declaration, and its value is kept
+    /// for later uses:
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let run = |src| {
+    ///     let program = parse_program(src, ParseOptions::default()).unwrap();
+    ///     let mut out = Vec::new();
+    ///     let mut env = Env::new();
+    ///     env.output = Box::new(&mut out);
+    ///     let result = eval(&program[0], &mut env).map(|v| v.to_string());
+    ///     drop(env);
+    ///     (result.unwrap(), String::from_utf8(out).unwrap())
+    /// };
+    ///
+    /// assert_eq!(run("let lazy x = print(1); 2"), ("2".to_string(), "".to_string()));
+    /// assert_eq!(
+    ///     run("let lazy x = print(5); let y = print(0); x + x"),
+    ///     ("10".to_string(), "0\n5\n".to_string())
+    /// );
+    /// assert_eq!(
+    ///     run("let x = 1; let lazy y = x + 1; let x = 10; [x, y]"),
+    ///     ("[10, 2]".to_string(), "".to_string())
+    /// );
+    /// ```
+    Let {
+        name: String,
+        /// Declared with `let lazy`.
+        lazy: bool,
+        /// The message of a `@deprecated("message")` attribute before the
+        /// declaration, see [`Lint::Deprecated`](crate::lint::Lint::Deprecated).
+        deprecated: Option<String>,
+        rhs: Box<Expr>,
+        then: Box<Expr>,
+    },
+    /// `fn name args = body; then`. Declaring a name again, like declaring a
+    /// variable again with `let`, shadows the earlier declaration in the
+    /// code after it, while the code before keeps seeing the earlier one.
+    /// The `redefined-function` and `shadowed-variable` lints, denied by
+    /// `--no-redefine`, report this.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "fn f x = x + 1; let a = f(1); fn f x = x * 10; let b = 1; let b = 2; [a, f(1), b]";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// assert_eq!(eval(&program[0], &mut Env::new()).unwrap().to_string(), "[2, 10, 2]");
+    /// ```
+    Fn {
+        name: String,
+        args: Vec<String>,
+        /// As for `Let`.
+        deprecated: Option<String>,
+        body: Box<Expr>,
+        then: Box<Expr>,
+    },
+    /// `\x y -> body`, an anonymous function. The body extends as far as
+    /// possible.
+    Lambda {
+        args: Vec<String>,
+        body: Box<Expr>,
+    },
+    /// `for var in list do body`, the list of `body`'s values with `var`
+    /// bound to each element of `list` in turn. The body extends as far as
+    /// possible.
+    ///
+    /// ```
+    /// use kyomusky::eval::ErrorKind;
+    /// use kyomusky::run::eval_str;
+    ///
+    /// assert_eq!(eval_str("for x in [10, 20, 30] do x + 1").unwrap(), "[11, 21, 31]");
+    /// assert_eq!(eval_str("for x in 3 do x").unwrap_err().kind, ErrorKind::Type);
+    /// ```
+    For {
+        var: String,
+        list: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `if let some(name) = value then then else otherwise`: `then` with
+    /// `name` bound to what `value` holds if it is `some`, or `otherwise` if
+    /// it is `none`. Any other value is a type error, there being no truthy
+    /// values. The `else` branch extends as far as possible.
+    ///
+    /// ```
+    /// use kyomusky::eval::ErrorKind;
+    /// use kyomusky::run::eval_str;
+    ///
+    /// let src = "if let some(x) = some(20) then x + 1 else 0";
+    /// assert_eq!(eval_str(src).unwrap(), "21");
+    /// assert_eq!(eval_str("if let some(x) = none then x + 1 else 0").unwrap(), "0");
+    /// let src = r#"let m = {"a": 1}; if let some(a) = m["a"] then a else if let some(b) = m["b"] then b else 0"#;
+    /// assert_eq!(eval_str(src).unwrap(), "1");
+    /// // `x` is only in scope in the `then` branch.
+    /// let err = eval_str("if let some(x) = none then 1 else x").unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::UnboundVariable);
+    /// assert_eq!(eval_str("if let some(x) = 1 then x else 0").unwrap_err().kind, ErrorKind::Type);
+    /// ```
+    IfLet {
+        name: String,
+        value: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    /// `[body for var in list ... if filter]`, the list of `body` for each
+    /// combination of elements of the lists, keeping those where `filter`
+    /// holds. A list can use the variables of the generators before it.
+    /// Sugar that [`desugar`](crate::desugar::desugar) rewrites into calls of
+    /// `map`, `flat_map` and `filter`.
+    ///
+    /// ```
+    /// use kyomusky::run::eval_str;
+    ///
+    /// assert_eq!(eval_str("[x * 2 for x in [1, 2, 3]]").unwrap(), "[2, 4, 6]");
+    /// assert_eq!(eval_str("[x * 2 for x in [1, 2, 3, 4] if x > 2]").unwrap(), "[6, 8]");
+    /// assert_eq!(
+    ///     eval_str("[[x, y] for x in [1, 2, 3] for y in [x, 3] if x < y]").unwrap(),
+    ///     "[[1, 3], [2, 3]]"
+    /// );
+    /// assert_eq!(eval_str("[x for x in []]").unwrap(), "[]");
+    /// ```
+    Comprehension {
+        body: Box<Expr>,
+        /// The variables and the lists they take elements from, outermost
+        /// first.
+        generators: Vec<(String, Expr)>,
+        filter: Option<Box<Expr>>,
+    },
+}
+
+/// The operator of an [`ExprKind::Unary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    /// `-a`. Only numbers can be negated; anything else is a type error at
+    /// the operand.
+    ///
+    /// ```
+    /// use kyomusky::eval::ErrorKind;
+    /// use kyomusky::run::eval_str;
+    ///
+    /// assert_eq!(eval_str("-5").unwrap(), "-5");
+    ///
+    /// for (src, found) in [("-true", "bool"), ("-\"x\"", "string")] {
+    ///     let err = eval_str(src).unwrap_err();
+    ///     assert_eq!(err.kind, ErrorKind::Type);
+    ///     assert_eq!(err.message, format!("Type error: expected number, found {found}"));
+    ///     assert_eq!(err.span, 1..src.len());
+    /// }
+    /// ```
+    Neg,
+    /// `!a`, or `not a`. Only booleans can be negated.
+    ///
+    /// ```
+    /// use kyomusky::ast::{ExprKind, UnaryOp};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    /// use kyomusky::run::eval_str;
+    ///
+    /// assert_eq!(eval_str("[!true, not false, !!true, not (1 > 2)]").unwrap(), "[false, true, true, true]");
+    ///
+    /// let err = eval_str("!1").unwrap_err();
+    /// assert_eq!(err.message, "Type error: expected boolean, found number");
+    /// assert_eq!(err.span, 1..2);
+    ///
+    /// let program = parse_program("not -x", ParseOptions::default()).unwrap();
+    /// let ExprKind::Unary { op: UnaryOp::Not, operand } = &program[0].kind else {
+    ///     panic!("expected `not`");
+    /// };
+    /// assert!(matches!(operand.kind, ExprKind::Unary { op: UnaryOp::Neg, .. }));
+    /// assert_eq!(program[0].to_string(), "!-x");
+    /// ```
+    Not,
+}
+
+impl UnaryOp {
+    /// How the operator is printed.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+}
+
+/// The operator of an [`ExprKind::Binary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    /// `a + b`, and likewise `-`, `*` and `/`. Operands must be numbers.
+    ///
+    /// ```
+    /// use kyomusky::eval::ErrorKind;
+    /// use kyomusky::run::eval_str;
+    ///
+    /// assert_eq!(eval_str("[7 + 2, 7 - 2, 7 * 2, 7 / 2]").unwrap(), "[9, 5, 14, 3.5]");
+    /// assert_eq!(eval_str("[1 + 2 * 3, (1 + 2) * 3, 8 - 4 - 2, 8 / 4 / 2]").unwrap(), "[7, 9, 2, 1]");
+    /// assert_eq!(eval_str("[2 - -1, -2 * 3, 1 - 2 + 3]").unwrap(), "[3, -6, 2]");
+    /// assert_eq!(eval_str("[1] * 2").unwrap_err().kind, ErrorKind::Type);
+    /// assert_eq!(eval_str("1 + true").unwrap_err().kind, ErrorKind::Type);
+    /// ```
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// `a ^ b`, or `a ** b`. Right-associative, and binds tighter than a
+    /// `-` before it: `-2 ^ 2` is `-(2 ^ 2)`.
+    ///
+    /// ```
+    /// use kyomusky::ast::{BinOp, ExprKind};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let parse = |src| parse_program(src, ParseOptions::default());
+    /// let op = |src| match parse(src).unwrap()[0].kind {
+    ///     ExprKind::Binary { op, .. } => Some(op),
+    ///     _ => None,
+    /// };
+    /// assert_eq!(parse("2 ** 3 ** 2"), parse("2 ^ 3 ^ 2"));
+    /// assert_eq!(parse("2 ** 3 ** 2").unwrap()[0].to_string(), "2 ^ 3 ^ 2");
+    /// assert_eq!(parse("(2 ** 3) ** 2").unwrap()[0].to_string(), "(2 ^ 3) ^ 2");
+    ///
+    /// assert_eq!(op("a ** b"), Some(BinOp::Pow));
+    /// assert_eq!(op("a * -b"), Some(BinOp::Mul));
+    /// assert_eq!(op("-2 ^ 2"), None);
+    /// assert!(parse("a * *b").is_err());
+    /// ```
+    Pow,
+
+    Eq,
+    NotEq,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+
+    /// `a && b`, or `a and b`. `b` is only evaluated if `a` is true.
+    And,
+    /// `a || b`, or `a or b`. `b` is only evaluated if `a` is false.
+    Or,
+    /// `a |> f`, i.e. `f(a)`, or `a |> f(b)`, i.e. `f(a, b)`. Sugar that
+    /// [`desugar`](crate::desugar::desugar) removes before evaluation.
+    Pipe,
+}
+
+impl BinOp {
+    /// How the operator is printed.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Pow => "^",
+            BinOp::Eq => "==",
+            BinOp::NotEq => "!=",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::LtEq => "<=",
+            BinOp::GtEq => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+            BinOp::Pipe => "|>",
+        }
+    }
+
+    /// Whether the operator compares its operands.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinOp::Eq | BinOp::NotEq | BinOp::Lt | BinOp::Gt | BinOp::LtEq | BinOp::GtEq
+        )
+    }
+}
+
+/// A piece of an interpolated string. `\\${` is a literal `${`.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let run = |src: &str| {
+///     let program = parse_program(src, ParseOptions::default()).unwrap();
+///     let value = eval(&program[0], &mut Env::new()).unwrap();
+///     (value.to_string(), program[0].to_string())
+/// };
+///
+/// assert_eq!(run(r#"let x = 2; "x is ${x * 3}""#).0, "x is 6");
+/// assert_eq!(run(r#""${1} + ${[2]} costs $5""#).0, "1 + [2] costs $5");
+///
+/// let (value, printed) = run(r#""a \${b} c""#);
+/// assert_eq!(value, "a ${b} c");
+/// assert_eq!(printed, r#""a \${b} c""#);
+/// ```
+#[derive(Debug, Clone)]
+pub enum StrPart {
+    Lit(String),
+    /// `${expr}`
+    Expr(Expr),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// The direct subexpressions, in source order.
+    pub fn children(&self) -> Vec<&Expr> {
+        self.kind.children()
+    }
+
+    /// The number of nodes in the tree, including this one.
+    pub fn node_count(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// The `let` and `fn` declarations at the head of a declaration chain, in
+    /// order.
+    pub fn declarations(&self) -> Vec<&Expr> {
+        let mut decls = Vec::new();
+        let mut rest = self;
+        while let ExprKind::Let { then, .. } | ExprKind::Fn { then, .. } = &rest.kind {
+            decls.push(rest);
+            rest = then;
+        }
+        decls
+    }
+
+    /// The innermost node whose span contains the character at `offset`.
+    ///
+    /// Spans are half-open, so an offset right between two siblings, as in
+    /// `a+b` at the `+`, belongs to the one starting there. Since declarations
+    /// span only up to their `;`, whitespace between them, like an offset past
+    /// the end of the source, is in no node at all.
+    pub fn node_at(&self, offset: usize) -> Option<&Expr> {
+        self.path_to(offset).pop()
+    }
+
+    /// The nodes from this one down to [`Expr::node_at`], each the parent of
+    /// the next. Empty if no node contains `offset`.
+    pub fn path_to(&self, offset: usize) -> Vec<&Expr> {
+        let contains = |e: &Expr| e.span.start <= offset && offset < e.span.end;
+        let mut path = vec![];
+        // Length of the path up to the innermost node containing `offset`.
+        let mut found = 0;
+        let mut node = self;
+        loop {
+            path.push(node);
+            if contains(node) {
+                found = path.len();
+            }
+            let children = node.children();
+            // The rest of a declaration chain lies outside the declaration's
+            // span, so look there when no child contains the offset.
+            let next = match children.iter().find(|c| contains(c)) {
+                Some(child) => *child,
+                None => match &node.kind {
+                    ExprKind::Let { then, .. } | ExprKind::Fn { then, .. } => then,
+                    _ => break,
+                },
+            };
+            node = next;
+        }
+        path.truncate(found);
+        path
+    }
+
+    /// The number of nodes on the longest path from this node to a leaf; a
+    /// single literal has depth 1.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((expr, d)) = stack.pop() {
+            depth = depth.max(d);
+            stack.extend(expr.children().into_iter().map(|child| (child, d + 1)));
+        }
+        depth
+    }
+
+    /// Iterates over this node and all its descendants in pre-order, i.e. each
+    /// node before its children, and children in source order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { stack: vec![self] }
+    }
+
+    /// Like [`Expr::iter`], with the span of each node.
+    pub fn iter_with_spans(&self) -> impl Iterator<Item = (&Expr, &Span)> {
+        self.iter().map(|e| (e, &e.span))
+    }
+
+    /// The first node in pre-order satisfying `pred`.
+    pub fn find(&self, mut pred: impl FnMut(&Expr) -> bool) -> Option<&Expr> {
+        self.iter().find(|e| pred(e))
+    }
+
+    /// Whether any node of the tree satisfies `pred`.
+    pub fn any(&self, pred: impl FnMut(&Expr) -> bool) -> bool {
+        self.find(pred).is_some()
+    }
+}
+
+/// A pre-order iterator over a tree, see [`Expr::iter`]. It keeps a worklist
+/// instead of recursing, so deep trees cannot overflow the stack.
+pub struct Iter<'a> {
+    stack: Vec<&'a Expr>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Expr;
+
+    fn next(&mut self) -> Option<&'a Expr> {
+        let expr = self.stack.pop()?;
+        self.stack.extend(expr.children().into_iter().rev());
+        Some(expr)
+    }
+}
+
+impl Expr {
+    /// Rebuilds the tree bottom-up: the children of a node are rewritten
+    /// first, then the node itself is handed to `f`. Nodes keep their spans
+    /// unless `f` replaces them.
+    ///
+    /// The traversal uses an explicit stack, so arbitrarily deep trees don't
+    /// overflow.
+    pub fn transform(self, f: &mut impl FnMut(Expr) -> Expr) -> Expr {
+        match self.try_transform(&mut |e| Ok::<_, std::convert::Infallible>(f(e))) {
+            Ok(e) => e,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`Expr::transform`], but stops at the first error returned by `f`.
+    pub fn try_transform<E>(self, f: &mut impl FnMut(Expr) -> Result<Expr, E>) -> Result<Expr, E> {
+        enum Frame {
+            /// A node whose children are yet to be rewritten.
+            Enter(Expr),
+            /// A node whose rewritten children are the last `n` on `done`.
+            Exit(Expr, usize),
+        }
+
+        let mut stack = vec![Frame::Enter(self)];
+        let mut done: Vec<Expr> = Vec::new();
+        while let Some(frame) = stack.pop() {
+            match frame {
+                Frame::Enter(mut expr) => {
+                    let children: Vec<Expr> = expr
+                        .kind
+                        .children_mut()
+                        .into_iter()
+                        .map(|child| {
+                            let span = child.span.clone();
+                            std::mem::replace(child, Expr::new(ExprKind::Error, span))
+                        })
+                        .collect();
+                    stack.push(Frame::Exit(expr, children.len()));
+                    stack.extend(children.into_iter().rev().map(Frame::Enter));
+                }
+                Frame::Exit(mut expr, n) => {
+                    let children = done.split_off(done.len() - n);
+                    for (slot, child) in expr.kind.children_mut().into_iter().zip(children) {
+                        *slot = child;
+                    }
+                    done.push(f(expr)?);
+                }
+            }
+        }
+        Ok(done.pop().unwrap())
+    }
+}
+
+/// [`Expr::transform`] as a function, for rewrites like constant folding and
+/// desugaring. Nodes `f` returns unchanged keep their spans, and so do the
+/// nodes it rebuilds unless it gives them new ones, so errors found later
+/// still point into the original source.
+///
+/// ```
+/// use kyomusky::ast::{map_expr, ExprKind};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "x * 2 + y";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// // Rename `x` to `z`, keeping the span of the `Var`.
+/// let renamed = map_expr(program[0].clone(), |mut e| {
+///     if e.kind == ExprKind::Var("x".to_string()) {
+///         e.kind = ExprKind::Var("z".to_string());
+///     }
+///     e
+/// });
+/// assert_eq!(renamed.to_string(), "z * 2 + y");
+/// let spans = |e: &kyomusky::ast::Expr| e.iter().map(|n| n.span.clone()).collect::<Vec<_>>();
+/// assert_eq!(spans(&renamed), spans(&program[0]));
+/// ```
+pub fn map_expr(expr: Expr, mut f: impl FnMut(Expr) -> Expr) -> Expr {
+    expr.transform(&mut f)
+}
+
+impl ExprKind {
+    /// The direct subexpressions, in source order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            ExprKind::Error
+            | ExprKind::Num(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_)
+            | ExprKind::None
+            | ExprKind::Var(_) => vec![],
+            ExprKind::Unary { operand: a, .. }
+            | ExprKind::NamedArg(_, a)
+            | ExprKind::Spread(a)
+            | ExprKind::Block(a)
+            | ExprKind::Lambda { body: a, .. } => vec![a],
+            ExprKind::Binary { lhs: a, rhs: b, .. }
+            | ExprKind::Index(a, b)
+            | ExprKind::For {
+                list: a, body: b, ..
+            } => vec![a, b],
+            ExprKind::List(items) => items.iter().collect(),
+            ExprKind::InterpolatedStr(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    StrPart::Lit(_) => None,
+                    StrPart::Expr(e) => Some(e),
+                })
+                .collect(),
+            ExprKind::Map(entries) => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+            ExprKind::IndexAssign {
+                target,
+                index,
+                value,
+            } => vec![target, index, value],
+            ExprKind::Call(_, args) => args.iter().collect(),
+            ExprKind::Apply(callee, args) => std::iter::once(&**callee).chain(args).collect(),
+            ExprKind::Let { rhs, then, .. } => vec![rhs, then],
+            ExprKind::Fn { body, then, .. } => vec![body, then],
+            ExprKind::IfLet {
+                value,
+                then,
+                otherwise,
+                ..
+            } => vec![value, then, otherwise],
+            ExprKind::Comprehension {
+                body,
+                generators,
+                filter,
+            } => std::iter::once(&**body)
+                .chain(generators.iter().map(|(_, list)| list))
+                .chain(filter.iter().map(|filter| &**filter))
+                .collect(),
+        }
+    }
+
+    /// Mutable references to the direct subexpressions, in source order.
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            ExprKind::Error
+            | ExprKind::Num(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_)
+            | ExprKind::None
+            | ExprKind::Var(_) => vec![],
+            ExprKind::Unary { operand: a, .. }
+            | ExprKind::NamedArg(_, a)
+            | ExprKind::Spread(a)
+            | ExprKind::Block(a)
+            | ExprKind::Lambda { body: a, .. } => vec![a],
+            ExprKind::Binary { lhs: a, rhs: b, .. }
+            | ExprKind::Index(a, b)
+            | ExprKind::For {
+                list: a, body: b, ..
+            } => vec![a, b],
+            ExprKind::List(items) => items.iter_mut().collect(),
+            ExprKind::InterpolatedStr(parts) => parts
+                .iter_mut()
+                .filter_map(|part| match part {
+                    StrPart::Lit(_) => None,
+                    StrPart::Expr(e) => Some(e),
+                })
+                .collect(),
+            ExprKind::Map(entries) => entries.iter_mut().flat_map(|(k, v)| [k, v]).collect(),
+            ExprKind::IndexAssign {
+                target,
+                index,
+                value,
+            } => vec![target, index, value],
+            ExprKind::Call(_, args) => args.iter_mut().collect(),
+            ExprKind::Apply(callee, args) => std::iter::once(&mut **callee)
+                .chain(args.iter_mut())
+                .collect(),
+            ExprKind::Let { rhs, then, .. } => vec![rhs, then],
+            ExprKind::Fn { body, then, .. } => vec![body, then],
+            ExprKind::IfLet {
+                value,
+                then,
+                otherwise,
+                ..
+            } => vec![value, then, otherwise],
+            ExprKind::Comprehension {
+                body,
+                generators,
+                filter,
+            } => std::iter::once(&mut **body)
+                .chain(generators.iter_mut().map(|(_, list)| list))
+                .chain(filter.iter_mut().map(|filter| &mut **filter))
+                .collect(),
+        }
+    }
+
+    /// The name of the variant, as shown in `Debug` output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExprKind::Error => "Error",
+            ExprKind::Num(_) => "Num",
+            ExprKind::Str(_) => "Str",
+            ExprKind::InterpolatedStr(_) => "InterpolatedStr",
+            ExprKind::Bool(_) => "Bool",
+            ExprKind::None => "None",
+            ExprKind::Var(_) => "Var",
+            ExprKind::Unary { .. } => "Unary",
+            ExprKind::Binary { .. } => "Binary",
+            ExprKind::List(_) => "List",
+            ExprKind::Map(_) => "Map",
+            ExprKind::Index(..) => "Index",
+            ExprKind::IndexAssign { .. } => "IndexAssign",
+            ExprKind::Call(..) => "Call",
+            ExprKind::Apply(..) => "Apply",
+            ExprKind::NamedArg(..) => "NamedArg",
+            ExprKind::Spread(_) => "Spread",
+            ExprKind::Block(_) => "Block",
+            ExprKind::Let { .. } => "Let",
+            ExprKind::Fn { .. } => "Fn",
+            ExprKind::Lambda { .. } => "Lambda",
+            ExprKind::For { .. } => "For",
+            ExprKind::IfLet { .. } => "IfLet",
+            ExprKind::Comprehension { .. } => "Comprehension",
+        }
+    }
+}
+
+/// The contents of a node other than its children.
+#[derive(PartialEq, Hash)]
+enum Payload<'a> {
+    None,
+    Num(u64),
+    Bool(bool),
+    Unary(UnaryOp),
+    Binary(BinOp),
+    Text(&'a str),
+    Let(&'a str, bool, Option<&'a str>),
+    Fn(&'a str, &'a [String], Option<&'a str>),
+    Lambda(&'a [String]),
+    /// The variables of the generators of a comprehension.
+    Vars(Vec<&'a str>),
+    /// The literal parts of an interpolated string, with `None` for each
+    /// expression.
+    Parts(Vec<Option<&'a str>>),
+}
+
+impl ExprKind {
+    fn payload(&self) -> Payload<'_> {
+        match self {
+            ExprKind::Num(x) => Payload::Num(x.to_bits()),
+            ExprKind::Bool(b) => Payload::Bool(*b),
+            ExprKind::Unary { op, .. } => Payload::Unary(*op),
+            ExprKind::Binary { op, .. } => Payload::Binary(*op),
+            ExprKind::Str(s)
+            | ExprKind::Var(s)
+            | ExprKind::Call(s, _)
+            | ExprKind::NamedArg(s, _)
+            | ExprKind::For { var: s, .. }
+            | ExprKind::IfLet { name: s, .. } => Payload::Text(s),
+            ExprKind::Let {
+                name,
+                lazy,
+                deprecated,
+                ..
+            } => Payload::Let(name, *lazy, deprecated.as_deref()),
+            ExprKind::Fn {
+                name,
+                args,
+                deprecated,
+                ..
+            } => Payload::Fn(name, args, deprecated.as_deref()),
+            ExprKind::Lambda { args, .. } => Payload::Lambda(args),
+            ExprKind::Comprehension { generators, .. } => {
+                Payload::Vars(generators.iter().map(|(var, _)| var.as_str()).collect())
+            }
+            ExprKind::InterpolatedStr(parts) => Payload::Parts(
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        StrPart::Lit(s) => Some(s.as_str()),
+                        StrPart::Expr(_) => None,
+                    })
+                    .collect(),
+            ),
+            _ => Payload::None,
+        }
+    }
+}
+
+impl PartialEq for ExprKind {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.children(), other.children());
+        self.name() == other.name()
+            && self.payload() == other.payload()
+            && a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| a.kind == b.kind)
+    }
+}
+
+impl Eq for ExprKind {}
+
+impl std::hash::Hash for ExprKind {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name().hash(state);
+        self.payload().hash(state);
+        for child in self.children() {
+            child.kind.hash(state);
+        }
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Expr {}
+
+/// The first place where two trees differ, see [`Expr::diff`].
+#[derive(Debug)]
+pub struct Difference<'a> {
+    /// The index of each node among its parent's children, from the
+    /// children of the roots down to `left` and `right`.
+    pub path: Vec<usize>,
+    pub left: &'a Expr,
+    pub right: &'a Expr,
+}
+
+impl Expr {
+    /// The first pair of nodes, in pre-order, that differ in kind, in what
+    /// they hold besides their children, or in their number of children.
+    /// `None` exactly when the trees are equal, which ignores spans.
+    ///
+    /// ```
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let parse = |src| parse_program(src, ParseOptions::default()).unwrap().remove(0);
+    /// let a = parse("let x = 1 + 2 * y; f(x)");
+    /// let b = parse("let x =\n    1+(2*y) ;\nf( x )");
+    /// assert_eq!(a, b);
+    /// assert!(a.diff(&b).is_none());
+    ///
+    /// let c = parse("let x = 1 + 2 * z; f(x)");
+    /// let diff = a.diff(&c).unwrap();
+    /// assert_eq!(diff.path, [0, 1, 1]);
+    /// assert_eq!(diff.left.to_string(), "y");
+    /// assert_eq!(diff.right.to_string(), "z");
+    /// ```
+    pub fn diff<'a>(&'a self, other: &'a Expr) -> Option<Difference<'a>> {
+        let mut stack = vec![(self, other, vec![])];
+        while let Some((left, right, path)) = stack.pop() {
+            let (a, b) = (left.children(), right.children());
+            if left.kind.name() != right.kind.name()
+                || left.kind.payload() != right.kind.payload()
+                || a.len() != b.len()
+            {
+                return Some(Difference { path, left, right });
+            }
+            for (i, (a, b)) in a.into_iter().zip(b).enumerate().rev() {
+                let mut path = path.clone();
+                path.push(i);
+                stack.push((a, b, path));
+            }
+        }
+        None
+    }
+}
+
+impl std::hash::Hash for Expr {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+    }
+}
+
+impl std::fmt::Debug for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+/// The precedence of literals, names, calls and indexing.
+const ATOM: u8 = 10;
+
+impl Expr {
+    /// How tightly the expression binds when printed, from `:=` (loosest) to
+    /// atoms and indexing (tightest). An operand needs parentheses when it
+    /// binds more loosely than its operator allows.
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExprKind::Let { .. }
+            | ExprKind::Fn { .. }
+            | ExprKind::Lambda { .. }
+            | ExprKind::For { .. }
+            | ExprKind::IfLet { .. }
+            | ExprKind::NamedArg(..)
+            | ExprKind::Spread(_) => 0,
+            ExprKind::IndexAssign { .. } => 1,
+            ExprKind::Binary { op, .. } => match op {
+                BinOp::Pipe => 2,
+                BinOp::Or => 3,
+                BinOp::And => 4,
+                BinOp::Eq | BinOp::NotEq | BinOp::Lt | BinOp::Gt | BinOp::LtEq | BinOp::GtEq => 5,
+                BinOp::Add | BinOp::Sub => 6,
+                BinOp::Mul | BinOp::Div => 7,
+                BinOp::Pow => 9,
+            },
+            // Negative numbers are printed with a minus sign, and infinities
+            // and NaN as the divisions that produce them.
+            ExprKind::Num(x) if !x.is_finite() => 7,
+            ExprKind::Unary { .. } => 8,
+            ExprKind::Num(x) if x.is_sign_negative() => 8,
+            _ => ATOM,
+        }
+    }
+
+    /// Like `Display`, but number literals are printed as they are written
+    /// in `src`, the source the tree was parsed from, e.g. `0x10` or `1.50`
+    /// rather than `16` or `1.5`. Numbers that are not literals of `src`,
+    /// such as those computed by constant folding, are printed as usual.
+    ///
+    /// ```
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "0x10 + 1.50 * x";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// assert_eq!(program[0].display_with_source(src).to_string(), src);
+    /// assert_eq!(program[0].to_string(), "16 + 1.5 * x");
+    /// ```
+    pub fn display_with_source<'a>(&'a self, src: &'a str) -> impl std::fmt::Display + 'a {
+        Printer {
+            expr: self,
+            src: Some(src),
+            compact: false,
+        }
+    }
+
+    /// Like `Display`, but as short as possible: without the whitespace that
+    /// isn't needed to separate words. Parses back to the same tree.
+    ///
+    /// ```
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let parse = |src: &str| parse_program(src, ParseOptions::default()).unwrap();
+    /// let src = "let x = 1 + 2 ; x";
+    /// let minified = parse(src)[0].minified().to_string();
+    /// assert_eq!(minified, "let x=1+2;x");
+    /// assert_eq!(parse(&minified), parse(src));
+    ///
+    /// for src in [
+    ///     "fn f x y = { let z = x - -y; [z, z * 2] }; f(1, y = 2)[0]",
+    ///     r#"let m = {"a": 1, "b": [1, 2]}; m["a"] := (1 + 2) * 3"#,
+    ///     "let lazy k = \\x y -> x ^ -y; k(2, 1) > 0 and !(1 == 2)",
+    ///     "[x * y for x in [1, 2] for y in [3] if x != y] |> len",
+    ///     "if let some(v) = find(\\x -> x >= 2, [1, 2]) then v else 0",
+    ///     "for x in [0x10, 1.50] do \"${x}\"",
+    /// ] {
+    ///     let minified = parse(src)[0].minified().to_string();
+    ///     assert!(minified.len() < src.len(), "{minified}");
+    ///     assert_eq!(parse(&minified), parse(src), "{minified}");
+    /// }
+    /// ```
+    pub fn minified(&self) -> impl std::fmt::Display + '_ {
+        Printer {
+            expr: self,
+            src: None,
+            compact: true,
+        }
+    }
+}
+
+/// Prints an expression as source code, see [`Expr::display_with_source`].
+struct Printer<'a> {
+    expr: &'a Expr,
+    src: Option<&'a str>,
+    /// Leave out optional whitespace, see [`Expr::minified`].
+    compact: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn child<'b>(&self, expr: &'b Expr) -> Printer<'b>
+    where
+        'a: 'b,
+    {
+        Printer {
+            expr,
+            src: self.src,
+            compact: self.compact,
+        }
+    }
+
+    /// The whitespace around operators and after separators: none when
+    /// compact.
+    fn space(&self) -> &'static str {
+        if self.compact {
+            ""
+        } else {
+            " "
+        }
+    }
+
+    /// The whitespace after a declaration: a new line, or none when compact.
+    fn line_break(&self) -> &'static str {
+        if self.compact {
+            ""
+        } else {
+            "\n"
+        }
+    }
+
+    /// Prints `expr`, parenthesized if it binds more loosely than `min`.
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter, expr: &Expr, min: u8) -> std::fmt::Result {
+        if expr.precedence() < min {
+            write!(f, "({})", self.child(expr))
+        } else {
+            write!(f, "{}", self.child(expr))
+        }
+    }
+
+    fn fmt_list<'b>(
+        &self,
+        f: &mut std::fmt::Formatter,
+        items: impl IntoIterator<Item = &'b Expr>,
+    ) -> std::fmt::Result {
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ",{}", self.space())?;
+            }
+            write!(f, "{}", self.child(item))?;
+        }
+        Ok(())
+    }
+
+    /// The text of the number literal `x` in the source, if it is one.
+    fn literal(&self, x: f64) -> Option<String> {
+        let span = &self.expr.span;
+        let text: String = self
+            .src?
+            .chars()
+            .skip(span.start)
+            .take(span.len())
+            .collect();
+        (crate::parser::number_value(&text)? == x).then_some(text)
+    }
+}
+
+/// Writes a string literal using only the escapes the parser understands.
+fn fmt_str(f: &mut std::fmt::Formatter, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    fmt_str_contents(f, s)?;
+    write!(f, "\"")
+}
+
+/// Writes the `@deprecated("message")` attribute of a declaration, if any.
+fn write_deprecated(f: &mut std::fmt::Formatter, message: Option<&str>) -> std::fmt::Result {
+    if let Some(message) = message {
+        write!(f, "@deprecated(")?;
+        fmt_str(f, message)?;
+        write!(f, ") ")?;
+    }
+    Ok(())
+}
+
+/// Writes the characters of a string literal between its quotes. A `$` is
+/// escaped before `{`, where it would start an interpolation.
+fn fmt_str_contents(f: &mut std::fmt::Formatter, s: &str) -> std::fmt::Result {
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => write!(f, "\\\\")?,
+            '"' => write!(f, "\\\"")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '$' if chars.peek() == Some(&'{') => write!(f, "\\$")?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Printer {
+            expr: self,
+            src: None,
+            compact: false,
+        }
+        .fmt(f)
+    }
+}
+
+impl std::fmt::Display for Printer<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let prec = self.expr.precedence();
+        let sp = self.space();
+
+        match &self.expr.kind {
+            // Not valid source, but keeps the output of broken programs
+            // readable.
+            ExprKind::Error => write!(f, "<error>"),
+            // Only constant folding or a hand-built tree puts these in a
+            // literal; the divisions evaluate to them once the `non-finite`
+            // error is downgraded.
+            ExprKind::Num(x) if x.is_nan() => write!(f, "0{sp}/{sp}0"),
+            ExprKind::Num(x) if x.is_infinite() => {
+                let sign = if *x < 0.0 { "-" } else { "" };
+                write!(f, "{sign}1{sp}/{sp}0")
+            }
+            ExprKind::Num(x) => match self.literal(*x) {
+                Some(text) => write!(f, "{text}"),
+                None => write!(f, "{x}"),
+            },
+            ExprKind::Str(s) => fmt_str(f, s),
+            ExprKind::InterpolatedStr(parts) => {
+                write!(f, "\"")?;
+                for part in parts {
+                    match part {
+                        StrPart::Lit(s) => fmt_str_contents(f, s)?,
+                        StrPart::Expr(e) => write!(f, "${{{}}}", self.child(e))?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            ExprKind::Bool(b) => write!(f, "{b}"),
+            ExprKind::None => write!(f, "none"),
+            ExprKind::Var(name) => write!(f, "{name}"),
+
+            ExprKind::Unary { op, operand } => {
+                write!(f, "{}", op.symbol())?;
+                self.fmt_operand(f, operand, prec)
+            }
+            // Right-associative, and the right operand can be negated.
+            ExprKind::Binary {
+                op: BinOp::Pow,
+                lhs,
+                rhs,
+            } => {
+                self.fmt_operand(f, lhs, prec + 1)?;
+                write!(f, "{sp}^{sp}")?;
+                self.fmt_operand(f, rhs, prec - 1)
+            }
+            // Other operators are left-associative, so a right operand at the
+            // same level needs parentheses: `a - (b - c)`.
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.fmt_operand(f, lhs, prec)?;
+                write!(f, "{sp}{}{sp}", op.symbol())?;
+                self.fmt_operand(f, rhs, prec + 1)
+            }
+
+            ExprKind::List(items) => {
+                write!(f, "[")?;
+                self.fmt_list(f, items)?;
+                write!(f, "]")
+            }
+            ExprKind::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",{sp}")?;
+                    }
+                    write!(f, "{}:{sp}{}", self.child(k), self.child(v))?;
+                }
+                write!(f, "}}")
+            }
+            ExprKind::Index(target, index) => {
+                self.fmt_operand(f, target, prec)?;
+                write!(f, "[{}]", self.child(index))
+            }
+            // `:=` is right-associative, and its target is always an index.
+            ExprKind::IndexAssign {
+                target,
+                index,
+                value,
+            } => {
+                self.fmt_operand(f, target, ATOM)?;
+                write!(f, "[{}]{sp}:={sp}", self.child(index))?;
+                self.fmt_operand(f, value, prec)
+            }
+
+            ExprKind::Call(name, args) => {
+                write!(f, "{name}(")?;
+                self.fmt_list(f, args)?;
+                write!(f, ")")
+            }
+            ExprKind::Apply(callee, args) => {
+                self.fmt_operand(f, callee, prec)?;
+                write!(f, "(")?;
+                self.fmt_list(f, args)?;
+                write!(f, ")")
+            }
+            ExprKind::NamedArg(name, value) => write!(f, "{name}{sp}={sp}{}", self.child(value)),
+            ExprKind::Spread(list) => write!(f, "...{}", self.child(list)),
+            ExprKind::Block(chain) => write!(f, "{{{sp}{}{sp}}}", self.child(chain)),
+            ExprKind::Let {
+                name,
+                lazy,
+                deprecated,
+                rhs,
+                then,
+            } => {
+                write_deprecated(f, deprecated.as_deref())?;
+                let lazy = if *lazy { "lazy " } else { "" };
+                write!(
+                    f,
+                    "let {lazy}{name}{sp}={sp}{};{}{}",
+                    self.child(rhs),
+                    self.line_break(),
+                    self.child(then)
+                )
+            }
+            ExprKind::Fn {
+                name,
+                args,
+                deprecated,
+                body,
+                then,
+            } => {
+                write_deprecated(f, deprecated.as_deref())?;
+                write!(f, "fn {name}")?;
+                for arg in args {
+                    write!(f, " {arg}")?;
+                }
+                write!(
+                    f,
+                    "{sp}={sp}{};{}{}",
+                    self.child(body),
+                    self.line_break(),
+                    self.child(then)
+                )
+            }
+            ExprKind::Lambda { args, body } => {
+                // No space between `\` and `->` without parameters.
+                let gap = if args.is_empty() { "" } else { sp };
+                write!(f, "\\{}{gap}->{sp}{}", args.join(" "), self.child(body))
+            }
+            ExprKind::For { var, list, body } => {
+                write!(
+                    f,
+                    "for {var} in {} do {}",
+                    self.child(list),
+                    self.child(body)
+                )
+            }
+            ExprKind::IfLet {
+                name,
+                value,
+                then,
+                otherwise,
+            } => {
+                write!(
+                    f,
+                    "if let some({name}){sp}={sp}{} then {} else {}",
+                    self.child(value),
+                    self.child(then),
+                    self.child(otherwise)
+                )
+            }
+            ExprKind::Comprehension {
+                body,
+                generators,
+                filter,
+            } => {
+                write!(f, "[{}", self.child(body))?;
+                for (var, list) in generators {
+                    write!(f, " for {var} in {}", self.child(list))?;
+                }
+                if let Some(filter) = filter {
+                    write!(f, " if {}", self.child(filter))?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Summary statistics over repeated timings, for the `bench` subcommand.
+
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timings {
+    pub iterations: usize,
+    pub min: Duration,
+    /// The middle sample, or the mean of the two middle ones.
+    pub median: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+impl Timings {
+    /// Summarizes `samples`, or returns `None` if there are none.
+    pub fn of(samples: &[Duration]) -> Option<Timings> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+        let n = sorted.len();
+        let median = if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2
+        };
+        Some(Timings {
+            iterations: n,
+            min: sorted[0],
+            median,
+            max: sorted[n - 1],
+            mean: sorted.iter().sum::<Duration>() / n as u32,
+        })
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "iterations: {}", self.iterations)?;
+        writeln!(f, "min:        {:?}", self.min)?;
+        writeln!(f, "median:     {:?}", self.median)?;
+        writeln!(f, "max:        {:?}", self.max)?;
+        writeln!(f, "mean:       {:?}", self.mean)
+    }
+}
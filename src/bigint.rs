@@ -0,0 +1,209 @@
+//! Arbitrary-precision integers, used when the `bigint` feature is enabled so
+//! that integer arithmetic stays exact beyond the range of `f64`.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Each limb holds nine decimal digits, which keeps printing trivial.
+const BASE: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    /// Little-endian limbs without trailing zeros; zero has no limbs.
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    /// Converts an integral float. Returns `None` for fractional or
+    /// non-finite values.
+    pub fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() || x.fract() != 0.0 {
+            return None;
+        }
+        format!("{:.0}", x.abs())
+            .parse::<BigInt>()
+            .ok()
+            .map(|b| b.with_sign(x < 0.0))
+    }
+
+    /// The nearest float, which may be infinite.
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    fn with_sign(mut self, negative: bool) -> Self {
+        self.negative = negative && !self.is_zero();
+        self
+    }
+
+    fn trim(mut self) -> Self {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        let negative = self.negative;
+        self.with_sign(negative)
+    }
+
+    fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+        a.len()
+            .cmp(&b.len())
+            .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+    }
+
+    fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut out = Vec::with_capacity(a.len().max(b.len()) + 1);
+        let mut carry = 0;
+        for i in 0..a.len().max(b.len()) {
+            let sum = carry + *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64;
+            out.push((sum % BASE) as u32);
+            carry = sum / BASE;
+        }
+        if carry > 0 {
+            out.push(carry as u32);
+        }
+        out
+    }
+
+    /// Computes `a - b`, where `a` must not be smaller than `b`.
+    fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut out = Vec::with_capacity(a.len());
+        let mut borrow = 0;
+        for (i, &limb) in a.iter().enumerate() {
+            let mut diff = limb as i64 - borrow - *b.get(i).unwrap_or(&0) as i64;
+            borrow = 0;
+            if diff < 0 {
+                diff += BASE as i64;
+                borrow = 1;
+            }
+            out.push(diff as u32);
+        }
+        out
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            let limbs = Self::add_magnitude(&self.limbs, &other.limbs);
+            return BigInt {
+                negative: self.negative,
+                limbs,
+            }
+            .trim();
+        }
+        match Self::cmp_magnitude(&self.limbs, &other.limbs) {
+            Ordering::Less => BigInt {
+                negative: other.negative,
+                limbs: Self::sub_magnitude(&other.limbs, &self.limbs),
+            },
+            _ => BigInt {
+                negative: self.negative,
+                limbs: Self::sub_magnitude(&self.limbs, &other.limbs),
+            },
+        }
+        .trim()
+    }
+
+    pub fn neg(&self) -> BigInt {
+        self.clone().with_sign(!self.negative)
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut out = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let cur = out[i + j] + a as u64 * b as u64 + carry;
+                out[i + j] = cur % BASE;
+                carry = cur / BASE;
+            }
+            let mut k = i + other.limbs.len();
+            while carry > 0 {
+                let cur = out[k] + carry;
+                out[k] = cur % BASE;
+                carry = cur / BASE;
+                k += 1;
+            }
+        }
+        BigInt {
+            negative: self.negative != other.negative,
+            limbs: out.into_iter().map(|limb| limb as u32).collect(),
+        }
+        .trim()
+    }
+}
+
+impl From<u64> for BigInt {
+    fn from(mut n: u64) -> Self {
+        let mut limbs = Vec::new();
+        while n > 0 {
+            limbs.push((n % BASE) as u32);
+            n /= BASE;
+        }
+        BigInt {
+            negative: false,
+            limbs,
+        }
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => Self::cmp_magnitude(&self.limbs, &other.limbs),
+            (true, true) => Self::cmp_magnitude(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::str::FromStr for BigInt {
+    type Err = ();
+
+    /// Parses an optionally negative string of decimal digits.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        let limbs = digits
+            .as_bytes()
+            .rchunks(9)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap().parse().unwrap())
+            .collect();
+        Ok(BigInt { negative, limbs }.trim())
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some((last, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{last}")?;
+        for limb in rest.iter().rev() {
+            write!(f, "{limb:09}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+//! Constructors for building syntax trees without going through the parser.
+//!
+//! Arithmetic operators work on `Expr` directly:
+//!
+//! ```
+//! use kyomusky::build::*;
+//!
+//! // let x = 2; f(x * 3, -1)
+//! let program = let_("x", num(2.0), call("f", [var("x") * num(3.0), -num(1.0)]));
+//! assert_eq!(program.to_string(), "let x = 2;\nf(x * 3, -1)");
+//! ```
+//!
+//! Built nodes have an empty span at offset 0. Since spans are ignored when
+//! comparing expressions, they can be checked against parsed ones.
+
+use crate::ast::{BinOp, Expr, ExprKind, UnaryOp};
+
+fn node(kind: ExprKind) -> Expr {
+    Expr::new(kind, 0..0)
+}
+
+pub fn num(x: f64) -> Expr {
+    node(ExprKind::Num(x))
+}
+
+pub fn string(s: &str) -> Expr {
+    node(ExprKind::Str(s.to_string()))
+}
+
+pub fn boolean(b: bool) -> Expr {
+    node(ExprKind::Bool(b))
+}
+
+pub fn var(name: &str) -> Expr {
+    node(ExprKind::Var(name.to_string()))
+}
+
+pub fn list(items: impl IntoIterator<Item = Expr>) -> Expr {
+    node(ExprKind::List(items.into_iter().collect()))
+}
+
+pub fn index(target: Expr, index: Expr) -> Expr {
+    node(ExprKind::Index(Box::new(target), Box::new(index)))
+}
+
+pub fn call(name: &str, args: impl IntoIterator<Item = Expr>) -> Expr {
+    node(ExprKind::Call(name.to_string(), args.into_iter().collect()))
+}
+
+/// `name = value`, to be passed among the arguments of [`call`].
+pub fn named(name: &str, value: Expr) -> Expr {
+    node(ExprKind::NamedArg(name.to_string(), Box::new(value)))
+}
+
+/// `...list`, to be passed among the arguments of [`call`].
+pub fn spread(list: Expr) -> Expr {
+    node(ExprKind::Spread(Box::new(list)))
+}
+
+pub fn let_(name: &str, rhs: Expr, then: Expr) -> Expr {
+    node(ExprKind::Let {
+        name: name.to_string(),
+        lazy: false,
+        deprecated: None,
+        rhs: Box::new(rhs),
+        then: Box::new(then),
+    })
+}
+
+pub fn fn_<'a>(
+    name: &str,
+    args: impl IntoIterator<Item = &'a str>,
+    body: Expr,
+    then: Expr,
+) -> Expr {
+    node(ExprKind::Fn {
+        name: name.to_string(),
+        args: args.into_iter().map(str::to_string).collect(),
+        deprecated: None,
+        body: Box::new(body),
+        then: Box::new(then),
+    })
+}
+
+macro_rules! binary_op {
+    ($trait:ident, $method:ident, $op:ident) => {
+        impl std::ops::$trait for Expr {
+            type Output = Expr;
+
+            fn $method(self, rhs: Expr) -> Expr {
+                node(ExprKind::Binary {
+                    op: BinOp::$op,
+                    lhs: Box::new(self),
+                    rhs: Box::new(rhs),
+                })
+            }
+        }
+    };
+}
+
+binary_op!(Add, add, Add);
+binary_op!(Sub, sub, Sub);
+binary_op!(Mul, mul, Mul);
+binary_op!(Div, div, Div);
+
+impl std::ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        node(ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand: Box::new(self),
+        })
+    }
+}
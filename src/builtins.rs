@@ -0,0 +1,1489 @@
+//! Functions that can be called without being declared. A function declared
+//! with `fn` shadows a built-in of the same name.
+
+use crate::ast::Span;
+use crate::eval::{
+    call_function, compare, expect_bool, Env, ErrorKind, EvalError, FunctionValue, Value,
+};
+use crate::json::Json;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+#[cfg(feature = "bigint")]
+use crate::bigint::BigInt;
+
+/// Evaluated arguments, each with the span of the expression it came from.
+pub type Args = [(Value, Span)];
+
+/// The number of arguments a built-in accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exact(m) => n == m,
+            Arity::AtLeast(m) => n >= m,
+        }
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{n}"),
+            Arity::AtLeast(n) => write!(f, "at least {n}"),
+        }
+    }
+}
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    /// Receives the evaluated arguments and the span of the whole call.
+    pub call: fn(&mut Env, &Args, &Span) -> Result<Value, EvalError>,
+    /// Whether the result only depends on the arguments, so that calling it
+    /// is allowed by [`Env::deterministic`]. False for built-ins reading
+    /// input, random numbers or the time.
+    pub deterministic: bool,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "arity",
+        arity: Arity::Exact(1),
+        call: arity,
+        deterministic: true,
+    },
+    Builtin {
+        name: "chars",
+        arity: Arity::Exact(1),
+        call: chars,
+        deterministic: true,
+    },
+    Builtin {
+        name: "clamp",
+        arity: Arity::Exact(3),
+        call: clamp,
+        deterministic: true,
+    },
+    Builtin {
+        name: "clock",
+        arity: Arity::Exact(0),
+        call: clock,
+        deterministic: false,
+    },
+    Builtin {
+        name: "compare",
+        arity: Arity::Exact(2),
+        call: compare_values,
+        deterministic: true,
+    },
+    Builtin {
+        name: "compose",
+        arity: Arity::Exact(2),
+        call: compose,
+        deterministic: true,
+    },
+    Builtin {
+        name: "drop",
+        arity: Arity::Exact(2),
+        call: drop,
+        deterministic: true,
+    },
+    Builtin {
+        name: "drop_while",
+        arity: Arity::Exact(2),
+        call: drop_while,
+        deterministic: true,
+    },
+    Builtin {
+        name: "ends_with",
+        arity: Arity::Exact(2),
+        call: ends_with,
+        deterministic: true,
+    },
+    // WebAssembly hosts have no environment variables to read.
+    #[cfg(not(feature = "wasm"))]
+    Builtin {
+        name: "env",
+        arity: Arity::Exact(1),
+        call: env_var,
+        deterministic: false,
+    },
+    Builtin {
+        name: "euclid_mod",
+        arity: Arity::Exact(2),
+        call: euclid_mod,
+        deterministic: true,
+    },
+    Builtin {
+        name: "factorial",
+        arity: Arity::Exact(1),
+        call: factorial,
+        deterministic: true,
+    },
+    Builtin {
+        name: "filter",
+        arity: Arity::Exact(2),
+        call: filter,
+        deterministic: true,
+    },
+    Builtin {
+        name: "find",
+        arity: Arity::Exact(2),
+        call: find,
+        deterministic: true,
+    },
+    Builtin {
+        name: "flat_map",
+        arity: Arity::Exact(2),
+        call: flat_map,
+        deterministic: true,
+    },
+    Builtin {
+        name: "floor_mod",
+        arity: Arity::Exact(2),
+        call: floor_mod,
+        deterministic: true,
+    },
+    Builtin {
+        name: "from_json",
+        arity: Arity::Exact(1),
+        call: from_json,
+        deterministic: true,
+    },
+    Builtin {
+        name: "input",
+        arity: Arity::Exact(0),
+        call: input,
+        deterministic: false,
+    },
+    Builtin {
+        name: "len",
+        arity: Arity::Exact(1),
+        call: len,
+        deterministic: true,
+    },
+    Builtin {
+        name: "map",
+        arity: Arity::Exact(2),
+        call: map,
+        deterministic: true,
+    },
+    Builtin {
+        name: "max",
+        arity: Arity::AtLeast(1),
+        call: max,
+        deterministic: true,
+    },
+    Builtin {
+        name: "min",
+        arity: Arity::AtLeast(1),
+        call: min,
+        deterministic: true,
+    },
+    Builtin {
+        name: "name",
+        arity: Arity::Exact(1),
+        call: name,
+        deterministic: true,
+    },
+    Builtin {
+        name: "print",
+        arity: Arity::Exact(1),
+        call: print,
+        deterministic: true,
+    },
+    Builtin {
+        name: "now",
+        arity: Arity::Exact(0),
+        call: now,
+        deterministic: false,
+    },
+    Builtin {
+        name: "random",
+        arity: Arity::Exact(0),
+        call: random,
+        deterministic: false,
+    },
+    Builtin {
+        name: "range_list",
+        arity: Arity::Exact(3),
+        call: range_list,
+        deterministic: true,
+    },
+    Builtin {
+        name: "repeat",
+        arity: Arity::Exact(2),
+        call: repeat,
+        deterministic: true,
+    },
+    Builtin {
+        name: "replace",
+        arity: Arity::Exact(3),
+        call: replace,
+        deterministic: true,
+    },
+    Builtin {
+        name: "sample",
+        arity: Arity::Exact(2),
+        call: sample,
+        deterministic: false,
+    },
+    Builtin {
+        name: "shuffle",
+        arity: Arity::Exact(1),
+        call: shuffle,
+        deterministic: false,
+    },
+    Builtin {
+        name: "slice",
+        arity: Arity::Exact(3),
+        call: slice,
+        deterministic: true,
+    },
+    Builtin {
+        name: "some",
+        arity: Arity::Exact(1),
+        call: some,
+        deterministic: true,
+    },
+    Builtin {
+        name: "sqrt",
+        arity: Arity::Exact(1),
+        call: sqrt,
+        deterministic: true,
+    },
+    Builtin {
+        name: "starts_with",
+        arity: Arity::Exact(2),
+        call: starts_with,
+        deterministic: true,
+    },
+    Builtin {
+        name: "take",
+        arity: Arity::Exact(2),
+        call: take,
+        deterministic: true,
+    },
+    Builtin {
+        name: "take_while",
+        arity: Arity::Exact(2),
+        call: take_while,
+        deterministic: true,
+    },
+    Builtin {
+        name: "to_base",
+        arity: Arity::Exact(2),
+        call: to_base,
+        deterministic: true,
+    },
+    Builtin {
+        name: "to_bin",
+        arity: Arity::Exact(1),
+        call: to_bin,
+        deterministic: true,
+    },
+    Builtin {
+        name: "to_hex",
+        arity: Arity::Exact(1),
+        call: to_hex,
+        deterministic: true,
+    },
+    Builtin {
+        name: "to_json",
+        arity: Arity::Exact(1),
+        call: to_json,
+        deterministic: true,
+    },
+    Builtin {
+        name: "to_lower",
+        arity: Arity::Exact(1),
+        call: to_lower,
+        deterministic: true,
+    },
+    Builtin {
+        name: "to_upper",
+        arity: Arity::Exact(1),
+        call: to_upper,
+        deterministic: true,
+    },
+    Builtin {
+        name: "trim",
+        arity: Arity::Exact(1),
+        call: trim,
+        deterministic: true,
+    },
+    Builtin {
+        name: "zip_with",
+        arity: Arity::Exact(3),
+        call: zip_with,
+        deterministic: true,
+    },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+/// Converts a number to a non-negative integer.
+fn as_count(value: &Value, span: &Span) -> Result<u64, EvalError> {
+    let n = value.as_integer(span)?;
+    u64::try_from(n).map_err(|_| {
+        EvalError::new(
+            ErrorKind::Type,
+            format!("Type error: expected a non-negative integer, found {n}"),
+            span,
+        )
+    })
+}
+
+/// `factorial(n)` is exact with the `bigint` feature. Otherwise results above
+/// 2^53 are rounded, and `factorial(171)` and above overflow.
+fn factorial(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let (n, n_span) = &args[0];
+    let n = as_count(n, n_span)?;
+
+    #[cfg(feature = "bigint")]
+    {
+        let _ = (env, span);
+        let result = (2..=n).fold(BigInt::from(1), |acc, i| acc.mul(&BigInt::from(i)));
+        Ok(Value::from_big(result))
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    {
+        // Stop early, as the loop would take forever for huge `n`.
+        let result = if n > 170 {
+            f64::INFINITY
+        } else {
+            (2..=n).fold(1.0, |acc, i| acc * i as f64)
+        };
+        if result.is_finite() {
+            return Ok(Value::Num(result));
+        }
+        let err = EvalError::new(
+            ErrorKind::NonFinite,
+            format!("Arithmetic overflow: factorial({n}) is too large"),
+            span,
+        );
+        env.recover(err, Value::Num(result))
+    }
+}
+
+/// The numbers `min` or `max` choose from: either the arguments, or the
+/// elements of a single list argument.
+fn candidates(name: &str, args: &Args) -> Result<Vec<(Value, Span)>, EvalError> {
+    let candidates: Vec<_> = match args {
+        [(Value::List(xs), span)] => {
+            let xs = xs.borrow();
+            if xs.is_empty() {
+                return Err(EvalError::new(
+                    ErrorKind::Arity,
+                    format!("`{name}` of an empty list"),
+                    span,
+                ));
+            }
+            xs.iter().map(|x| (x.clone(), span.clone())).collect()
+        }
+        _ => args.to_vec(),
+    };
+    for (x, span) in &candidates {
+        x.as_num(span)?;
+    }
+    Ok(candidates)
+}
+
+/// Picks the candidate that compares as `wanted` against all others. NaN
+/// wins over any number.
+fn extremum(name: &str, args: &Args, wanted: Ordering) -> Result<Value, EvalError> {
+    let mut candidates = candidates(name, args)?.into_iter();
+    let (mut best, _) = candidates.next().unwrap();
+    for (x, span) in candidates {
+        match compare(&x, &best, &span)? {
+            Some(ord) if ord == wanted => best = x,
+            Some(_) => {}
+            None if best.as_num(&span)?.is_nan() => {}
+            None => best = x,
+        }
+    }
+    Ok(best)
+}
+
+/// `min(a, b, ...)` or `min(list)`.
+fn min(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    extremum("min", args, Ordering::Less)
+}
+
+/// `max(a, b, ...)` or `max(list)`.
+fn max(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    extremum("max", args, Ordering::Greater)
+}
+
+/// `clamp(x, lo, hi)` limits `x` to the range `lo..=hi`. Given a list, it
+/// returns a new list with every element clamped.
+fn clamp(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let [(x, x_span), (lo, lo_span), (hi, hi_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    lo.as_num(lo_span)?;
+    hi.as_num(hi_span)?;
+    if compare(lo, hi, hi_span)? == Some(Ordering::Greater) {
+        return Err(EvalError::new(
+            ErrorKind::Type,
+            format!("Type error: `clamp` bounds are reversed, {lo} is greater than {hi}"),
+            &(lo_span.start..hi_span.end),
+        ));
+    }
+    let clamp_one = |x: &Value| -> Result<Value, EvalError> {
+        x.as_num(x_span)?;
+        Ok(if compare(x, lo, x_span)? == Some(Ordering::Less) {
+            lo.clone()
+        } else if compare(x, hi, x_span)? == Some(Ordering::Greater) {
+            hi.clone()
+        } else {
+            x.clone()
+        })
+    };
+    match x {
+        Value::List(xs) => {
+            let clamped = xs
+                .borrow()
+                .iter()
+                .map(clamp_one)
+                .collect::<Result<_, _>>()?;
+            Ok(Value::List(Rc::new(RefCell::new(clamped))))
+        }
+        x => clamp_one(x),
+    }
+}
+
+/// `compare(a, b)` is `-1` if `a` comes before `b`, `0` if they are equal and
+/// `1` if `a` comes after `b`, in the order of `<`. Only numbers, strings and
+/// booleans can be compared, each with values of its own type.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("[compare(1, 2), compare(2, 2), compare(3, 2)]").unwrap(), "[-1, 0, 1]");
+/// let src = r#"[compare("apple", "banana"), compare("b", "b"), compare("b", "B")]"#;
+/// assert_eq!(eval_str(src).unwrap(), "[-1, 0, 1]");
+/// assert_eq!(eval_str("compare(false, true)").unwrap(), "-1");
+///
+/// let err = eval_str(r#"compare(1, "a")"#).unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Type);
+/// assert_eq!(err.message, "Type error: cannot compare number with string");
+/// assert_eq!(eval_str("compare(sqrt, sqrt)").unwrap_err().kind, ErrorKind::Type);
+/// assert_eq!(eval_str("compare([1], [1])").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn compare_values(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(a, _), (b, _)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    match compare(a, b, span)? {
+        Some(ord) => Ok(Value::Num(ord as i8 as f64)),
+        None => {
+            let err = EvalError::new(ErrorKind::NonFinite, "Cannot compare NaN", span);
+            env.recover(err, Value::Num(f64::NAN))
+        }
+    }
+}
+
+/// Copies a list, counting the copy towards the memory limit.
+fn as_list(env: &mut Env, value: &Value, span: &Span) -> Result<Vec<Value>, EvalError> {
+    match value {
+        Value::List(xs) => {
+            env.allocate(xs.borrow().len(), span)?;
+            Ok(xs.borrow().clone())
+        }
+        v => Err(v.type_error("list", span)),
+    }
+}
+
+/// `random()` is a number in `0..1`, drawn uniformly.
+fn random(env: &mut Env, _: &Args, _: &Span) -> Result<Value, EvalError> {
+    Ok(Value::Num(env.rng.next_f64()))
+}
+
+/// `shuffle(list)` returns a copy of `list` in random order.
+fn shuffle(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (xs, xs_span) = &args[0];
+    let mut xs = as_list(env, xs, xs_span)?;
+    for i in (1..xs.len()).rev() {
+        xs.swap(i, env.rng.below(i + 1));
+    }
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+/// `sample(list, k)` returns `k` elements of `list` picked at random, each at
+/// most once, in the order they were picked.
+fn sample(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(xs, xs_span), (k, k_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let mut xs = as_list(env, xs, xs_span)?;
+    let k = as_count(k, k_span)?;
+    if k > xs.len() as u64 {
+        return Err(EvalError::new(
+            ErrorKind::Arity,
+            format!("Cannot `sample` {k} elements from a list of {}", xs.len()),
+            span,
+        ));
+    }
+    let k = k as usize;
+    for i in 0..k {
+        let j = i + env.rng.below(xs.len() - i);
+        xs.swap(i, j);
+    }
+    xs.truncate(k);
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+fn sqrt(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let (x, x_span) = &args[0];
+    let x = x.as_num(x_span)?;
+    let result = x.sqrt();
+    if result.is_nan() && !x.is_nan() {
+        let err = EvalError::new(
+            ErrorKind::NonFinite,
+            format!("Square root of negative number {x}"),
+            span,
+        );
+        return env.recover(err, Value::Num(result));
+    }
+    Ok(Value::Num(result))
+}
+
+/// `floor_mod(a, b)` is the remainder of dividing `a` by `b` rounded down,
+/// which has the sign of `b`. `euclid_mod(a, b)` is never negative. Unlike
+/// the truncated remainder of most languages, neither has the sign of `a`.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(-7.0_f64 % 3.0, -1.0);
+/// assert_eq!(eval_str("[floor_mod(-7, 3), euclid_mod(-7, 3)]").unwrap(), "[2, 2]");
+/// assert_eq!(eval_str("[floor_mod(7, -3), euclid_mod(7, -3)]").unwrap(), "[-2, 1]");
+/// assert_eq!(eval_str("[floor_mod(-7, -3), euclid_mod(-7, -3)]").unwrap(), "[-1, 2]");
+///
+/// assert_eq!(eval_str("floor_mod(1, 0)").unwrap_err().kind, ErrorKind::NonFinite);
+/// assert_eq!(eval_str("euclid_mod(1, 0)").unwrap_err().kind, ErrorKind::NonFinite);
+/// ```
+fn floor_mod(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    modulo(env, "floor_mod", args, span, |a, b| a - b * (a / b).floor())
+}
+
+fn euclid_mod(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    modulo(env, "euclid_mod", args, span, f64::rem_euclid)
+}
+
+/// Applies a remainder operation, which must not divide by zero.
+fn modulo(
+    env: &mut Env,
+    name: &str,
+    args: &Args,
+    span: &Span,
+    op: fn(f64, f64) -> f64,
+) -> Result<Value, EvalError> {
+    let [(a, a_span), (b, b_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let (a, b) = (a.as_num(a_span)?, b.as_num(b_span)?);
+    let result = op(a, b);
+    if b == 0.0 {
+        let err = EvalError::new(
+            ErrorKind::NonFinite,
+            format!("Division by zero in `{name}({a}, {b})`"),
+            span,
+        );
+        return env.recover(err, Value::Num(result));
+    }
+    Ok(Value::Num(result))
+}
+
+/// `arity(f)` is the number of parameters of a function, or the least number
+/// of arguments it takes, e.g. 1 for `max`.
+fn arity(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (f, f_span) = &args[0];
+    let (Arity::Exact(n) | Arity::AtLeast(n)) = f.as_function(f_span)?.arity;
+    Ok(Value::Num(n as f64))
+}
+
+/// `name(f)` is the name a function was declared with, or `""` for a lambda.
+fn name(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (f, f_span) = &args[0];
+    Ok(Value::Str(f.as_function(f_span)?.name.clone()))
+}
+
+/// `compose(f, g)` is a function of one argument `x` returning `f(g(x))`.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env, ErrorKind};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "compose(\\x -> x+1, \\x -> x*2)(3)";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// assert_eq!(eval(&program[0], &mut Env::new()).unwrap().to_string(), "7");
+///
+/// let src = "fn add x y = x + y; compose(add, sqrt)";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let err = eval(&program[0], &mut Env::new()).unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Arity);
+/// assert_eq!(&src[err.span], "add");
+/// ```
+fn compose(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let unary = |(f, span): &(Value, Span)| {
+        let f = f.as_function(span)?;
+        if !f.arity.accepts(1) {
+            return Err(EvalError::new(
+                ErrorKind::Arity,
+                format!(
+                    "`compose` takes functions of one argument, found one taking {}",
+                    f.arity
+                ),
+                span,
+            ));
+        }
+        Ok(f.clone())
+    };
+    let (f, g) = (unary(&args[0])?, unary(&args[1])?);
+    Ok(Value::Function(Rc::new(FunctionValue::compose(f, g))))
+}
+
+/// `map(f, list)` is the list of `f(x)` for each element `x` of `list`. `f`
+/// may be any function value, built-ins included.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "map(sqrt, [1,4,9])";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// assert_eq!(eval(&program[0], &mut Env::new()).unwrap().to_string(), "[1, 2, 3]");
+/// ```
+fn map(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(f, f_span), (xs, xs_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let f = f.as_function(f_span)?.clone();
+    let ys = as_list(env, xs, xs_span)?
+        .into_iter()
+        .map(|x| call_function(env, &f, &[(x, xs_span.clone())], span))
+        .collect::<Result<_, _>>()?;
+    Ok(Value::List(Rc::new(RefCell::new(ys))))
+}
+
+/// `zip_with(f, a, b)` is the list of `f(x, y)` for each pair of elements `x`
+/// and `y` at the same position in `a` and `b`. The longer list is cut short.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "[zip_with(\\x y -> x+y, [1,2], [3,4]), zip_with(max, [1,5,9], [4,2])]";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let value = eval(&program[0], &mut Env::new()).unwrap();
+/// assert_eq!(value.to_string(), "[[4, 6], [4, 5]]");
+/// ```
+fn zip_with(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(f, f_span), (xs, xs_span), (ys, ys_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let f = f.as_function(f_span)?.clone();
+    let (xs, ys) = (as_list(env, xs, xs_span)?, as_list(env, ys, ys_span)?);
+    let zs = xs
+        .into_iter()
+        .zip(ys)
+        .map(|(x, y)| {
+            let args = [(x, xs_span.clone()), (y, ys_span.clone())];
+            call_function(env, &f, &args, span)
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Value::List(Rc::new(RefCell::new(zs))))
+}
+
+/// `filter(pred, list)` is the list of the elements `x` of `list` for which
+/// `pred(x)` is true, in order.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("filter(\\x -> x > 1, [3, 1, 2])").unwrap(), "[3, 2]");
+/// assert_eq!(eval_str("filter(sqrt, [1])").unwrap_err().kind, ErrorKind::Type);
+/// assert_eq!(eval_str("filter(\\x -> true, 3)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn filter(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(pred, pred_span), (xs, xs_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let pred = pred.as_function(pred_span)?.clone();
+    let mut ys = Vec::new();
+    for x in as_list(env, xs, xs_span)? {
+        let keep = call_function(env, &pred, &[(x.clone(), xs_span.clone())], span)?;
+        if expect_bool(&keep, pred_span)? {
+            ys.push(x);
+        }
+    }
+    Ok(Value::List(Rc::new(RefCell::new(ys))))
+}
+
+/// `find(pred, list)` is `some(x)` for the first element `x` of `list` for
+/// which `pred(x)` is true, or `none` if there is none. `pred` must return a
+/// boolean, and is not called on the elements after the first match.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("find(\\x -> x > 1, [1, 3, 2])").unwrap(), "some(3)");
+/// assert_eq!(eval_str("find(\\x -> x > 5, [1, 3, 2])").unwrap(), "none");
+/// let src = "if let some(x) = find(\\x -> x > 1, [1, 3]) then x * 10 else 0";
+/// assert_eq!(eval_str(src).unwrap(), "30");
+/// let src = "if let some(x) = find(\\x -> x > 5, [1, 3]) then x * 10 else 0";
+/// assert_eq!(eval_str(src).unwrap(), "0");
+/// assert_eq!(eval_str("find(\\x -> true, [])").unwrap(), "none");
+/// assert_eq!(eval_str(r#"find(\x -> x > 1, [1, 2, "a"])"#).unwrap(), "some(2)");
+///
+/// let err = eval_str("find(\\x -> x, [1])").unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Type);
+/// assert_eq!(err.message, "Type error: expected boolean, found number");
+/// assert_eq!(eval_str("find(\\x -> x > 1, 3)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn find(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(pred, pred_span), (xs, xs_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let pred = pred.as_function(pred_span)?.clone();
+    for x in as_list(env, xs, xs_span)? {
+        let found = call_function(env, &pred, &[(x.clone(), xs_span.clone())], span)?;
+        if expect_bool(&found, pred_span)? {
+            return Ok(Value::Optional(Some(Box::new(x))));
+        }
+    }
+    Ok(Value::Optional(None))
+}
+
+/// `some(x)` is the optional value holding `x`.
+///
+/// ```
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str(r#"[some("a"), none, some(none)]"#).unwrap(), r#"[some("a"), none, some(none)]"#);
+/// assert_eq!(eval_str("some(1) == some(1)").unwrap(), "true");
+/// assert_eq!(eval_str("some(1) == none").unwrap(), "false");
+/// ```
+fn some(_env: &mut Env, args: &Args, _span: &Span) -> Result<Value, EvalError> {
+    let (x, _) = &args[0];
+    Ok(Value::Optional(Some(Box::new(x.clone()))))
+}
+
+/// `flat_map(f, list)` is the concatenation of the lists `f(x)` for each
+/// element `x` of `list`.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("flat_map(\\x -> [x, x * 10], [1, 2])").unwrap(), "[1, 10, 2, 20]");
+/// assert_eq!(eval_str("flat_map(\\x -> [[x]], [1])").unwrap(), "[[1]]");
+/// assert_eq!(eval_str("flat_map(sqrt, [1, 4])").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn flat_map(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(f, f_span), (xs, xs_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let f = f.as_function(f_span)?.clone();
+    let mut ys = Vec::new();
+    for x in as_list(env, xs, xs_span)? {
+        let y = call_function(env, &f, &[(x, xs_span.clone())], span)?;
+        ys.extend(as_list(env, &y, span)?);
+    }
+    Ok(Value::List(Rc::new(RefCell::new(ys))))
+}
+
+/// `take(n, list)` is the first `n` elements of `list`, and `drop(n, list)`
+/// the rest. `n` can exceed the length of the list.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("[take(2, [1, 2, 3]), drop(2, [1, 2, 3])]").unwrap(), "[[1, 2], [3]]");
+/// assert_eq!(eval_str("[take(5, [1, 2]), drop(5, [1, 2])]").unwrap(), "[[1, 2], []]");
+/// assert_eq!(eval_str("take(-1, [1, 2])").unwrap_err().kind, ErrorKind::Type);
+/// assert_eq!(eval_str("drop(-1, [1, 2])").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn take(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (n, mut xs) = split_args(env, args)?;
+    xs.truncate(n);
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+fn drop(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (n, mut xs) = split_args(env, args)?;
+    xs.drain(..n.min(xs.len()));
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+/// The count and list arguments of `take` and `drop`.
+fn split_args(env: &mut Env, args: &Args) -> Result<(usize, Vec<Value>), EvalError> {
+    let [(n, n_span), (xs, xs_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let n = as_count(n, n_span)?;
+    let xs = as_list(env, xs, xs_span)?;
+    Ok((usize::try_from(n).unwrap_or(usize::MAX), xs))
+}
+
+/// `repeat(x, n)` is the list of `n` times `x`. A list or map `x` is not
+/// copied: the elements are all the same one. Asking for more elements than
+/// there is memory for is a `memory-limit` error.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("repeat(0, 3)").unwrap(), "[0, 0, 0]");
+/// assert_eq!(eval_str(r#"repeat("a", 0)"#).unwrap(), "[]");
+/// let src = "let xs = repeat([0], 2); let _ = xs[0][0] := 1; xs";
+/// assert_eq!(eval_str(src).unwrap(), "[[1], [1]]");
+/// assert_eq!(eval_str("repeat(0, -1)").unwrap_err().kind, ErrorKind::Type);
+/// assert_eq!(eval_str("repeat(0, 1.5)").unwrap_err().kind, ErrorKind::Type);
+/// assert_eq!(eval_str("repeat(0, 2 ^ 60)").unwrap_err().kind, ErrorKind::MemoryLimit);
+/// ```
+fn repeat(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(x, _), (n, n_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let n = usize::try_from(as_count(n, n_span)?).unwrap_or(usize::MAX);
+    env.allocate(n, span)?;
+    let mut xs = list_with_capacity(n, span)?;
+    xs.resize(n, x.clone());
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+/// An empty list with room for `n` elements, or a `memory-limit` error if
+/// there is no memory for them, rather than aborting the process.
+fn list_with_capacity(n: usize, span: &Span) -> Result<Vec<Value>, EvalError> {
+    let mut xs = Vec::new();
+    xs.try_reserve_exact(n).map_err(|_| {
+        EvalError::new(
+            ErrorKind::MemoryLimit,
+            format!("Out of memory for a list of {n} elements"),
+            span,
+        )
+    })?;
+    Ok(xs)
+}
+
+/// `range_list(start, end, step)` is the list of `start`, `start + step`,
+/// `start + 2 * step` and so on, up to but excluding `end`. With a negative
+/// `step` it counts down to `end`, and it is empty when `step` goes away from
+/// `end`. A `step` of zero is an error, and so is asking for more numbers
+/// than there is memory for.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("range_list(0, 10, 2)").unwrap(), "[0, 2, 4, 6, 8]");
+/// assert_eq!(eval_str("range_list(0, 9, 2)").unwrap(), "[0, 2, 4, 6, 8]");
+/// assert_eq!(eval_str("range_list(3, 0, -1)").unwrap(), "[3, 2, 1]");
+/// assert_eq!(eval_str("range_list(0, 1, 0.25)").unwrap(), "[0, 0.25, 0.5, 0.75]");
+/// assert_eq!(eval_str("range_list(0, 10, -1)").unwrap(), "[]");
+/// assert_eq!(eval_str("range_list(5, 5, 1)").unwrap(), "[]");
+///
+/// let err = eval_str("range_list(0, 10, 0)").unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Type);
+/// assert_eq!(err.message, "Type error: expected a non-zero step, found 0");
+/// let err = eval_str("range_list(0, 10 ^ 308, 10 ^ -308)").unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::NonFinite);
+/// let err = eval_str("range_list(0, 2 ^ 60, 1)").unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::MemoryLimit);
+/// ```
+fn range_list(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(start, start_span), (end, end_span), (step, step_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let start = start.as_num(start_span)?;
+    let end = end.as_num(end_span)?;
+    let step = step.as_num(step_span)?;
+    if step == 0.0 {
+        return Err(EvalError::new(
+            ErrorKind::Type,
+            format!("Type error: expected a non-zero step, found {step}"),
+            step_span,
+        ));
+    }
+    // `max` also turns a NaN count, from NaN bounds, into an empty list.
+    let n = ((end - start) / step).ceil().max(0.0);
+    if n.is_infinite() {
+        return Err(EvalError::new(
+            ErrorKind::NonFinite,
+            "Cannot make a list of infinitely many numbers",
+            span,
+        ));
+    }
+    let n = n as usize;
+    env.allocate(n, span)?;
+    let mut xs = list_with_capacity(n, span)?;
+    xs.extend((0..n).map(|i| Value::Num(start + i as f64 * step)));
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+/// `take_while(pred, list)` is the longest prefix of `list` whose elements
+/// all satisfy `pred`, and `drop_while(pred, list)` the rest.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// let src = "let small = \\x -> x < 3; let xs = [1, 2, 3, 1];
+///     [take_while(small, xs), drop_while(small, xs)]";
+/// assert_eq!(eval_str(src).unwrap(), "[[1, 2], [3, 1]]");
+/// assert_eq!(eval_str("take_while(\\x -> x, [1])").unwrap_err().kind, ErrorKind::Type);
+/// assert_eq!(eval_str("drop_while(\\x -> x, [1])").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn take_while(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let (n, mut xs) = prefix_while(env, args, span)?;
+    xs.truncate(n);
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+fn drop_while(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let (n, mut xs) = prefix_while(env, args, span)?;
+    xs.drain(..n);
+    Ok(Value::List(Rc::new(RefCell::new(xs))))
+}
+
+/// The length of the prefix of the list satisfying the predicate, for
+/// `take_while` and `drop_while`, and the list.
+fn prefix_while(env: &mut Env, args: &Args, span: &Span) -> Result<(usize, Vec<Value>), EvalError> {
+    let [(pred, pred_span), (xs, xs_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let pred = pred.as_function(pred_span)?.clone();
+    let xs = as_list(env, xs, xs_span)?;
+    let mut n = 0;
+    for x in &xs {
+        let keep = call_function(env, &pred, &[(x.clone(), xs_span.clone())], span)?;
+        if !expect_bool(&keep, pred_span)? {
+            break;
+        }
+        n += 1;
+    }
+    Ok((n, xs))
+}
+
+/// `chars(s)` is the list of the characters of `s` (Unicode scalar values),
+/// each as a string of its own.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("chars(\"ab\")").unwrap(), r#"["a", "b"]"#);
+/// assert_eq!(eval_str("chars(\"\")").unwrap(), "[]");
+/// assert_eq!(eval_str("chars(12)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn chars(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (s, s_span) = &args[0];
+    let s = s.as_str(s_span)?;
+    env.allocate(s.chars().count(), s_span)?;
+    let chars = s.chars().map(|c| Value::Str(c.to_string())).collect();
+    Ok(Value::List(Rc::new(RefCell::new(chars))))
+}
+
+/// `trim(s)` is `s` without the whitespace (in the Unicode sense) at either
+/// end.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("trim(\"  a b \\n\")").unwrap(), "a b");
+/// assert_eq!(eval_str("trim(\"\u{3000}x\")").unwrap(), "x");
+/// assert_eq!(eval_str("trim(\"  \")").unwrap(), "");
+/// assert_eq!(eval_str("trim(1)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn trim(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (s, s_span) = &args[0];
+    let trimmed = s.as_str(s_span)?.trim();
+    env.allocate(trimmed.len(), s_span)?;
+    Ok(Value::Str(trimmed.to_string()))
+}
+
+/// `to_upper(s)` is `s` in upper case, following Unicode, so a character can
+/// become several.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("to_upper(\"héllo\")").unwrap(), "HÉLLO");
+/// assert_eq!(eval_str("to_upper(\"straße\")").unwrap(), "STRASSE");
+/// assert_eq!(eval_str("to_upper([\"a\"])").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn to_upper(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (s, s_span) = &args[0];
+    let upper = s.as_str(s_span)?.to_uppercase();
+    env.allocate(upper.len(), s_span)?;
+    Ok(Value::Str(upper))
+}
+
+/// `to_lower(s)` is `s` in lower case, following Unicode, including a final
+/// sigma.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("to_lower(\"ÀB\")").unwrap(), "àb");
+/// assert_eq!(eval_str("to_lower(\"ΟΔΟΣ\")").unwrap(), "οδος");
+/// assert_eq!(eval_str("to_lower(true)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn to_lower(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (s, s_span) = &args[0];
+    let lower = s.as_str(s_span)?.to_lowercase();
+    env.allocate(lower.len(), s_span)?;
+    Ok(Value::Str(lower))
+}
+
+/// `starts_with(s, prefix)` is whether the string `s` begins with `prefix`.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("starts_with(\"kyomu\", \"ky\")").unwrap(), "true");
+/// assert_eq!(eval_str("starts_with(\"kyomu\", \"\")").unwrap(), "true");
+/// assert_eq!(eval_str("starts_with(\"ky\", \"kyomu\")").unwrap(), "false");
+/// assert_eq!(eval_str("starts_with(\"k1\", 1)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn starts_with(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let [(s, s_span), (prefix, prefix_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let s = s.as_str(s_span)?;
+    Ok(Value::Bool(s.starts_with(prefix.as_str(prefix_span)?)))
+}
+
+/// `ends_with(s, suffix)` is whether the string `s` ends with `suffix`.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("ends_with(\"señor\", \"ñor\")").unwrap(), "true");
+/// assert_eq!(eval_str("ends_with(\"señor\", \"se\")").unwrap(), "false");
+/// assert_eq!(eval_str("ends_with([\"a\"], \"a\")").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn ends_with(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let [(s, s_span), (suffix, suffix_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let s = s.as_str(s_span)?;
+    Ok(Value::Bool(s.ends_with(suffix.as_str(suffix_span)?)))
+}
+
+/// `replace(s, from, to)` is `s` with every occurrence of `from` replaced by
+/// `to`, from left to right, so occurrences don't overlap. An empty `from`
+/// is an error rather than matching between every two characters.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// let run = |src| eval_str(src).map_err(|e| (e.kind, e.message));
+///
+/// assert_eq!(run(r#"replace("hello world", "world", "there")"#).unwrap(), "hello there");
+/// assert_eq!(run(r#"replace("a-b-c", "-", ", ")"#).unwrap(), "a, b, c");
+/// assert_eq!(run(r#"replace("aaa", "aa", "b")"#).unwrap(), "ba");
+/// assert_eq!(run(r#"replace("abc", "x", "y")"#).unwrap(), "abc");
+/// assert_eq!(
+///     run(r#"replace("abc", "", "-")"#).unwrap_err(),
+///     (ErrorKind::Type, "Type error: `replace` expects a non-empty string to replace".to_string())
+/// );
+/// assert_eq!(run(r#"replace("a1", 1, "2")"#).unwrap_err().0, ErrorKind::Type);
+/// ```
+fn replace(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(s, s_span), (from, from_span), (to, to_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let (s, from, to) = (
+        s.as_str(s_span)?,
+        from.as_str(from_span)?,
+        to.as_str(to_span)?,
+    );
+    if from.is_empty() {
+        return Err(EvalError::new(
+            ErrorKind::Type,
+            "Type error: `replace` expects a non-empty string to replace",
+            from_span,
+        ));
+    }
+    let replaced = s.replace(from, to);
+    env.allocate(replaced.len(), span)?;
+    Ok(Value::Str(replaced))
+}
+
+/// `len(x)` is the number of elements of a list, characters of a string or
+/// entries of a map.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("len([1, 2, 3])").unwrap(), "3");
+/// assert_eq!(eval_str("len(\"héllo\")").unwrap(), "5");
+/// assert_eq!(eval_str("len({\"a\": 1})").unwrap(), "1");
+/// assert_eq!(eval_str("len(1)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn len(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (x, x_span) = &args[0];
+    let n = match x {
+        Value::List(xs) => xs.borrow().len(),
+        Value::Str(s) => s.chars().count(),
+        Value::Map(entries) => entries.borrow().len(),
+        v => return Err(v.type_error("list, string or map", x_span)),
+    };
+    Ok(Value::Num(n as f64))
+}
+
+/// `slice(xs, start, end)` is the part of a list or string from `start` up
+/// to but excluding `end`. Negative bounds count from the end, as indices
+/// do, so `slice(xs, -2, len(xs))` is the last two elements. Bounds beyond
+/// either end are errors, and a `start` after `end` gives an empty slice.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("let xs = [1, 2, 3]; slice(xs, -2, len(xs))").unwrap(), "[2, 3]");
+/// assert_eq!(eval_str("slice([1, 2, 3], 0, -1)").unwrap(), "[1, 2]");
+/// assert_eq!(eval_str("slice(\"hello\", 1, 3)").unwrap(), "el");
+/// assert_eq!(eval_str("slice(\"hello\", -3, -1)").unwrap(), "ll");
+/// assert_eq!(eval_str("slice([1, 2, 3], 2, 1)").unwrap(), "[]");
+///
+/// let err = eval_str("slice([1, 2, 3], -4, 3)").unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::IndexOutOfBounds);
+/// assert_eq!(err.message, "Slice bound -4 out of range for length 3");
+/// ```
+fn slice(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let [(xs, xs_span), (start, start_span), (end, end_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    match xs {
+        Value::List(xs) => {
+            let xs = xs.borrow();
+            let start = slice_bound(start, xs.len(), start_span)?;
+            let end = slice_bound(end, xs.len(), end_span)?.max(start);
+            env.allocate(end - start, span)?;
+            Ok(Value::List(Rc::new(RefCell::new(xs[start..end].to_vec()))))
+        }
+        Value::Str(s) => {
+            let len = s.chars().count();
+            let start = slice_bound(start, len, start_span)?;
+            let end = slice_bound(end, len, end_span)?.max(start);
+            Ok(Value::Str(
+                s.chars().skip(start).take(end - start).collect(),
+            ))
+        }
+        v => Err(v.type_error("list or string", xs_span)),
+    }
+}
+
+/// Converts a number to a position from 0 to `len` inclusive, counting
+/// negative numbers from the end.
+fn slice_bound(value: &Value, len: usize, span: &Span) -> Result<usize, EvalError> {
+    let n = value.as_integer(span)?;
+    let i = if n < 0 { len as i64 + n } else { n };
+    if i < 0 || i > len as i64 {
+        return Err(EvalError::new(
+            ErrorKind::IndexOutOfBounds,
+            format!("Slice bound {n} out of range for length {len}"),
+            span,
+        ));
+    }
+    Ok(i as usize)
+}
+
+/// `to_base(n, base)` writes the integer `n` in `base`, from 2 to 36, with
+/// lowercase letters for digits above 9. `to_hex(n)` and `to_bin(n)` are
+/// bases 16 and 2.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("to_hex(255)").unwrap(), "ff");
+/// assert_eq!(eval_str("to_bin(5)").unwrap(), "101");
+/// assert_eq!(eval_str("to_base(-35, 36)").unwrap(), "-z");
+/// assert_eq!(eval_str("to_base(0, 7)").unwrap(), "0");
+///
+/// let src = "to_base(10, 37)";
+/// let err = eval_str(src).unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Type);
+/// assert_eq!(err.message, "Type error: expected a base from 2 to 36, found 37");
+/// assert_eq!(&src[err.span], "37");
+/// assert_eq!(eval_str("to_hex(1.5)").unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn to_base(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let [(n, n_span), (base, base_span)] = args else {
+        unreachable!("arity is checked before the call");
+    };
+    let base = base.as_integer(base_span)?;
+    if !(2..=36).contains(&base) {
+        return Err(EvalError::new(
+            ErrorKind::Type,
+            format!("Type error: expected a base from 2 to 36, found {base}"),
+            base_span,
+        ));
+    }
+    Ok(Value::Str(in_base(n, n_span, base as u32)?))
+}
+
+fn to_bin(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (n, n_span) = &args[0];
+    Ok(Value::Str(in_base(n, n_span, 2)?))
+}
+
+fn to_hex(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (n, n_span) = &args[0];
+    Ok(Value::Str(in_base(n, n_span, 16)?))
+}
+
+fn in_base(n: &Value, span: &Span, base: u32) -> Result<String, EvalError> {
+    let n = n.as_integer(span)?;
+    let mut m = n.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        let digit = char::from_digit((m % u64::from(base)) as u32, base);
+        digits.push(digit.expect("the remainder is below the base"));
+        m /= u64::from(base);
+        if m == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+    Ok(digits.into_iter().rev().collect())
+}
+
+/// `print(x)` writes `x` on a line of [`Env::output`] and returns it.
+fn print(env: &mut Env, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let (x, _) = &args[0];
+    writeln!(env.output, "{x}").map_err(|e| {
+        EvalError::new(
+            ErrorKind::Input,
+            format!("Failed to write output: {e}"),
+            span,
+        )
+    })?;
+    Ok(x.clone())
+}
+
+/// `to_json(x)` is `x` written as JSON text, and `from_json(s)` the value
+/// the JSON text `s` describes: objects are maps, arrays are lists. JSON
+/// `null`, functions and lists or maps inside themselves have no counterpart.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// let src = r#"to_json({"a": [1, 2.5, "x\n"], "b": {"c": true}})"#;
+/// let json = r#"{"a":[1,2.5,"x\n"],"b":{"c":true}}"#;
+/// assert_eq!(eval_str(src).unwrap(), json);
+/// let src = format!("to_json(from_json({:?}))", json);
+/// assert_eq!(eval_str(&src).unwrap(), json);
+/// assert_eq!(eval_str(r#"from_json("[1, [\"a\"]]")[1][0]"#).unwrap(), "a");
+///
+/// assert_eq!(eval_str("to_json([some(1), none])").unwrap(), "[1,null]");
+///
+/// // Unlike `--output json`, functions have no JSON form.
+/// let err = eval_str("to_json([1, sqrt])").unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Type);
+/// assert_eq!(err.message, "Type error: expected a value representable in JSON, found function");
+/// assert_eq!(eval_str("to_json(some(sqrt))").unwrap_err().kind, ErrorKind::Type);
+/// let err = eval_str("let xs = [0]; let _ = xs[0] := xs; to_json(xs)").unwrap_err();
+/// assert_eq!(err.message, "Type error: a list inside itself has no JSON form");
+/// assert_eq!(eval_str(r#"from_json("[1,")"#).unwrap_err().kind, ErrorKind::Input);
+/// assert_eq!(eval_str(r#"from_json("null")"#).unwrap_err().kind, ErrorKind::Type);
+/// ```
+fn to_json(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (x, x_span) = &args[0];
+    Ok(Value::Str(json_of(x, x_span, &mut Vec::new())?.to_string()))
+}
+
+/// `value` as JSON, nested in the lists and maps of `ancestors`, see
+/// [`Value::address`].
+fn json_of(value: &Value, span: &Span, ancestors: &mut Vec<usize>) -> Result<Json, EvalError> {
+    if let Some(address) = value.address() {
+        if ancestors.contains(&address) {
+            return Err(EvalError::new(
+                ErrorKind::Type,
+                format!("Type error: a {} inside itself has no JSON form", value.type_name()),
+                span,
+            ));
+        }
+        ancestors.push(address);
+    }
+    let json = match value {
+        Value::List(xs) => Json::Array(
+            xs.borrow()
+                .iter()
+                .map(|x| json_of(x, span, ancestors))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Map(entries) => Json::Object(
+            entries
+                .borrow()
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), json_of(v, span, ancestors)?)))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Optional(Some(x)) => json_of(x, span, ancestors)?,
+        Value::Function(_) => return Err(value.type_error("a value representable in JSON", span)),
+        _ => value.to_json(),
+    };
+    if value.address().is_some() {
+        ancestors.pop();
+    }
+    Ok(json)
+}
+
+fn from_json(env: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (s, s_span) = &args[0];
+    let json = Json::parse(s.as_str(s_span)?)
+        .map_err(|e| EvalError::new(ErrorKind::Input, format!("Invalid JSON: {e}"), s_span))?;
+    value_of(env, json, s_span)
+}
+
+fn value_of(env: &mut Env, json: Json, span: &Span) -> Result<Value, EvalError> {
+    Ok(match json {
+        Json::Null => {
+            return Err(EvalError::new(
+                ErrorKind::Type,
+                "Type error: JSON `null` has no corresponding value",
+                span,
+            ))
+        }
+        Json::Bool(b) => Value::Bool(b),
+        Json::Num(x) => Value::Num(x),
+        Json::Str(s) => {
+            env.allocate(s.len(), span)?;
+            Value::Str(s)
+        }
+        Json::Array(items) => {
+            env.allocate(items.len(), span)?;
+            let xs = items
+                .into_iter()
+                .map(|item| value_of(env, item, span))
+                .collect::<Result<_, _>>()?;
+            Value::List(Rc::new(RefCell::new(xs)))
+        }
+        Json::Object(members) => {
+            env.allocate(members.len(), span)?;
+            let mut entries: Vec<(String, Value)> = Vec::new();
+            for (k, v) in members {
+                let v = value_of(env, v, span)?;
+                // Like in a map literal, the last duplicate key wins.
+                match entries.iter_mut().find(|(key, _)| *key == k) {
+                    Some(entry) => entry.1 = v,
+                    None => entries.push((k, v)),
+                }
+            }
+            Value::Map(Rc::new(RefCell::new(entries)))
+        }
+    })
+}
+
+/// `now()` is the current Unix time, in seconds.
+fn now(_: &mut Env, _: &Args, _: &Span) -> Result<Value, EvalError> {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Value::Num(since_epoch.as_secs_f64()))
+}
+
+/// `env(name)` is `some` of the value of the environment variable `name`, or
+/// `none` if it is not set or not valid Unicode.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str_with;
+///
+/// let run = |src, deterministic| eval_str_with(src, |env| env.deterministic = deterministic);
+///
+/// std::env::set_var("KYOMUSKY_DOCTEST_ENV", "hello");
+/// std::env::remove_var("KYOMUSKY_DOCTEST_UNSET");
+/// assert_eq!(run(r#"env("KYOMUSKY_DOCTEST_ENV")"#, false).unwrap(), r#"some("hello")"#);
+/// assert_eq!(run(r#"env("KYOMUSKY_DOCTEST_UNSET")"#, false).unwrap(), "none");
+/// let src = r#"if let some(s) = env("KYOMUSKY_DOCTEST_UNSET") then s else "default""#;
+/// assert_eq!(run(src, false).unwrap(), "default");
+/// assert_eq!(run("env(1)", false).unwrap_err().kind, ErrorKind::Type);
+///
+/// let err = run(r#"env("KYOMUSKY_DOCTEST_ENV")"#, true).unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Nondeterministic);
+/// ```
+#[cfg(not(feature = "wasm"))]
+fn env_var(_: &mut Env, args: &Args, _: &Span) -> Result<Value, EvalError> {
+    let (name, name_span) = &args[0];
+    let value = std::env::var(name.as_str(name_span)?).ok();
+    Ok(Value::Optional(value.map(|s| Box::new(Value::Str(s)))))
+}
+
+/// `clock()` is a time in seconds that never goes backwards, unlike `now()`,
+/// for measuring durations. Only differences between two calls are
+/// meaningful.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "let start = clock(); let n = factorial(100); clock() - start";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let elapsed = eval(&program[0], &mut Env::new()).unwrap();
+/// assert!(elapsed.to_string().parse::<f64>().unwrap() >= 0.0);
+/// ```
+fn clock(_: &mut Env, _: &Args, _: &Span) -> Result<Value, EvalError> {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let start = START.get_or_init(Instant::now);
+    Ok(Value::Num(start.elapsed().as_secs_f64()))
+}
+
+/// `input()` reads the next number from the environment's input source.
+fn input(env: &mut Env, _: &Args, span: &Span) -> Result<Value, EvalError> {
+    let Some(token) = env.input.next_token() else {
+        return Err(EvalError::new(
+            ErrorKind::Input,
+            format!(
+                "Input exhausted after reading {} value{}",
+                env.inputs_read,
+                if env.inputs_read == 1 { "" } else { "s" }
+            ),
+            span,
+        ));
+    };
+    let x = token.parse().map_err(|_| {
+        EvalError::new(
+            ErrorKind::Input,
+            format!("Expected a number as input, found `{token}`"),
+            span,
+        )
+    })?;
+    env.inputs_read += 1;
+    Ok(Value::Num(x))
+}
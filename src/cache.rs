@@ -0,0 +1,41 @@
+//! Memoization of parse results across runs of `--watch`, so that a file
+//! touched without changing its contents is not parsed and evaluated again.
+
+use crate::ast::Expr;
+use crate::parser::{parse_program, ParseOptions};
+use chumsky::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+pub type ParseResult = Result<Vec<Expr>, Vec<Simple<char>>>;
+
+/// The result of the last parse, keyed by a hash of its source.
+#[derive(Default)]
+pub struct ParseCache {
+    entry: Option<(u64, ParseResult)>,
+}
+
+fn hash_source(src: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    src.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ParseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses `src`, unless it hashes the same as the source parsed last time.
+    /// Returns the result, and whether it was freshly parsed.
+    pub fn parse(&mut self, src: &str, options: ParseOptions) -> (&ParseResult, bool) {
+        let hash = hash_source(src);
+        let fresh = !matches!(&self.entry, Some((h, _)) if *h == hash);
+        if fresh {
+            let result = parse_program(src, options);
+            self.entry = Some((hash, result));
+        }
+        let (_, result) = self.entry.as_ref().unwrap();
+        (result, fresh)
+    }
+}
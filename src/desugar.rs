@@ -0,0 +1,107 @@
+//! Rewriting syntactic sugar into the core syntax that evaluation and the
+//! transpilers understand. Spans are kept, so errors in the rewritten tree
+//! still point at what the user wrote.
+
+use crate::ast::{map_expr, BinOp, Expr, ExprKind, Span};
+
+/// Removes all sugar from the tree, i.e. pipelines and list comprehensions.
+///
+/// ```
+/// use kyomusky::desugar::desugar;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let core = |src| {
+///     let program = parse_program(src, ParseOptions::default()).unwrap();
+///     format!("{:?}", desugar(program[0].clone()))
+/// };
+/// assert_eq!(core("a |> f"), r#"Call("f", [Var("a")])"#);
+/// assert_eq!(core("a |> f(b) |> g"), r#"Call("g", [Call("f", [Var("a"), Var("b")])])"#);
+/// assert_eq!(core("a |> fs[0]"), r#"Apply(Index(Var("fs"), Num(0.0)), [Var("a")])"#);
+///
+/// let core = |src| {
+///     let program = parse_program(src, ParseOptions::default()).unwrap();
+///     desugar(program[0].clone()).to_string()
+/// };
+/// assert_eq!(core("[x * 2 for x in xs]"), r"map(\x -> x * 2, xs)");
+/// assert_eq!(
+///     core("[x * 2 for x in xs if x > 2]"),
+///     r"map(\x -> x * 2, filter(\x -> x > 2, xs))"
+/// );
+/// assert_eq!(
+///     core("[x + y for x in xs for y in ys if x < y]"),
+///     r"flat_map(\x -> map(\y -> x + y, filter(\y -> x < y, ys)), xs)"
+/// );
+/// ```
+pub fn desugar(expr: Expr) -> Expr {
+    map_expr(expr, |expr| desugar_comprehension(desugar_pipe(expr)))
+}
+
+/// `a |> f(b)` becomes `f(a, b)`, and `a |> f` becomes `f(a)`, calling `f`
+/// by name when it is one.
+fn desugar_pipe(expr: Expr) -> Expr {
+    let ExprKind::Binary {
+        op: BinOp::Pipe,
+        lhs: arg,
+        rhs: f,
+    } = expr.kind
+    else {
+        return expr;
+    };
+    let f = *f;
+    let kind = match f.kind {
+        ExprKind::Call(name, mut args) => {
+            args.insert(0, *arg);
+            ExprKind::Call(name, args)
+        }
+        ExprKind::Var(name) => ExprKind::Call(name, vec![*arg]),
+        _ => ExprKind::Apply(Box::new(f), vec![*arg]),
+    };
+    Expr::new(kind, expr.span)
+}
+
+/// `[body for x in xs if cond]` becomes `map(\x -> body, filter(\x -> cond,
+/// xs))`. Each generator but the last becomes a `flat_map` over the rest, so
+/// the filter sees the variables of every generator.
+fn desugar_comprehension(expr: Expr) -> Expr {
+    let ExprKind::Comprehension {
+        body,
+        generators,
+        filter,
+    } = expr.kind
+    else {
+        return expr;
+    };
+    let span = expr.span;
+    let mut generators = generators.into_iter().rev();
+    let (var, list) = generators.next().expect("a comprehension has a generator");
+    let list = match filter {
+        // The predicate has the span of the condition, where `filter`
+        // reports a non-boolean result.
+        Some(filter) => {
+            let filter_span = filter.span.clone();
+            call(&span, "filter", lambda(&filter_span, &var, *filter), list)
+        }
+        None => list,
+    };
+    let mut output = call(&span, "map", lambda(&span, &var, *body), list);
+    for (var, list) in generators {
+        output = call(&span, "flat_map", lambda(&span, &var, output), list);
+    }
+    output
+}
+
+/// `name(f, list)`, for `map`, `flat_map` and `filter`.
+fn call(span: &Span, name: &str, f: Expr, list: Expr) -> Expr {
+    Expr::new(
+        ExprKind::Call(name.to_string(), vec![f, list]),
+        span.clone(),
+    )
+}
+
+fn lambda(span: &Span, var: &str, body: Expr) -> Expr {
+    let kind = ExprKind::Lambda {
+        args: vec![var.to_string()],
+        body: Box::new(body),
+    };
+    Expr::new(kind, span.clone())
+}
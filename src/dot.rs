@@ -0,0 +1,81 @@
+//! Graphviz rendering of syntax trees.
+
+use crate::ast::{Expr, ExprKind};
+use crate::eval::{EvalError, Value};
+use std::fmt::Write;
+
+/// The outcome of evaluating each node, as recorded in `Env::values`.
+pub type Values<'a> = [(&'a Expr, Result<Value, EvalError>)];
+
+/// Renders `expr` as a `digraph`, with each node labelled by its variant and
+/// contents. Nodes found in `values` are annotated with what they evaluated
+/// to (the last time, for function bodies run more than once), and nodes that
+/// failed are filled in red.
+pub fn graph(expr: &Expr, values: &Values) -> String {
+    let mut out = String::from("digraph {\n    node [shape=box];\n");
+    let mut next_id = 0;
+    // Nodes still to be printed, with the id of their parent.
+    let mut stack = vec![(expr, None)];
+    while let Some((expr, parent)) = stack.pop() {
+        let id = next_id;
+        next_id += 1;
+
+        let mut label = label(&expr.kind);
+        let outcome = values.iter().rev().find(|(e, _)| std::ptr::eq(*e, expr));
+        let style = match outcome {
+            Some((_, Ok(value))) => {
+                write!(label, "\n= {value}").unwrap();
+                ""
+            }
+            Some((_, Err(err))) => {
+                write!(label, "\n{err}").unwrap();
+                ", style=filled, fillcolor=\"#ffb3b3\""
+            }
+            None if matches!(expr.kind, ExprKind::Error) => ", style=filled, fillcolor=\"#ffb3b3\"",
+            None => "",
+        };
+        writeln!(out, "    n{id} [label={}{style}];", quote(&label)).unwrap();
+        if let Some(parent) = parent {
+            writeln!(out, "    n{parent} -> n{id};").unwrap();
+        }
+
+        for child in expr.children().into_iter().rev() {
+            stack.push((child, Some(id)));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn label(kind: &ExprKind) -> String {
+    let name = kind.name();
+    match kind {
+        ExprKind::Num(x) => format!("{name} {x}"),
+        ExprKind::Str(s) => format!("{name} {s:?}"),
+        ExprKind::Bool(b) => format!("{name} {b}"),
+        ExprKind::Unary { op, .. } => format!("{name} {}", op.symbol()),
+        ExprKind::Binary { op, .. } => format!("{name} {}", op.symbol()),
+        ExprKind::Var(s)
+        | ExprKind::Call(s, _)
+        | ExprKind::NamedArg(s, _)
+        | ExprKind::Let { name: s, .. } => format!("{name} {s}"),
+        ExprKind::Fn { name: f, args, .. } => format!("{name} {f}({})", args.join(", ")),
+        _ => name.to_string(),
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
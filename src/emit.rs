@@ -0,0 +1,179 @@
+//! Renderings of syntax trees for `--emit`, besides Graphviz graphs, which
+//! are in [`dot`](crate::dot), and source code, which is `Display`.
+
+use crate::ast::{Expr, ExprKind, StrPart};
+use crate::json::Json;
+
+/// Renders `expr` as an S-expression: operators and calls are lists headed
+/// by the operator or function, and other nodes are lists headed by the
+/// name of their kind, followed by their names and then their children.
+///
+/// ```
+/// use kyomusky::emit::sexpr;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let render = |src| sexpr(&parse_program(src, ParseOptions::default()).unwrap()[0]);
+/// assert_eq!(render("1+2"), "(+ 1 2)");
+/// assert_eq!(render("-f(x, \"a\") * 2 ^ 3"), r#"(* (- (f x "a")) (^ 2 3))"#);
+/// assert_eq!(
+///     render("let xs = [1, 2]; fn g a b = a; map(\\x -> g(x, 1), xs)"),
+///     "(let xs (list 1 2) (fn g (a b) a (map (lambda (x) (g x 1)) xs)))"
+/// );
+/// assert_eq!(render("\"n = ${n}\""), r#"(str "n = " n)"#);
+/// ```
+pub fn sexpr(expr: &Expr) -> String {
+    let mut out = String::new();
+    write_sexpr(expr, &mut out);
+    out
+}
+
+fn write_sexpr(expr: &Expr, out: &mut String) {
+    let list = |names: &[&str]| format!("({})", names.join(" "));
+    let (head, atoms): (String, Vec<String>) = match &expr.kind {
+        ExprKind::Error => return out.push_str("<error>"),
+        ExprKind::Num(x) => return out.push_str(&x.to_string()),
+        ExprKind::Str(s) => return out.push_str(&format!("{s:?}")),
+        ExprKind::Bool(b) => return out.push_str(&b.to_string()),
+        ExprKind::None => return out.push_str("none"),
+        ExprKind::Var(name) => return out.push_str(name),
+        ExprKind::InterpolatedStr(parts) => {
+            out.push_str("(str");
+            for part in parts {
+                out.push(' ');
+                match part {
+                    StrPart::Lit(s) => out.push_str(&format!("{s:?}")),
+                    StrPart::Expr(e) => write_sexpr(e, out),
+                }
+            }
+            return out.push(')');
+        }
+        ExprKind::Unary { op, .. } => (op.symbol().to_string(), vec![]),
+        ExprKind::Binary { op, .. } => (op.symbol().to_string(), vec![]),
+        ExprKind::Call(name, _) => (name.clone(), vec![]),
+        ExprKind::NamedArg(name, _) => ("named".to_string(), vec![name.clone()]),
+        ExprKind::Let { name, lazy, .. } => {
+            let head = if *lazy { "let-lazy" } else { "let" };
+            (head.to_string(), vec![name.clone()])
+        }
+        ExprKind::Fn { name, args, .. } => {
+            let args: Vec<_> = args.iter().map(String::as_str).collect();
+            ("fn".to_string(), vec![name.clone(), list(&args)])
+        }
+        ExprKind::Lambda { args, .. } => {
+            let args: Vec<_> = args.iter().map(String::as_str).collect();
+            ("lambda".to_string(), vec![list(&args)])
+        }
+        ExprKind::For { var, .. } => ("for".to_string(), vec![var.clone()]),
+        ExprKind::IfLet { name, .. } => ("if-let".to_string(), vec![name.clone()]),
+        ExprKind::Comprehension { generators, .. } => {
+            let vars: Vec<_> = generators.iter().map(|(var, _)| var.as_str()).collect();
+            ("comprehension".to_string(), vec![list(&vars)])
+        }
+        ExprKind::List(_) => ("list".to_string(), vec![]),
+        ExprKind::Map(_) => ("map".to_string(), vec![]),
+        ExprKind::Spread(_) => ("spread".to_string(), vec![]),
+        ExprKind::Index(..) => ("index".to_string(), vec![]),
+        ExprKind::IndexAssign { .. } => ("index-assign".to_string(), vec![]),
+        ExprKind::Apply(..) => ("apply".to_string(), vec![]),
+        ExprKind::Block(_) => ("block".to_string(), vec![]),
+    };
+    out.push('(');
+    out.push_str(&head);
+    for atom in atoms {
+        out.push(' ');
+        out.push_str(&atom);
+    }
+    for child in expr.children() {
+        out.push(' ');
+        write_sexpr(child, out);
+    }
+    out.push(')');
+}
+
+/// Renders `expr` as a JSON object with its `kind`, its `span`, what the
+/// node holds besides its children, and its `children`.
+///
+/// ```
+/// use kyomusky::emit::json;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let program = parse_program("f(x) + 1", ParseOptions::default()).unwrap();
+/// assert_eq!(
+///     json(&program[0]).to_string(),
+///     concat!(
+///         r#"{"kind":"Binary","span":[0,8],"op":"+","children":["#,
+///         r#"{"kind":"Call","span":[0,4],"name":"f","children":["#,
+///         r#"{"kind":"Var","span":[2,3],"name":"x","children":[]}]},"#,
+///         r#"{"kind":"Num","span":[7,8],"value":1,"children":[]}]}"#,
+///     )
+/// );
+/// ```
+pub fn json(expr: &Expr) -> Json {
+    let names = |names: &[String]| Json::Array(names.iter().map(|n| n.as_str().into()).collect());
+    let mut members = vec![
+        ("kind".to_string(), expr.kind.name().into()),
+        (
+            "span".to_string(),
+            Json::Array(vec![expr.span.start.into(), expr.span.end.into()]),
+        ),
+    ];
+    let mut add = |key: &str, value: Json| members.push((key.to_string(), value));
+    match &expr.kind {
+        ExprKind::Error | ExprKind::None | ExprKind::List(_) | ExprKind::Map(_) => {}
+        ExprKind::Num(x) => add("value", (*x).into()),
+        ExprKind::Str(s) => add("value", s.as_str().into()),
+        ExprKind::Bool(b) => add("value", (*b).into()),
+        ExprKind::InterpolatedStr(parts) => {
+            // The literal parts, with `null` for each embedded expression.
+            let parts = parts.iter().map(|part| match part {
+                StrPart::Lit(s) => s.as_str().into(),
+                StrPart::Expr(_) => Json::Null,
+            });
+            add("parts", Json::Array(parts.collect()));
+        }
+        ExprKind::Var(name) | ExprKind::Call(name, _) | ExprKind::NamedArg(name, _) => {
+            add("name", name.as_str().into())
+        }
+        ExprKind::Unary { op, .. } => add("op", op.symbol().into()),
+        ExprKind::Binary { op, .. } => add("op", op.symbol().into()),
+        ExprKind::Let {
+            name,
+            lazy,
+            deprecated,
+            ..
+        } => {
+            add("name", name.as_str().into());
+            add("lazy", (*lazy).into());
+            if let Some(message) = deprecated {
+                add("deprecated", message.as_str().into());
+            }
+        }
+        ExprKind::Fn {
+            name,
+            args,
+            deprecated,
+            ..
+        } => {
+            add("name", name.as_str().into());
+            add("params", names(args));
+            if let Some(message) = deprecated {
+                add("deprecated", message.as_str().into());
+            }
+        }
+        ExprKind::Lambda { args, .. } => add("params", names(args)),
+        ExprKind::For { var, .. } => add("var", var.as_str().into()),
+        ExprKind::IfLet { name, .. } => add("name", name.as_str().into()),
+        ExprKind::Comprehension { generators, .. } => {
+            let vars = generators.iter().map(|(var, _)| var.as_str().into());
+            add("vars", Json::Array(vars.collect()));
+        }
+        ExprKind::Spread(_)
+        | ExprKind::Index(..)
+        | ExprKind::IndexAssign { .. }
+        | ExprKind::Apply(..)
+        | ExprKind::Block(_) => {}
+    }
+    let children = expr.children().into_iter().map(json).collect();
+    members.push(("children".to_string(), Json::Array(children)));
+    Json::Object(members)
+}
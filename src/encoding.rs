@@ -0,0 +1,81 @@
+//! Decoding of source files into the `String`s the parser works on.
+//!
+//! Spans are offsets into the decoded text, so they stay correct whatever the
+//! file's encoding was.
+
+use std::io;
+
+/// A UTF-8 byte order mark, which some editors put at the start of files.
+const BOM: &str = "\u{feff}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// UTF-8, with or without a byte order mark.
+    #[default]
+    Utf8,
+    /// ISO-8859-1, where every byte is the code point of the same value.
+    Latin1,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "utf8" | "utf-8" => Some(Encoding::Utf8),
+            "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes `bytes`, dropping a leading UTF-8 byte order mark.
+///
+/// ```
+/// use kyomusky::encoding::{decode, Encoding};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let plain = decode(b"let x = 1; x + 1", Encoding::Utf8).unwrap();
+/// let bom = decode(b"\xef\xbb\xbflet x = 1; x + 1", Encoding::Utf8).unwrap();
+/// assert_eq!(bom, plain);
+/// assert_eq!(
+///     parse_program(&bom, ParseOptions::default()),
+///     parse_program(&plain, ParseOptions::default()),
+/// );
+///
+/// assert_eq!(decode(b"\"caf\xe9\"", Encoding::Latin1).unwrap(), "\"café\"");
+/// assert!(decode(b"\"caf\xe9\"", Encoding::Utf8).is_err());
+/// ```
+pub fn decode(bytes: &[u8], encoding: Encoding) -> io::Result<String> {
+    match encoding {
+        Encoding::Utf8 => {
+            let text = std::str::from_utf8(bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(text.strip_prefix(BOM).unwrap_or(text).to_string())
+        }
+        Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+    }
+}
+
+/// Reads and decodes the file at `path`.
+pub fn read(path: impl AsRef<std::path::Path>, encoding: Encoding) -> io::Result<String> {
+    decode(&std::fs::read(path)?, encoding)
+}
+
+/// Like [`read`], with a message for the command line if the file is missing,
+/// unreadable or not in `encoding`.
+///
+/// ```
+/// use kyomusky::encoding::{read_file, Encoding};
+///
+/// let err = read_file("no/such/program.ky", Encoding::Utf8).unwrap_err();
+/// assert!(err.starts_with("Cannot read `no/such/program.ky`: "), "{err}");
+///
+/// let path = std::env::temp_dir().join("kyomusky-read-file-latin1.ky");
+/// std::fs::write(&path, b"\"caf\xe9\"").unwrap();
+/// let path = path.to_str().unwrap();
+/// assert_eq!(read_file(path, Encoding::Latin1).unwrap(), "\"café\"");
+/// let err = read_file(path, Encoding::Utf8).unwrap_err();
+/// assert!(err.starts_with(&format!("Cannot read `{path}`: invalid utf-8")), "{err}");
+/// ```
+pub fn read_file(path: &str, encoding: Encoding) -> Result<String, String> {
+    read(path, encoding).map_err(|e| format!("Cannot read `{path}`: {e}"))
+}
@@ -0,0 +1,2420 @@
+use crate::ast::{BinOp, Expr, ExprKind, Span, StrPart, UnaryOp};
+#[cfg(feature = "bigint")]
+use crate::bigint::BigInt;
+use crate::builtins::{self, Args, Arity, Builtin};
+use crate::input::{Input, StdinInput};
+use crate::json::Json;
+use crate::memo::{self, Memo};
+use crate::number::NumberFormat;
+use crate::resolve::{self, Slots};
+use crate::rng::Rng;
+use crate::stack;
+use crate::step::Debugger;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Lists and maps are shared, mutable containers: binding one to another
+/// name does not copy it, so an `xs[0] := 9` is visible through every name
+/// referring to the same list.
+///
+/// `Display` shows values the way the user wrote them, while `Debug` names
+/// the variant of every value, e.g. `List([Num(1.0), Str("1")])`, and prints
+/// numbers with enough digits to read them back exactly.
+///
+/// A precision, as in `{:.3}`, limits how much of a large value `Display`
+/// shows: at most that many elements of each list or map, and characters of
+/// each string, with `…` marking what was left out.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let program = parse_program("[1, 2, 3, 4, 5]", ParseOptions::default()).unwrap();
+/// let value = eval(&program[0], &mut Env::new()).unwrap();
+/// assert_eq!(format!("{value:.3}"), "[1, 2, 3, … 2 more]");
+/// assert_eq!(value.to_string(), "[1, 2, 3, 4, 5]");
+/// ```
+#[derive(Clone, PartialEq)]
+pub enum Value {
+    Num(f64),
+    /// An integer too large to be represented exactly by `Num`. Only produced
+    /// by arithmetic on integers, and behaves like any other number.
+    #[cfg(feature = "bigint")]
+    Int(BigInt),
+    Str(String),
+    Bool(bool),
+    List(Rc<RefCell<Vec<Value>>>),
+    /// Entries are kept in insertion order, so a map always displays the
+    /// same way. Assigning to an existing key keeps its place.
+    ///
+    /// ```
+    /// use kyomusky::run::eval_str;
+    ///
+    /// assert_eq!(eval_str(r#"{"b": 1, "a": 2}"#).unwrap(), r#"{"b": 1, "a": 2}"#);
+    /// assert_eq!(eval_str(r#"{"b": 1, "a": 2, "b": 3}"#).unwrap(), r#"{"b": 3, "a": 2}"#);
+    /// let src = r#"let m = {"b": 1}; let _ = m["a"] := 2; let _ = m["b"] := 3; m"#;
+    /// for _ in 0..3 {
+    ///     assert_eq!(eval_str(src).unwrap(), r#"{"b": 3, "a": 2}"#);
+    /// }
+    /// ```
+    Map(Rc<RefCell<Vec<(String, Value)>>>),
+    /// `some(x)`, holding `x`, or `none`, holding nothing: the result of an
+    /// operation that may have none, taken apart with `if let`.
+    Optional(Option<Box<Value>>),
+    Function(Rc<FunctionValue>),
+}
+
+/// A function as a value: a lambda, or a function referred to by its name
+/// rather than called. It can only be called in the [`Env`] that created it.
+///
+/// ```
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("fn add x y = x + y; [name(add), arity(add)]").unwrap(), r#"["add", 2]"#);
+/// assert_eq!(eval_str("let inc = \\x -> x + 1; [name(inc), arity(inc), inc(1)]").unwrap(), r#"["", 1, 2]"#);
+/// assert_eq!(eval_str("fn twice f x = f(f(x)); twice(\\x -> x * 3, 2)").unwrap(), "18");
+/// ```
+pub struct FunctionValue {
+    /// The name the function was declared with, or empty for a lambda.
+    pub name: String,
+    pub arity: Arity,
+    callee: Callee,
+}
+
+enum Callee {
+    /// An index into the closures of the `Env`.
+    Closure(usize),
+    Builtin(&'static Builtin),
+    /// `f(g(x))`, for `compose(f, g)`.
+    Compose(Rc<FunctionValue>, Rc<FunctionValue>),
+    /// A function with its first arguments already given, see
+    /// [`Env::curry`].
+    Partial(Rc<FunctionValue>, Vec<(Value, Span)>),
+}
+
+impl FunctionValue {
+    /// A lambda calling `g` with its one argument, then `f` with the result.
+    pub(crate) fn compose(f: Rc<FunctionValue>, g: Rc<FunctionValue>) -> Self {
+        Self {
+            name: String::new(),
+            arity: Arity::Exact(1),
+            callee: Callee::Compose(f, g),
+        }
+    }
+
+    /// `f` waiting for the arguments after `args`, which must be fewer than
+    /// it takes.
+    fn partial(f: Rc<FunctionValue>, args: Vec<(Value, Span)>) -> Self {
+        let Arity::Exact(n) = f.arity else {
+            unreachable!("only functions of a fixed arity are partially applied");
+        };
+        Self {
+            name: f.name.clone(),
+            arity: Arity::Exact(n - args.len()),
+            callee: Callee::Partial(f, args),
+        }
+    }
+
+    /// How error messages refer to the function.
+    fn what(&self) -> String {
+        if self.name.is_empty() {
+            "lambda".to_string()
+        } else {
+            format!("function `{}`", self.name)
+        }
+    }
+}
+
+/// Function values are only equal to themselves.
+impl PartialEq for FunctionValue {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Num(_) => "number",
+            #[cfg(feature = "bigint")]
+            Value::Int(_) => "number",
+            Value::Str(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Optional(_) => "optional",
+            Value::Function(_) => "function",
+        }
+    }
+
+    /// Keeps an integer exact: values below 2^53 in magnitude fit in a `Num`.
+    #[cfg(feature = "bigint")]
+    pub fn from_big(n: BigInt) -> Value {
+        let x = n.to_f64();
+        if x.abs() < MAX_EXACT {
+            Value::Num(x)
+        } else {
+            Value::Int(n)
+        }
+    }
+
+    /// The value as a big integer, if it is an integral number.
+    #[cfg(feature = "bigint")]
+    fn as_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Num(x) => BigInt::from_f64(*x),
+            Value::Int(n) => Some(n.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn type_error(&self, expected: &str, span: &Span) -> EvalError {
+        EvalError::new(
+            ErrorKind::Type,
+            format!(
+                "Type error: expected {expected}, found {}",
+                self.type_name()
+            ),
+            span,
+        )
+    }
+
+    pub fn as_num(&self, span: &Span) -> Result<f64, EvalError> {
+        match self {
+            Value::Num(x) => Ok(*x),
+            #[cfg(feature = "bigint")]
+            Value::Int(n) => Ok(n.to_f64()),
+            v => Err(v.type_error("number", span)),
+        }
+    }
+
+    /// The value as an integer, for indices, counts and the like. Every
+    /// fractional number gets the same error, wherever it is used:
+    ///
+    /// ```
+    /// use kyomusky::eval::ErrorKind;
+    /// use kyomusky::run::eval_str;
+    ///
+    /// for (src, x) in [
+    ///     ("[1, 2, 3][0.5]", "0.5"),
+    ///     ("\"abc\"[-1.5]", "-1.5"),
+    ///     ("let xs = [1, 2]; xs[1.5] := 0", "1.5"),
+    ///     ("to_hex(2.5)", "2.5"),
+    ///     ("to_base(10, 16.5)", "16.5"),
+    ///     ("take(1.5, [1, 2])", "1.5"),
+    ///     ("slice([1, 2], 0, 0.5)", "0.5"),
+    /// ] {
+    ///     let err = eval_str(src).unwrap_err();
+    ///     assert_eq!(err.kind, ErrorKind::Type, "{src}");
+    ///     assert_eq!(err.message, format!("Type error: expected an integer, found {x}"));
+    /// }
+    /// assert_eq!(
+    ///     eval_str("to_hex(2 ^ 64)").unwrap_err().message,
+    ///     "Type error: expected an integer from -2^63 to 2^63, found 18446744073709552000"
+    /// );
+    /// ```
+    pub fn as_integer(&self, span: &Span) -> Result<i64, EvalError> {
+        let x = self.as_num(span)?;
+        if x.fract() != 0.0 || x.is_infinite() {
+            return Err(EvalError::new(
+                ErrorKind::Type,
+                format!("Type error: expected an integer, found {x}"),
+                span,
+            ));
+        }
+        // -2^63 is the only integral `f64` of magnitude 2^63 or more fitting
+        // an `i64`, and leaving it out keeps the range symmetric.
+        if x.abs() >= 2f64.powi(63) {
+            return Err(EvalError::new(
+                ErrorKind::Type,
+                format!("Type error: expected an integer from -2^63 to 2^63, found {x}"),
+                span,
+            ));
+        }
+        Ok(x as i64)
+    }
+
+    pub fn as_str(&self, span: &Span) -> Result<&str, EvalError> {
+        match self {
+            Value::Str(s) => Ok(s),
+            v => Err(v.type_error("string", span)),
+        }
+    }
+
+    pub fn as_function(&self, span: &Span) -> Result<&Rc<FunctionValue>, EvalError> {
+        match self {
+            Value::Function(f) => Ok(f),
+            v => Err(v.type_error("function", span)),
+        }
+    }
+
+    /// Converts a number to a position in a list of `len` elements. Negative
+    /// numbers count from the end, so `-1` is the last element.
+    ///
+    /// ```
+    /// use kyomusky::run::eval_str;
+    ///
+    /// let run = |src| eval_str(src).map_err(|e| e.message);
+    /// assert_eq!(run("[1, 2, 3][-1]"), Ok("3".to_string()));
+    /// assert_eq!(run("[1, 2, 3][-3]"), Ok("1".to_string()));
+    /// assert_eq!(run("\"abc\"[-1]"), Ok("c".to_string()));
+    /// assert_eq!(
+    ///     run("[1, 2][-3]"),
+    ///     Err("Index -3 out of bounds for list of length 2".to_string())
+    /// );
+    /// assert_eq!(
+    ///     run("\"ab\"[-3]"),
+    ///     Err("Index -3 out of bounds for string of length 2".to_string())
+    /// );
+    /// ```
+    pub fn as_index(&self, len: usize, span: &Span) -> Result<usize, EvalError> {
+        self.index_into(len, "list", span)
+    }
+
+    fn index_into(&self, len: usize, what: &str, span: &Span) -> Result<usize, EvalError> {
+        let n = self.as_integer(span)?;
+        let i = if n < 0 { len as i64 + n } else { n };
+        if i < 0 || i >= len as i64 {
+            return Err(EvalError::new(
+                ErrorKind::IndexOutOfBounds,
+                format!("Index {n} out of bounds for {what} of length {len}"),
+                span,
+            ));
+        }
+        Ok(i as usize)
+    }
+
+    /// The value as JSON, for `--output json`. Every value has one: `none` is
+    /// `null`, `some(x)` is `x`, and a function is an object describing it,
+    /// without what it captured. A list or map inside itself is `null` there.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let run = |src| {
+    ///     let program = parse_program(src, ParseOptions::default()).unwrap();
+    ///     let value = eval(&program[0], &mut Env::new()).unwrap();
+    ///     value.to_json().to_string()
+    /// };
+    ///
+    /// let src = "fn add x y = x + y; let k = 2; [add, \\x -> x * k, max, some(1), none]";
+    /// assert_eq!(
+    ///     run(src),
+    ///     r#"[{"type":"function","name":"add","arity":2},{"type":"function","name":"","arity":1},{"type":"function","name":"max","arity":1},1,null]"#
+    /// );
+    /// ```
+    pub fn to_json(&self) -> Json {
+        self.to_json_within(&mut Vec::new())
+    }
+
+    /// [`Value::to_json`] of a value nested in the lists and maps of
+    /// `ancestors`, see [`Value::address`].
+    fn to_json_within(&self, ancestors: &mut Vec<usize>) -> Json {
+        if let Some(address) = self.address() {
+            if ancestors.contains(&address) {
+                return Json::Null;
+            }
+            ancestors.push(address);
+        }
+        let json = match self {
+            Value::Num(x) => Json::Num(*x),
+            #[cfg(feature = "bigint")]
+            Value::Int(n) => Json::Num(n.to_f64()),
+            Value::Str(s) => Json::Str(s.clone()),
+            Value::Bool(b) => Json::Bool(*b),
+            Value::List(xs) => Json::Array(
+                xs.borrow()
+                    .iter()
+                    .map(|x| x.to_json_within(ancestors))
+                    .collect(),
+            ),
+            Value::Map(entries) => Json::Object(
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.to_json_within(ancestors)))
+                    .collect(),
+            ),
+            Value::Optional(None) => Json::Null,
+            Value::Optional(Some(x)) => x.to_json_within(ancestors),
+            Value::Function(function) => {
+                let (Arity::Exact(n) | Arity::AtLeast(n)) = function.arity;
+                Json::object([
+                    ("type", "function".into()),
+                    ("name", function.name.as_str().into()),
+                    ("arity", (n as f64).into()),
+                ])
+            }
+        };
+        if self.address().is_some() {
+            ancestors.pop();
+        }
+        json
+    }
+
+    /// Where the elements of a list or map are, which is the same for every
+    /// copy of it. A list can be put inside itself with `:=`, so functions
+    /// walking nested values keep the addresses of the ones they are inside
+    /// to stop at such a cycle.
+    pub(crate) fn address(&self) -> Option<usize> {
+        match self {
+            Value::List(xs) => Some(Rc::as_ptr(xs) as *const () as usize),
+            Value::Map(entries) => Some(Rc::as_ptr(entries) as *const () as usize),
+            _ => None,
+        }
+    }
+
+    /// Displays the value like `Display`, with its numbers printed following
+    /// `numbers`.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::number::NumberFormat;
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = r#"[1234.5678, {"x": 2}, "3.5"]"#;
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let value = eval(&program[0], &mut Env::new()).unwrap();
+    /// let numbers = NumberFormat { precision: Some(2), grouping: true, ..NumberFormat::default() };
+    /// assert_eq!(value.formatted(&numbers).to_string(), r#"[1,234.57, {"x": 2.00}, "3.5"]"#);
+    /// assert_eq!(format!("{:.1}", value.formatted(&numbers)), "[1,234.57, … 2 more]");
+    /// ```
+    pub fn formatted<'v>(&'v self, numbers: &'v NumberFormat) -> Formatted<'v> {
+        Formatted {
+            value: self,
+            numbers,
+            ancestors: &[],
+        }
+    }
+}
+
+/// A value displayed with a policy for its numbers, see [`Value::formatted`].
+pub struct Formatted<'v> {
+    value: &'v Value,
+    numbers: &'v NumberFormat,
+    /// The addresses of the lists and maps this value is inside, shown as
+    /// `[…]` and `{…}` when it contains one of them.
+    ancestors: &'v [usize],
+}
+
+impl Formatted<'_> {
+    /// Formats `value`, an element of this one, as it would be written in
+    /// source, quoting strings. Keeps the precision of `f`, see `Display`.
+    fn fmt_nested(&self, value: &Value, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ancestors = [self.ancestors, self.value.address().as_slice()].concat();
+        let v = Formatted {
+            value,
+            numbers: self.numbers,
+            ancestors: &ancestors,
+        };
+        match (value, f.precision()) {
+            (Value::Str(s), Some(n)) if s.chars().count() > n => {
+                let prefix: String = s.chars().take(n).collect();
+                write!(f, "{prefix:?}…")
+            }
+            (Value::Str(s), _) => write!(f, "{s:?}"),
+            (_, Some(n)) => write!(f, "{v:.n$}"),
+            (_, None) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// Writes the `…` ending a list or map shown with a precision of `shown`
+/// elements.
+fn fmt_rest(f: &mut std::fmt::Formatter, shown: usize, len: usize) -> std::fmt::Result {
+    if len > shown {
+        let separator = if shown > 0 { ", " } else { "" };
+        write!(f, "{separator}… {} more", len - shown)?;
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.formatted(&NumberFormat::default()).fmt(f)
+    }
+}
+
+impl std::fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.value.address().is_some_and(|address| self.ancestors.contains(&address)) {
+            let cycle = if matches!(self.value, Value::List(_)) { "[…]" } else { "{…}" };
+            return write!(f, "{cycle}");
+        }
+        match self.value {
+            Value::Num(x) => write!(f, "{}", self.numbers.format(*x)),
+            #[cfg(feature = "bigint")]
+            Value::Int(n) => write!(f, "{}", self.numbers.format_integer(&n.to_string())),
+            Value::Str(s) => match f.precision() {
+                Some(n) if s.chars().count() > n => {
+                    let prefix: String = s.chars().take(n).collect();
+                    write!(f, "{prefix}…")
+                }
+                _ => write!(f, "{s}"),
+            },
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::List(xs) => {
+                let xs = xs.borrow();
+                let shown = f.precision().unwrap_or(usize::MAX).min(xs.len());
+                write!(f, "[")?;
+                for (i, x) in xs[..shown].iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_nested(x, f)?;
+                }
+                fmt_rest(f, shown, xs.len())?;
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                let entries = entries.borrow();
+                let shown = f.precision().unwrap_or(usize::MAX).min(entries.len());
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries[..shown].iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{k:?}: ")?;
+                    self.fmt_nested(v, f)?;
+                }
+                fmt_rest(f, shown, entries.len())?;
+                write!(f, "}}")
+            }
+            Value::Optional(None) => write!(f, "none"),
+            Value::Optional(Some(x)) => {
+                write!(f, "some(")?;
+                self.fmt_nested(x, f)?;
+                write!(f, ")")
+            }
+            Value::Function(function) if function.name.is_empty() => write!(f, "<lambda>"),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        DebugValue {
+            value: self,
+            ancestors: &[],
+        }
+        .fmt(f)
+    }
+}
+
+/// A value shown with `Debug`, nested in the lists and maps of `ancestors`,
+/// see [`Value::address`]. One it is inside is shown as `List(…)` or
+/// `Map(…)`.
+struct DebugValue<'a> {
+    value: &'a Value,
+    ancestors: &'a [usize],
+}
+
+impl std::fmt::Debug for DebugValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let address = self.value.address();
+        if address.is_some_and(|address| self.ancestors.contains(&address)) {
+            let name = if matches!(self.value, Value::List(_)) { "List" } else { "Map" };
+            return write!(f, "{name}(…)");
+        }
+        let ancestors = [self.ancestors, address.as_slice()].concat();
+        let nested = |value| DebugValue {
+            value,
+            ancestors: &ancestors,
+        };
+        match self.value {
+            Value::Num(x) => f.debug_tuple("Num").field(x).finish(),
+            #[cfg(feature = "bigint")]
+            Value::Int(n) => write!(f, "Int({n})"),
+            Value::Str(s) => f.debug_tuple("Str").field(s).finish(),
+            Value::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            Value::List(xs) => {
+                let xs = xs.borrow();
+                let xs: Vec<_> = xs.iter().map(nested).collect();
+                f.debug_tuple("List").field(&xs).finish()
+            }
+            Value::Map(entries) => {
+                let entries = entries.borrow();
+                let map = entries.iter().map(|(k, v)| (k, nested(v)));
+                f.debug_tuple("Map")
+                    .field(&DebugMap(map.collect()))
+                    .finish()
+            }
+            Value::Optional(x) => f
+                .debug_tuple("Optional")
+                .field(&x.as_deref().map(nested))
+                .finish(),
+            Value::Function(function) => f.debug_tuple("Function").field(&function.name).finish(),
+        }
+    }
+}
+
+/// Shows map entries as `{"key": value}` rather than a list of pairs.
+struct DebugMap<'a>(Vec<(&'a String, DebugValue<'a>)>);
+
+impl std::fmt::Debug for DebugMap<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(k, v)| (k, v)))
+            .finish()
+    }
+}
+
+/// The one check behind every condition: the operands of `not`, `and` and
+/// `or`, the predicates of `filter`, `find`, `take_while` and `drop_while`,
+/// and the `if` of a list comprehension. They all fail the same way, at the
+/// condition:
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// for (src, condition) in [
+///     ("not 1", "1"),
+///     ("1 and true", "1"),
+///     ("false or 1", "1"),
+///     ("filter(\\x -> x, [1])", "\\x -> x"),
+///     ("[x for x in [1] if x + 1]", "x + 1"),
+/// ] {
+///     let err = eval_str(src).unwrap_err();
+///     assert_eq!(err.kind, ErrorKind::Type);
+///     assert_eq!(err.message, "Type error: expected boolean, found number");
+///     assert_eq!(&src[err.span], condition);
+/// }
+/// ```
+pub fn expect_bool(value: &Value, span: &Span) -> Result<bool, EvalError> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        v => Err(v.type_error("boolean", span)),
+    }
+}
+
+/// Compares two values of the same type. Numbers use the usual float
+/// ordering and strings are ordered lexicographically by Unicode scalar
+/// value (which is the same as comparing their UTF-8 bytes).
+pub fn compare(a: &Value, b: &Value, span: &Span) -> Result<Option<Ordering>, EvalError> {
+    #[cfg(feature = "bigint")]
+    if matches!(a, Value::Int(_)) || matches!(b, Value::Int(_)) {
+        if let (Some(x), Some(y)) = (a.as_big(), b.as_big()) {
+            return Ok(Some(x.cmp(&y)));
+        }
+        if let (Ok(x), Ok(y)) = (a.as_num(span), b.as_num(span)) {
+            return Ok(x.partial_cmp(&y));
+        }
+    }
+    match (a, b) {
+        (Value::Num(a), Value::Num(b)) => Ok(a.partial_cmp(b)),
+        (Value::Str(a), Value::Str(b)) => Ok(Some(a.cmp(b))),
+        (Value::Bool(a), Value::Bool(b)) => Ok(Some(a.cmp(b))),
+        (a, b) => Err(EvalError::new(
+            ErrorKind::Type,
+            format!(
+                "Type error: cannot compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ),
+            span,
+        )),
+    }
+}
+
+/// Structural equality: lists are equal when their elements are pairwise
+/// equal, and maps when they have the same keys with equal values, regardless
+/// of insertion order. Values of different types are never equal.
+pub fn deep_equal(a: &Value, b: &Value) -> bool {
+    deep_equal_within(a, b, 0.0)
+}
+
+/// Like [`deep_equal`], but numbers at most `eps` apart are equal, as are
+/// numbers nested in lists and maps.
+///
+/// Lists and maps put inside themselves are compared until the same pair
+/// comes up again, which counts as equal.
+///
+/// ```
+/// use kyomusky::run::eval_str;
+///
+/// let cycles = "let xs = [0]; let ys = [0]; let _ = xs[0] := xs; let _ = ys[0] := ys;";
+/// let equal = |src| eval_str(&format!("{cycles} {src}")).unwrap();
+/// assert_eq!(equal("[xs == xs, xs == ys, xs == [xs]]"), "[true, true, true]");
+/// assert_eq!(equal("xs == [ys, 1]"), "false");
+/// ```
+pub fn deep_equal_within(a: &Value, b: &Value, eps: f64) -> bool {
+    deep_equal_in(a, b, eps, &mut Vec::new())
+}
+
+/// [`deep_equal_within`] of values nested in the pairs of lists and maps
+/// being compared, see [`Value::address`].
+fn deep_equal_in(a: &Value, b: &Value, eps: f64, pairs: &mut Vec<(usize, usize)>) -> bool {
+    if let (Some(x), Some(y)) = (a.address(), b.address()) {
+        if pairs.contains(&(x, y)) {
+            return true;
+        }
+        pairs.push((x, y));
+        let equal = deep_equal_nested(a, b, eps, pairs);
+        pairs.pop();
+        return equal;
+    }
+    deep_equal_nested(a, b, eps, pairs)
+}
+
+fn deep_equal_nested(a: &Value, b: &Value, eps: f64, pairs: &mut Vec<(usize, usize)>) -> bool {
+    #[cfg(feature = "bigint")]
+    if matches!(a, Value::Int(_)) || matches!(b, Value::Int(_)) {
+        return compare(a, b, &(0..0)) == Ok(Some(Ordering::Equal));
+    }
+    match (a, b) {
+        (Value::Num(a), Value::Num(b)) => a == b || (a - b).abs() <= eps,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::List(a), Value::List(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b.iter())
+                    .all(|(a, b)| deep_equal_in(a, b, eps, pairs))
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len()
+                && a.iter().all(|(key, a)| {
+                    b.iter()
+                        .find(|(k, _)| k == key)
+                        .is_some_and(|(_, b)| deep_equal_in(a, b, eps, pairs))
+                })
+        }
+        (Value::Optional(a), Value::Optional(b)) => match (a, b) {
+            (Some(a), Some(b)) => deep_equal_in(a, b, eps, pairs),
+            (a, b) => a.is_none() && b.is_none(),
+        },
+        (Value::Function(a), Value::Function(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Integers up to this magnitude are represented exactly by an `f64`.
+#[cfg(feature = "bigint")]
+const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// Adds, subtracts or multiplies two integers exactly when the result would
+/// be too large for an `f64` to represent. Returns `None` to fall back to
+/// float arithmetic, e.g. when either operand is not an integer.
+#[cfg(feature = "bigint")]
+fn int_arith(op: BinOp, lhs: &Value, rhs: &Value) -> Option<Value> {
+    type Op = (fn(f64, f64) -> f64, fn(&BigInt, &BigInt) -> BigInt);
+    let (float_op, big_op): Op = match op {
+        BinOp::Add => (|x, y| x + y, BigInt::add),
+        BinOp::Sub => (|x, y| x - y, BigInt::sub),
+        BinOp::Mul => (|x, y| x * y, BigInt::mul),
+        _ => return None,
+    };
+    if let (Value::Num(x), Value::Num(y)) = (lhs, rhs) {
+        if float_op(*x, *y).abs() < MAX_EXACT {
+            return None;
+        }
+    }
+    let (x, y) = (lhs.as_big()?, rhs.as_big()?);
+    Some(Value::from_big(big_op(&x, &y)))
+}
+
+/// Implements `==`, with numbers at most `eps` apart considered equal.
+/// Comparing values of different types is an error, but mismatched elements
+/// nested inside lists or maps just make them unequal.
+pub fn equal(a: &Value, b: &Value, eps: f64, span: &Span) -> Result<bool, EvalError> {
+    if a.type_name() != b.type_name() {
+        compare(a, b, span)?;
+    }
+    Ok(deep_equal_within(a, b, eps))
+}
+
+/// The category of an [`EvalError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Syntax,
+    Type,
+    UnboundVariable,
+    UnknownFunction,
+    Arity,
+    IndexOutOfBounds,
+    NonFinite,
+    Input,
+    StackExhausted,
+    /// A function registered with [`Env::register_fn`] failed.
+    Native,
+    /// More was allocated than [`Env::memory_limit`] allows, or than there is
+    /// memory for.
+    MemoryLimit,
+    /// Evaluation panicked, which is a bug in the interpreter or in a native
+    /// function. Only reported by [`eval_catching_panics`].
+    Internal,
+    /// A built-in reading input, random numbers or the time was called
+    /// while [`Env::deterministic`] is set.
+    Nondeterministic,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 13] = [
+        ErrorKind::Syntax,
+        ErrorKind::Type,
+        ErrorKind::UnboundVariable,
+        ErrorKind::UnknownFunction,
+        ErrorKind::Arity,
+        ErrorKind::IndexOutOfBounds,
+        ErrorKind::NonFinite,
+        ErrorKind::Input,
+        ErrorKind::StackExhausted,
+        ErrorKind::Native,
+        ErrorKind::MemoryLimit,
+        ErrorKind::Internal,
+        ErrorKind::Nondeterministic,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::Type => "type",
+            ErrorKind::UnboundVariable => "unbound-variable",
+            ErrorKind::UnknownFunction => "unknown-function",
+            ErrorKind::Arity => "arity",
+            ErrorKind::IndexOutOfBounds => "index-out-of-bounds",
+            ErrorKind::NonFinite => "non-finite",
+            ErrorKind::Input => "input",
+            ErrorKind::StackExhausted => "stack-exhausted",
+            ErrorKind::Native => "native",
+            ErrorKind::MemoryLimit => "memory-limit",
+            ErrorKind::Internal => "internal",
+            ErrorKind::Nondeterministic => "nondeterministic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Whether evaluation can sensibly carry on after this kind of error.
+    ///
+    /// - `non-finite`: arithmetic on finite numbers producing an infinity or
+    ///   NaN (e.g. `1 / 0`). The non-finite result is kept.
+    /// - `index-out-of-bounds`: reading past the end of a list evaluates to
+    ///   NaN, and assigning past the end does nothing.
+    pub fn is_downgradable(self) -> bool {
+        matches!(self, ErrorKind::NonFinite | ErrorKind::IndexOutOfBounds)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Span,
+    /// Another place relevant to the error, with what it is, e.g. the
+    /// declaration of a function called with the wrong number of arguments.
+    pub secondary: Option<(Span, String)>,
+    /// The calls the error happened in, innermost first: the name of each
+    /// function called, empty for a lambda, with the span of the call. A
+    /// function calling itself as its last step leaves a single call.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "fn a x = 1 + b(x); fn b x = c(x) * 2; fn c x = x + true; a(1)";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let err = eval(&program[0], &mut Env::new()).unwrap_err();
+    /// let calls = err.stack.iter().map(|(name, span)| (name.as_str(), &src[span.clone()]));
+    /// let calls: Vec<_> = calls.collect();
+    /// assert_eq!(calls, [("c", "c(x)"), ("b", "b(x)"), ("a", "a(1)")]);
+    ///
+    /// let src = "let f = \\x -> x + true; map(f, [1])";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let err = eval(&program[0], &mut Env::new()).unwrap_err();
+    /// assert_eq!(err.stack.len(), 1);
+    /// assert_eq!(err.stack[0].0, "");
+    ///
+    /// // Errors outside of any function, or in arguments, have no calls.
+    /// let src = "fn f x = x; f(1 + true)";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// assert!(eval(&program[0], &mut Env::new()).unwrap_err().stack.is_empty());
+    /// ```
+    pub stack: Vec<(String, Span)>,
+}
+
+impl EvalError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>, span: &Span) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            span: span.clone(),
+            secondary: None,
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn with_secondary(mut self, span: &Span, label: impl Into<String>) -> Self {
+        self.secondary = Some((span.clone(), label.into()));
+        self
+    }
+
+    /// Adds the call to `name` at `span` to the stack, as the error leaves
+    /// the function.
+    fn in_call(mut self, name: &str, span: &Span) -> Self {
+        self.stack.push((name.to_string(), span.clone()));
+        self
+    }
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Which kinds of errors are reported as warnings instead of aborting
+/// evaluation. By default every error is fatal.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPolicy {
+    downgraded: Vec<ErrorKind>,
+}
+
+impl ErrorPolicy {
+    /// Makes every downgradable kind of error a warning.
+    pub fn lenient() -> Self {
+        Self {
+            downgraded: ErrorKind::ALL
+                .into_iter()
+                .filter(|kind| kind.is_downgradable())
+                .collect(),
+        }
+    }
+
+    pub fn downgrade(&mut self, kind: ErrorKind) -> Result<(), String> {
+        if !kind.is_downgradable() {
+            return Err(format!("`{}` errors cannot be downgraded", kind.name()));
+        }
+        if !self.is_downgraded(kind) {
+            self.downgraded.push(kind);
+        }
+        Ok(())
+    }
+
+    pub fn is_downgraded(&self, kind: ErrorKind) -> bool {
+        self.downgraded.contains(&kind)
+    }
+}
+
+/// The variables in scope where a function was declared.
+type Scope<'a> = Rc<[(&'a String, Binding<'a>)]>;
+
+/// What a variable is bound to.
+#[derive(Clone)]
+enum Binding<'a> {
+    Value(Value),
+    /// Declared with `let lazy`, shared by every scope capturing it so that
+    /// it is evaluated at most once.
+    Lazy(Rc<Thunk<'a>>),
+}
+
+/// The right-hand side of a `let lazy`, with the variables in scope there.
+struct Thunk<'a> {
+    expr: &'a Expr,
+    scope: Scope<'a>,
+    value: RefCell<Option<Value>>,
+}
+
+impl Binding<'_> {
+    /// The value, if it is known without evaluating anything.
+    fn evaluated(&self) -> Option<Value> {
+        match self {
+            Binding::Value(value) => Some(value.clone()),
+            Binding::Lazy(thunk) => thunk.value.borrow().clone(),
+        }
+    }
+}
+
+/// The value of `binding`, evaluating it first if it is a lazy one never
+/// used so far.
+fn force<'a>(binding: &Binding<'a>, env: &mut Env<'a>) -> Result<Value, EvalError> {
+    let thunk = match binding {
+        Binding::Value(value) => return Ok(value.clone()),
+        Binding::Lazy(thunk) => thunk,
+    };
+    if let Some(value) = &*thunk.value.borrow() {
+        return Ok(value.clone());
+    }
+    let caller_vars = std::mem::replace(&mut env.vars, thunk.scope.to_vec());
+    let output = eval(thunk.expr, env);
+    env.vars = caller_vars;
+    let value = output?;
+    *thunk.value.borrow_mut() = Some(value.clone());
+    Ok(value)
+}
+
+/// The binding of the innermost variable called `name`.
+fn lookup<'a>(env: &Env<'a>, name: &str) -> Option<Binding<'a>> {
+    env.vars
+        .iter()
+        .rev()
+        .find(|(var, _)| *var == name)
+        .map(|(_, binding)| binding.clone())
+}
+
+type NativeFn<'a> = dyn Fn(&[f64]) -> Result<f64, String> + 'a;
+
+/// A function declared with `fn`, or a lambda, with the variables in scope
+/// where it was declared, which is all its body sees besides its parameters.
+#[derive(Clone)]
+struct Function<'a> {
+    /// Empty for a lambda.
+    name: &'a str,
+    params: &'a [String],
+    body: &'a Expr,
+    scope: Scope<'a>,
+    /// The span of the declaration.
+    span: Span,
+}
+
+/// A function registered with [`Env::register_fn`].
+#[derive(Clone)]
+struct Native<'a> {
+    name: String,
+    arity: usize,
+    call: Rc<NativeFn<'a>>,
+}
+
+/// What a function value calls.
+#[derive(Clone)]
+enum Closure<'a> {
+    Function(Function<'a>),
+    Native(Native<'a>),
+}
+
+/// Variables or functions coming into or going out of scope, see
+/// [`Env::scopes`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeEvent {
+    /// Whether the names came into scope, rather than left it.
+    pub push: bool,
+    pub names: Vec<String>,
+    /// The node bringing them into scope: a `let`, `fn`, call, `for` or
+    /// `if let`.
+    pub span: Span,
+}
+
+impl std::fmt::Display for ScopeEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let change = if self.push { "push" } else { "pop" };
+        let span = &self.span;
+        write!(f, "{change} {} at {span:?}", self.names.join(", "))
+    }
+}
+
+/// The state of an evaluation: the variables and functions in scope, how
+/// errors are handled, and where `input()` reads from.
+pub struct Env<'a> {
+    vars: Vec<(&'a String, Binding<'a>)>,
+    /// Functions are looked up where they are called, unlike variables.
+    fns: Vec<Function<'a>>,
+    natives: Vec<Native<'a>>,
+    /// The functions behind every function value created so far, see
+    /// [`FunctionValue`].
+    closures: Vec<Closure<'a>>,
+    pub policy: ErrorPolicy,
+    /// Errors that were downgraded to warnings by the policy.
+    pub warnings: Vec<EvalError>,
+    /// Standard input by default.
+    pub input: Box<dyn Input>,
+    /// Number of values `input()` has returned so far.
+    pub inputs_read: usize,
+    /// Where `print` writes. Standard output by default.
+    pub output: Box<dyn Write + 'a>,
+    /// Drives `shuffle` and `sample`. Seeded from the current time by
+    /// default.
+    pub rng: Rng,
+    /// How far apart two numbers can be and still compare equal with `==`
+    /// and `!=`. Zero by default, i.e. equality is exact, and `1/10 + 2/10 ==
+    /// 3/10` is false.
+    pub float_eps: f64,
+    /// Whether calls can resolve to built-in functions. When disabled, only
+    /// functions declared with `fn` exist.
+    pub builtins: bool,
+    /// Whether a name resolves to a built-in before a function declared with
+    /// `fn` or registered with [`Env::register_fn`]. By default built-ins
+    /// come last, so a program can replace them.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "fn sqrt x = 0; [sqrt(4), map(sqrt, [9])[0]]";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let mut env = Env::new();
+    /// assert_eq!(eval(&program[0], &mut env).unwrap().to_string(), "[0, 0]");
+    ///
+    /// let mut env = Env::new();
+    /// env.builtins_first = true;
+    /// assert_eq!(eval(&program[0], &mut env).unwrap().to_string(), "[2, 3]");
+    /// ```
+    pub builtins_first: bool,
+    /// Whether calling a function with fewer arguments than it takes is a
+    /// partial application rather than an error: it returns a function
+    /// taking the remaining arguments, so `add(1)(2)` is `add(1, 2)`.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env, ErrorKind};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "fn add x y = x + y; [add(1)(2), add(1, 2), add()(1)(2)]";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let mut env = Env::new();
+    /// env.curry = true;
+    /// assert_eq!(eval(&program[0], &mut env).unwrap().to_string(), "[3, 3, 3]");
+    ///
+    /// let src = "fn add x y = x + y; add(1)(2, 3)";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let mut env = Env::new();
+    /// env.curry = true;
+    /// assert_eq!(eval(&program[0], &mut env).unwrap_err().kind, ErrorKind::Arity);
+    /// ```
+    pub curry: bool,
+    /// The most list elements and string bytes evaluation may allocate in
+    /// total, to keep untrusted programs from exhausting memory. Unlimited by
+    /// default.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env, ErrorKind};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// // Each line doubles the length of `s`.
+    /// let src = format!("let s = \"ab\";\n{}s", "let s = \"${s}${s}\";\n".repeat(40));
+    /// let program = parse_program(&src, ParseOptions::default()).unwrap();
+    /// let mut env = Env::new();
+    /// env.memory_limit = Some(1 << 20);
+    /// let err = eval(&program[0], &mut env).unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::MemoryLimit);
+    /// assert!(env.allocated <= 1 << 20);
+    /// ```
+    pub memory_limit: Option<usize>,
+    /// List elements and string bytes allocated so far, see
+    /// [`Env::memory_limit`].
+    pub allocated: usize,
+    /// When set, called before evaluating each node, for `--step`.
+    pub debugger: Option<Box<dyn Debugger + 'a>>,
+    /// When set, the outcome of every node evaluated is appended to it, for
+    /// `--graph-eval`.
+    pub values: Option<Vec<(&'a Expr, Result<Value, EvalError>)>>,
+    /// When set, every variable or function coming into or going out of
+    /// scope is appended to it, for `--trace-scope`.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let log = |src| {
+    ///     let program = parse_program(src, ParseOptions::default()).unwrap();
+    ///     let mut env = Env::new();
+    ///     env.scopes = Some(Vec::new());
+    ///     eval(&program[0], &mut env).unwrap();
+    ///     let scopes = env.scopes.unwrap();
+    ///     scopes.iter().map(|e| e.to_string()).collect::<Vec<_>>()
+    /// };
+    ///
+    /// assert_eq!(log("let x = 1; x + 1"), ["push x at 0..10", "pop x at 0..10"]);
+    /// assert_eq!(
+    ///     log("fn f a b = a + b; { let y = f(1, 2); y }"),
+    ///     [
+    ///         "push f at 0..17",
+    ///         "push a, b at 28..35",
+    ///         "pop a, b at 28..35",
+    ///         "push y at 20..36",
+    ///         "pop y at 20..36",
+    ///         "pop f at 0..17",
+    ///     ]
+    /// );
+    /// ```
+    pub scopes: Option<Vec<ScopeEvent>>,
+    /// Whether calls that are pure, by the criteria of the [`memo`] module,
+    /// are evaluated only once for each value of the variables they read.
+    /// Disabled by default.
+    ///
+    /// ```
+    /// use kyomusky::ast::{BinOp, ExprKind};
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "for i in [1, 2, 3] do sqrt(16) + sqrt(i * i)";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// // How many times `16` and `i * i` are evaluated.
+    /// let count = |memoize| {
+    ///     let mut env = Env::new();
+    ///     env.memoize = memoize;
+    ///     env.values = Some(Vec::new());
+    ///     let value = eval(&program[0], &mut env).unwrap();
+    ///     assert_eq!(value.to_string(), "[5, 6, 7]");
+    ///     let values = env.values.unwrap();
+    ///     let count = |pred: fn(&ExprKind) -> bool| {
+    ///         values.iter().filter(|(e, _)| pred(&e.kind)).count()
+    ///     };
+    ///     (
+    ///         count(|e| matches!(e, ExprKind::Num(x) if *x == 16.0)),
+    ///         count(|e| matches!(e, ExprKind::Binary { op: BinOp::Mul, .. })),
+    ///     )
+    /// };
+    /// assert_eq!(count(false), (3, 3));
+    /// assert_eq!(count(true), (1, 3));
+    /// ```
+    pub memoize: bool,
+    memo: Memo<'a>,
+    /// Whether calling a built-in that is not
+    /// [deterministic](Builtin::deterministic), such as `random` or `input`,
+    /// is an error, so that a program is known to give the same result on
+    /// every run.
+    ///
+    /// ```
+    /// use kyomusky::eval::ErrorKind;
+    /// use kyomusky::run::eval_str_with;
+    ///
+    /// let run = |src| eval_str_with(src, |env| env.deterministic = true);
+    ///
+    /// assert_eq!(run("max(map(sqrt, [1, 4]))").unwrap(), "2");
+    ///
+    /// let src = "1 + random()";
+    /// let err = run(src).unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::Nondeterministic);
+    /// assert_eq!(err.message, "`random` is not deterministic, but evaluation must be");
+    /// assert_eq!(&src[err.span], "random()");
+    ///
+    /// let err = run("map(shuffle, [[1, 2]])").unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::Nondeterministic);
+    /// ```
+    pub deterministic: bool,
+    /// The slots of the variables of the program, see [`Env::resolve`].
+    slots: Slots<'a>,
+}
+
+impl Default for Env<'_> {
+    fn default() -> Self {
+        Self {
+            vars: Vec::new(),
+            fns: Vec::new(),
+            natives: Vec::new(),
+            closures: Vec::new(),
+            policy: ErrorPolicy::default(),
+            warnings: Vec::new(),
+            input: Box::new(StdinInput::default()),
+            inputs_read: 0,
+            output: Box::new(std::io::stdout()),
+            rng: Rng::default(),
+            float_eps: 0.0,
+            builtins: true,
+            builtins_first: false,
+            curry: false,
+            memory_limit: None,
+            allocated: 0,
+            debugger: None,
+            values: None,
+            scopes: None,
+            memoize: false,
+            memo: Memo::default(),
+            deterministic: false,
+            slots: Slots::default(),
+        }
+    }
+}
+
+impl<'a> Env<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `names` coming into scope, or leaving it if not `push`, for
+    /// the node at `span`, when [`Env::scopes`] is set.
+    fn log_scope<'n>(
+        &mut self,
+        push: bool,
+        names: impl IntoIterator<Item = &'n String>,
+        span: &Span,
+    ) {
+        if let Some(scopes) = &mut self.scopes {
+            let names: Vec<_> = names.into_iter().cloned().collect();
+            if !names.is_empty() {
+                scopes.push(ScopeEvent {
+                    push,
+                    names,
+                    span: span.clone(),
+                });
+            }
+        }
+    }
+
+    /// Resolves the variables of `program` to their slots (see
+    /// [`resolve`](crate::resolve::resolve)), so that evaluating it reads
+    /// each variable from its position in scope rather than searching the
+    /// scope for its name. Replaces the slots of any program resolved before.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "let a = 1; fn f x = { let y = x * 10; a + y }; { let b = 2; fn g = b; [f(b), g()] }";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let mut env = Env::new();
+    /// env.resolve(&program[0]);
+    /// assert_eq!(eval(&program[0], &mut env).unwrap().to_string(), "[21, 2]");
+    /// ```
+    pub fn resolve(&mut self, program: &'a Expr) {
+        self.slots = resolve::resolve(program);
+    }
+
+    /// Counts `n` more list elements or string bytes, for the value built at
+    /// `span`, failing if that exceeds the memory limit.
+    pub(crate) fn allocate(&mut self, n: usize, span: &Span) -> Result<(), EvalError> {
+        let allocated = self.allocated.saturating_add(n);
+        match self.memory_limit {
+            Some(limit) if allocated > limit => Err(EvalError::new(
+                ErrorKind::MemoryLimit,
+                format!("Memory limit exceeded: more than {limit} list elements and string bytes"),
+                span,
+            )),
+            _ => {
+                self.allocated = allocated;
+                Ok(())
+            }
+        }
+    }
+
+    /// Makes a Rust function callable from programs as `name`, with `arity`
+    /// numeric arguments. A call resolves to a function declared with `fn`
+    /// first, then to a registered function, then to a built-in, so
+    /// registering a built-in's name replaces it, unless
+    /// [`Env::builtins_first`] is set. Registering a name again
+    /// replaces the previous function.
+    ///
+    /// An `Err` returned by `f` fails the evaluation with a `native` error.
+    ///
+    /// ```
+    /// use kyomusky::eval::{eval, Env, ErrorKind};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let parse = |src| parse_program(src, ParseOptions::default()).unwrap();
+    /// let (total, missing, extra) = (
+    ///     parse("lookup_price(42) * 2"),
+    ///     parse("lookup_price(7)"),
+    ///     parse("lookup_price(42, 1)"),
+    /// );
+    ///
+    /// let mut env = Env::new();
+    /// env.register_fn("lookup_price", 1, |args| {
+    ///     if args[0] == 42.0 {
+    ///         Ok(9.5)
+    ///     } else {
+    ///         Err(format!("no product {}", args[0]))
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(eval(&total[0], &mut env).unwrap().to_string(), "19");
+    ///
+    /// let err = eval(&missing[0], &mut env).unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::Native);
+    /// assert_eq!(err.message, "`lookup_price` failed: no product 7");
+    ///
+    /// let err = eval(&extra[0], &mut env).unwrap_err();
+    /// assert_eq!(err.kind, ErrorKind::Arity);
+    /// ```
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: usize,
+        f: impl Fn(&[f64]) -> Result<f64, String> + 'a,
+    ) {
+        self.natives.retain(|native| native.name != name);
+        self.natives.push(Native {
+            name: name.to_string(),
+            arity,
+            call: Rc::new(f),
+        });
+    }
+
+    /// The built-in `name` refers to, if built-ins are enabled.
+    fn builtin(&self, name: &str) -> Option<&'static Builtin> {
+        builtins::lookup(name).filter(|_| self.builtins)
+    }
+
+    /// The arity of the function `name` refers to, resolved like a call.
+    fn arity_of(&self, name: &str) -> Option<Arity> {
+        if let Some(builtin) = self.builtin(name).filter(|_| self.builtins_first) {
+            return Some(builtin.arity);
+        }
+        if let Some(function) = self.fns.iter().rev().find(|f| f.name == name) {
+            return Some(Arity::Exact(function.params.len()));
+        }
+        if let Some(native) = self.natives.iter().find(|n| n.name == name) {
+            return Some(Arity::Exact(native.arity));
+        }
+        self.builtin(name).map(|builtin| builtin.arity)
+    }
+
+    /// The function `name` refers to, as a value, resolved like a call.
+    fn function_value(&mut self, name: &str) -> Option<Value> {
+        if let Some(builtin) = self.builtin(name).filter(|_| self.builtins_first) {
+            return Some(builtin_value(builtin));
+        }
+        if let Some(function) = self.fns.iter().rev().find(|f| f.name == name) {
+            let arity = Arity::Exact(function.params.len());
+            let closure = Closure::Function(function.clone());
+            return Some(self.closure(name, arity, closure));
+        }
+        if let Some(native) = self.natives.iter().find(|n| n.name == name) {
+            let arity = Arity::Exact(native.arity);
+            let closure = Closure::Native(native.clone());
+            return Some(self.closure(name, arity, closure));
+        }
+        self.builtin(name).map(builtin_value)
+    }
+
+    fn closure(&mut self, name: &str, arity: Arity, closure: Closure<'a>) -> Value {
+        self.closures.push(closure);
+        Value::Function(Rc::new(FunctionValue {
+            name: name.to_string(),
+            arity,
+            callee: Callee::Closure(self.closures.len() - 1),
+        }))
+    }
+
+    /// Either records `err` as a warning and carries on with `fallback`, or
+    /// fails with it, depending on the policy.
+    pub(crate) fn recover(&mut self, err: EvalError, fallback: Value) -> Result<Value, EvalError> {
+        if self.policy.is_downgraded(err.kind) {
+            self.warnings.push(err);
+            Ok(fallback)
+        } else {
+            Err(err)
+        }
+    }
+}
+
+/// Like [`eval`], but a panic during evaluation is returned as an `internal`
+/// error instead of unwinding into the caller. The environment may be left
+/// partway through an assignment afterwards.
+///
+/// ```
+/// use kyomusky::eval::{eval_catching_panics, Env, ErrorKind};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "1 + broken(2)";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let mut env = Env::new();
+/// env.register_fn("broken", 1, |_| panic!("oops"));
+///
+/// let err = eval_catching_panics(&program[0], &mut env).unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Internal);
+/// assert_eq!(err.message, "Internal error: oops");
+/// assert_eq!(&src[err.span], src);
+/// ```
+pub fn eval_catching_panics<'a>(expr: &'a Expr, env: &mut Env<'a>) -> Result<Value, EvalError> {
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| eval(expr, env)));
+    result.unwrap_or_else(|payload| {
+        let message = if let Some(s) = payload.downcast_ref::<&str>() {
+            s
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.as_str()
+        } else {
+            "evaluation panicked"
+        };
+        Err(EvalError::new(
+            ErrorKind::Internal,
+            format!("Internal error: {message}"),
+            &expr.span,
+        ))
+    })
+}
+
+pub fn eval<'a>(expr: &'a Expr, env: &mut Env<'a>) -> Result<Value, EvalError> {
+    if stack::exhausted() {
+        #[cfg(feature = "trace")]
+        tracing::warn!(span = ?expr.span, "stack exhausted");
+        return Err(EvalError::new(
+            ErrorKind::StackExhausted,
+            "Evaluation aborted: stack exhausted; try a larger --stack-size",
+            &expr.span,
+        ));
+    }
+    if let Some(debugger) = &mut env.debugger {
+        // Lazy bindings not evaluated yet are left out.
+        let vars: Vec<_> = env
+            .vars
+            .iter()
+            .filter_map(|(name, binding)| Some((*name, binding.evaluated()?)))
+            .collect();
+        debugger.before(expr, &vars);
+    }
+    let result = if env.memoize {
+        eval_memoized(expr, env)
+    } else {
+        eval_node(expr, env)
+    };
+    if let Some(values) = &mut env.values {
+        values.push((expr, result.clone()));
+    }
+    result
+}
+
+/// Evaluates `expr`, reusing its result if it is a pure call evaluated before
+/// with the same values of its variables.
+fn eval_memoized<'a>(expr: &'a Expr, env: &mut Env<'a>) -> Result<Value, EvalError> {
+    let Some(key) = memo_key(expr, env) else {
+        return eval_node(expr, env);
+    };
+    if let Some(value) = env.memo.results.get(&key) {
+        #[cfg(feature = "trace")]
+        tracing::debug!(span = ?expr.span, "memoized");
+        return Ok(value.clone());
+    }
+    let result = eval_node(expr, env);
+    if let Ok(value) = &result {
+        if memo::is_cacheable(value) {
+            env.memo.results.insert(key, value.clone());
+        }
+    }
+    result
+}
+
+/// The node and the values of its variables, if `expr` is a pure call
+/// whose built-ins are not shadowed.
+fn memo_key<'a>(expr: &'a Expr, env: &mut Env<'a>) -> Option<(*const Expr, String)> {
+    if !matches!(expr.kind, ExprKind::Call(..)) || !env.builtins {
+        return None;
+    }
+    let deps = env
+        .memo
+        .deps
+        .entry(expr)
+        .or_insert_with(|| memo::pure_deps(expr))
+        .as_ref()?;
+    let shadowed = |name: &str| {
+        env.vars.iter().any(|(n, _)| *n == name)
+            || !env.builtins_first
+                && (env.fns.iter().any(|f| f.name == name)
+                    || env.natives.iter().any(|n| n.name == name))
+    };
+    if deps.calls.iter().any(|name| shadowed(name)) {
+        return None;
+    }
+    let mut snapshot = String::new();
+    for name in &deps.vars {
+        // A lazy variable is cacheable once evaluated.
+        let (_, binding) = env.vars.iter().rev().find(|(n, _)| *n == name)?;
+        let value = binding.evaluated()?;
+        if matches!(value, Value::Function(_)) {
+            return None;
+        }
+        snapshot.push_str(&format!("{value:?};"));
+    }
+    Some((expr, snapshot))
+}
+
+fn eval_node<'a>(expr: &'a Expr, env: &mut Env<'a>) -> Result<Value, EvalError> {
+    use Ordering::*;
+
+    match &expr.kind {
+        ExprKind::Error => Err(EvalError::new(
+            ErrorKind::Syntax,
+            "Cannot evaluate an expression that failed to parse",
+            &expr.span,
+        )),
+        ExprKind::NamedArg(name, _) => Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!("Named argument `{name}` outside of a function call"),
+            &expr.span,
+        )),
+        ExprKind::Spread(_) => Err(EvalError::new(
+            ErrorKind::Syntax,
+            "Spread argument outside of a function call",
+            &expr.span,
+        )),
+        ExprKind::Binary {
+            op: BinOp::Pipe, ..
+        } => Err(EvalError::new(
+            ErrorKind::Syntax,
+            "Cannot evaluate `|>` before desugaring, see `desugar::desugar`",
+            &expr.span,
+        )),
+        ExprKind::Comprehension { .. } => Err(EvalError::new(
+            ErrorKind::Syntax,
+            "Cannot evaluate a list comprehension before desugaring, see `desugar::desugar`",
+            &expr.span,
+        )),
+        ExprKind::Num(x) => Ok(Value::Num(*x)),
+        ExprKind::Str(s) => {
+            env.allocate(s.len(), &expr.span)?;
+            Ok(Value::Str(s.clone()))
+        }
+        ExprKind::InterpolatedStr(parts) => {
+            let mut s = String::new();
+            for part in parts {
+                match part {
+                    StrPart::Lit(lit) => {
+                        env.allocate(lit.len(), &expr.span)?;
+                        s.push_str(lit);
+                    }
+                    StrPart::Expr(e) => {
+                        let value = eval(e, env)?.to_string();
+                        env.allocate(value.len(), &expr.span)?;
+                        s.push_str(&value);
+                    }
+                }
+            }
+            Ok(Value::Str(s))
+        }
+        ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+        ExprKind::None => Ok(Value::Optional(None)),
+        ExprKind::Unary { op, operand } => match (op, eval(operand, env)?) {
+            #[cfg(feature = "bigint")]
+            (UnaryOp::Neg, Value::Int(n)) => Ok(Value::Int(n.neg())),
+            (UnaryOp::Neg, v) => Ok(Value::Num(-v.as_num(&operand.span)?)),
+            (UnaryOp::Not, v) => Ok(Value::Bool(!expect_bool(&v, &operand.span)?)),
+        },
+        ExprKind::Binary {
+            op: op @ (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow),
+            lhs: a,
+            rhs: b,
+        } => {
+            let lhs = eval(a, env)?;
+            let rhs = eval(b, env)?;
+            #[cfg(feature = "bigint")]
+            if let Some(result) = int_arith(*op, &lhs, &rhs) {
+                return Ok(result);
+            }
+            let x = lhs.as_num(&a.span)?;
+            let y = rhs.as_num(&b.span)?;
+            let result = match op {
+                BinOp::Add => x + y,
+                BinOp::Sub => x - y,
+                BinOp::Mul => x * y,
+                BinOp::Div => x / y,
+                _ => x.powf(y),
+            };
+
+            if result.is_finite() || !x.is_finite() || !y.is_finite() {
+                return Ok(Value::Num(result));
+            }
+            let message = if *op == BinOp::Div && y == 0.0 {
+                "Division by zero".to_string()
+            } else if result.is_nan() {
+                format!("Fractional power of negative number {x}")
+            } else {
+                format!("Arithmetic overflow: result is {result}")
+            };
+            let err = EvalError::new(ErrorKind::NonFinite, message, &expr.span);
+            env.recover(err, Value::Num(result))
+        }
+
+        ExprKind::Binary {
+            op: op @ (BinOp::Eq | BinOp::NotEq),
+            lhs: a,
+            rhs: b,
+        } => {
+            let equal = equal(&eval(a, env)?, &eval(b, env)?, env.float_eps, &expr.span)?;
+            Ok(Value::Bool(equal == (*op == BinOp::Eq)))
+        }
+        ExprKind::Binary {
+            op: op @ (BinOp::Lt | BinOp::Gt | BinOp::LtEq | BinOp::GtEq),
+            lhs: a,
+            rhs: b,
+        } => {
+            let ord = compare(&eval(a, env)?, &eval(b, env)?, &expr.span)?;
+            Ok(Value::Bool(match op {
+                BinOp::Lt => ord == Some(Less),
+                BinOp::Gt => ord == Some(Greater),
+                BinOp::LtEq => matches!(ord, Some(Less | Equal)),
+                _ => matches!(ord, Some(Greater | Equal)),
+            }))
+        }
+
+        // The right-hand side is only evaluated if the left doesn't decide
+        // the result.
+        ExprKind::Binary {
+            op: op @ (BinOp::And | BinOp::Or),
+            lhs: a,
+            rhs: b,
+        } => {
+            let lhs = expect_bool(&eval(a, env)?, &a.span)?;
+            if lhs == (*op == BinOp::Or) {
+                return Ok(Value::Bool(lhs));
+            }
+            Ok(Value::Bool(expect_bool(&eval(b, env)?, &b.span)?))
+        }
+
+        ExprKind::List(items) => {
+            env.allocate(items.len(), &expr.span)?;
+            let items = items
+                .iter()
+                .map(|item| eval(item, env))
+                .collect::<Result<_, _>>()?;
+            Ok(Value::List(Rc::new(RefCell::new(items))))
+        }
+        ExprKind::Map(entries) => {
+            env.allocate(entries.len(), &expr.span)?;
+            let mut map: Vec<(String, Value)> = Vec::new();
+            for (k, v) in entries {
+                let key = eval(k, env)?.as_str(&k.span)?.to_string();
+                let v = eval(v, env)?;
+                match map.iter_mut().find(|(existing, _)| *existing == key) {
+                    Some(entry) => entry.1 = v,
+                    None => map.push((key, v)),
+                }
+            }
+            Ok(Value::Map(Rc::new(RefCell::new(map))))
+        }
+        ExprKind::Index(target, index) => {
+            let target = eval(target, env)?;
+            let key = eval(index, env)?;
+            let found = match &target {
+                Value::List(xs) => {
+                    let xs = xs.borrow();
+                    key.as_index(xs.len(), &index.span).map(|i| xs[i].clone())
+                }
+                Value::Str(s) => {
+                    let len = s.chars().count();
+                    key.index_into(len, "string", &index.span)
+                        .map(|i| Value::Str(s.chars().nth(i).unwrap().to_string()))
+                }
+                Value::Map(entries) => {
+                    let k = key.as_str(&index.span)?;
+                    let entries = entries.borrow();
+                    let found = entries.iter().find(|(key, _)| key == k);
+                    Ok(Value::Optional(found.map(|(_, v)| Box::new(v.clone()))))
+                }
+                v => Err(EvalError::new(
+                    ErrorKind::Type,
+                    format!("Type error: cannot index into {}", v.type_name()),
+                    &expr.span,
+                )),
+            };
+            found.or_else(|err| env.recover(err, Value::Num(f64::NAN)))
+        }
+        ExprKind::IndexAssign {
+            target,
+            index,
+            value,
+        } => {
+            let target = eval(target, env)?;
+            let key = eval(index, env)?;
+            let value = eval(value, env)?;
+            match &target {
+                Value::List(xs) => {
+                    let mut xs = xs.borrow_mut();
+                    match key.as_index(xs.len(), &index.span) {
+                        Ok(i) => xs[i] = value.clone(),
+                        Err(err) => return env.recover(err, Value::Num(f64::NAN)),
+                    }
+                }
+                Value::Map(entries) => {
+                    let k = key.as_str(&index.span)?;
+                    let mut entries = entries.borrow_mut();
+                    match entries.iter_mut().find(|(key, _)| key == k) {
+                        Some(entry) => entry.1 = value.clone(),
+                        None => entries.push((k.to_string(), value.clone())),
+                    }
+                }
+                v => {
+                    return Err(EvalError::new(
+                        ErrorKind::Type,
+                        format!("Type error: cannot index into {}", v.type_name()),
+                        &expr.span,
+                    ))
+                }
+            }
+            Ok(value)
+        }
+
+        ExprKind::Var(name) => {
+            // The resolver declares variables as evaluation does, so the
+            // slot holds the innermost variable of this name.
+            let binding = match env.slots.get(expr) {
+                Some(slot) => {
+                    let i = env.vars.len() - 1 - slot;
+                    debug_assert_eq!(
+                        Some(i),
+                        env.vars.iter().rposition(|(var, _)| *var == name),
+                        "`{name}` resolved to another variable"
+                    );
+                    Some(env.vars[i].1.clone())
+                }
+                None => lookup(env, name),
+            };
+            if let Some(binding) = binding {
+                force(&binding, env)
+            } else if let Some(function) = env.function_value(name) {
+                Ok(function)
+            } else {
+                Err(EvalError::new(
+                    ErrorKind::UnboundVariable,
+                    format!("Cannot find variable `{name}` in scope"),
+                    &expr.span,
+                ))
+            }
+        }
+        ExprKind::Block(chain) => {
+            // Every function of the block is in scope from the start. Called
+            // before its declaration, a function sees the variables in scope
+            // at the start of the block.
+            let depth = env.fns.len();
+            declare_block(chain, env);
+            let output = eval(chain, env);
+            env.fns.truncate(depth);
+            output
+        }
+        ExprKind::Let {
+            name,
+            lazy,
+            rhs,
+            then,
+            ..
+        } => {
+            let rhs = bind(*lazy, rhs, env)?;
+            env.vars.push((name, rhs));
+            env.log_scope(true, [name], &expr.span);
+            let output = eval(then, env);
+            env.log_scope(false, [name], &expr.span);
+            env.vars.pop();
+            output
+        }
+
+        ExprKind::Call(name, args) => {
+            #[cfg(feature = "trace")]
+            tracing::trace!(name = %name, args = args.len(), "call");
+            let callee_span = expr.span.start..expr.span.start + name.chars().count();
+            // Calls with spread arguments go through a function value, which
+            // checks the arity once the arguments are evaluated.
+            if has_spread(args) {
+                let callee = match env.function_value(name) {
+                    Some(callee) => callee,
+                    None => match lookup(env, name) {
+                        Some(callee) => force(&callee, env)?,
+                        None => {
+                            return Err(EvalError::new(
+                                ErrorKind::UnknownFunction,
+                                format!("Cannot find function `{name}` in scope"),
+                                &expr.span,
+                            ))
+                        }
+                    },
+                };
+                return apply(callee, &callee_span, args, env, &expr.span);
+            }
+            if env.curry
+                && env
+                    .arity_of(name)
+                    .is_some_and(|a| under_applied(a, args.len()))
+            {
+                let callee = env.function_value(name).unwrap();
+                return apply(callee, &callee_span, args, env, &expr.span);
+            }
+            let builtin = env.builtin(name);
+            // With `builtins_first`, a built-in hides functions of the same name.
+            let hidden = env.builtins_first && builtin.is_some();
+            let function = env.fns.iter().rev().find(|f| f.name == name);
+            let Some(function) = function.filter(|_| !hidden).cloned() else {
+                let native = env.natives.iter().find(|n| n.name == *name);
+                if let Some(native) = native.filter(|_| !hidden).cloned() {
+                    reject_named_args(&format!("native function `{name}`"), args)?;
+                    check_arity(name, Arity::Exact(native.arity), args, &expr.span)?;
+                    let args = eval_args(args, env)?;
+                    return call_native(&native, &args, &expr.span);
+                }
+                if let Some(builtin) = builtin {
+                    reject_named_args(&format!("built-in `{name}`"), args)?;
+                    check_arity(name, builtin.arity, args, &expr.span)?;
+                    let args = eval_args(args, env)?;
+                    return call_builtin(builtin, env, &args, &expr.span);
+                }
+                // A variable holding a function value, like a parameter.
+                let Some(callee) = lookup(env, name) else {
+                    return Err(EvalError::new(
+                        ErrorKind::UnknownFunction,
+                        format!("Cannot find function `{name}` in scope"),
+                        &expr.span,
+                    ));
+                };
+                let callee = force(&callee, env)?;
+                return apply(callee, &callee_span, args, env, &expr.span);
+            };
+
+            let params = function.params;
+            check_arity(name, Arity::Exact(params.len()), args, &expr.span)
+                .map_err(|err| err.with_secondary(&function.span, "defined here"))?;
+
+            let mut args_evaled = eval_params(name, params, args, env)?;
+            let mut vars = function.scope.to_vec();
+            vars.append(&mut args_evaled);
+            let caller_vars = std::mem::replace(&mut env.vars, vars);
+            env.log_scope(true, params, &expr.span);
+            let output = eval_body(&function, env).map_err(|err| err.in_call(name, &expr.span));
+            env.log_scope(false, params, &expr.span);
+            env.vars = caller_vars;
+            output
+        }
+
+        ExprKind::Apply(callee, args) => {
+            let callee_value = eval(callee, env)?;
+            apply(callee_value, &callee.span, args, env, &expr.span)
+        }
+        ExprKind::For { var, list, body } => {
+            let xs = match eval(list, env)? {
+                Value::List(xs) => xs.borrow().clone(),
+                v => return Err(v.type_error("list", &list.span)),
+            };
+            env.allocate(xs.len(), &expr.span)?;
+            let mut ys = Vec::with_capacity(xs.len());
+            for x in xs {
+                env.vars.push((var, Binding::Value(x)));
+                env.log_scope(true, [var], &expr.span);
+                let y = eval(body, env);
+                env.log_scope(false, [var], &expr.span);
+                env.vars.pop();
+                ys.push(y?);
+            }
+            Ok(Value::List(Rc::new(RefCell::new(ys))))
+        }
+        ExprKind::IfLet {
+            name,
+            value,
+            then,
+            otherwise,
+        } => match eval(value, env)? {
+            Value::Optional(Some(x)) => {
+                env.vars.push((name, Binding::Value(*x)));
+                env.log_scope(true, [name], &expr.span);
+                let output = eval(then, env);
+                env.log_scope(false, [name], &expr.span);
+                env.vars.pop();
+                output
+            }
+            Value::Optional(None) => eval(otherwise, env),
+            v => Err(v.type_error("optional", &value.span)),
+        },
+        ExprKind::Lambda { args, body } => {
+            let function = Function {
+                name: "",
+                params: args,
+                body,
+                scope: env.vars.as_slice().into(),
+                span: expr.span.clone(),
+            };
+            Ok(env.closure("", Arity::Exact(args.len()), Closure::Function(function)))
+        }
+
+        ExprKind::Fn { then, .. } => {
+            declare(expr, env.vars.as_slice().into(), env);
+            let names = fn_name(expr);
+            env.log_scope(true, names, &expr.span);
+            let output = eval(then, env);
+            env.log_scope(false, names, &expr.span);
+            env.fns.pop();
+            output
+        }
+    }
+}
+
+/// What the right-hand side `rhs` of a `let` binds its variable to.
+fn bind<'a>(lazy: bool, rhs: &'a Expr, env: &mut Env<'a>) -> Result<Binding<'a>, EvalError> {
+    if lazy {
+        return Ok(Binding::Lazy(Rc::new(Thunk {
+            expr: rhs,
+            scope: env.vars.as_slice().into(),
+            value: RefCell::new(None),
+        })));
+    }
+    Ok(Binding::Value(eval(rhs, env)?))
+}
+
+/// Brings the function declared by `decl`, if it is a `fn`, into scope.
+fn declare<'a>(decl: &'a Expr, scope: Scope<'a>, env: &mut Env<'a>) {
+    if let ExprKind::Fn {
+        name, args, body, ..
+    } = &decl.kind
+    {
+        env.fns.push(Function {
+            name,
+            params: args,
+            body,
+            scope,
+            span: decl.span.clone(),
+        });
+    }
+}
+
+/// The name of the function declared by `decl`, if it is a `fn`.
+fn fn_name(decl: &Expr) -> Option<&String> {
+    match &decl.kind {
+        ExprKind::Fn { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+/// Brings every function of the block `chain` into scope. Called before its
+/// declaration, a function sees the variables in scope at the start of the
+/// block.
+fn declare_block<'a>(chain: &'a Expr, env: &mut Env<'a>) {
+    let scope: Scope = env.vars.as_slice().into();
+    for decl in chain.declarations() {
+        declare(decl, scope.clone(), env);
+    }
+}
+
+/// Evaluates the arguments of a call to the function `name`, each bound to
+/// its parameter, in the order of the parameters.
+fn eval_params<'a>(
+    name: &str,
+    params: &'a [String],
+    args: &'a [Expr],
+    env: &mut Env<'a>,
+) -> Result<Vec<(&'a String, Binding<'a>)>, EvalError> {
+    let mut bound = Vec::with_capacity(args.len());
+    for (param, arg) in bind_args(name, params, args)? {
+        bound.push((param, Binding::Value(eval(arg, env)?)));
+    }
+    // Named arguments can come in any order, but variables must be in scope
+    // in the order `resolve` expects.
+    bound.sort_by_key(|(param, _)| *param);
+    Ok(bound
+        .into_iter()
+        .map(|(param, binding)| (&params[param], binding))
+        .collect())
+}
+
+/// What is left of a function body evaluated by [`eval_step`].
+enum Step<'a> {
+    Done(Value),
+    /// The body ended with a call to its own function, with these arguments.
+    Again(Vec<(&'a String, Binding<'a>)>),
+}
+
+/// Evaluates the body of `function`, whose parameters are in scope, as a
+/// loop rather than a recursion over the calls to itself that compute its
+/// result. So an accumulator-style function like
+/// `fn count n acc = { let _ = acc[0] := acc[0] + n; n < 1 or count(n - 1, acc) }`
+/// runs in constant stack however large `n` is.
+///
+/// This is a heuristic, and only finds such calls where nothing but a
+/// `let`, a `fn` or a block declaring them, or an `and` or `or` (whose
+/// operand must then be a boolean) stands between the call and the result.
+/// Anything else, like `1 + count(n - 1)`, or a call through a function
+/// value or to a function shadowing this one, is evaluated recursively, as
+/// is every call while debugging or recording values or scopes, which need
+/// each node.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+/// use kyomusky::stack;
+///
+/// let src = "fn sum n acc = { let _ = acc[0] := acc[0] + n; n < 1 or sum(n - 1, acc) }; \
+///            let acc = [0]; let _ = sum(100000, acc); acc[0]";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let value = stack::with_stack(stack::MIN_SIZE, || {
+///     eval(&program[0], &mut Env::new()).map(|v| v.to_string()).map_err(|e| e.message)
+/// });
+/// assert_eq!(value, Ok("5000050000".to_string()));
+///
+/// // The operand of `or` must still be a boolean.
+/// let src = "fn f n = n < 1 or { let _ = n; f(n - 1) }; fn g n = n > 0 and f(n); g(2)";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// assert_eq!(eval(&program[0], &mut Env::new()).unwrap().to_string(), "true");
+/// ```
+fn eval_body<'a>(function: &Function<'a>, env: &mut Env<'a>) -> Result<Value, EvalError> {
+    if env.debugger.is_some() || env.values.is_some() || env.scopes.is_some() {
+        return eval(function.body, env);
+    }
+    // The `and` or `or` operand the last call left the result of, which
+    // must be a boolean, like every such operand the calls before left.
+    let mut operand = None;
+    loop {
+        match eval_step(function, function.body, &mut operand, env)? {
+            Step::Done(value) => {
+                return match operand {
+                    Some(span) => Ok(Value::Bool(expect_bool(&value, &span)?)),
+                    None => Ok(value),
+                }
+            }
+            Step::Again(mut args) => {
+                let mut vars = function.scope.to_vec();
+                vars.append(&mut args);
+                env.vars = vars;
+            }
+        }
+    }
+}
+
+/// Evaluates `expr`, in the body of `function`, up to a call to `function`
+/// whose result is the result of `expr`. See [`eval_body`].
+fn eval_step<'a>(
+    function: &Function<'a>,
+    expr: &'a Expr,
+    operand: &mut Option<Span>,
+    env: &mut Env<'a>,
+) -> Result<Step<'a>, EvalError> {
+    if stack::exhausted() {
+        return eval(expr, env).map(Step::Done);
+    }
+    match &expr.kind {
+        ExprKind::Call(name, args) if calls_itself(function, name, args, env) => {
+            #[cfg(feature = "trace")]
+            tracing::trace!(name = %name, args = args.len(), "call");
+            Ok(Step::Again(eval_params(name, function.params, args, env)?))
+        }
+        ExprKind::Let {
+            name,
+            lazy,
+            rhs,
+            then,
+            ..
+        } => {
+            let rhs = bind(*lazy, rhs, env)?;
+            env.vars.push((name, rhs));
+            let step = eval_step(function, then, operand, env);
+            env.vars.pop();
+            step
+        }
+        ExprKind::Fn { then, .. } => {
+            declare(expr, env.vars.as_slice().into(), env);
+            let step = eval_step(function, then, operand, env);
+            env.fns.pop();
+            step
+        }
+        ExprKind::Block(chain) => {
+            let depth = env.fns.len();
+            declare_block(chain, env);
+            let step = eval_step(function, chain, operand, env);
+            env.fns.truncate(depth);
+            step
+        }
+        ExprKind::IfLet {
+            name,
+            value,
+            then,
+            otherwise,
+        } => match eval(value, env)? {
+            Value::Optional(Some(x)) => {
+                env.vars.push((name, Binding::Value(*x)));
+                let step = eval_step(function, then, operand, env);
+                env.vars.pop();
+                step
+            }
+            Value::Optional(None) => eval_step(function, otherwise, operand, env),
+            v => Err(v.type_error("optional", &value.span)),
+        },
+        ExprKind::Binary {
+            op: op @ (BinOp::And | BinOp::Or),
+            lhs: a,
+            rhs: b,
+        } => {
+            let lhs = expect_bool(&eval(a, env)?, &a.span)?;
+            if lhs == (*op == BinOp::Or) {
+                return Ok(Step::Done(Value::Bool(lhs)));
+            }
+            match eval_step(function, b, operand, env)? {
+                Step::Done(value) => {
+                    Ok(Step::Done(Value::Bool(expect_bool(&value, &b.span)?)))
+                }
+                Step::Again(args) => {
+                    *operand = Some(b.span.clone());
+                    Ok(Step::Again(args))
+                }
+            }
+        }
+        _ => eval(expr, env).map(Step::Done),
+    }
+}
+
+/// Whether the call `name(args)` in the body of `function` calls `function`
+/// itself with all of its parameters, as the `Call` arm of [`eval_node`]
+/// would resolve it.
+fn calls_itself(function: &Function, name: &str, args: &[Expr], env: &Env) -> bool {
+    name == function.name
+        && args.len() == function.params.len()
+        && !has_spread(args)
+        && !(env.builtins_first && env.builtin(name).is_some())
+        && env
+            .fns
+            .iter()
+            .rev()
+            .find(|f| f.name == name)
+            .is_some_and(|f| std::ptr::eq(f.body, function.body))
+}
+
+/// Matches the arguments of a call to the parameters of the function: each
+/// argument, in evaluation order, with the index of the parameter it binds.
+/// Positional arguments fill parameters from the left, and named arguments
+/// the remaining ones. The number of arguments must already be checked.
+fn bind_args<'e>(
+    name: &str,
+    params: &[String],
+    args: &'e [Expr],
+) -> Result<Vec<(usize, &'e Expr)>, EvalError> {
+    let mut bound: Vec<(usize, &Expr)> = Vec::with_capacity(args.len());
+    let mut named = false;
+    for (i, arg) in args.iter().enumerate() {
+        let ExprKind::NamedArg(arg_name, value) = &arg.kind else {
+            if named {
+                return Err(EvalError::new(
+                    ErrorKind::Arity,
+                    "Positional arguments must come before named arguments",
+                    &arg.span,
+                ));
+            }
+            bound.push((i, arg));
+            continue;
+        };
+        named = true;
+        let Some(param) = params.iter().position(|p| p == arg_name) else {
+            return Err(EvalError::new(
+                ErrorKind::Arity,
+                format!("Function `{name}` has no parameter named `{arg_name}`"),
+                &arg.span,
+            ));
+        };
+        if bound.iter().any(|(p, _)| *p == param) {
+            return Err(EvalError::new(
+                ErrorKind::Arity,
+                format!("Parameter `{arg_name}` of function `{name}` is given twice"),
+                &arg.span,
+            ));
+        }
+        bound.push((param, value));
+    }
+    Ok(bound)
+}
+
+fn call_native(native: &Native, args: &Args, span: &Span) -> Result<Value, EvalError> {
+    let args = args
+        .iter()
+        .map(|(arg, span)| arg.as_num(span))
+        .collect::<Result<Vec<_>, _>>()?;
+    (native.call)(&args).map(Value::Num).map_err(|message| {
+        EvalError::new(
+            ErrorKind::Native,
+            format!("`{}` failed: {message}", native.name),
+            span,
+        )
+    })
+}
+
+/// Calls `callee`, the value of the expression at `callee_span`, with the
+/// arguments of the call at `span`.
+fn apply<'a>(
+    callee: Value,
+    callee_span: &Span,
+    args: &'a [Expr],
+    env: &mut Env<'a>,
+    span: &Span,
+) -> Result<Value, EvalError> {
+    let Value::Function(function) = callee else {
+        return Err(EvalError::new(
+            ErrorKind::Type,
+            format!("Type error: cannot call {}", callee.type_name()),
+            callee_span,
+        ));
+    };
+    reject_named_args("a function value", args)?;
+    let curry = env.curry;
+    // Whether `n` arguments make a partial application, if they are accepted.
+    let check = |n| {
+        let partial = curry && under_applied(function.arity, n);
+        if !function.arity.accepts(n) && !partial {
+            let args_span = callee_span.end..span.end;
+            return Err(arity_error(&function.what(), function.arity, n, &args_span));
+        }
+        Ok(partial)
+    };
+    // Without spread arguments, the arity is checked before evaluating any.
+    if !has_spread(args) {
+        check(args.len())?;
+    }
+    let args = eval_args(args, env)?;
+    let partial = check(args.len())?;
+    if partial {
+        return Ok(Value::Function(Rc::new(FunctionValue::partial(
+            function, args,
+        ))));
+    }
+    call_function(env, &function, &args, span)
+}
+
+/// A built-in as a function value.
+fn builtin_value(builtin: &'static Builtin) -> Value {
+    Value::Function(Rc::new(FunctionValue {
+        name: builtin.name.to_string(),
+        arity: builtin.arity,
+        callee: Callee::Builtin(builtin),
+    }))
+}
+
+/// Calls a built-in with evaluated arguments, for the call at `span`.
+fn call_builtin(
+    builtin: &Builtin,
+    env: &mut Env,
+    args: &Args,
+    span: &Span,
+) -> Result<Value, EvalError> {
+    if env.deterministic && !builtin.deterministic {
+        return Err(EvalError::new(
+            ErrorKind::Nondeterministic,
+            format!(
+                "`{}` is not deterministic, but evaluation must be",
+                builtin.name
+            ),
+            span,
+        ));
+    }
+    (builtin.call)(env, args, span)
+}
+
+/// Calls a function value with evaluated arguments, for the call at `span`.
+pub(crate) fn call_function(
+    env: &mut Env,
+    function: &FunctionValue,
+    args: &Args,
+    span: &Span,
+) -> Result<Value, EvalError> {
+    if !function.arity.accepts(args.len()) {
+        return Err(arity_error(
+            &function.what(),
+            function.arity,
+            args.len(),
+            span,
+        ));
+    }
+    let closure = match &function.callee {
+        Callee::Builtin(builtin) => return call_builtin(builtin, env, args, span),
+        Callee::Compose(f, g) => {
+            let inner = call_function(env, g, args, span)?;
+            return call_function(env, f, &[(inner, span.clone())], span);
+        }
+        Callee::Partial(f, given) => {
+            let args: Vec<_> = given.iter().chain(args).cloned().collect();
+            return call_function(env, f, &args, span);
+        }
+        Callee::Closure(i) => env.closures.get(*i).cloned(),
+    };
+    match closure {
+        Some(Closure::Function(function)) => {
+            let mut vars = function.scope.to_vec();
+            vars.extend(
+                function
+                    .params
+                    .iter()
+                    .zip(args)
+                    .map(|(p, (v, _))| (p, Binding::Value(v.clone()))),
+            );
+            let caller_vars = std::mem::replace(&mut env.vars, vars);
+            env.log_scope(true, function.params, span);
+            let output = eval_body(&function, env).map_err(|err| err.in_call(function.name, span));
+            env.log_scope(false, function.params, span);
+            env.vars = caller_vars;
+            output
+        }
+        Some(Closure::Native(native)) => call_native(&native, args, span),
+        None => Err(EvalError::new(
+            ErrorKind::Type,
+            format!(
+                "Type error: cannot call {} outside of its evaluation",
+                function.what()
+            ),
+            span,
+        )),
+    }
+}
+
+/// Whether `n` arguments are too few for a function of `arity`, and can be
+/// partially applied.
+fn under_applied(arity: Arity, n: usize) -> bool {
+    matches!(arity, Arity::Exact(m) if n < m)
+}
+
+/// Evaluates the arguments of a call, spreading the elements of `...list`
+/// arguments, which all get the span of the spread.
+fn eval_args<'a>(args: &'a [Expr], env: &mut Env<'a>) -> Result<Vec<(Value, Span)>, EvalError> {
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        let ExprKind::Spread(list) = &arg.kind else {
+            values.push((eval(arg, env)?, arg.span.clone()));
+            continue;
+        };
+        match eval(list, env)? {
+            Value::List(xs) => {
+                let xs = xs.borrow();
+                values.extend(xs.iter().map(|x| (x.clone(), arg.span.clone())));
+            }
+            v => return Err(v.type_error("list", &list.span)),
+        }
+    }
+    Ok(values)
+}
+
+fn has_spread(args: &[Expr]) -> bool {
+    args.iter()
+        .any(|arg| matches!(arg.kind, ExprKind::Spread(_)))
+}
+
+/// Fails at the first named argument of a call to `what`, which only takes
+/// positional arguments.
+fn reject_named_args(what: &str, args: &[Expr]) -> Result<(), EvalError> {
+    match args
+        .iter()
+        .find(|arg| matches!(arg.kind, ExprKind::NamedArg(..)))
+    {
+        Some(arg) => Err(EvalError::new(
+            ErrorKind::Arity,
+            format!("{what} does not support named arguments"),
+            &arg.span,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Fails unless `args`, passed in the call at `call`, are as many as
+/// `expected`. The error points at the argument list.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env, ErrorKind};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let src = "fn add x y = x + y; add(1)";
+/// let program = parse_program(src, ParseOptions::default()).unwrap();
+/// let err = eval(&program[0], &mut Env::new()).unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::Arity);
+/// assert_eq!(&src[err.span], "(1)");
+/// let (defined, label) = err.secondary.unwrap();
+/// assert_eq!(&src[defined], "fn add x y = x + y;");
+/// assert_eq!(label, "defined here");
+/// ```
+fn check_arity(name: &str, expected: Arity, args: &[Expr], call: &Span) -> Result<(), EvalError> {
+    let found = args.len();
+    if expected.accepts(found) {
+        return Ok(());
+    }
+    // A method call `x.f(..)` starts with its first argument, so there is
+    // no telling where the argument list starts.
+    let span = match args.first() {
+        Some(receiver) if receiver.span.start == call.start => call.clone(),
+        _ => call.start + name.chars().count()..call.end,
+    };
+    Err(arity_error(
+        &format!("function `{name}`"),
+        expected,
+        found,
+        &span,
+    ))
+}
+
+fn arity_error(what: &str, expected: Arity, found: usize, span: &Span) -> EvalError {
+    EvalError::new(
+        ErrorKind::Arity,
+        format!("Wrong number of arguments for {what}: expected {expected}, found {found}"),
+        span,
+    )
+}
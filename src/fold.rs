@@ -0,0 +1,61 @@
+//! Constant folding: arithmetic on number literals is computed ahead of
+//! evaluation, e.g. `x * (2 + 3)` becomes `x * 5`.
+
+use crate::ast::{map_expr, BinOp, Expr, ExprKind, UnaryOp};
+use crate::desugar::desugar;
+
+/// Folds every operation whose operands are literals. Operations that would
+/// produce an infinity or NaN are kept, so that evaluating them still reports
+/// the error, as are results too large to be exact with the `bigint` feature.
+pub fn fold_constants(expr: Expr) -> Expr {
+    map_expr(expr, fold_node)
+}
+
+/// The whole front end after parsing: sugar is removed first, so that
+/// constants it exposes are folded too.
+///
+/// ```
+/// use kyomusky::fold::lower;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let program = parse_program("1 + 2 |> double", ParseOptions::default()).unwrap();
+/// let ir = lower(program[0].clone());
+/// assert_eq!(format!("{ir:?}"), r#"Call("double", [Num(3.0)])"#);
+/// ```
+pub fn lower(expr: Expr) -> Expr {
+    fold_constants(desugar(expr))
+}
+
+/// Integers up to this magnitude are represented exactly by an `f64`.
+const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+fn fold_node(expr: Expr) -> Expr {
+    let num = |e: &Expr| match e.kind {
+        ExprKind::Num(x) => Some(x),
+        _ => None,
+    };
+    let result = match &expr.kind {
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => num(operand).map(|x| -x),
+        ExprKind::Binary { op, lhs, rhs } => match (num(lhs), num(rhs)) {
+            (Some(x), Some(y)) => match op {
+                BinOp::Add => Some(x + y),
+                BinOp::Sub => Some(x - y),
+                BinOp::Mul => Some(x * y),
+                BinOp::Div => Some(x / y),
+                BinOp::Pow => Some(x.powf(y)),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    match result {
+        Some(x) if x.is_finite() && !(cfg!(feature = "bigint") && x.abs() >= MAX_EXACT) => {
+            Expr::new(ExprKind::Num(x), expr.span)
+        }
+        _ => expr,
+    }
+}
@@ -0,0 +1,75 @@
+//! A record of the grammar branches the parser tries, for `--grammar-trace`.
+//!
+//! Only the alternatives of an atom are traced, as that is where most
+//! ambiguities are resolved. Recording is off unless enabled with
+//! [`record`], and costs a check of a thread-local flag per branch tried.
+
+use crate::ast::Span;
+use chumsky::prelude::*;
+use std::cell::RefCell;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The parser started trying `branch` at a character offset.
+    Try(&'static str, usize),
+    /// `branch` matched the span. A branch tried without matching failed.
+    Match(&'static str, Span),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Try(branch, at) => write!(f, "try {branch} at {at}"),
+            Event::Match(branch, span) => write!(f, "matched {branch} at {span:?}"),
+        }
+    }
+}
+
+thread_local! {
+    static EVENTS: RefCell<Option<Vec<Event>>> = const { RefCell::new(None) };
+}
+
+fn push(event: Event) {
+    EVENTS.with_borrow_mut(|events| {
+        if let Some(events) = events {
+            events.push(event);
+        }
+    });
+}
+
+/// Runs `f`, returning its result and the branches tried by parsers it ran
+/// on this thread.
+///
+/// ```
+/// use kyomusky::grammar_trace::{record, Event};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let (parsed, events) = record(|| parse_program("f(1)", ParseOptions::default()));
+/// assert!(parsed.is_ok());
+/// // `f` could have been a variable, but is a call.
+/// assert!(events.contains(&Event::Try("call", 0)));
+/// assert!(events.contains(&Event::Match("call", 0..4)));
+/// assert!(events.contains(&Event::Match("number", 2..3)));
+/// assert!(!events.iter().any(|e| matches!(e, Event::Match("variable", _))));
+/// ```
+pub fn record<T>(f: impl FnOnce() -> T) -> (T, Vec<Event>) {
+    let outer = EVENTS.replace(Some(Vec::new()));
+    let result = f();
+    let events = EVENTS.replace(outer).unwrap_or_default();
+    (result, events)
+}
+
+/// Wraps `parser` to record trying and matching it as `branch`.
+pub(crate) fn traced<O>(
+    branch: &'static str,
+    parser: impl Parser<char, O, Error = Simple<char>> + Clone,
+) -> impl Parser<char, O, Error = Simple<char>> + Clone {
+    empty()
+        .map_with_span(move |(), span: Span| push(Event::Try(branch, span.start)))
+        .ignore_then(parser)
+        .map_with_span(move |output, span: Span| {
+            push(Event::Match(branch, span));
+            output
+        })
+}
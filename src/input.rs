@@ -0,0 +1,57 @@
+//! Sources of the values read by the `input()` builtin.
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+pub trait Input {
+    /// The next whitespace-separated token, or `None` once the input is
+    /// exhausted.
+    fn next_token(&mut self) -> Option<String>;
+}
+
+/// Tokens given up front, e.g. with `--input-data`.
+pub struct DataInput {
+    tokens: VecDeque<String>,
+}
+
+impl DataInput {
+    pub fn new(data: &str) -> Self {
+        Self {
+            tokens: data.split_whitespace().map(str::to_string).collect(),
+        }
+    }
+}
+
+impl Input for DataInput {
+    fn next_token(&mut self) -> Option<String> {
+        self.tokens.pop_front()
+    }
+}
+
+/// Reads standard input a line at a time, only when a token is needed, so
+/// that prompts can be answered interactively.
+#[derive(Default)]
+pub struct StdinInput {
+    pending: DataInput,
+}
+
+impl Default for DataInput {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl Input for StdinInput {
+    fn next_token(&mut self) -> Option<String> {
+        loop {
+            if let Some(token) = self.pending.next_token() {
+                return Some(token);
+            }
+            let mut line = String::new();
+            match std::io::stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => self.pending = DataInput::new(&line),
+            }
+        }
+    }
+}
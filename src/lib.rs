@@ -0,0 +1,32 @@
+pub mod analysis;
+pub mod ast;
+pub mod bench;
+#[cfg(feature = "bigint")]
+pub mod bigint;
+pub mod build;
+pub mod builtins;
+pub mod cache;
+pub mod desugar;
+pub mod dot;
+pub mod emit;
+pub mod encoding;
+pub mod eval;
+pub mod fold;
+pub mod grammar_trace;
+pub mod input;
+pub mod json;
+pub mod lint;
+pub mod lsp;
+pub mod memo;
+pub mod number;
+pub mod parser;
+pub mod prelude;
+pub mod resolve;
+pub mod rng;
+pub mod run;
+pub mod stack;
+pub mod stats;
+pub mod step;
+pub mod theme;
+pub mod transpile;
+pub mod visit;
@@ -0,0 +1,294 @@
+//! The registry of warnings the analysis can produce, and the levels they
+//! are reported at.
+//!
+//! Levels are set on the command line like rustc's: `-A name` (allow),
+//! `-W name` (warn) and `-D name` (deny, i.e. report as an error), where
+//! `name` is a lint or `warnings` for every lint that would otherwise warn.
+
+use crate::analysis::{
+    deprecated_uses, redefined_functions, shadowed_variables, unused_variables, Diagnostic,
+    Severity,
+};
+use crate::ast::{Expr, ExprKind, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// A kind of warning, identified by a stable name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// A `let` whose value is never read. Names starting with `_` are
+    /// exempt.
+    UnusedVariable,
+    /// A `let` of a variable already declared in the same declaration chain.
+    /// Names starting with `_` are exempt. Allowed unless `--strict`.
+    ShadowedVariable,
+    /// A `fn` of a function already declared in the same declaration chain.
+    /// Allowed unless `--strict`.
+    RedefinedFunction,
+    /// A use of a variable or function declared with a
+    /// `@deprecated("message")` attribute, which still works as usual.
+    ///
+    /// ```
+    /// use kyomusky::analysis::Severity;
+    /// use kyomusky::lint::{check, Lint, LintLevels};
+    /// use kyomusky::parser::{parse_program, ParseOptions};
+    ///
+    /// let src = "@deprecated(\"use g instead\") fn f x = x;\nfn g x = x;\nf(1) + g(2)";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let diagnostics = check(&program, &LintLevels::default());
+    /// assert_eq!(diagnostics.len(), 1);
+    /// let d = &diagnostics[0];
+    /// assert_eq!(d.lint, Some(Lint::Deprecated));
+    /// assert_eq!(d.severity, Severity::Warning);
+    /// assert_eq!(d.message, "Function `f` is deprecated: use g instead");
+    /// assert_eq!(&src[d.span.clone()], "f");
+    /// let (declared, label) = d.secondary.clone().unwrap();
+    /// assert_eq!(&src[declared], "@deprecated(\"use g instead\") fn f x = ");
+    /// assert_eq!(label, "deprecated here");
+    ///
+    /// // Variables too, and the attribute changes nothing else.
+    /// let src = "@deprecated(\"\") let x = 1; let y = 2; x + y";
+    /// let program = parse_program(src, ParseOptions::default()).unwrap();
+    /// let diagnostics = check(&program, &LintLevels::default());
+    /// assert_eq!(diagnostics[0].message, "Variable `x` is deprecated");
+    /// assert_eq!(diagnostics[0].span, 38..39);
+    /// ```
+    Deprecated,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 4] = [
+        Lint::UnusedVariable,
+        Lint::ShadowedVariable,
+        Lint::RedefinedFunction,
+        Lint::Deprecated,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::ShadowedVariable => "shadowed-variable",
+            Lint::RedefinedFunction => "redefined-function",
+            Lint::Deprecated => "deprecated",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn default_level(self) -> LintLevel {
+        match self {
+            Lint::UnusedVariable | Lint::Deprecated => LintLevel::Warn,
+            Lint::ShadowedVariable | Lint::RedefinedFunction => LintLevel::Allow,
+        }
+    }
+}
+
+/// The level of each lint, after command line overrides.
+#[derive(Debug, Clone, Default)]
+pub struct LintLevels {
+    overrides: Vec<(Lint, LintLevel)>,
+    /// The level given to `warnings`, which applies to lints that would
+    /// otherwise warn.
+    warnings: Option<LintLevel>,
+    /// Whether lints allowed by default warn, for `--strict`.
+    strict: bool,
+}
+
+impl LintLevels {
+    /// Sets the level of the lint called `name`, or of every warning if
+    /// `name` is `warnings`. Later calls take precedence.
+    pub fn set(&mut self, name: &str, level: LintLevel) -> Result<(), String> {
+        if name == "warnings" {
+            self.warnings = Some(level);
+            return Ok(());
+        }
+        let lint = Lint::from_name(name).ok_or_else(|| format!("Unknown lint `{name}`"))?;
+        self.overrides.retain(|(l, _)| *l != lint);
+        self.overrides.push((lint, level));
+        Ok(())
+    }
+
+    /// Makes the lints allowed by default warn, unless set otherwise.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        let default = match lint.default_level() {
+            LintLevel::Allow if self.strict => LintLevel::Warn,
+            level => level,
+        };
+        let level = self
+            .overrides
+            .iter()
+            .find(|(l, _)| *l == lint)
+            .map_or(default, |(_, level)| *level);
+        match (level, self.warnings) {
+            (LintLevel::Warn, Some(warnings)) => warnings,
+            _ => level,
+        }
+    }
+
+    /// Adds a diagnostic for `lint` at its level, unless it is allowed.
+    pub fn emit(
+        &self,
+        lint: Lint,
+        span: Span,
+        message: String,
+        secondary: Option<(Span, String)>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let severity = match self.level(lint) {
+            LintLevel::Allow => return,
+            LintLevel::Warn => Severity::Warning,
+            LintLevel::Deny => Severity::Error,
+        };
+        diagnostics.push(Diagnostic {
+            span,
+            severity,
+            message,
+            lint: Some(lint),
+            secondary,
+        });
+    }
+}
+
+/// Just `let name =` or `fn name args =`, with any attribute before, not the
+/// rest of the chain.
+fn let_head(decl: &Expr) -> Span {
+    match &decl.kind {
+        ExprKind::Let { rhs, .. } => decl.span.start..rhs.span.start,
+        ExprKind::Fn { body, .. } => decl.span.start..body.span.start,
+        _ => decl.span.clone(),
+    }
+}
+
+/// Runs every lint over the declaration chains of a program.
+///
+/// ```
+/// use kyomusky::analysis::Severity;
+/// use kyomusky::lint::{check, Lint, LintLevel, LintLevels};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+///
+/// let mut strict = LintLevels::default();
+/// strict.set_strict(true);
+/// let shadowed = |src| {
+///     let program = parse_program(src, ParseOptions::default()).unwrap();
+///     let diagnostics = check(&program, &strict);
+///     diagnostics
+///         .into_iter()
+///         .filter(|d| d.lint == Some(Lint::ShadowedVariable))
+///         .map(|d| (src[d.span].to_string(), src[d.secondary.unwrap().0].to_string()))
+///         .collect::<Vec<_>>()
+/// };
+///
+/// let same_scope = shadowed("let x = 1; let x = x + 1; x");
+/// assert_eq!(same_scope, [("let x = ".to_string(), "let x = ".to_string())]);
+/// assert_eq!(shadowed("let x = 1; let y = { let x = 2; x }; x + y"), []);
+///
+/// // What `--no-redefine` sets.
+/// let mut no_redefine = LintLevels::default();
+/// no_redefine.set("shadowed-variable", LintLevel::Deny).unwrap();
+/// no_redefine.set("redefined-function", LintLevel::Deny).unwrap();
+/// let errors = |src, levels: &LintLevels| {
+///     let program = parse_program(src, ParseOptions::default()).unwrap();
+///     let diagnostics = check(&program, levels);
+///     diagnostics
+///         .into_iter()
+///         .filter(|d| d.severity == Severity::Error)
+///         .map(|d| d.message)
+///         .collect::<Vec<_>>()
+/// };
+/// let src = "fn f x = x; fn f x = x + 1; let y = f(1); let y = 2; y";
+/// assert_eq!(errors(src, &LintLevels::default()), Vec::<String>::new());
+/// assert_eq!(
+///     errors(src, &no_redefine),
+///     [
+///         "Variable `y` shadows one declared earlier in the same scope",
+///         "Function `f` redefines one declared earlier in the same scope",
+///     ]
+/// );
+/// ```
+pub fn check(program: &[Expr], levels: &LintLevels) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for chain in program {
+        for decl in unused_variables(chain) {
+            let ExprKind::Let { name, .. } = &decl.kind else {
+                continue;
+            };
+            if name.starts_with('_') {
+                continue;
+            }
+            let message = format!("Unused variable `{name}`");
+            levels.emit(
+                Lint::UnusedVariable,
+                let_head(decl),
+                message,
+                None,
+                &mut diagnostics,
+            );
+        }
+        for (decl, earlier) in shadowed_variables(chain) {
+            let ExprKind::Let { name, .. } = &decl.kind else {
+                continue;
+            };
+            if name.starts_with('_') {
+                continue;
+            }
+            let message =
+                format!("Variable `{name}` shadows one declared earlier in the same scope");
+            let secondary = Some((let_head(earlier), "shadowed declaration".to_string()));
+            levels.emit(
+                Lint::ShadowedVariable,
+                let_head(decl),
+                message,
+                secondary,
+                &mut diagnostics,
+            );
+        }
+        for (decl, earlier) in redefined_functions(chain) {
+            let ExprKind::Fn { name, .. } = &decl.kind else {
+                continue;
+            };
+            let message =
+                format!("Function `{name}` redefines one declared earlier in the same scope");
+            let secondary = Some((let_head(earlier), "earlier definition".to_string()));
+            levels.emit(
+                Lint::RedefinedFunction,
+                let_head(decl),
+                message,
+                secondary,
+                &mut diagnostics,
+            );
+        }
+        for (span, decl) in deprecated_uses(chain) {
+            let (what, name, note) = match &decl.kind {
+                ExprKind::Let {
+                    name,
+                    deprecated: Some(note),
+                    ..
+                } => ("Variable", name, note),
+                ExprKind::Fn {
+                    name,
+                    deprecated: Some(note),
+                    ..
+                } => ("Function", name, note),
+                _ => continue,
+            };
+            let message = match note.as_str() {
+                "" => format!("{what} `{name}` is deprecated"),
+                note => format!("{what} `{name}` is deprecated: {note}"),
+            };
+            let secondary = Some((let_head(decl), "deprecated here".to_string()));
+            levels.emit(Lint::Deprecated, span, message, secondary, &mut diagnostics);
+        }
+    }
+    diagnostics
+}
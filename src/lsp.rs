@@ -0,0 +1,308 @@
+//! A minimal Language Server Protocol server speaking JSON-RPC over stdio.
+//!
+//! Supported requests are diagnostics on open/change, go to definition,
+//! document symbols (the declared functions) and hover over function names.
+
+use crate::analysis::{analyze, Severity};
+use crate::ast::Span;
+use crate::json::Json;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// Converts a character offset into an LSP `(line, character)` position,
+/// where `character` counts UTF-16 code units as the protocol requires.
+pub fn position(src: &str, offset: usize) -> (usize, usize) {
+    let mut line = 0;
+    let mut character = 0;
+    for c in src.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    (line, character)
+}
+
+/// The inverse of [`position`].
+pub fn offset(src: &str, line: usize, character: usize) -> usize {
+    let mut cur_line = 0;
+    let mut cur_character = 0;
+    for (i, c) in src.chars().enumerate() {
+        if cur_line == line && cur_character >= character || cur_line > line {
+            return i;
+        }
+        if c == '\n' {
+            cur_line += 1;
+            cur_character = 0;
+        } else {
+            cur_character += c.len_utf16();
+        }
+    }
+    src.chars().count()
+}
+
+fn range(src: &str, span: &Span) -> Json {
+    let pos = |offset| {
+        let (line, character) = position(src, offset);
+        Json::object([("line", line.into()), ("character", character.into())])
+    };
+    Json::object([("start", pos(span.start)), ("end", pos(span.end))])
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    exited: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the client has sent the `exit` notification.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Handles one incoming message, returning the messages to send back.
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let params = msg.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+
+        let result = match msg.get("method").as_str().unwrap_or("") {
+            "initialize" => Json::object([(
+                "capabilities",
+                Json::object([
+                    ("textDocumentSync", 1.0.into()),
+                    ("definitionProvider", true.into()),
+                    ("documentSymbolProvider", true.into()),
+                    ("hoverProvider", true.into()),
+                ]),
+            )]),
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.documents
+                    .insert(uri.to_string(), text.unwrap_or("").to_string());
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didChange" => {
+                // The server asks for full-document sync, so the last change
+                // holds the whole new text.
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                if let Some(text) = changes.last().and_then(|c| c.get("text").as_str()) {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![];
+            }
+            "textDocument/definition" => self.definition(uri, params.get("position")),
+            "textDocument/documentSymbol" => self.symbols(uri),
+            "textDocument/hover" => self.hover(uri, params.get("position")),
+            "shutdown" => Json::Null,
+            "exit" => {
+                self.exited = true;
+                return vec![];
+            }
+            // Notifications we don't care about need no reply.
+            _ if msg.get("id") == &Json::Null => return vec![],
+            method => {
+                return vec![Json::object([
+                    ("jsonrpc", "2.0".into()),
+                    ("id", msg.get("id").clone()),
+                    (
+                        "error",
+                        Json::object([
+                            ("code", (-32601.0).into()),
+                            ("message", format!("Unsupported method `{method}`").into()),
+                        ]),
+                    ),
+                ])];
+            }
+        };
+
+        vec![Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", msg.get("id").clone()),
+            ("result", result),
+        ])]
+    }
+
+    fn cursor(&self, uri: &str, pos: &Json) -> Option<(&str, usize)> {
+        let src = self.documents.get(uri)?;
+        let line = pos.get("line").as_f64()? as usize;
+        let character = pos.get("character").as_f64()? as usize;
+        Some((src, offset(src, line, character)))
+    }
+
+    fn diagnostics(&self, uri: &str) -> Json {
+        let src = self.documents.get(uri).map(String::as_str).unwrap_or("");
+        let diagnostics = analyze(src)
+            .diagnostics
+            .into_iter()
+            .map(|d| {
+                let severity = match d.severity {
+                    Severity::Error => 1.0,
+                    Severity::Warning => 2.0,
+                };
+                Json::object([
+                    ("range", range(src, &d.span)),
+                    ("severity", severity.into()),
+                    ("source", "kyomusky".into()),
+                    ("message", d.message.into()),
+                ])
+            })
+            .collect();
+
+        Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                Json::object([
+                    ("uri", uri.into()),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ])
+    }
+
+    fn definition(&self, uri: &str, pos: &Json) -> Json {
+        let Some((src, offset)) = self.cursor(uri, pos) else {
+            return Json::Null;
+        };
+        match analyze(src).definition_at(offset) {
+            Some(span) => Json::object([("uri", uri.into()), ("range", range(src, &span))]),
+            None => Json::Null,
+        }
+    }
+
+    fn symbols(&self, uri: &str) -> Json {
+        let Some(src) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        const FUNCTION: f64 = 12.0;
+        let symbols = analyze(src)
+            .functions
+            .into_iter()
+            .map(|f| {
+                Json::object([
+                    ("name", f.name.into()),
+                    ("detail", f.params.join(" ").into()),
+                    ("kind", FUNCTION.into()),
+                    ("range", range(src, &f.span)),
+                    ("selectionRange", range(src, &f.span)),
+                ])
+            })
+            .collect();
+        Json::Array(symbols)
+    }
+
+    fn hover(&self, uri: &str, pos: &Json) -> Json {
+        let Some((src, offset)) = self.cursor(uri, pos) else {
+            return Json::Null;
+        };
+        match analyze(src).function_at(offset) {
+            Some(f) => {
+                let signature = format!("fn {}({})", f.name, f.params.join(", "));
+                Json::object([(
+                    "contents",
+                    Json::object([("kind", "plaintext".into()), ("value", signature.into())]),
+                )])
+            }
+            None => Json::Null,
+        }
+    }
+}
+
+/// The largest message body read, in bytes. Larger bodies are skipped
+/// rather than buffered.
+const MAX_MESSAGE_LEN: usize = 64 << 20;
+
+/// Reads the next message, or `None` at the end of the input. A message that
+/// can't be read, because its header has no valid `Content-Length`, its body
+/// is over [`MAX_MESSAGE_LEN`] or isn't valid JSON, is returned as
+/// `Some(Err(..))` with the reason, so that the server can answer it and
+/// carry on. A body cut short by the end of the input is dropped.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Json, String>>> {
+    let mut len = None;
+    loop {
+        let mut line = Vec::new();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<u64>().ok();
+        }
+    }
+
+    let Some(len) = len else {
+        return Ok(Some(Err("Missing or invalid Content-Length".to_string())));
+    };
+    if len > MAX_MESSAGE_LEN as u64 {
+        let skipped = io::copy(&mut io::Read::take(&mut *input, len), &mut io::sink())?;
+        if skipped < len {
+            return Ok(None);
+        }
+        return Ok(Some(Err(format!(
+            "Message of {len} bytes exceeds the limit of {MAX_MESSAGE_LEN}"
+        ))));
+    }
+    let mut body = vec![0; len as usize];
+    match input.read_exact(&mut body) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let body = String::from_utf8_lossy(&body);
+    Ok(Some(Json::parse(&body)))
+}
+
+/// The JSON-RPC reply to a message that can't be read. Its `id` can't be
+/// known, so it is `null` as the specification requires.
+fn parse_error(message: String) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", Json::Null),
+        (
+            "error",
+            Json::object([("code", (-32700.0).into()), ("message", message.into())]),
+        ),
+    ])
+}
+
+fn write_message(output: &mut impl Write, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+/// Serves requests read from `input` until the client exits or closes the
+/// stream. Malformed messages are answered with a parse error rather than
+/// stopping the server.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(msg) = read_message(&mut input)? {
+        let replies = match msg {
+            Ok(msg) => server.handle(&msg),
+            Err(e) => vec![parse_error(e)],
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -1,211 +1,1265 @@
-use ariadne::{ColorGenerator, Label, Report, ReportKind, Source};
+use ariadne::{Label, Report, ReportKind, Source};
 use chumsky::prelude::*;
+use kyomusky::analysis::{Diagnostic, Severity};
+use kyomusky::ast::{Expr, Span};
+use kyomusky::bench::Timings;
+use kyomusky::cache::{ParseCache, ParseResult};
+use kyomusky::desugar::desugar;
+use kyomusky::dot;
+use kyomusky::emit;
+use kyomusky::encoding::{self, Encoding};
+use kyomusky::eval::{eval, eval_catching_panics, Env, ErrorKind, ErrorPolicy, EvalError};
+use kyomusky::fold::lower;
+use kyomusky::grammar_trace;
+use kyomusky::input::{DataInput, Input, StdinInput};
+use kyomusky::json::Json;
+use kyomusky::lint::{self, LintLevel, LintLevels};
+use kyomusky::number::{Notation, NumberFormat};
+use kyomusky::parser::{
+    blank_comments, error_message, is_incomplete, parse_prelude, parse_program, parse_with_spans,
+    ParseOptions,
+};
+use kyomusky::prelude::{self, prepend, PRELUDE};
+use kyomusky::rng::Rng;
+use kyomusky::stack;
+use kyomusky::stats::{ast_stats, Metrics};
+use kyomusky::step::Stepper;
+use kyomusky::theme::{self, Theme};
+use kyomusky::transpile;
 
-#[derive(Debug)]
-enum Expr {
-    Num(f64),
-    Var(String),
-
-    Neg(Box<Expr>),
-    Add(Box<Expr>, Box<Expr>),
-    Sub(Box<Expr>, Box<Expr>),
-    Mul(Box<Expr>, Box<Expr>),
-    Div(Box<Expr>, Box<Expr>),
-
-    Call(String, Vec<Expr>),
-    Let {
-        name: String,
-        rhs: Box<Expr>,
-        then: Box<Expr>,
-    },
-    Fn {
-        name: String,
-        args: Vec<String>,
-        body: Box<Expr>,
-        then: Box<Expr>,
-    },
-}
-
-fn parser() -> impl Parser<char, Expr, Error = Simple<char>> {
-    let ident = text::ident().padded();
-
-    let expr = recursive(|expr| {
-        let int = text::int(10)
-            .map(|s: String| Expr::Num(s.parse().unwrap()))
-            .padded();
-
-        let call = ident
-            .then(
-                expr.clone()
-                    .separated_by(just(','))
-                    .allow_trailing()
-                    .delimited_by(just('('), just(')')),
-            )
-            .map(|(f, args)| Expr::Call(f, args));
+const EMIT_FORMATS: &str =
+    "`--emit` expects `metrics`, `json`, `sexpr`, `dot`, `source` or `minified`";
 
-        let atom = int
-            .or(expr.delimited_by(just('('), just(')')))
-            .or(call)
-            .or(ident.map(Expr::Var));
+/// Number of diagnostics rendered when `--max-errors` is not given.
+const DEFAULT_MAX_ERRORS: usize = 20;
 
-        let op = |c| just(c).padded();
+/// Number of calls listed in the note of an evaluation error, innermost
+/// first.
+const MAX_NOTE_CALLS: usize = 10;
 
-        let unary = op('-')
-            .repeated()
-            .then(atom)
-            .foldr(|_op, rhs| Expr::Neg(Box::new(rhs)));
+/// Stack size for evaluation when `--stack-size` is not given.
+const DEFAULT_STACK_SIZE: usize = 8 << 20;
 
-        let mul = op('*').to(Expr::Mul as fn(_, _) -> _);
-        let div = op('/').to(Expr::Div as fn(_, _) -> _);
-        let add = op('+').to(Expr::Add as fn(_, _) -> _);
-        let sub = op('-').to(Expr::Sub as fn(_, _) -> _);
+/// Number of evaluations timed by `bench` when `--iters` is not given.
+const DEFAULT_BENCH_ITERS: usize = 100;
 
-        let product = unary
-            .clone()
-            .then(mul.or(div).then(unary).repeated())
-            .foldl(|lhs, (op, rhs)| op(Box::new(lhs), Box::new(rhs)));
+/// The levels accepted by `--log`, least verbose first.
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
 
-        let sum = product
-            .clone()
-            .then(add.or(sub).then(product).repeated())
-            .foldl(|lhs, (op, rhs)| op(Box::new(lhs), Box::new(rhs)));
+/// How often `--watch` checks whether the file was modified.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
 
-        sum
-    });
+struct Options {
+    path: String,
+    /// Maximum number of diagnostics to render; `0` means no limit.
+    max_errors: usize,
+    policy: ErrorPolicy,
+    output: OutputFormat,
+    #[cfg(feature = "trace")]
+    log: Option<tracing::Level>,
+    /// Print node counts and depth of the syntax tree before evaluating.
+    ast_stats: bool,
+    /// Print only the value of each declaration chain, without its syntax
+    /// tree.
+    quiet: bool,
+    /// Values for `input()` given with `--input-data` or `--input-file`,
+    /// instead of reading standard input.
+    input_data: Option<String>,
+    parse: ParseOptions,
+    /// Tolerance of `==` on numbers, see `Env::float_eps`.
+    float_eps: f64,
+    /// Disable built-in functions, leaving only those declared with `fn`.
+    no_std_builtins: bool,
+    /// Partially apply functions called with too few arguments, see
+    /// `Env::curry`.
+    curry: bool,
+    /// Stop after parsing, reporting syntax errors only.
+    parse_only: bool,
+    /// Print information about the program instead of evaluating it.
+    emit: Option<Emit>,
+    /// Print each declaration chain with its sugar removed, instead of
+    /// evaluating it.
+    desugar: bool,
+    /// Print each declaration chain desugared and constant-folded, instead
+    /// of evaluating it.
+    dump_ir: bool,
+    /// Evaluate pure calls once for each value of their variables, see
+    /// `Env::memoize`.
+    memoize: bool,
+    /// Resolve names to built-ins before declared functions, see
+    /// `Env::builtins_first`.
+    builtins_first: bool,
+    /// Colors of the reports.
+    theme: Theme,
+    /// Fail on calls to built-ins reading input, random numbers or the
+    /// time, see `Env::deterministic`.
+    deterministic: bool,
+    /// Print the syntax tree as a Graphviz graph annotated with the value of
+    /// each node, instead of the result.
+    graph_eval: bool,
+    /// Print every variable and function coming into or going out of scope
+    /// to stderr.
+    trace_scope: bool,
+    /// Print the grammar branches tried while parsing the program to stderr.
+    grammar_trace: bool,
+    /// Pause before evaluating each node, showing it and the variables in
+    /// scope.
+    step: bool,
+    /// Stack size of the thread running the program, in bytes.
+    stack_size: usize,
+    /// Keep running, and re-run the program each time the file changes.
+    watch: bool,
+    lints: LintLevels,
+    /// Seed for `shuffle` and `sample`, to make runs reproducible.
+    seed: Option<u64>,
+    /// Don't put the prelude in front of the program.
+    no_prelude: bool,
+    /// A file to use as the prelude instead of the built-in one.
+    prelude: Option<String>,
+    /// How many elements of a list or map, or characters of a string, to
+    /// print of the result.
+    max_output: Option<usize>,
+    /// How to print the numbers of the result.
+    numbers: NumberFormat,
+    /// How many list elements and string bytes a program may allocate.
+    limit_memory: Option<usize>,
+    /// Encoding of the program and prelude files.
+    input_encoding: Encoding,
+}
 
-    let decl = recursive(|decl| {
-        let r#let = text::keyword("let")
-            .ignore_then(ident)
-            .then_ignore(just('='))
-            .then(expr.clone())
-            .then_ignore(just(';'))
-            .then(decl.clone())
-            .map(|((name, rhs), then)| Expr::Let {
-                name,
-                rhs: Box::new(rhs),
-                then: Box::new(then),
-            });
-
-        let r#fn = text::keyword("fn")
-            .ignore_then(ident)
-            .then(ident.repeated())
-            .then_ignore(just('='))
-            .then(expr.clone())
-            .then_ignore(just(';'))
-            .then(decl)
-            .map(|(((name, args), body), then)| Expr::Fn {
-                name,
-                args,
-                body: Box::new(body),
-                then: Box::new(then),
-            });
-
-        r#let.or(r#fn).or(expr).padded()
-    });
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Human,
+    /// One JSON object per evaluated program on stdout; everything else goes
+    /// to stderr.
+    Json,
+}
 
-    decl.then_ignore(end())
-}
-
-fn eval<'a>(
-    expr: &'a Expr,
-    vars: &mut Vec<(&'a String, f64)>,
-    fns: &mut Vec<(&'a String, &'a [String], &'a Expr)>,
-) -> Result<f64, String> {
-    match expr {
-        Expr::Num(x) => Ok(*x),
-        Expr::Neg(a) => Ok(-eval(a, vars, fns)?),
-        Expr::Add(a, b) => Ok(eval(a, vars, fns)? + eval(b, vars, fns)?),
-        Expr::Sub(a, b) => Ok(eval(a, vars, fns)? - eval(b, vars, fns)?),
-        Expr::Mul(a, b) => Ok(eval(a, vars, fns)? * eval(b, vars, fns)?),
-        Expr::Div(a, b) => Ok(eval(a, vars, fns)? / eval(b, vars, fns)?),
-
-        Expr::Var(name) => {
-            if let Some((_, val)) = vars.iter().rev().find(|(var, _)| *var == name) {
-                Ok(*val)
-            } else {
-                Err(format!("Cannot find variable `{name}` in scope"))
+/// What `--emit` prints for each declaration chain, as parsed.
+#[derive(Clone, Copy, PartialEq)]
+enum Emit {
+    /// Size and shape measurements.
+    Metrics,
+    /// The syntax tree as a JSON object.
+    Json,
+    /// The syntax tree as an S-expression.
+    Sexpr,
+    /// The syntax tree as a Graphviz graph.
+    Dot,
+    /// The syntax tree printed back as source code.
+    Source,
+    /// The syntax tree printed back as source code, as short as possible.
+    Minified,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "metrics" => Some(Emit::Metrics),
+            "json" => Some(Emit::Json),
+            "sexpr" => Some(Emit::Sexpr),
+            "dot" => Some(Emit::Dot),
+            "source" => Some(Emit::Source),
+            "minified" => Some(Emit::Minified),
+            _ => None,
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut path = None;
+    let mut max_errors = DEFAULT_MAX_ERRORS;
+    let mut policy = ErrorPolicy::default();
+    let mut output = OutputFormat::Human;
+    #[cfg(feature = "trace")]
+    let mut log = None;
+    let mut ast_stats = false;
+    let mut quiet = false;
+    let mut input_data = None;
+    let mut parse = ParseOptions::default();
+    let mut float_eps = 0.0;
+    let mut no_std_builtins = false;
+    let mut curry = false;
+    let mut parse_only = false;
+    let mut emit = None;
+    let mut desugar = false;
+    let mut dump_ir = false;
+    let mut memoize = false;
+    let mut builtins_first = false;
+    let mut theme = Theme::default();
+    let mut deterministic = false;
+    let mut graph_eval = false;
+    let mut trace_scope = false;
+    let mut step = false;
+    let mut grammar_trace = false;
+    let mut stack_size = DEFAULT_STACK_SIZE;
+    let mut watch = false;
+    let mut lints = LintLevels::default();
+    let mut seed = None;
+    let mut no_prelude = false;
+    let mut prelude = None;
+    let mut max_output = None;
+    let mut numbers = NumberFormat::default();
+    let mut limit_memory = None;
+    let mut input_encoding = Encoding::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Only syntax errors are capped, so `--max-parse-errors` is the
+            // same flag.
+            "--max-errors" | "--max-parse-errors" => {
+                let n = args.next().ok_or(format!("`{arg}` expects a number"))?;
+                max_errors = n
+                    .parse()
+                    .map_err(|_| format!("Invalid value for `{arg}`: `{n}`"))?;
+            }
+            "--output" => {
+                output = match args.next().as_deref() {
+                    Some("human") => OutputFormat::Human,
+                    Some("json") => OutputFormat::Json,
+                    _ => return Err("`--output` expects `human` or `json`".to_string()),
+                }
+            }
+            "--log" => {
+                let name = args.next().ok_or("`--log` expects a level")?;
+                if !LOG_LEVELS.contains(&name.as_str()) {
+                    return Err(format!("Unknown log level `{name}`"));
+                }
+                #[cfg(not(feature = "trace"))]
+                return Err("`--log` requires building with the `trace` feature".to_string());
+                #[cfg(feature = "trace")]
+                {
+                    log = Some(name.parse().expect("the level names are checked above"));
+                }
+            }
+            "--ast-stats" => ast_stats = true,
+            "--quiet" => quiet = true,
+            "--float-eps" => {
+                let eps = args.next().ok_or("`--float-eps` expects a number")?;
+                float_eps = match eps.parse::<f64>() {
+                    Ok(x) if x >= 0.0 && x.is_finite() => x,
+                    _ => return Err(format!("Invalid value for `--float-eps`: `{eps}`")),
+                };
+            }
+            "--no-std-builtins" => no_std_builtins = true,
+            "--curry" => curry = true,
+            "--emit" => {
+                let name = args.next().unwrap_or_default();
+                emit = Some(Emit::from_name(&name).ok_or(EMIT_FORMATS)?);
+            }
+            _ if arg.starts_with("--emit=") => {
+                emit = Some(Emit::from_name(&arg["--emit=".len()..]).ok_or(EMIT_FORMATS)?);
+            }
+            "--minify" => emit = Some(Emit::Minified),
+            "--parse-only" => parse_only = true,
+            "--desugar" => desugar = true,
+            "--dump-ir" => dump_ir = true,
+            "--memoize-pure" => memoize = true,
+            "--builtins-first" => builtins_first = true,
+            "--deterministic" => deterministic = true,
+            "--graph-eval" => graph_eval = true,
+            "--trace-scope" => trace_scope = true,
+            "--step" => step = true,
+            "--grammar-trace" => grammar_trace = true,
+            "--watch" => watch = true,
+            "--stack-size" => {
+                let size = args.next().ok_or("`--stack-size` expects a size")?;
+                stack_size = match stack::parse_size(&size) {
+                    Some(n) if n >= stack::MIN_SIZE => n,
+                    Some(_) => return Err("`--stack-size` must be at least 1M".to_string()),
+                    None => return Err(format!("Invalid value for `--stack-size`: `{size}`")),
+                };
+            }
+            "--theme" => {
+                let name = args.next().ok_or("`--theme` expects a theme name")?;
+                theme = Theme::from_name(&name).ok_or_else(|| format!("Unknown theme `{name}`"))?;
+            }
+            "--strict" => lints.set_strict(true),
+            "--no-redefine" => {
+                for name in ["shadowed-variable", "redefined-function"] {
+                    lints.set(name, LintLevel::Deny)?;
+                }
+            }
+            "-A" | "-W" | "-D" => {
+                let name = args.next().ok_or(format!("`{arg}` expects a lint name"))?;
+                let level = match arg.as_str() {
+                    "-A" => LintLevel::Allow,
+                    "-W" => LintLevel::Warn,
+                    _ => LintLevel::Deny,
+                };
+                lints.set(&name, level)?;
             }
+            "--seed" => {
+                let n = args.next().ok_or("`--seed` expects a number")?;
+                seed = Some(
+                    n.parse()
+                        .map_err(|_| format!("Invalid value for `--seed`: `{n}`"))?,
+                );
+            }
+            "--max-output" => {
+                let n = args.next().ok_or("`--max-output` expects a number")?;
+                max_output = Some(
+                    n.parse()
+                        .map_err(|_| format!("Invalid value for `--max-output`: `{n}`"))?,
+                );
+            }
+            "--precision" => {
+                let n = args.next().ok_or("`--precision` expects a number")?;
+                numbers.precision = Some(
+                    n.parse()
+                        .map_err(|_| format!("Invalid value for `--precision`: `{n}`"))?,
+                );
+            }
+            "--notation" => {
+                let name = args.next().ok_or("`--notation` expects a notation")?;
+                numbers.notation = Notation::from_name(&name)
+                    .ok_or_else(|| format!("Unknown notation `{name}`"))?;
+            }
+            "--group-digits" => numbers.grouping = true,
+            "--trim-zeros" => numbers.trim_zeros = true,
+            "--input-encoding" => {
+                let name = args
+                    .next()
+                    .ok_or("`--input-encoding` expects an encoding")?;
+                input_encoding = Encoding::from_name(&name)
+                    .ok_or_else(|| format!("Unknown encoding `{name}`"))?;
+            }
+            "--limit-memory" => {
+                let n = args.next().ok_or("`--limit-memory` expects a number")?;
+                limit_memory = Some(
+                    n.parse()
+                        .map_err(|_| format!("Invalid value for `--limit-memory`: `{n}`"))?,
+                );
+            }
+            "--no-prelude" => no_prelude = true,
+            "--prelude" => prelude = Some(args.next().ok_or("`--prelude` expects a path")?),
+            "--newline-terminates" => parse.newline_terminates = true,
+            "--max-literal-len" => {
+                let n = args.next().ok_or("`--max-literal-len` expects a number")?;
+                parse.max_literal_len = n
+                    .parse()
+                    .map_err(|_| format!("Invalid value for `--max-literal-len`: `{n}`"))?;
+            }
+            "--input-data" => {
+                input_data = Some(args.next().ok_or("`--input-data` expects a string")?);
+            }
+            "--input-file" => {
+                let file = args.next().ok_or("`--input-file` expects a path")?;
+                let data = std::fs::read_to_string(&file)
+                    .map_err(|e| format!("Cannot read input file `{file}`: {e}"))?;
+                input_data = Some(data);
+            }
+            "--no-eval-errors-fatal" => policy = ErrorPolicy::lenient(),
+            _ if arg.starts_with("--no-eval-errors-fatal=") => {
+                for name in arg["--no-eval-errors-fatal=".len()..].split(',') {
+                    let kind = ErrorKind::from_name(name)
+                        .ok_or_else(|| format!("Unknown error kind `{name}`"))?;
+                    policy.downgrade(kind)?;
+                }
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unexpected argument `{arg}`")),
         }
+    }
+
+    Ok(Options {
+        path: path.ok_or("Missing input file")?,
+        max_errors,
+        policy,
+        output,
+        #[cfg(feature = "trace")]
+        log,
+        ast_stats,
+        quiet,
+        input_data,
+        parse,
+        float_eps,
+        no_std_builtins,
+        curry,
+        parse_only,
+        emit,
+        desugar,
+        dump_ir,
+        memoize,
+        builtins_first,
+        theme,
+        deterministic,
+        graph_eval,
+        trace_scope,
+        step,
+        grammar_trace,
+        stack_size,
+        watch,
+        lints,
+        seed,
+        no_prelude,
+        prelude,
+        max_output,
+        numbers,
+        limit_memory,
+        input_encoding,
+    })
+}
+
+/// Orders errors by position and drops exact duplicates, so that the first
+/// reports shown are the earliest (and usually most relevant) ones.
+fn sort_errors(errs: &mut Vec<Simple<char>>) {
+    errs.sort_by_key(|e| (e.span().start, e.span().end));
+    errs.dedup_by(|a, b| a.span() == b.span() && error_message(a) == error_message(b));
+}
+
+fn report_syntax_errors(
+    path: &str,
+    src: &str,
+    mut errs: Vec<Simple<char>>,
+    max_errors: usize,
+    mut out: impl std::io::Write,
+) {
+    sort_errors(&mut errs);
+
+    let total = errs.len();
+    let shown = match max_errors {
+        0 => total,
+        n => n.min(total),
+    };
+
+    let mut colors = theme::current().label_colors();
+    for e in errs.into_iter().take(shown) {
+        let span = e.span();
+
+        let report = Report::build(ReportKind::Error, path, span.start)
+            .with_config(theme::current().config());
+        // There is no line to put a label on in an empty file.
+        let report = if src.is_empty() {
+            report.with_message(format!("Syntax error: {}", error_message(&e)))
+        } else {
+            report.with_message("Syntax error").with_label(
+                Label::new((path, span))
+                    .with_message(error_message(&e))
+                    .with_color(colors.next_color()),
+            )
+        };
+        report
+            .finish()
+            .write((path, Source::from(src)), &mut out)
+            .unwrap();
+    }
 
-        Expr::Let { name, rhs, then } => {
-            let rhs = eval(rhs, vars, fns)?;
-            vars.push((name, rhs));
-            let output = eval(then, vars, fns);
-            vars.pop();
-            output
+    if shown < total {
+        writeln!(
+            out,
+            "…and {} more errors (re-run with --max-errors 0 for all)",
+            total - shown
+        )
+        .unwrap();
+    }
+}
+
+/// The declarations put in front of the program, with their source for error
+/// reports.
+struct Prelude {
+    path: String,
+    src: String,
+    /// Relocated past [`prelude::OFFSET`].
+    chain: Expr,
+}
+
+/// Parses the prelude selected by the options, if any. Syntax errors are
+/// reported against the prelude's own file, and end the process.
+fn load_prelude(opts: &Options) -> Option<Prelude> {
+    if opts.no_prelude {
+        return None;
+    }
+    let (path, src) = match &opts.prelude {
+        Some(path) => match encoding::read(path, opts.input_encoding) {
+            Ok(src) => (path.as_str(), src),
+            Err(e) => {
+                eprintln!("Cannot read prelude `{path}`: {e}");
+                std::process::exit(1);
+            }
+        },
+        None => ("<prelude>", PRELUDE.to_string()),
+    };
+    match parse_prelude(&src) {
+        Ok(chain) => {
+            let mut chain = desugar(chain);
+            prelude::relocate(&mut chain);
+            Some(Prelude {
+                path: path.to_string(),
+                src,
+                chain,
+            })
+        }
+        Err(errs) => {
+            report_syntax_errors(path, &src, errs, opts.max_errors, std::io::stdout());
+            std::process::exit(2);
         }
+    }
+}
 
-        Expr::Call(name, args) => {
-            let fn_ = fns.iter().rev().find(|(var, _, _)| *var == name);
+/// `kyomusky transpile --target LANG [--module] FILE`: prints the program
+/// translated to another language.
+fn transpile(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut target = None;
+    let mut module = false;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => target = Some(args.next().ok_or("`--target` expects a language")?),
+            "--module" => module = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unexpected argument `{arg}`")),
+        }
+    }
+    let path = path.ok_or("Missing input file")?;
+    let src = encoding::read_file(&path, Encoding::Utf8)?;
+
+    let ast = match parse_with_spans(&src) {
+        Ok(tree) => desugar(tree.expr),
+        Err(errs) => {
+            report_syntax_errors(&path, &src, errs, DEFAULT_MAX_ERRORS, std::io::stdout());
+            std::process::exit(2);
+        }
+    };
+
+    let output = match target.as_deref() {
+        Some("rust") => transpile::rust::transpile(&ast),
+        Some("js") => transpile::js::transpile(&ast, module),
+        Some(other) => return Err(format!("Unknown transpile target `{other}`")),
+        None => return Err("Missing `--target`".to_string()),
+    };
 
-            if fn_.is_none() {
-                return Err(format!("Cannot find function `{}` in scope", name));
+    match output {
+        Ok(output) => print!("{output}"),
+        Err(e) => {
+            Report::build(ReportKind::Error, &path, e.span.start)
+                .with_config(theme::current().config())
+                .with_message(e.to_string())
+                .with_label(Label::new((&path, e.span)))
+                .finish()
+                .eprint((&path, Source::from(src.as_str())))
+                .unwrap();
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// `kyomusky bench [--iters N] [--resolve] FILE`: evaluates the program
+/// repeatedly and prints timing statistics. The program is parsed only once,
+/// outside of the timings. With `--resolve`, each evaluation resolves the
+/// variables to slots first (see `Env::resolve`), to compare with looking
+/// them up by name.
+fn bench(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut iters = DEFAULT_BENCH_ITERS;
+    let mut resolve = false;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iters" => {
+                let n = args.next().ok_or("`--iters` expects a number")?;
+                iters = match n.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("Invalid value for `--iters`: `{n}`")),
+                };
             }
+            "--resolve" => resolve = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unexpected argument `{arg}`")),
+        }
+    }
+    let path = path.ok_or("Missing input file")?;
+    let src = encoding::read_file(&path, Encoding::Utf8)?;
 
-            let (_, arg_names, body) = fn_.copied().unwrap();
+    let prelude = desugar(parse_prelude(PRELUDE).expect("the built-in prelude is valid"));
+    let program: Vec<_> = match parse_program(&src, ParseOptions::default()) {
+        Ok(program) => program
+            .into_iter()
+            .map(|ast| prepend(&prelude, desugar(ast)))
+            .collect(),
+        Err(errs) => {
+            report_syntax_errors(&path, &src, errs, DEFAULT_MAX_ERRORS, std::io::stdout());
+            std::process::exit(2);
+        }
+    };
 
-            if arg_names.len() != args.len() {
-                return Err(format!(
-                    "Wrong number of arguments for function `{name}`: expected {}, found {}",
-                    arg_names.len(),
-                    args.len(),
-                ));
+    let mut samples = Vec::with_capacity(iters);
+    for _ in 0..iters {
+        let start = std::time::Instant::now();
+        for ast in &program {
+            let mut env = Env::new();
+            if resolve {
+                env.resolve(ast);
+            }
+            if let Err(e) = eval(ast, &mut env) {
+                println!("Evaluation error: {e}");
+                std::process::exit(1);
             }
+        }
+        samples.push(start.elapsed());
+    }
+    print!("{}", Timings::of(&samples).unwrap());
+    Ok(())
+}
 
-            let mut args_evaled = args
-                .iter()
-                .map(|arg| eval(arg, vars, fns))
-                .zip(arg_names.iter())
-                .map(|(var, name)| Ok((name, var?)))
-                .collect::<Result<_, String>>()?;
+/// `kyomusky --ast-diff [--newline-terminates] A B`: tells whether two
+/// programs parse to the same syntax tree, ignoring layout, and if not, shows
+/// the first nodes that differ. Returns whether they are the same.
+fn ast_diff(args: impl Iterator<Item = String>) -> Result<bool, String> {
+    let mut parse = ParseOptions::default();
+    let mut paths = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--newline-terminates" => parse.newline_terminates = true,
+            _ if paths.len() < 2 => paths.push(arg),
+            _ => return Err(format!("Unexpected argument `{arg}`")),
+        }
+    }
+    let [a, b] = &paths[..] else {
+        return Err("`--ast-diff` expects two files".to_string());
+    };
+    let mut programs = vec![];
+    for path in [a, b] {
+        let src = encoding::read_file(path, Encoding::Utf8)?;
+        match parse_program(&src, parse) {
+            Ok(program) => programs.push((path.as_str(), src, program)),
+            Err(errs) => {
+                report_syntax_errors(path, &src, errs, DEFAULT_MAX_ERRORS, std::io::stdout());
+                std::process::exit(2);
+            }
+        }
+    }
+    let [(a, a_src, a_chains), (b, b_src, b_chains)] = &programs[..] else {
+        unreachable!("both files are parsed");
+    };
+
+    let diff = a_chains
+        .iter()
+        .zip(b_chains)
+        .enumerate()
+        .find_map(|(i, (x, y))| x.diff(y).map(|diff| (i, diff)));
+    let Some((i, diff)) = diff else {
+        if a_chains.len() == b_chains.len() {
+            println!("The syntax trees are the same");
+            return Ok(true);
+        }
+        println!(
+            "`{a}` has {} declaration chains and `{b}` has {}",
+            a_chains.len(),
+            b_chains.len()
+        );
+        return Ok(false);
+    };
+
+    // Each step of the path with the kind of the node it leads to.
+    let mut steps = vec![format!("[{i}] {}", a_chains[i].kind.name())];
+    let mut node = &a_chains[i];
+    for &j in &diff.path {
+        node = node.children()[j];
+        steps.push(format!("[{j}] {}", node.kind.name()));
+    }
+    let mut colors = theme::current().label_colors();
+    Report::build(ReportKind::Error, a.to_string(), diff.left.span.start)
+        .with_config(theme::current().config())
+        .with_message(format!("Syntax trees differ at {}", steps.join(" > ")))
+        .with_label(
+            Label::new((a.to_string(), diff.left.span.clone()))
+                .with_message(diff.left.kind.name())
+                .with_color(colors.next_color()),
+        )
+        .with_label(
+            Label::new((b.to_string(), diff.right.span.clone()))
+                .with_message(diff.right.kind.name())
+                .with_color(colors.next_color()),
+        )
+        .finish()
+        .print(ariadne::sources([
+            (a.to_string(), a_src.as_str()),
+            (b.to_string(), b_src.as_str()),
+        ]))
+        .unwrap();
+    Ok(false)
+}
+
+/// `kyomusky --repl`: reads programs from standard input and evaluates each
+/// one as soon as it is complete. Input that could still be completed, like
+/// an unclosed bracket or a trailing operator, continues on the next line
+/// after a `.` prompt. Each program is evaluated on its own, after the
+/// prelude.
+fn repl() {
+    use std::io::Write;
+
+    let mut chain = desugar(parse_prelude(PRELUDE).expect("the built-in prelude is valid"));
+    prelude::relocate(&mut chain);
+    let prelude = Prelude {
+        path: "<prelude>".to_string(),
+        src: PRELUDE.to_string(),
+        chain,
+    };
+    let mut src = String::new();
+    loop {
+        print!("{}", if src.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush().unwrap();
+        let mut line = String::new();
+        let eof = matches!(std::io::stdin().read_line(&mut line), Ok(0) | Err(_));
+        src.push_str(&line);
+        if blank_comments(&src).trim().is_empty() {
+            src.clear();
+        } else if eof || !is_incomplete(&src, ParseOptions::default()) {
+            let path = "<repl>";
+            match parse_program(&src, ParseOptions::default()) {
+                Ok(program) => {
+                    let sources = Sources {
+                        path,
+                        src: &src,
+                        prelude: Some(&prelude),
+                    };
+                    for ast in program {
+                        let chain = prepend(&prelude.chain, desugar(ast));
+                        let result = eval(&chain, &mut Env::new());
+                        match result {
+                            Ok(value) => println!("{value}"),
+                            Err(e) => report_eval_error(&sources, &e),
+                        }
+                    }
+                }
+                Err(errs) => {
+                    report_syntax_errors(path, &src, errs, DEFAULT_MAX_ERRORS, std::io::stdout())
+                }
+            }
+            src.clear();
+        }
+        if eof {
+            println!();
+            return;
+        }
+    }
+}
+
+/// The sources that spans found during evaluation can point into: the
+/// program, and the prelude in front of it.
+struct Sources<'a> {
+    path: &'a str,
+    src: &'a str,
+    prelude: Option<&'a Prelude>,
+}
+
+impl Sources<'_> {
+    /// The file `span` is in, and the span within that file.
+    fn locate(&self, span: &Span) -> (String, Span) {
+        match self.prelude {
+            Some(prelude) if span.start >= prelude::OFFSET => (
+                prelude.path.clone(),
+                span.start - prelude::OFFSET..span.end - prelude::OFFSET,
+            ),
+            _ => (self.path.to_string(), span.clone()),
+        }
+    }
+
+    /// Where `span` starts, as `file:line:column`, counting from 1.
+    fn position(&self, span: &Span) -> String {
+        let (file, span) = self.locate(span);
+        let src = match self.prelude {
+            Some(prelude) if file == prelude.path => prelude.src.as_str(),
+            _ => self.src,
+        };
+        let before: Vec<char> = src.chars().take(span.start).collect();
+        let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+        format!("{file}:{line}:{column}")
+    }
+
+    fn cache(&self) -> impl ariadne::Cache<String> + '_ {
+        let prelude = self.prelude.map(|p| (p.path.clone(), p.src.as_str()));
+        ariadne::sources(
+            [(self.path.to_string(), self.src)]
+                .into_iter()
+                .chain(prelude),
+        )
+    }
+}
 
-            vars.append(&mut args_evaled);
-            let output = eval(body, vars, fns);
-            vars.truncate(vars.len() - args_evaled.len());
-            output
+fn report_eval_error(sources: &Sources, err: &EvalError) {
+    let (file, span) = sources.locate(&err.span);
+    let mut report = Report::build(ReportKind::Error, file.clone(), span.start)
+        .with_config(theme::current().config())
+        .with_message("Evaluation error")
+        .with_label(Label::new((file, span)).with_message(&err.message));
+    if let Some((span, label)) = &err.secondary {
+        report = report.with_label(Label::new(sources.locate(span)).with_message(label));
+    }
+    if !err.stack.is_empty() {
+        // Runs of the same call, as in deep recursion, are shown once.
+        let mut calls: Vec<(&(String, Span), usize)> = Vec::new();
+        for frame in &err.stack {
+            match calls.last_mut() {
+                Some((last, n)) if *last == frame => *n += 1,
+                _ => calls.push((frame, 1)),
+            }
+        }
+        let mut note = Vec::new();
+        for ((name, span), n) in calls.iter().take(MAX_NOTE_CALLS) {
+            let name = if name.is_empty() { "<lambda>" } else { name };
+            let mut call = format!("`{name}` called at {}", sources.position(span));
+            if *n > 1 {
+                call.push_str(&format!(" (repeated {n} times)"));
+            }
+            note.push(call);
         }
+        if calls.len() > MAX_NOTE_CALLS {
+            note.push(format!("{} more calls", calls.len() - MAX_NOTE_CALLS));
+        }
+        report = report.with_note(format!("in {}", note.join(", from ")));
+    }
+    report.finish().print(sources.cache()).unwrap();
+}
 
-        Expr::Fn {
-            name,
-            args,
-            body,
-            then,
-        } => {
-            fns.push((name, args, body));
-            let output = eval(then, vars, fns);
-            fns.pop();
-            output
+fn report_diagnostics(path: &str, src: &str, diagnostics: &[Diagnostic]) {
+    for d in diagnostics {
+        let kind = match d.severity {
+            Severity::Error => ReportKind::Error,
+            Severity::Warning => ReportKind::Warning,
+        };
+        let title = match d.lint {
+            Some(lint) => format!("`{}` lint", lint.name()),
+            None => "Static analysis".to_string(),
+        };
+        let mut report = Report::build(kind, path, d.span.start)
+            .with_config(theme::current().config())
+            .with_message(title)
+            .with_label(Label::new((path, d.span.clone())).with_message(&d.message));
+        if let Some((span, label)) = &d.secondary {
+            report = report.with_label(Label::new((path, span.clone())).with_message(label));
         }
+        report.finish().eprint((path, Source::from(src))).unwrap();
     }
 }
 
+fn json_diagnostics<'a>(diagnostics: impl IntoIterator<Item = &'a Diagnostic>) -> Json {
+    let diagnostics = diagnostics.into_iter().map(|d| {
+        Json::object([
+            ("lint", d.lint.map_or(Json::Null, |lint| lint.name().into())),
+            ("severity", d.severity.name().into()),
+            ("message", d.message.clone().into()),
+            (
+                "span",
+                Json::Array(vec![d.span.start.into(), d.span.end.into()]),
+            ),
+        ])
+    });
+    Json::Array(diagnostics.collect())
+}
+
+fn json_error(kind: &str, message: String, span: &std::ops::Range<usize>) -> Json {
+    Json::object([
+        ("ok", false.into()),
+        (
+            "error",
+            Json::object([
+                ("kind", kind.into()),
+                ("message", message.into()),
+                (
+                    "span",
+                    Json::Array(vec![span.start.into(), span.end.into()]),
+                ),
+            ]),
+        ),
+    ])
+}
+
+const USAGE: &str =
+    "Usage: kyomusky [--max-errors N] [--no-eval-errors-fatal[=KIND,...]] [--output human|json]
+                [--log error|warn|info|debug|trace] [--newline-terminates] [--max-literal-len N]
+                [--input-data DATA | --input-file PATH] [--float-eps EPS] [--no-std-builtins]
+                [--builtins-first] [--curry] [--parse-only] [--desugar] [--dump-ir]
+                [--graph-eval] [--emit metrics|json|sexpr|dot|source|minified] [--minify]
+                [--ast-stats] [--quiet]
+                [--step] [--grammar-trace] [--trace-scope] [--stack-size SIZE[K|M|G]] [--seed N]
+                [--strict] [--no-redefine] [-A|-W|-D LINT|warnings]...
+                [--theme default|high-contrast|monochrome] [--no-prelude | --prelude PATH]
+                [--max-output N] [--precision N] [--notation plain|scientific]
+                [--group-digits] [--trim-zeros] [--limit-memory N]
+                [--input-encoding utf8|latin1] [--memoize-pure] [--deterministic] [--watch] FILE
+       kyomusky transpile --target rust|js [--module] FILE
+       kyomusky bench [--iters N] [--resolve] FILE
+       kyomusky --ast-diff [--newline-terminates] FILE FILE
+       kyomusky --repl
+       kyomusky lsp";
+
 fn main() {
-    let path = std::env::args().nth(1).unwrap();
-    let src = std::fs::read_to_string(&path).unwrap();
-    let mut vars = Vec::new();
-    let mut fns = Vec::new();
-
-    match parser().parse(src.clone()) {
-        Ok(ast) => match eval(&ast, &mut vars, &mut fns) {
-            Ok(output) => println!("ast:  {ast:?}\neval: {output}"),
-            Err(eval_err) => println!("Evaluation error: {}", eval_err),
-        },
-        Err(parse_errs) => {
-            let mut colors = ColorGenerator::new();
-            for e in parse_errs {
-                let span = e.span();
-
-                Report::build(ReportKind::Error, &path, span.start)
-                    .with_message("Syntax error")
-                    .with_label(
-                        Label::new((&path, span))
-                            .with_message(e.to_string())
-                            .with_color(colors.next()),
+    if std::env::args().nth(1).as_deref() == Some("lsp") {
+        let stdin = std::io::stdin();
+        if let Err(e) = kyomusky::lsp::run(stdin.lock(), std::io::stdout()) {
+            eprintln!("Language server error: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("transpile") {
+        if let Err(msg) = transpile(std::env::args().skip(2)) {
+            eprintln!("{msg}\n{USAGE}");
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("bench") {
+        if let Err(msg) = bench(std::env::args().skip(2)) {
+            eprintln!("{msg}\n{USAGE}");
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("--repl") {
+        if let Some(arg) = std::env::args().nth(2) {
+            eprintln!("Unexpected argument `{arg}`\n{USAGE}");
+            std::process::exit(2);
+        }
+        stack::with_stack(DEFAULT_STACK_SIZE, repl);
+        return;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("--ast-diff") {
+        match ast_diff(std::env::args().skip(2)) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(msg) => {
+                eprintln!("{msg}\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("{msg}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    theme::set(opts.theme);
+    #[cfg(feature = "trace")]
+    if let Some(level) = opts.log {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()))
+            .with_max_level(level)
+            .with_target(false)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .init();
+    }
+    let code = stack::with_stack(opts.stack_size, || {
+        let prelude = load_prelude(&opts);
+        if opts.watch {
+            watch(&opts, prelude.as_ref());
+            return 0;
+        }
+
+        let src = match encoding::read_file(&opts.path, opts.input_encoding) {
+            Ok(src) => src,
+            Err(msg) => {
+                eprintln!("{msg}");
+                return 1;
+            }
+        };
+        let parsed = {
+            #[cfg(feature = "trace")]
+            let _span = tracing::info_span!("parse", len = src.chars().count()).entered();
+            if opts.grammar_trace {
+                let (parsed, events) = grammar_trace::record(|| parse_program(&src, opts.parse));
+                events.iter().for_each(|event| eprintln!("{event}"));
+                parsed
+            } else {
+                parse_program(&src, opts.parse)
+            }
+        };
+        run(&opts, prelude.as_ref(), &src, parsed)
+    });
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+/// Re-runs the program whenever the file changes. Runs where the contents are
+/// the same as last time, e.g. after an editor merely touched the file, are
+/// skipped.
+fn watch(opts: &Options, prelude: Option<&Prelude>) {
+    let mut cache = ParseCache::new();
+    let mut modified = None;
+    loop {
+        let mtime = std::fs::metadata(&opts.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if mtime != modified {
+            modified = mtime;
+            match encoding::read_file(&opts.path, opts.input_encoding) {
+                Ok(src) => {
+                    let (parsed, fresh) = {
+                        #[cfg(feature = "trace")]
+                        let _span =
+                            tracing::info_span!("parse", len = src.chars().count()).entered();
+                        cache.parse(&src, opts.parse)
+                    };
+                    if fresh {
+                        run(opts, prelude, &src, parsed.clone());
+                    }
+                }
+                Err(msg) => eprintln!("{msg}"),
+            }
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Lines read from standard input one at a time, without holding the lock in
+/// between, so that `input()` can read from it too.
+fn stdin_lines() -> impl Iterator<Item = String> {
+    std::iter::from_fn(|| {
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    })
+}
+
+/// Runs the program, unless a denied lint rejects it, and returns the exit
+/// code: 2 when the program has syntax errors, 1 when a denied lint rejects
+/// it or a declaration chain fails to evaluate, and 0 otherwise.
+fn run(opts: &Options, prelude: Option<&Prelude>, src: &str, parsed: ParseResult) -> i32 {
+    let path = opts.path.as_str();
+    let sources = Sources { path, src, prelude };
+    match parsed {
+        Ok(_) if opts.parse_only => 0,
+        Ok(program) => {
+            let diagnostics = lint::check(&program, &opts.lints);
+            report_diagnostics(path, src, &diagnostics);
+            if let Some(denied) = diagnostics.iter().find(|d| d.severity == Severity::Error) {
+                if opts.output == OutputFormat::Json {
+                    let mut json = json_error("lint", denied.message.clone(), &denied.span);
+                    if let Json::Object(members) = &mut json {
+                        members.push(("diagnostics".to_string(), json_diagnostics(&diagnostics)));
+                    }
+                    println!("{json}");
+                }
+                return 1;
+            }
+
+            if opts.ast_stats {
+                let stats = ast_stats(&program);
+                match opts.output {
+                    OutputFormat::Human => print!("{stats}"),
+                    OutputFormat::Json => eprint!("{stats}"),
+                }
+            }
+
+            if let Some(emit) = opts.emit {
+                for (i, ast) in program.iter().enumerate() {
+                    match emit {
+                        Emit::Metrics => {
+                            if i > 0 {
+                                println!();
+                            }
+                            print!("{}", Metrics::of(ast));
+                        }
+                        Emit::Json => println!("{}", emit::json(ast)),
+                        Emit::Sexpr => println!("{}", emit::sexpr(ast)),
+                        Emit::Dot => print!("{}", dot::graph(ast, &[])),
+                        Emit::Source => println!("{}", ast.display_with_source(src)),
+                        Emit::Minified => println!("{}", ast.minified()),
+                    }
+                }
+                return 0;
+            }
+
+            if opts.desugar {
+                for ast in &program {
+                    println!("{:?}", desugar(ast.clone()));
+                }
+                return 0;
+            }
+
+            if opts.dump_ir {
+                for ast in &program {
+                    println!("{:?}", lower(ast.clone()));
+                }
+                return 0;
+            }
+
+            let mut input: Box<dyn Input> = match &opts.input_data {
+                Some(data) => Box::new(DataInput::new(data)),
+                None => Box::new(StdinInput::default()),
+            };
+            let mut inputs_read = 0;
+            let mut rng = opts.seed.map_or_else(Rng::from_time, Rng::new);
+            let mut code = 0;
+
+            // Each top-level declaration chain is evaluated on its own, but
+            // they all read from the same input and random numbers. `i` only
+            // labels the evaluation span.
+            #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+            for (i, ast) in program.iter().enumerate() {
+                let core = desugar(ast.clone());
+                let chain = prelude.map(|prelude| prepend(&prelude.chain, core.clone()));
+                let chain = chain.as_ref().unwrap_or(&core);
+                let mut env = Env::new();
+                env.policy = opts.policy.clone();
+                env.input = input;
+                env.inputs_read = inputs_read;
+                env.rng = rng;
+                env.float_eps = opts.float_eps;
+                env.builtins = !opts.no_std_builtins;
+                env.builtins_first = opts.builtins_first;
+                env.curry = opts.curry;
+                env.memoize = opts.memoize;
+                env.deterministic = opts.deterministic;
+                env.memory_limit = opts.limit_memory;
+                // Keep standard output for the JSON result alone.
+                if opts.output == OutputFormat::Json {
+                    env.output = Box::new(std::io::stderr());
+                }
+                if opts.step {
+                    env.debugger = Some(Box::new(Stepper::new(stdin_lines(), std::io::stderr())));
+                }
+                if opts.graph_eval {
+                    env.values = Some(Vec::new());
+                }
+                if opts.trace_scope {
+                    env.scopes = Some(Vec::new());
+                }
+                let start = std::time::Instant::now();
+                let result = {
+                    #[cfg(feature = "trace")]
+                    let _span = tracing::info_span!(
+                        "eval",
+                        chain = i,
+                        nodes = tracing::field::Empty
                     )
-                    .finish()
-                    .print((&path, Source::from(src.as_str())))
-                    .unwrap();
+                    .entered();
+                    #[cfg(feature = "trace")]
+                    if !_span.is_disabled() {
+                        _span.record("nodes", chain.node_count());
+                    }
+                    eval_catching_panics(chain, &mut env)
+                };
+                let elapsed = start.elapsed();
+                input = env.input;
+                inputs_read = env.inputs_read;
+                rng = env.rng;
+
+                for warning in &env.warnings {
+                    let (file, span) = sources.locate(&warning.span);
+                    Report::build(ReportKind::Warning, file.clone(), span.start)
+                        .with_config(theme::current().config())
+                        .with_message(format!(
+                            "Evaluation error downgraded (`{}`)",
+                            warning.kind.name()
+                        ))
+                        .with_label(Label::new((file, span)).with_message(&warning.message))
+                        .finish()
+                        .eprint(sources.cache())
+                        .unwrap();
+                }
+
+                for event in env.scopes.iter().flatten() {
+                    let change = if event.push { "push" } else { "pop" };
+                    let names = event.names.join(", ");
+                    eprintln!(
+                        "scope: {change} {names} at {}",
+                        sources.position(&event.span)
+                    );
+                }
+
+                if result.is_err() {
+                    code = 1;
+                }
+
+                if let Some(values) = &env.values {
+                    print!("{}", dot::graph(chain, values));
+                    continue;
+                }
+
+                if opts.output == OutputFormat::Json {
+                    if !opts.quiet {
+                        eprintln!("ast:  {ast:?}");
+                    }
+                    // Rounded to microseconds, the precision worth reporting.
+                    let time_ms = (elapsed.as_secs_f64() * 1e6).round() / 1e3;
+                    let mut json = match result {
+                        Ok(value) => Json::object([
+                            ("ok", true.into()),
+                            ("value", value.to_json()),
+                            ("time_ms", time_ms.into()),
+                        ]),
+                        Err(e) => json_error("runtime", e.message, &e.span),
+                    };
+                    let warnings: Vec<_> = env
+                        .warnings
+                        .iter()
+                        .map(|warning| Diagnostic {
+                            span: sources.locate(&warning.span).1,
+                            severity: Severity::Warning,
+                            message: format!("{} (`{}`)", warning.message, warning.kind.name()),
+                            lint: None,
+                            secondary: None,
+                        })
+                        .collect();
+                    let chain = diagnostics
+                        .iter()
+                        .filter(|d| ast.span.start <= d.span.start && d.span.end <= ast.span.end)
+                        .chain(&warnings);
+                    if let Json::Object(members) = &mut json {
+                        members.push(("diagnostics".to_string(), json_diagnostics(chain)));
+                    }
+                    println!("{json}");
+                    continue;
+                }
+
+                match result {
+                    Ok(output) => {
+                        let output = output.formatted(&opts.numbers);
+                        let output = match opts.max_output {
+                            Some(max) => format!("{output:.max$}"),
+                            None => output.to_string(),
+                        };
+                        if opts.quiet {
+                            println!("{output}");
+                        } else {
+                            println!("ast:  {ast:?}\neval: {output}");
+                        }
+                    }
+                    Err(eval_err) => report_eval_error(&sources, &eval_err),
+                }
             }
+            code
+        }
+        Err(mut parse_errs) if opts.output == OutputFormat::Json => {
+            sort_errors(&mut parse_errs);
+            let first = &parse_errs[0];
+            let mut json = json_error("parse", error_message(first), &first.span());
+            let diagnostics: Vec<_> = parse_errs
+                .iter()
+                .map(|e| Diagnostic {
+                    span: e.span(),
+                    severity: Severity::Error,
+                    message: error_message(e),
+                    lint: None,
+                    secondary: None,
+                })
+                .collect();
+            if let Json::Object(members) = &mut json {
+                members.push(("diagnostics".to_string(), json_diagnostics(&diagnostics)));
+            }
+            report_syntax_errors(path, src, parse_errs, opts.max_errors, std::io::stderr());
+            println!("{json}");
+            2
+        }
+        Err(parse_errs) => {
+            report_syntax_errors(path, src, parse_errs, opts.max_errors, std::io::stdout());
+            2
         }
     }
 }
@@ -0,0 +1,104 @@
+//! Caching of pure calls within one evaluation, see [`Env::memoize`].
+//!
+//! A call is pure, and its result reused, when evaluating it again could not
+//! give a different value or have an effect:
+//!
+//! - it calls a built-in from [`PURE_BUILTINS`], which excludes `input`,
+//!   `shuffle` and `sample`, and built-ins calling a function argument, which
+//!   could be anything;
+//! - its arguments are made only of literals, variables, operators, indexing,
+//!   list and map literals, strings and such calls, so there is no
+//!   assignment, binding or function value in it;
+//! - when it is evaluated, no function declared with `fn`, registered
+//!   function or variable shadows a built-in it calls, and none of the
+//!   variables it reads holds a function;
+//! - its result is a number, string or boolean. Lists and maps can be
+//!   modified in place, so every evaluation builds a fresh one.
+//!
+//! Results are keyed by the node and the values of the variables it reads, so
+//! `sqrt(x)` is computed once for each value `x` takes.
+//!
+//! [`Env::memoize`]: crate::eval::Env::memoize
+
+use crate::ast::{Expr, ExprKind};
+use crate::eval::Value;
+use std::collections::HashMap;
+
+/// Built-ins whose result depends only on their arguments.
+pub const PURE_BUILTINS: &[&str] = &[
+    "chars",
+    "clamp",
+    "compare",
+    "drop",
+    "ends_with",
+    "euclid_mod",
+    "factorial",
+    "floor_mod",
+    "from_json",
+    "len",
+    "max",
+    "min",
+    "range_list",
+    "repeat",
+    "replace",
+    "slice",
+    "sqrt",
+    "starts_with",
+    "take",
+    "to_base",
+    "to_bin",
+    "to_hex",
+    "to_json",
+    "to_lower",
+    "to_upper",
+    "trim",
+];
+
+/// What the value of a pure expression depends on.
+#[derive(Default)]
+pub(crate) struct Deps<'a> {
+    /// The variables it reads.
+    pub vars: Vec<&'a str>,
+    /// The built-ins it calls, which must not be shadowed.
+    pub calls: Vec<&'a str>,
+}
+
+/// The dependencies of `expr` if it is a pure call, `None` otherwise.
+pub(crate) fn pure_deps(expr: &Expr) -> Option<Deps<'_>> {
+    let mut deps = Deps::default();
+    (matches!(expr.kind, ExprKind::Call(..)) && collect(expr, &mut deps)).then_some(deps)
+}
+
+fn collect<'a>(expr: &'a Expr, deps: &mut Deps<'a>) -> bool {
+    match &expr.kind {
+        ExprKind::Var(name) => deps.vars.push(name),
+        ExprKind::Call(name, _) if PURE_BUILTINS.contains(&name.as_str()) => deps.calls.push(name),
+        ExprKind::Num(_)
+        | ExprKind::Str(_)
+        | ExprKind::Bool(_)
+        | ExprKind::InterpolatedStr(_)
+        | ExprKind::Unary { .. }
+        | ExprKind::Binary { .. }
+        | ExprKind::Index(..)
+        | ExprKind::List(_)
+        | ExprKind::Map(_) => {}
+        _ => return false,
+    }
+    expr.children()
+        .into_iter()
+        .all(|child| collect(child, deps))
+}
+
+/// Whether a result can be shared between evaluations of a call.
+pub(crate) fn is_cacheable(value: &Value) -> bool {
+    !matches!(value, Value::List(_) | Value::Map(_) | Value::Function(_))
+}
+
+/// The cache of one evaluation.
+#[derive(Default)]
+pub(crate) struct Memo<'a> {
+    /// The dependencies of each call node met so far, by address.
+    pub deps: HashMap<*const Expr, Option<Deps<'a>>>,
+    /// Results by call node and the values of its variables.
+    pub results: HashMap<(*const Expr, String), Value>,
+}
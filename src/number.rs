@@ -0,0 +1,123 @@
+//! How numbers are printed: the policy `Value::formatted` applies to every
+//! number of a value, selected on the command line with `--precision`,
+//! `--notation`, `--group-digits` and `--trim-zeros`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// All digits of the integer part, like `1234.5`.
+    #[default]
+    Plain,
+    /// One digit before the point and a power of ten, like `1.2345e3`.
+    Scientific,
+}
+
+impl Notation {
+    pub const ALL: [Notation; 2] = [Notation::Plain, Notation::Scientific];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Notation::Plain => "plain",
+            Notation::Scientific => "scientific",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|notation| notation.name() == name)
+    }
+}
+
+/// A policy for printing numbers. The default prints them as Rust does,
+/// with as many digits as it takes to read the number back exactly.
+///
+/// ```
+/// use kyomusky::number::{Notation, NumberFormat};
+///
+/// let default = NumberFormat::default();
+/// assert_eq!(default.format(1234567.5), "1234567.5");
+/// assert_eq!(default.format(0.1 + 0.2), "0.30000000000000004");
+///
+/// let cents = NumberFormat { precision: Some(2), ..NumberFormat::default() };
+/// assert_eq!(cents.format(3.14159), "3.14");
+/// assert_eq!(cents.format(2.0), "2.00");
+/// let trimmed = NumberFormat { trim_zeros: true, ..cents };
+/// assert_eq!(trimmed.format(2.0), "2");
+/// assert_eq!(trimmed.format(2.5), "2.5");
+///
+/// let grouped = NumberFormat { grouping: true, ..cents };
+/// assert_eq!(grouped.format(1234567.891), "1,234,567.89");
+/// assert_eq!(grouped.format(-1234.0), "-1,234.00");
+/// assert_eq!(grouped.format(999.0), "999.00");
+///
+/// let scientific = NumberFormat { notation: Notation::Scientific, ..NumberFormat::default() };
+/// assert_eq!(scientific.format(1234.5), "1.2345e3");
+/// let scientific = NumberFormat { precision: Some(3), trim_zeros: true, ..scientific };
+/// assert_eq!(scientific.format(0.00012), "1.2e-4");
+/// assert_eq!(scientific.format(-98766.0), "-9.877e4");
+///
+/// assert_eq!(grouped.format(f64::NAN), "NaN");
+/// assert_eq!(grouped.format(f64::NEG_INFINITY), "-inf");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NumberFormat {
+    /// Digits after the point, rounding the number; `None` for as many as
+    /// it takes to read it back exactly.
+    pub precision: Option<usize>,
+    pub notation: Notation,
+    /// Separate groups of three digits of the integer part with `,`.
+    pub grouping: bool,
+    /// Drop the zeros ending the fractional part, and the point if no digit
+    /// is left after it.
+    pub trim_zeros: bool,
+}
+
+impl NumberFormat {
+    pub fn format(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return x.to_string();
+        }
+        let formatted = match (self.notation, self.precision) {
+            (Notation::Plain, None) => format!("{x}"),
+            (Notation::Plain, Some(p)) => format!("{x:.p$}"),
+            (Notation::Scientific, None) => format!("{x:e}"),
+            (Notation::Scientific, Some(p)) => format!("{x:.p$e}"),
+        };
+        let (mantissa, exponent) = match formatted.split_once('e') {
+            Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+            None => (formatted.as_str(), None),
+        };
+        let mut mantissa = self.format_integer(mantissa);
+        if self.trim_zeros && mantissa.contains('.') {
+            mantissa.truncate(mantissa.trim_end_matches('0').trim_end_matches('.').len());
+        }
+        match exponent {
+            Some(exponent) => format!("{mantissa}e{exponent}"),
+            None => mantissa,
+        }
+    }
+
+    /// Groups the digits of the integer part of `number`, a number written
+    /// in plain notation, if the policy says to. Used as it is for integers
+    /// that are not `f64`, to which the other settings don't apply.
+    pub fn format_integer(&self, number: &str) -> String {
+        if !self.grouping {
+            return number.to_string();
+        }
+        let (sign, rest) = match number.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", number),
+        };
+        let end = rest.find('.').unwrap_or(rest.len());
+        let (digits, fraction) = rest.split_at(end);
+        let mut grouped = String::from(sign);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+        grouped.push_str(fraction);
+        grouped
+    }
+}
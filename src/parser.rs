@@ -0,0 +1,976 @@
+use crate::ast::{BinOp, Expr, ExprKind, Span, StrPart, UnaryOp};
+use crate::grammar_trace::traced;
+use chumsky::error::SimpleReason;
+use chumsky::prelude::*;
+
+/// Words that cannot be used as variable or function names.
+pub const KEYWORDS: &[&str] = &[
+    "let", "fn", "true", "false", "and", "or", "not", "for", "in", "do", "if", "then", "else",
+    "none",
+];
+
+/// How deeply expressions can be nested, see [`check_nesting`].
+pub const MAX_NESTING: usize = 1024;
+
+/// An operator following an operand.
+#[derive(Clone)]
+enum Postfix {
+    /// `[index]`
+    Index(Expr),
+    /// `(args)`
+    Apply(Vec<Expr>),
+    /// `.name(args)`, calling `name` with the operand as first argument.
+    Method(String, Vec<Expr>),
+}
+
+/// The longest number or string literal accepted when
+/// [`ParseOptions::max_literal_len`] is not changed, in characters.
+pub const DEFAULT_MAX_LITERAL_LEN: usize = 65_536;
+
+/// Options changing the accepted syntax.
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// Whether a line break ends an expression. By default whitespace,
+    /// including line breaks, is insignificant.
+    ///
+    /// When set, an operator, `(` of a call or `[` of an index must be on the
+    /// same line as the operand before it, so each line of a scratch file is
+    /// its own expression even if the next one starts with `-`. Line breaks
+    /// are still allowed after an operator and inside brackets.
+    pub newline_terminates: bool,
+    /// The most characters a number literal, or the literal text of a
+    /// string, may have; `0` means no limit. Longer literals are syntax
+    /// errors, so hostile input cannot make the tree hold huge strings or
+    /// have megabytes of digits converted to a number.
+    ///
+    /// ```
+    /// use kyomusky::parser::{error_message, parse_program, ParseOptions};
+    ///
+    /// let options = ParseOptions {
+    ///     max_literal_len: 5,
+    ///     ..ParseOptions::default()
+    /// };
+    /// let parse = |src| parse_program(src, options);
+    /// assert!(parse("12345 + 1.234").is_ok());
+    /// assert!(parse("\"abcde\"").is_ok());
+    /// assert!(parse("\"abc${123456 - 1}de\"").is_err());
+    ///
+    /// let errs = parse("1 + 123456").unwrap_err();
+    /// assert_eq!(
+    ///     error_message(&errs[0]),
+    ///     "number literal is longer than the limit of 5 characters"
+    /// );
+    /// assert_eq!(errs[0].span(), 4..10);
+    ///
+    /// let errs = parse("\"abc${x}def\"").unwrap_err();
+    /// assert_eq!(
+    ///     error_message(&errs[0]),
+    ///     "string literal is longer than the limit of 5 characters"
+    /// );
+    ///
+    /// let unlimited = ParseOptions {
+    ///     max_literal_len: 0,
+    ///     ..ParseOptions::default()
+    /// };
+    /// assert!(parse_program(&"9".repeat(100_000), unlimited).is_ok());
+    /// ```
+    pub max_literal_len: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            newline_terminates: false,
+            max_literal_len: DEFAULT_MAX_LITERAL_LEN,
+        }
+    }
+}
+
+/// Whether `len` characters exceed a literal length limit of `max`.
+fn too_long(len: usize, max: usize) -> bool {
+    max != 0 && len > max
+}
+
+/// A closing bracket or separator, which may follow a line break.
+fn close(c: char) -> impl Parser<char, char, Error = Simple<char>> + Clone {
+    text::whitespace().ignore_then(just(c))
+}
+
+/// Comma-separated `item`s between `open` and `end`. A comma after the last
+/// one is allowed, as in `[1, 2,]`, but not an empty item, as in `[1,,2]`.
+fn comma_list<T>(
+    item: impl Parser<char, T, Error = Simple<char>> + Clone,
+    open: char,
+    end: char,
+) -> impl Parser<char, Vec<T>, Error = Simple<char>> + Clone {
+    item.separated_by(close(','))
+        .allow_trailing()
+        .delimited_by(just(open), close(end))
+}
+
+/// The value of a number literal: decimal digits with an optional
+/// fractional part, as in `1.50`, or hexadecimal digits after `0x`.
+pub fn number_value(text: &str) -> Option<f64> {
+    if let Some(digits) = text.strip_prefix("0x") {
+        let digits = digits.chars().map(|c| c.to_digit(16).map(f64::from));
+        return digits.reduce(|n, d| Some(n? * 16.0 + d?))?;
+    }
+    let (int, frac) = text.split_once('.').unwrap_or((text, "0"));
+    let all_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if !all_digits(int) || !all_digits(frac) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn binary(lhs: Expr, (op, rhs): (BinOp, Expr)) -> Expr {
+    let span = lhs.span.start..rhs.span.end;
+    let kind = ExprKind::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    };
+    Expr::new(kind, span)
+}
+
+/// Parses a declaration chain: any number of `let` and `fn` declarations
+/// followed by an expression.
+///
+/// For a `prelude`, the expression can be left out at the end of the input,
+/// and an empty `Error` node takes its place.
+fn decl(
+    options: ParseOptions,
+    prelude: bool,
+) -> impl Parser<char, Expr, Error = Simple<char>> + Clone {
+    // Whitespace between an operand and whatever continues the expression
+    // after it.
+    let gap = if options.newline_terminates {
+        filter(|c: &char| c.is_whitespace() && *c != '\n')
+            .repeated()
+            .ignored()
+            .boxed()
+    } else {
+        text::whitespace().ignored().boxed()
+    };
+
+    let ident = text::ident().try_map(|name: String, span| {
+        if KEYWORDS.contains(&name.as_str()) {
+            Err(Simple::custom(span, format!("`{name}` is a keyword")))
+        } else {
+            Ok(name)
+        }
+    });
+
+    let escape = just('\\').ignore_then(choice((
+        just('\\'),
+        just('"'),
+        just('$'),
+        just('n').to('\n'),
+        just('t').to('\t'),
+    )));
+
+    recursive(|decl| {
+        let expr = recursive(|expr| {
+            let hex = just('0')
+                .chain(just('x'))
+                .chain::<char, _, _>(filter(char::is_ascii_hexdigit).repeated().at_least(1))
+                .collect::<String>();
+            let decimal = text::int(10)
+                .chain::<char, _, _>(just('.').chain(text::digits(10)).or_not().flatten())
+                .collect::<String>();
+            let max_len = options.max_literal_len;
+            let number = hex.or(decimal).try_map(move |s, span| {
+                if too_long(s.chars().count(), max_len) {
+                    let message =
+                        format!("number literal is longer than the limit of {max_len} characters");
+                    return Err(Simple::custom(span, message));
+                }
+                Ok(ExprKind::Num(number_value(&s).unwrap()))
+            });
+
+            // `name = value`, but not `name == value`.
+            let named_arg = text::whitespace()
+                .ignore_then(ident.map_with_span(|name, span: Span| (name, span)))
+                .then_ignore(text::whitespace())
+                .then_ignore(just('=').then(none_of('=').rewind()))
+                .then(expr.clone())
+                .map(|((name, span), value): ((String, Span), Expr)| {
+                    let span = span.start..value.span.end;
+                    Expr::new(ExprKind::NamedArg(name, Box::new(value)), span)
+                });
+
+            // `...list`, spreading the elements as positional arguments.
+            let spread = text::whitespace()
+                .ignore_then(just("...").map_with_span(|_, span: Span| span))
+                .then(expr.clone())
+                .map(|(span, list): (Span, Expr)| {
+                    let span = span.start..list.span.end;
+                    Expr::new(ExprKind::Spread(Box::new(list)), span)
+                });
+
+            let args = comma_list(named_arg.or(spread).or(expr.clone()), '(', ')').recover_with(
+                nested_delimiters('(', ')', [], |span| vec![Expr::new(ExprKind::Error, span)]),
+            );
+
+            let call = ident
+                .then_ignore(gap.clone())
+                .then(args.clone())
+                .map(|(f, args)| ExprKind::Call(f, args));
+
+            // `$` only starts an interpolation when followed by `{`.
+            let dollar = just('$').then_ignore(just('{').not().rewind());
+            let text = filter(|c| *c != '\\' && *c != '"' && *c != '$')
+                .or(escape)
+                .or(dollar)
+                .repeated()
+                .at_least(1)
+                .collect::<String>()
+                .map(StrPart::Lit);
+            let interpolation = expr
+                .clone()
+                .delimited_by(just("${"), close('}'))
+                .map(StrPart::Expr);
+            let string = text
+                .or(interpolation)
+                .repeated()
+                .delimited_by(just('"'), just('"'))
+                .try_map(move |parts, span| {
+                    let len: usize = parts
+                        .iter()
+                        .map(|part| match part {
+                            StrPart::Lit(s) => s.chars().count(),
+                            StrPart::Expr(_) => 0,
+                        })
+                        .sum();
+                    if too_long(len, max_len) {
+                        let message = format!(
+                            "string literal is longer than the limit of {max_len} characters"
+                        );
+                        return Err(Simple::custom(span, message));
+                    }
+                    Ok(match &parts[..] {
+                        [] => ExprKind::Str(String::new()),
+                        [StrPart::Lit(s)] => ExprKind::Str(s.clone()),
+                        _ => ExprKind::InterpolatedStr(parts),
+                    })
+                });
+
+            let boolean = text::keyword("true")
+                .map(|_| ExprKind::Bool(true))
+                .or(text::keyword("false").map(|_| ExprKind::Bool(false)));
+
+            let none = text::keyword("none").to(ExprKind::None);
+
+            let list = comma_list(expr.clone(), '[', ']').map(ExprKind::List);
+
+            let generator = text::whitespace()
+                .ignore_then(text::keyword("for"))
+                .ignore_then(ident.padded())
+                .then_ignore(text::keyword("in"))
+                .then(expr.clone());
+            let comprehension = just('[')
+                .ignore_then(expr.clone())
+                .then(generator.repeated().at_least(1))
+                .then(
+                    text::whitespace()
+                        .ignore_then(text::keyword("if"))
+                        .ignore_then(expr.clone())
+                        .or_not(),
+                )
+                .then_ignore(close(']'))
+                .map(|((body, generators), filter)| ExprKind::Comprehension {
+                    body: Box::new(body),
+                    generators,
+                    filter: filter.map(Box::new),
+                });
+
+            let entry = expr.clone().then_ignore(close(':')).then(expr.clone());
+            let map = comma_list(entry, '{', '}').map(ExprKind::Map);
+
+            // Anything between braces that isn't a map.
+            let block = decl
+                .clone()
+                .delimited_by(just('{'), close('}'))
+                .map(|chain| ExprKind::Block(Box::new(chain)));
+
+            let lambda = just('\\')
+                .ignore_then(ident.padded().repeated())
+                .then_ignore(text::whitespace())
+                .then_ignore(just("->"))
+                .then(expr.clone())
+                .map(|(args, body)| ExprKind::Lambda {
+                    args,
+                    body: Box::new(body),
+                });
+
+            let for_loop = text::keyword("for")
+                .ignore_then(ident.padded())
+                .then_ignore(text::keyword("in"))
+                .then(expr.clone())
+                .then_ignore(text::whitespace())
+                .then_ignore(text::keyword("do"))
+                .then(expr.clone())
+                .map(|((var, list), body)| ExprKind::For {
+                    var,
+                    list: Box::new(list),
+                    body: Box::new(body),
+                });
+
+            // `some` is only a keyword here: elsewhere it is a built-in.
+            let if_let = text::keyword("if")
+                .ignore_then(text::keyword("let").padded())
+                .ignore_then(text::keyword("some"))
+                .ignore_then(text::whitespace())
+                .ignore_then(ident.padded().delimited_by(just('('), close(')')))
+                .then_ignore(just('=').padded())
+                .then(expr.clone())
+                .then_ignore(text::whitespace())
+                .then_ignore(text::keyword("then"))
+                .then(expr.clone())
+                .then_ignore(text::whitespace())
+                .then_ignore(text::keyword("else"))
+                .then(expr.clone())
+                .map(|(((name, value), then), otherwise)| ExprKind::IfLet {
+                    name,
+                    value: Box::new(value),
+                    then: Box::new(then),
+                    otherwise: Box::new(otherwise),
+                });
+
+            let atom = text::whitespace()
+                .ignore_then(
+                    traced("number", number)
+                        .or(traced("string", string))
+                        .or(traced("boolean", boolean))
+                        .or(traced("none", none))
+                        .or(traced("lambda", lambda))
+                        .or(traced("for", for_loop))
+                        .or(traced("if let", if_let))
+                        .or(traced("comprehension", comprehension))
+                        .or(traced("list", list))
+                        .or(traced("map", map))
+                        .or(traced("block", block))
+                        .or(traced("call", call))
+                        .or(traced("variable", ident.map(ExprKind::Var)))
+                        .map_with_span(Expr::new)
+                        .or(traced(
+                            "parentheses",
+                            expr.clone()
+                                .delimited_by(just('('), close(')'))
+                                .recover_with(nested_delimiters('(', ')', [], |span| {
+                                    Expr::new(ExprKind::Error, span)
+                                })),
+                        )),
+                )
+                .then_ignore(gap.clone());
+
+            // Indexing, calls of the result of an expression, and method calls,
+            // applied in any order, e.g. `a[0](1).f()[2]`.
+            let postfix = choice((
+                expr.clone()
+                    .delimited_by(just('['), close(']'))
+                    .map(Postfix::Index),
+                args.clone().map(Postfix::Apply),
+                just('.')
+                    .ignore_then(text::whitespace())
+                    .ignore_then(ident)
+                    .then_ignore(gap.clone())
+                    .then(args)
+                    .map(|(name, args)| Postfix::Method(name, args)),
+            ))
+            .map_with_span(|postfix, span: Span| (postfix, span))
+            .then_ignore(gap.clone());
+
+            let index = atom
+                .then(postfix.repeated())
+                .foldl(|target, (postfix, span)| {
+                    let span = target.span.start..span.end;
+                    let kind = match postfix {
+                        Postfix::Index(index) => ExprKind::Index(Box::new(target), Box::new(index)),
+                        Postfix::Apply(args) => ExprKind::Apply(Box::new(target), args),
+                        Postfix::Method(name, mut args) => {
+                            args.insert(0, target);
+                            ExprKind::Call(name, args)
+                        }
+                    };
+                    Expr::new(kind, span)
+                });
+
+            let op = |c| just(c).then_ignore(text::whitespace());
+
+            // `^` and `**` bind tighter than a prefix operator on their left,
+            // so `-2 ^ 2` is `-(2 ^ 2)`, but their right operand can have one,
+            // as in `2 ^ -1`. They are right-associative.
+            let pow = just("**")
+                .or(just("^"))
+                .then_ignore(text::whitespace())
+                .to(BinOp::Pow);
+            let unary = recursive(|unary| {
+                let power = index
+                    .then(pow.then(unary).or_not())
+                    .map(|(lhs, rhs)| match rhs {
+                        Some(rhs) => binary(lhs, rhs),
+                        None => lhs,
+                    });
+                just('-')
+                    .to(UnaryOp::Neg)
+                    .or(just('!').to(UnaryOp::Not))
+                    .or(text::keyword("not").to(UnaryOp::Not))
+                    .map_with_span(|op, span: Span| (op, span))
+                    .padded()
+                    .repeated()
+                    .then(power)
+                    .foldr(|(op, span), rhs| {
+                        let span = span.start..rhs.span.end;
+                        let kind = ExprKind::Unary {
+                            op,
+                            operand: Box::new(rhs),
+                        };
+                        Expr::new(kind, span)
+                    })
+            });
+
+            let mul = op('*').to(BinOp::Mul);
+            let div = op('/').to(BinOp::Div);
+            let add = op('+').to(BinOp::Add);
+            let sub = op('-').to(BinOp::Sub);
+
+            let product = unary
+                .clone()
+                .then(mul.or(div).then(unary).repeated())
+                .foldl(binary);
+
+            let sum = product
+                .clone()
+                .then(add.or(sub).then(product).repeated())
+                .foldl(binary);
+
+            let cmp_op = |s| just(s).then_ignore(text::whitespace());
+
+            let eq = cmp_op("==").to(BinOp::Eq);
+            let not_eq = cmp_op("!=").to(BinOp::NotEq);
+            let lt_eq = cmp_op("<=").to(BinOp::LtEq);
+            let gt_eq = cmp_op(">=").to(BinOp::GtEq);
+            let lt = cmp_op("<").to(BinOp::Lt);
+            let gt = cmp_op(">").to(BinOp::Gt);
+
+            let comparison = sum
+                .clone()
+                .then(
+                    choice((eq, not_eq, lt_eq, gt_eq, lt, gt))
+                        .then(sum)
+                        .repeated(),
+                )
+                .foldl(binary);
+
+            // `and` and `or` are the same as `&&` and `||`.
+            let word_op = |symbol, word| {
+                just(symbol)
+                    .ignored()
+                    .or(text::keyword(word))
+                    .then_ignore(text::whitespace())
+            };
+            let and = word_op("&&", "and").to(BinOp::And);
+            let or = word_op("||", "or").to(BinOp::Or);
+
+            let conjunction = comparison
+                .clone()
+                .then(and.then(comparison).repeated())
+                .foldl(binary);
+
+            // Boxed so that a pipeline stage is one call deep rather than
+            // the whole operator ladder, which would otherwise overflow the
+            // 2 MiB stack of a spawned thread in debug builds.
+            let disjunction = conjunction
+                .clone()
+                .then(or.then(conjunction).repeated())
+                .foldl(binary)
+                .boxed();
+
+            let pipe = just("|>").then_ignore(text::whitespace()).to(BinOp::Pipe);
+
+            let pipeline = disjunction
+                .clone()
+                .then(pipe.then(disjunction).repeated())
+                .foldl(binary);
+
+            pipeline
+                .then(
+                    just(":=")
+                        .then_ignore(text::whitespace())
+                        .ignore_then(expr)
+                        .or_not(),
+                )
+                .try_map(|(lhs, rhs), span| match (lhs, rhs) {
+                    (lhs, None) => Ok(lhs),
+                    (
+                        Expr {
+                            kind: ExprKind::Index(target, index),
+                            span: lhs_span,
+                        },
+                        Some(value),
+                    ) => {
+                        let span = lhs_span.start..value.span.end;
+                        let kind = ExprKind::IndexAssign {
+                            target,
+                            index,
+                            value: Box::new(value),
+                        };
+                        Ok(Expr::new(kind, span))
+                    }
+                    (_, Some(_)) => Err(Simple::custom(
+                        span,
+                        "Only an indexing expression like `xs[0]` can be assigned to",
+                    )),
+                })
+        });
+
+        let rhs = expr
+            .clone()
+            .recover_with(skip_until([';'], |span| Expr::new(ExprKind::Error, span)));
+
+        // `lazy` is only a modifier here, so `let lazy = 1;` still binds a
+        // variable named `lazy`.
+        let binding = text::keyword("lazy")
+            .padded()
+            .ignore_then(ident.padded())
+            .map(|name| (true, name))
+            .or(ident.padded().map(|name| (false, name)));
+
+        // `@deprecated("message")` before a declaration. The message is a
+        // plain string, without interpolation.
+        let deprecated = just('@')
+            .ignore_then(text::keyword("deprecated"))
+            .ignore_then(
+                none_of("\\\"")
+                    .or(escape)
+                    .repeated()
+                    .collect::<String>()
+                    .delimited_by(just('"'), just('"'))
+                    .padded()
+                    .delimited_by(just('('), just(')')),
+            )
+            .then_ignore(text::whitespace())
+            .or_not();
+
+        let r#let = deprecated
+            .clone()
+            .then_ignore(text::keyword("let"))
+            .then(binding)
+            .then_ignore(just('='))
+            .then(rhs.clone())
+            .then_ignore(close(';'))
+            .map_with_span(|head, span: Span| (head, span))
+            .then(decl.clone())
+            .map(|((((deprecated, (lazy, name)), rhs), span), then)| {
+                let kind = ExprKind::Let {
+                    name,
+                    lazy,
+                    deprecated,
+                    rhs: Box::new(rhs),
+                    then: Box::new(then),
+                };
+                Expr::new(kind, span)
+            });
+
+        let r#fn = deprecated
+            .then_ignore(text::keyword("fn"))
+            .then(ident.padded())
+            .then(ident.padded().repeated())
+            .then_ignore(just('='))
+            .then(rhs)
+            .then_ignore(close(';'))
+            .map_with_span(|head, span: Span| (head, span))
+            .then(decl)
+            .map(|(((((deprecated, name), args), body), span), then)| {
+                let kind = ExprKind::Fn {
+                    name,
+                    args,
+                    deprecated,
+                    body: Box::new(body),
+                    then: Box::new(then),
+                };
+                Expr::new(kind, span)
+            });
+
+        let tail = if prelude {
+            expr.or(end().map_with_span(|(), span| Expr::new(ExprKind::Error, span)))
+                .boxed()
+        } else {
+            expr.boxed()
+        };
+
+        r#let.or(r#fn).or(tail).padded()
+    })
+}
+
+/// Fails where an expression would be nested more than [`MAX_NESTING`]
+/// levels deep.
+///
+/// The parsers recurse for each level of nesting, and so do the passes over
+/// the tree, even dropping it, so hostile input like thousands of `(`, `-`,
+/// `let`s or `+ 1`s overflows the stack. Input that isn't trusted should be
+/// checked with this before parsing; [`parse_program`] does both.
+///
+/// The depth is estimated from the tokens, without parsing: each bracket
+/// counts as a level, and within a bracket so does each operator, postfix
+/// bracket like the `[0]` of `xs[0]`, and each `let`, `fn`, `if`, `for` or
+/// lambda, whose body extends to the end of the bracket. Operators stop
+/// counting at the next `;` or `,`, declarations at the next `,`. This can
+/// overestimate, never underestimate, and `src` is expected to have its
+/// comments blanked.
+///
+/// ```
+/// use kyomusky::parser::check_nesting;
+///
+/// assert!(check_nesting(&"(".repeat(1000)).is_ok());
+/// assert!(check_nesting(&"(".repeat(2000)).is_err());
+/// assert!(check_nesting(&"-".repeat(2000)).is_err());
+/// assert!(check_nesting(&"let a = 1; ".repeat(2000)).is_err());
+/// // Siblings don't add up.
+/// assert!(check_nesting(&"[1 + 1, 2 ^ 3], ".repeat(2000)).is_ok());
+/// ```
+pub fn check_nesting(src: &str) -> Result<(), Simple<char>> {
+    /// The tokens counted within one bracket.
+    #[derive(Default)]
+    struct Level {
+        /// Whether this is the `${` of an interpolation, whose `}` goes back
+        /// into the string.
+        interpolation: bool,
+        /// Operators and postfix brackets since the last `;` or `,`.
+        operators: usize,
+        /// Declarations and lambdas since the last `,`.
+        declarations: usize,
+    }
+
+    // The outermost level is the source itself, which has no bracket.
+    let mut open = vec![Level::default()];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = src.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
+        let nested = open.len() > 1;
+        let level = open.last_mut().expect("the outermost level is never closed");
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                '$' if matches!(chars.peek(), Some((_, '{'))) => {
+                    chars.next();
+                    in_string = false;
+                    open.push(Level {
+                        interpolation: true,
+                        ..Level::default()
+                    });
+                    depth += 1;
+                }
+                _ => {}
+            }
+        } else {
+            match c {
+                '"' => in_string = true,
+                '(' | '[' | '{' => {
+                    open.push(Level::default());
+                    depth += 1;
+                }
+                ')' | ']' | '}' if nested => {
+                    let closed = open.pop().expect("checked above");
+                    depth -= 1 + closed.operators + closed.declarations;
+                    in_string = closed.interpolation;
+                    // The bracket may be an index or call of what's before it.
+                    open.last_mut().expect("checked above").operators += 1;
+                    depth += 1;
+                }
+                ';' => {
+                    depth -= level.operators;
+                    level.operators = 0;
+                }
+                ',' => {
+                    depth -= level.operators + level.declarations;
+                    level.operators = 0;
+                    level.declarations = 0;
+                }
+                '+' | '-' | '*' | '/' | '^' | '!' | '=' | '<' | '>' | '&' | '|' | ':' => {
+                    level.operators += 1;
+                    depth += 1;
+                }
+                '\\' => {
+                    level.declarations += 1;
+                    depth += 1;
+                }
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut word = String::from(c);
+                    while let Some(&(_, c)) = chars.peek() {
+                        if !(c.is_alphanumeric() || c == '_') {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    match word.as_str() {
+                        "let" | "fn" | "if" | "for" => level.declarations += 1,
+                        "not" | "and" | "or" => level.operators += 1,
+                        _ => continue,
+                    }
+                    depth += 1;
+                }
+                _ => {}
+            }
+        }
+        if depth > MAX_NESTING {
+            return Err(Simple::custom(
+                i..i + 1,
+                format!("expression nesting exceeds the limit of {MAX_NESTING}"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces each `//` comment, which runs to the end of its line, with as
+/// many spaces, so that the parsers only have whitespace to skip and spans
+/// still count the characters of `src`.
+///
+/// ```
+/// use kyomusky::parser::blank_comments;
+///
+/// assert_eq!(blank_comments("1 + 2 // three\n// ..."), "1 + 2         \n      ");
+/// assert_eq!(blank_comments(r#""a // b" // c"#), r#""a // b"     "#);
+/// assert_eq!(blank_comments("8 / 2"), "8 / 2");
+/// ```
+pub fn blank_comments(src: &str) -> std::borrow::Cow<'_, str> {
+    if !src.contains("//") {
+        return src.into();
+    }
+    // Strings are tracked as in `check_nesting`, for `//` inside them.
+    let mut out = String::with_capacity(src.len());
+    let mut open = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut in_comment = false;
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_comment {
+            in_comment = c != '\n';
+            out.push(if in_comment { ' ' } else { c });
+            continue;
+        }
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                '$' if chars.peek() == Some(&'{') => {
+                    out.push(c);
+                    out.extend(chars.next());
+                    in_string = false;
+                    open.push(true);
+                    continue;
+                }
+                _ => {}
+            }
+        } else {
+            match c {
+                '/' if chars.peek() == Some(&'/') => {
+                    in_comment = true;
+                    out.push(' ');
+                    continue;
+                }
+                '"' => in_string = true,
+                '(' | '[' | '{' => open.push(false),
+                ')' | ']' | '}' => in_string = open.pop() == Some(true),
+                _ => {}
+            }
+        }
+        out.push(c);
+    }
+    out.into()
+}
+
+/// Describes a syntax error. Unlike its `Display`, this shows the message of
+/// errors raised with `Simple::custom`, such as keywords used as names.
+pub fn error_message(e: &Simple<char>) -> String {
+    match e.reason() {
+        SimpleReason::Custom(message) => message.clone(),
+        _ => e.to_string(),
+    }
+}
+
+/// Parses a program made of a single declaration chain.
+pub fn parser() -> impl Parser<char, Expr, Error = Simple<char>> {
+    decl(ParseOptions::default(), false).then_ignore(end())
+}
+
+/// Parses a program made of one or more independent declaration chains, such
+/// as a scratch file with one calculation per line.
+///
+/// Since whitespace (including newlines) is insignificant, a line starting
+/// with an operator like `-` continues the expression on the line before.
+/// See [`ParseOptions::newline_terminates`] to change that.
+pub fn program() -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
+    program_with(ParseOptions::default())
+}
+
+pub fn program_with(options: ParseOptions) -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
+    decl(options, false)
+        .repeated()
+        .at_least(1)
+        .then_ignore(end())
+}
+
+/// Parses a program with [`program_with`], after making sure it is not
+/// nested too deeply to be parsed safely. `//` starts a comment running to
+/// the end of the line, see [`blank_comments`]. A program with nothing but
+/// whitespace and comments is an error of its own, spanning the whole
+/// source, rather than a complaint about the missing first expression.
+///
+/// ```
+/// use kyomusky::parser::{error_message, parse_program, ParseOptions};
+///
+/// let parse = |src| parse_program(src, ParseOptions::default());
+/// for src in ["[1, 2,]", "max(1, 2,)", "{\"a\": 1,}"] {
+///     assert!(parse(src).is_ok(), "{src}");
+/// }
+/// for src in ["[1,,2]", "max(,)", "{,}"] {
+///     assert!(parse(src).is_err(), "{src}");
+/// }
+///
+/// assert_eq!(parse("1 + // one\n2 // two").unwrap(), parse("1 + 2").unwrap());
+///
+/// for src in ["", " \n\t\n", "// nothing here\n  // yet"] {
+///     let errs = parse(src).unwrap_err();
+///     assert_eq!(errs.len(), 1);
+///     assert_eq!(error_message(&errs[0]), "empty program: nothing to evaluate");
+///     assert_eq!(errs[0].span(), 0..src.len());
+/// }
+/// ```
+pub fn parse_program(src: &str, options: ParseOptions) -> Result<Vec<Expr>, Vec<Simple<char>>> {
+    let src = &*blank_comments(src);
+    if src.trim().is_empty() {
+        return Err(vec![Simple::custom(
+            0..src.chars().count(),
+            "empty program: nothing to evaluate",
+        )]);
+    }
+    check_nesting(src).map_err(|e| vec![e])?;
+    program_with(options).parse(src)
+}
+
+/// A tree parsed by [`parse_with_spans`], with the source it came from, to
+/// find where its nodes are in bytes rather than in the characters of a
+/// [`Span`].
+pub struct SpannedExpr<'src> {
+    pub expr: Expr,
+    pub src: &'src str,
+}
+
+impl<'src> SpannedExpr<'src> {
+    /// The byte range of `node`, a node of this tree, in the source.
+    pub fn byte_range(&self, node: &Expr) -> std::ops::Range<usize> {
+        let offset = |i: usize| {
+            self.src
+                .char_indices()
+                .nth(i)
+                .map_or(self.src.len(), |(b, _)| b)
+        };
+        offset(node.span.start)..offset(node.span.end)
+    }
+
+    /// The source text of `node`.
+    pub fn text(&self, node: &Expr) -> &'src str {
+        &self.src[self.byte_range(node)]
+    }
+
+    /// The byte range of the first node in pre-order of the kind named `kind`,
+    /// see [`ExprKind::name`].
+    pub fn range_of(&self, kind: &str) -> Option<std::ops::Range<usize>> {
+        let node = self.expr.find(|e| e.kind.name() == kind)?;
+        Some(self.byte_range(node))
+    }
+}
+
+/// Parses a single declaration chain, like [`parser`] after [`check_nesting`],
+/// keeping the source to look up spans in.
+///
+/// ```
+/// use kyomusky::parser::parse_with_spans;
+///
+/// let src = "let x = 1 + 20; x";
+/// let tree = parse_with_spans(src).unwrap();
+/// assert_eq!(tree.range_of("Let"), Some(0..15));
+/// assert_eq!(tree.range_of("Binary"), Some(8..14));
+/// assert_eq!(tree.range_of("Num"), Some(8..9));
+/// let nums: Vec<_> = tree.expr.iter().filter(|e| e.kind.name() == "Num").collect();
+/// assert_eq!(tree.byte_range(nums[1]), 12..14);
+/// assert_eq!(tree.text(nums[1]), "20");
+/// assert_eq!(tree.range_of("Var"), Some(16..17));
+///
+/// // Spans count characters, byte ranges count bytes.
+/// let tree = parse_with_spans(r#""é" + "b""#).unwrap();
+/// let strs: Vec<_> = tree.expr.iter().filter(|e| e.kind.name() == "Str").collect();
+/// assert_eq!(strs[1].span, 6..9);
+/// assert_eq!(tree.byte_range(strs[1]), 7..10);
+/// assert_eq!(tree.text(strs[1]), r#""b""#);
+///
+/// assert!(parse_with_spans("1 +").is_err());
+/// ```
+pub fn parse_with_spans(src: &str) -> Result<SpannedExpr<'_>, Vec<Simple<char>>> {
+    let blanked = blank_comments(src);
+    check_nesting(&blanked).map_err(|e| vec![e])?;
+    let expr = parser().parse(&*blanked)?;
+    Ok(SpannedExpr { expr, src })
+}
+
+/// Whether `src` fails to parse only because it ends too early, as with an
+/// unclosed bracket or string, a trailing operator or a declaration with
+/// nothing after it, so that more lines could complete it. A REPL can then
+/// keep reading rather than report the errors.
+///
+/// ```
+/// use kyomusky::parser::{is_incomplete, ParseOptions};
+///
+/// let incomplete = |src| is_incomplete(src, ParseOptions::default());
+/// for src in ["f(1,", "(1 +", "1 +\n", "[1, 2", "\"abc", "fn f x =\n", "{ let x = 1;"] {
+///     assert!(incomplete(src), "{src:?}");
+/// }
+/// // Complete programs, and syntax errors no amount of input can fix.
+/// for src in ["f(1)", "1 + 2\n", "(1 + ]", "1 + )", "let if = 1; 2", ""] {
+///     assert!(!incomplete(src), "{src:?}");
+/// }
+/// ```
+pub fn is_incomplete(src: &str, options: ParseOptions) -> bool {
+    if blank_comments(src).trim().is_empty() {
+        return false;
+    }
+    let end = src.chars().count();
+    match parse_program(src, options) {
+        Ok(_) => false,
+        Err(errs) => errs
+            .iter()
+            .all(|e| e.found().is_none() && e.span().start == end),
+    }
+}
+
+/// Parses a prelude: `let` and `fn` declarations with no expression after
+/// them, to be put in front of programs with [`crate::prelude::prepend`]. The
+/// declarations are returned as a chain ending in an empty `Error` node.
+pub fn parse_prelude(src: &str) -> Result<Expr, Vec<Simple<char>>> {
+    let src = &*blank_comments(src);
+    check_nesting(src).map_err(|e| vec![e])?;
+    let chain = decl(ParseOptions::default(), true)
+        .then_ignore(end())
+        .parse(src)?;
+    let mut tail = &chain;
+    while let ExprKind::Let { then, .. } | ExprKind::Fn { then, .. } = &tail.kind {
+        tail = then;
+    }
+    if !matches!(tail.kind, ExprKind::Error) {
+        return Err(vec![Simple::custom(
+            tail.span.clone(),
+            "a prelude can only contain `let` and `fn` declarations",
+        )]);
+    }
+    Ok(chain)
+}
@@ -0,0 +1,43 @@
+//! Functions available to every program without being declared, written in
+//! the language itself. Unlike built-ins, they are ordinary declarations put
+//! in front of each declaration chain, so a program declaring a function of
+//! the same name simply shadows them.
+
+use crate::ast::{Expr, ExprKind};
+use crate::visit::VisitorMut;
+
+/// The prelude used unless `--no-prelude` or `--prelude` is given. Parse it
+/// with [`crate::parser::parse_prelude`].
+pub const PRELUDE: &str = include_str!("prelude.ky");
+
+/// Where [`relocate`] moves the spans of a prelude, far past the end of any
+/// program it is put in front of.
+pub const OFFSET: usize = 1 << 40;
+
+/// Moves every span of `prelude` by [`OFFSET`], so that errors inside it can
+/// be told apart from errors in the program.
+pub fn relocate(prelude: &mut Expr) {
+    struct Relocate;
+    impl VisitorMut for Relocate {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            expr.span = expr.span.start + OFFSET..expr.span.end + OFFSET;
+            self.walk_expr_mut(expr);
+        }
+    }
+    Relocate.visit_expr_mut(prelude);
+}
+
+/// Puts the declarations of `prelude`, as returned by `parse_prelude`, in
+/// front of `chain`.
+pub fn prepend(prelude: &Expr, chain: Expr) -> Expr {
+    let mut program = prelude.clone();
+    let mut tail = &mut program;
+    while matches!(tail.kind, ExprKind::Let { .. } | ExprKind::Fn { .. }) {
+        let (ExprKind::Let { then, .. } | ExprKind::Fn { then, .. }) = &mut tail.kind else {
+            unreachable!();
+        };
+        tail = then;
+    }
+    *tail = chain;
+    program
+}
@@ -0,0 +1,212 @@
+//! Resolution of variables to slots, so that evaluation reads a variable from
+//! its position in scope instead of searching the scope for its name.
+//!
+//! A variable's slot is how many variables were declared after it in the
+//! scope where it is read (its de Bruijn index), which the lexical scoping of
+//! variables fixes before evaluation. The slots are kept beside the tree,
+//! by node, rather than in it, and evaluation looks a variable up by name
+//! when it has none.
+
+use crate::ast::{Expr, ExprKind};
+use crate::visit::Visitor;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::marker::PhantomData;
+
+/// The slots of the variables of a tree, see [`resolve`].
+#[derive(Default)]
+pub struct Slots<'a> {
+    slots: HashMap<*const Expr, usize, BuildHasherDefault<AddressHasher>>,
+    tree: PhantomData<&'a Expr>,
+}
+
+/// Hashes the address of a node, much faster than the default hasher, which
+/// would take longer than looking most variables up by name.
+#[derive(Default)]
+struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("only addresses are hashed");
+    }
+
+    fn write_usize(&mut self, address: usize) {
+        // Spread the bits of the address, whose lowest ones are always zero,
+        // over the whole hash (Fibonacci hashing).
+        self.0 = (address as u64 >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+impl Slots<'_> {
+    /// The slot of `var`, a `Var` node of the resolved tree, or `None` if it
+    /// is not a variable declared in the tree or its slot isn't known.
+    pub fn get(&self, var: &Expr) -> Option<usize> {
+        self.slots.get(&(var as *const Expr)).copied()
+    }
+}
+
+/// Resolves each variable of `expr` to its slot in the scope it is read in.
+///
+/// Variables are in scope in the order evaluation declares them: those a
+/// function sees where it is declared, then its parameters, in order, then
+/// its `let`s and `for` variables. A function declared in a block is
+/// different: called before its declaration, it sees the variables in scope
+/// at the start of the block instead, so only the variables declared in its
+/// body get a slot. Neither do names of functions used as values.
+///
+/// ```
+/// use kyomusky::ast::ExprKind;
+/// use kyomusky::parser::{parse_program, ParseOptions};
+/// use kyomusky::resolve::resolve;
+///
+/// let slots = |src: &str| {
+///     let program = parse_program(src, ParseOptions::default()).unwrap();
+///     let slots = resolve(&program[0]);
+///     program[0]
+///         .iter()
+///         .filter_map(|node| match &node.kind {
+///             ExprKind::Var(name) => Some((name.clone(), slots.get(node))),
+///             _ => None,
+///         })
+///         .collect::<Vec<_>>()
+/// };
+///
+/// let src = "let a = 1; let b = 2; let c = a + b; let d = { let a = c; [a, b] }; [a, d]";
+/// let expected = [("a", 1), ("b", 0), ("c", 0), ("a", 0), ("b", 2), ("a", 3), ("d", 0)];
+/// let expected = expected.map(|(name, slot)| (name.to_string(), Some(slot)));
+/// assert_eq!(slots(src), expected);
+///
+/// let src = "let a = 1; fn f x y = x + y + a; \\z -> [z, a, f]";
+/// let expected = [("x", 1), ("y", 0), ("a", 2), ("z", 0), ("a", 1)];
+/// let mut expected = expected.map(|(name, slot)| (name.to_string(), Some(slot))).to_vec();
+/// expected.push(("f".to_string(), None));
+/// assert_eq!(slots(src), expected);
+///
+/// let src = "let a = 1; { let b = 2; fn g x = { let y = x; [y, b, a] }; g(b) }";
+/// let expected = [("x", Some(0)), ("y", Some(0)), ("b", None), ("a", None), ("b", Some(0))];
+/// let expected = expected.map(|(name, slot)| (name.to_string(), slot));
+/// assert_eq!(slots(src), expected);
+/// ```
+pub fn resolve(expr: &Expr) -> Slots<'_> {
+    let mut resolver = SlotResolver::default();
+    resolver.visit_expr(expr);
+    Slots {
+        slots: resolver.slots,
+        tree: PhantomData,
+    }
+}
+
+#[derive(Default)]
+struct SlotResolver<'a> {
+    /// The variables in scope, as evaluation declares them.
+    vars: Vec<&'a str>,
+    /// How many of `vars` may not be in scope at these positions, in the
+    /// body of a function declared in a block.
+    unknown: usize,
+    /// The functions declared in the blocks being visited.
+    hoisted: HashSet<*const Expr>,
+    slots: HashMap<*const Expr, usize, BuildHasherDefault<AddressHasher>>,
+}
+
+impl<'a> SlotResolver<'a> {
+    /// Visits `body` with `params` in scope, after the variables in scope
+    /// now.
+    fn visit_body(&mut self, params: &'a [String], body: &'a Expr) {
+        let depth = self.vars.len();
+        self.vars.extend(params.iter().map(String::as_str));
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+}
+
+impl<'a> Visitor<'a> for SlotResolver<'a> {
+    fn visit_var(&mut self, expr: &'a Expr, name: &'a str) {
+        if let Some(i) = self.vars.iter().rposition(|var| *var == name) {
+            if i >= self.unknown {
+                self.slots.insert(expr, self.vars.len() - 1 - i);
+            }
+        }
+    }
+
+    fn visit_let(&mut self, _expr: &'a Expr, name: &'a str, rhs: &'a Expr, then: &'a Expr) {
+        self.visit_expr(rhs);
+        self.vars.push(name);
+        self.visit_expr(then);
+        self.vars.pop();
+    }
+
+    fn visit_block(&mut self, _expr: &'a Expr, chain: &'a Expr) {
+        for decl in chain.declarations() {
+            if let ExprKind::Fn { .. } = decl.kind {
+                self.hoisted.insert(decl);
+            }
+        }
+        self.visit_expr(chain);
+    }
+
+    fn visit_fn(
+        &mut self,
+        expr: &'a Expr,
+        _name: &'a str,
+        params: &'a [String],
+        body: &'a Expr,
+        then: &'a Expr,
+    ) {
+        let unknown = self.unknown;
+        if self.hoisted.contains(&(expr as *const Expr)) {
+            self.unknown = self.vars.len();
+        }
+        self.visit_body(params, body);
+        self.unknown = unknown;
+        self.visit_expr(then);
+    }
+
+    fn visit_lambda(&mut self, _expr: &'a Expr, params: &'a [String], body: &'a Expr) {
+        self.visit_body(params, body);
+    }
+
+    fn visit_for(&mut self, _expr: &'a Expr, var: &'a str, list: &'a Expr, body: &'a Expr) {
+        self.visit_expr(list);
+        self.vars.push(var);
+        self.visit_expr(body);
+        self.vars.pop();
+    }
+
+    fn visit_if_let(
+        &mut self,
+        _expr: &'a Expr,
+        name: &'a str,
+        value: &'a Expr,
+        then: &'a Expr,
+        otherwise: &'a Expr,
+    ) {
+        self.visit_expr(value);
+        self.vars.push(name);
+        self.visit_expr(then);
+        self.vars.pop();
+        self.visit_expr(otherwise);
+    }
+
+    fn visit_comprehension(
+        &mut self,
+        _expr: &'a Expr,
+        body: &'a Expr,
+        generators: &'a [(String, Expr)],
+        filter: Option<&'a Expr>,
+    ) {
+        let depth = self.vars.len();
+        for (var, list) in generators {
+            self.visit_expr(list);
+            self.vars.push(var);
+        }
+        if let Some(filter) = filter {
+            self.visit_expr(filter);
+        }
+        self.visit_expr(body);
+        self.vars.truncate(depth);
+    }
+}
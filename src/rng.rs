@@ -0,0 +1,58 @@
+//! The pseudo-random number generator behind `shuffle`, `sample` and
+//! `random`.
+//!
+//! A given seed always produces the same sequence, on every platform:
+//!
+//! ```
+//! use kyomusky::rng::Rng;
+//!
+//! let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+//! let draws: Vec<_> = (0..5).map(|_| a.below(100)).collect();
+//! assert_eq!(draws, (0..5).map(|_| b.below(100)).collect::<Vec<_>>());
+//! ```
+
+/// SplitMix64: tiny, fast, and good enough for anything but cryptography.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeded from the current time, for runs without `--seed`.
+    pub fn from_time() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::new(now.as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..1`, with the 53 bits of precision of an `f64`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number in `0..n`, which must not be empty.
+    pub fn below(&mut self, n: usize) -> usize {
+        // The high bits of the product are close enough to uniform for any
+        // list that fits in memory.
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_time()
+    }
+}
@@ -0,0 +1,153 @@
+//! Running a whole file the way the command line does by default, from
+//! source to values, without printing anything, so that the outcome can be
+//! checked by tests.
+
+use crate::analysis::{Diagnostic, Severity};
+use crate::desugar::desugar;
+use crate::encoding::{self, Encoding};
+use crate::eval::{eval_catching_panics, Env, EvalError, Value};
+use crate::input::DataInput;
+use crate::parser::{error_message, parse_prelude, parse_program, ParseOptions};
+use crate::prelude::{self, prepend, PRELUDE};
+use std::io;
+use std::path::Path;
+
+/// How running a program ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunResult {
+    /// Every declaration chain was evaluated.
+    Value {
+        /// The value of each declaration chain.
+        values: Vec<Value>,
+        /// What `print` wrote.
+        output: String,
+    },
+    /// The program failed to parse. Nothing was evaluated.
+    Parse(Vec<Diagnostic>),
+    /// Evaluating a declaration chain failed, and the chains after it were
+    /// not evaluated. Errors inside the prelude have spans past
+    /// [`prelude::OFFSET`].
+    Eval(EvalError),
+}
+
+/// Reads the UTF-8 file at `path` and runs it with [`run_source`].
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::{run_file, RunResult};
+///
+/// let dir = std::env::temp_dir().join(format!("kyomusky-run-file-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let run = |name: &str, src: &str| {
+///     let path = dir.join(name);
+///     std::fs::write(&path, src).unwrap();
+///     run_file(&path).unwrap()
+/// };
+///
+/// let RunResult::Value { values, output } = run("ok.ky", "print(1 + 2)\nmax(4, 5)") else {
+///     panic!("expected values");
+/// };
+/// assert_eq!(values.iter().map(|v| v.to_string()).collect::<Vec<_>>(), ["3", "5"]);
+/// assert_eq!(output, "3\n");
+///
+/// let RunResult::Parse(diagnostics) = run("parse.ky", "1 +") else {
+///     panic!("expected a parse error");
+/// };
+/// assert_eq!(diagnostics.len(), 1);
+/// assert_eq!(diagnostics[0].span, 3..3);
+///
+/// let RunResult::Eval(err) = run("eval.ky", "1\nx + 1") else {
+///     panic!("expected an evaluation error");
+/// };
+/// assert_eq!(err.kind, ErrorKind::UnboundVariable);
+///
+/// assert!(run_file(dir.join("missing.ky")).is_err());
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn run_file(path: impl AsRef<Path>) -> io::Result<RunResult> {
+    let src = encoding::read(path, Encoding::Utf8)?;
+    Ok(run_source(&src))
+}
+
+/// Parses `src`, puts the built-in prelude in front of each declaration
+/// chain, and evaluates them in turn. `input()` finds no input, and a panic
+/// in the interpreter ends the run with an `internal` error.
+pub fn run_source(src: &str) -> RunResult {
+    let program = match parse_program(src, ParseOptions::default()) {
+        Ok(program) => program,
+        Err(mut errs) => {
+            errs.sort_by_key(|e| (e.span().start, e.span().end));
+            let diagnostics = errs
+                .into_iter()
+                .map(|e| Diagnostic {
+                    span: e.span(),
+                    severity: Severity::Error,
+                    message: error_message(&e),
+                    lint: None,
+                    secondary: None,
+                })
+                .collect();
+            return RunResult::Parse(diagnostics);
+        }
+    };
+
+    let mut prelude = desugar(parse_prelude(PRELUDE).expect("the built-in prelude is valid"));
+    prelude::relocate(&mut prelude);
+    let program: Vec<_> = program
+        .into_iter()
+        .map(|ast| prepend(&prelude, desugar(ast)))
+        .collect();
+
+    let mut output = Vec::new();
+    let mut values = Vec::new();
+    for chain in &program {
+        let mut env = Env::new();
+        env.input = Box::new(DataInput::new(""));
+        env.output = Box::new(&mut output);
+        match eval_catching_panics(chain, &mut env) {
+            Ok(value) => values.push(value),
+            Err(err) => return RunResult::Eval(err),
+        }
+    }
+    RunResult::Value {
+        values,
+        output: String::from_utf8_lossy(&output).into_owned(),
+    }
+}
+
+/// Evaluates the first declaration chain of `src` in a fresh [`Env`], and
+/// returns its value as printed. Unlike [`run_source`] the prelude is left
+/// out, which keeps examples and tests about a single feature short.
+///
+/// This is public, rather than `#[doc(hidden)]`, because the examples in
+/// this crate's documentation are compiled as separate crates and can only
+/// reach public items, and so that embedders can write the same one-line
+/// checks against the interpreter in their own tests.
+///
+/// # Panics
+///
+/// If `src` has syntax errors or is empty, since it is meant for sources
+/// written in the test calling it.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+///
+/// assert_eq!(eval_str("let x = 2; x * 3").unwrap(), "6");
+/// assert_eq!(eval_str("x").unwrap_err().kind, ErrorKind::UnboundVariable);
+/// ```
+pub fn eval_str(src: &str) -> Result<String, EvalError> {
+    eval_str_with(src, |_| {})
+}
+
+/// Like [`eval_str`], but lets `setup` change the [`Env`] first, e.g. to
+/// turn on an option of the interpreter or register a function. A panic in
+/// such a function is returned as an `internal` error.
+pub fn eval_str_with(src: &str, setup: impl FnOnce(&mut Env)) -> Result<String, EvalError> {
+    let program = parse_program(src, ParseOptions::default()).expect("`src` has no syntax errors");
+    let chain = desugar(program.into_iter().next().expect("`src` is not empty"));
+    let mut env = Env::new();
+    setup(&mut env);
+    let result = eval_catching_panics(&chain, &mut env);
+    result.map(|value| value.to_string())
+}
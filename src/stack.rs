@@ -0,0 +1,76 @@
+//! Evaluation on a thread with a known stack size, so that recursion too deep
+//! for it fails with an error instead of crashing the process.
+//!
+//! Values are not `Send`, so rather than moving an `Env` to another thread,
+//! the whole evaluation, environment included, runs inside the closure given
+//! to [`with_stack`].
+
+use std::cell::Cell;
+
+/// The smallest stack size accepted by the command line.
+pub const MIN_SIZE: usize = 1 << 20;
+
+/// Stack kept in reserve below the limit, for the frames of a single
+/// evaluation step (which are large in debug builds) and error reporting.
+const RESERVE: usize = 256 * 1024;
+
+thread_local! {
+    /// The address below which evaluation stops, or 0 if unknown.
+    static LIMIT: Cell<usize> = const { Cell::new(0) };
+}
+
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Runs `f` on a new thread with a stack of `bytes` bytes, and returns its
+/// result. Evaluation within `f` fails with a `stack` error once the stack
+/// is nearly exhausted.
+///
+/// This takes the place of an `eval_with_stack(expr, env, bytes)`: an `Env`
+/// can't be sent to another thread, so it is created inside `f` instead.
+///
+/// ```
+/// use kyomusky::eval::ErrorKind;
+/// use kyomusky::run::eval_str;
+/// use kyomusky::stack::with_stack;
+///
+/// let src = "fn count n = if let some(k) = find(\\x -> x > 0, [n]) then 1 + count(k - 1) else 0;
+///            count(100000)";
+/// let err = with_stack(1 << 20, || eval_str(src)).unwrap_err();
+/// assert_eq!(err.kind, ErrorKind::StackExhausted);
+/// ```
+pub fn with_stack<T: Send>(bytes: usize, f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(bytes)
+            .spawn_scoped(scope, || {
+                // The stack grows downwards from about here.
+                LIMIT.set(stack_address().saturating_sub(bytes.saturating_sub(RESERVE)));
+                f()
+            })
+            .expect("failed to spawn the evaluation thread");
+        match thread.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })
+}
+
+/// Whether the current thread is running out of the stack given to
+/// [`with_stack`]. Always false on other threads.
+pub(crate) fn exhausted() -> bool {
+    stack_address() < LIMIT.get()
+}
+
+/// Parses a size in bytes like `65536`, `512K`, `64M` or `1G`.
+pub fn parse_size(s: &str) -> Option<usize> {
+    let (digits, unit) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 1 << 10),
+        (i, 'M' | 'm') => (&s[..i], 1 << 20),
+        (i, 'G' | 'g') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
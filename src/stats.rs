@@ -0,0 +1,158 @@
+//! Size and shape metrics of a syntax tree.
+
+use crate::ast::{Expr, ExprKind};
+use crate::visit::Visitor;
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AstStats {
+    /// Total number of nodes.
+    pub nodes: usize,
+    /// Number of nodes on the longest path from a root to a leaf; a single
+    /// literal has depth 1.
+    pub depth: usize,
+    /// Number of nodes of each `ExprKind` variant present, most frequent
+    /// first (ties in name order).
+    pub per_variant: Vec<(&'static str, usize)>,
+}
+
+/// Collects statistics over every declaration chain of a program.
+pub fn ast_stats(program: &[Expr]) -> AstStats {
+    let mut collector = Collector::default();
+    for expr in program {
+        collector.visit_expr(expr);
+    }
+    let mut stats = collector.stats;
+    stats
+        .per_variant
+        .sort_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)));
+    stats
+}
+
+#[derive(Default)]
+struct Collector {
+    stats: AstStats,
+    /// Depth of the node being visited.
+    depth: usize,
+}
+
+impl Visitor<'_> for Collector {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.depth += 1;
+        let stats = &mut self.stats;
+        stats.nodes += 1;
+        stats.depth = stats.depth.max(self.depth);
+
+        let name = expr.kind.name();
+        match stats.per_variant.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => stats.per_variant.push((name, 1)),
+        }
+
+        self.walk_expr(expr);
+        self.depth -= 1;
+    }
+}
+
+impl fmt::Display for AstStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "nodes: {}", self.nodes)?;
+        writeln!(f, "depth: {}", self.depth)?;
+        for (name, count) in &self.per_variant {
+            writeln!(f, "  {name:<12}{count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Measurements of a single declaration chain, computed without recursion so
+/// that even pathologically deep trees can be measured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub nodes: usize,
+    /// See [`AstStats::depth`].
+    pub depth: usize,
+    /// See [`AstStats::per_variant`].
+    pub per_variant: Vec<(&'static str, usize)>,
+    /// Number of distinct variable names, bound or used.
+    pub variables: usize,
+    /// Number of distinct function names, declared or called.
+    pub functions: usize,
+    /// The largest number of arguments passed in a single call.
+    pub max_call_args: usize,
+    /// The largest number of `let` declarations enclosing each other. Each
+    /// declaration in a chain encloses the rest of it.
+    pub max_let_depth: usize,
+}
+
+impl Metrics {
+    pub fn of(expr: &Expr) -> Metrics {
+        let mut metrics = Metrics::default();
+        let mut variables = BTreeSet::new();
+        let mut functions = BTreeSet::new();
+
+        // Nodes to visit, with their depth and the number of `let`s around
+        // them (including themselves).
+        let mut stack = vec![(expr, 1, 0)];
+        while let Some((expr, depth, mut lets)) = stack.pop() {
+            metrics.nodes += 1;
+            metrics.depth = metrics.depth.max(depth);
+
+            let name = expr.kind.name();
+            match metrics.per_variant.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, count)) => *count += 1,
+                None => metrics.per_variant.push((name, 1)),
+            }
+
+            match &expr.kind {
+                ExprKind::Var(name) => {
+                    variables.insert(name);
+                }
+                ExprKind::Call(name, args) => {
+                    functions.insert(name);
+                    metrics.max_call_args = metrics.max_call_args.max(args.len());
+                }
+                ExprKind::Apply(_, args) => {
+                    metrics.max_call_args = metrics.max_call_args.max(args.len());
+                }
+                ExprKind::Let { name, .. } => {
+                    variables.insert(name);
+                    lets += 1;
+                    metrics.max_let_depth = metrics.max_let_depth.max(lets);
+                }
+                ExprKind::Fn { name, args, .. } => {
+                    functions.insert(name);
+                    variables.extend(args);
+                }
+                _ => {}
+            }
+
+            for child in expr.children() {
+                stack.push((child, depth + 1, lets));
+            }
+        }
+
+        metrics.variables = variables.len();
+        metrics.functions = functions.len();
+        metrics
+            .per_variant
+            .sort_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)));
+        metrics
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "nodes: {}", self.nodes)?;
+        writeln!(f, "depth: {}", self.depth)?;
+        writeln!(f, "variables: {}", self.variables)?;
+        writeln!(f, "functions: {}", self.functions)?;
+        writeln!(f, "max_call_args: {}", self.max_call_args)?;
+        writeln!(f, "max_let_depth: {}", self.max_let_depth)?;
+        for (name, count) in &self.per_variant {
+            writeln!(f, "nodes.{name}: {count}")?;
+        }
+        Ok(())
+    }
+}
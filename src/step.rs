@@ -0,0 +1,88 @@
+//! Stepping through evaluation one node at a time, for `--step`.
+
+use crate::ast::Expr;
+use crate::eval::Value;
+use std::io::Write;
+
+/// Called by [`eval`](crate::eval::eval) before each node, when set as
+/// [`Env::debugger`](crate::eval::Env::debugger).
+pub trait Debugger {
+    /// `vars` are the variables in scope, innermost last, shadowed ones
+    /// included.
+    fn before(&mut self, expr: &Expr, vars: &[(&String, Value)]);
+}
+
+/// Shows each node and the variables in scope, then waits for a line of
+/// input: an empty one steps to the next node, `c` runs to completion
+/// without stopping again. Running out of input also continues.
+///
+/// ```
+/// use kyomusky::eval::{eval, Env};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+/// use kyomusky::step::Stepper;
+///
+/// let program = parse_program("let x = 1; x + 2", ParseOptions::default()).unwrap();
+/// let mut out = Vec::new();
+/// let mut env = Env::new();
+/// env.debugger = Some(Box::new(Stepper::new(["", "", "c"].map(String::from), &mut out)));
+/// assert_eq!(eval(&program[0], &mut env).unwrap().to_string(), "3");
+/// drop(env);
+///
+/// let out = String::from_utf8(out).unwrap();
+/// let steps: Vec<_> = out.split("step ").skip(1).map(|s| s.lines().next().unwrap()).collect();
+/// assert_eq!(steps, ["1: let x = 1;", "2: 1", "3: x + 2"]);
+/// assert!(out.contains("  x = 1\n"));
+/// ```
+pub struct Stepper<I, W> {
+    input: I,
+    out: W,
+    steps: usize,
+    /// Set by `c`: stop pausing.
+    running: bool,
+}
+
+impl<I: Iterator<Item = String>, W: Write> Stepper<I, W> {
+    /// Reads commands from the lines of `input`, and writes to `out`.
+    pub fn new(input: impl IntoIterator<IntoIter = I>, out: W) -> Self {
+        Self {
+            input: input.into_iter(),
+            out,
+            steps: 0,
+            running: false,
+        }
+    }
+
+    /// Writes the node about to be evaluated and the variables visible to
+    /// it. Errors writing are ignored, as stepping is best effort.
+    fn show(&mut self, expr: &Expr, vars: &[(&String, Value)]) -> std::io::Result<()> {
+        // Declarations print the rest of their chain on the following lines.
+        let text = expr.to_string();
+        writeln!(
+            self.out,
+            "step {}: {}",
+            self.steps,
+            text.lines().next().unwrap_or("")
+        )?;
+        for (i, (name, value)) in vars.iter().enumerate() {
+            if !vars[i + 1..].iter().any(|(n, _)| n == name) {
+                writeln!(self.out, "  {name} = {value}")?;
+            }
+        }
+        write!(self.out, "(Enter: step, c: continue) ")?;
+        self.out.flush()
+    }
+}
+
+impl<I: Iterator<Item = String>, W: Write> Debugger for Stepper<I, W> {
+    fn before(&mut self, expr: &Expr, vars: &[(&String, Value)]) {
+        if self.running {
+            return;
+        }
+        self.steps += 1;
+        let _ = self.show(expr, vars);
+        match self.input.next() {
+            Some(line) if line.trim() != "c" => {}
+            _ => self.running = true,
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Color themes for the reports printed on the command line, selected with
+//! `--theme`.
+
+use ariadne::{Color, ColorGenerator, Config};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    /// ariadne's colors, with generated colors for labels.
+    #[default]
+    Default,
+    /// Labels in bright primary colors, easier to tell apart.
+    HighContrast,
+    /// No colors at all, i.e. no ANSI escapes in the output.
+    Monochrome,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Default, Theme::HighContrast, Theme::Monochrome];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::HighContrast => "high-contrast",
+            Theme::Monochrome => "monochrome",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|theme| theme.name() == name)
+    }
+
+    /// The configuration to build every report with.
+    ///
+    /// ```
+    /// use ariadne::{Label, Report, ReportKind, Source};
+    /// use kyomusky::theme::Theme;
+    ///
+    /// let render = |theme: Theme| {
+    ///     let mut colors = theme.label_colors();
+    ///     let mut out = Vec::new();
+    ///     Report::build(ReportKind::Error, "f", 0)
+    ///         .with_config(theme.config())
+    ///         .with_message("Syntax error")
+    ///         .with_label(Label::new(("f", 0..1)).with_color(colors.next_color()))
+    ///         .finish()
+    ///         .write(("f", Source::from("1 +")), &mut out)
+    ///         .unwrap();
+    ///     String::from_utf8(out).unwrap()
+    /// };
+    ///
+    /// assert!(render(Theme::Default).contains('\x1b'));
+    /// assert!(render(Theme::HighContrast).contains('\x1b'));
+    /// let plain = render(Theme::Monochrome);
+    /// assert!(!plain.contains('\x1b'));
+    /// assert!(plain.starts_with("Error: Syntax error"));
+    /// ```
+    pub fn config(self) -> Config {
+        Config::default().with_color(self != Theme::Monochrome)
+    }
+
+    /// The colors to give labels, in turn.
+    pub fn label_colors(self) -> LabelColors {
+        LabelColors {
+            theme: self,
+            generator: ColorGenerator::new(),
+            next: 0,
+        }
+    }
+}
+
+const HIGH_CONTRAST: [Color; 4] = [
+    Color::BrightYellow,
+    Color::BrightCyan,
+    Color::BrightMagenta,
+    Color::BrightGreen,
+];
+
+pub struct LabelColors {
+    theme: Theme,
+    generator: ColorGenerator,
+    next: usize,
+}
+
+impl LabelColors {
+    pub fn next_color(&mut self) -> Color {
+        match self.theme {
+            Theme::Default => self.generator.next(),
+            Theme::HighContrast => {
+                let color = HIGH_CONTRAST[self.next % HIGH_CONTRAST.len()];
+                self.next += 1;
+                color
+            }
+            // Not shown, as the configuration disables colors.
+            Theme::Monochrome => Color::Primary,
+        }
+    }
+}
+
+/// The theme of the reports printed by this process.
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+/// Makes `theme` the one [`current`] returns from now on.
+pub fn set(theme: Theme) {
+    CURRENT.store(theme as u8, Ordering::Relaxed);
+}
+
+pub fn current() -> Theme {
+    Theme::ALL[CURRENT.load(Ordering::Relaxed) as usize]
+}
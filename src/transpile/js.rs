@@ -0,0 +1,308 @@
+//! Emits a self-contained JavaScript program computing the same value as a
+//! program.
+//!
+//! Only numeric programs can be translated. JavaScript numbers are IEEE
+//! doubles just like the interpreter's, so arithmetic gives identical results.
+//! The differences that remain are handled by the generated runtime:
+//!
+//! - JavaScript silently produces `Infinity` or `NaN` for `1 / 0` and
+//!   overflows, while the interpreter reports an evaluation error. Every
+//!   operation is checked and throws with the interpreter's message instead.
+//! - JavaScript prints very large and very small numbers in exponent notation
+//!   (`1e+21`), and `-0` as `0`. Results are formatted to match the
+//!   interpreter's output instead.
+//!
+//! Functions become JavaScript functions which close over the variables in
+//! scope where they are declared, and calls are resolved to the functions
+//! visible where the call is written.
+//!
+//! Every name is mangled to `v<N>_name` (variables and parameters) or
+//! `f<N>_name` (functions) with a unique `N`, so names can't clash with
+//! reserved words, with each other when redeclared, or with the runtime's
+//! helpers, which start with `__`.
+
+use super::TranspileError;
+use crate::ast::{BinOp, Expr, ExprKind, UnaryOp};
+use std::fmt::Write;
+
+const RUNTIME: &str = r#""use strict";
+
+function __check(result, lhs, rhs, message) {
+  if (Number.isFinite(result) || !Number.isFinite(lhs) || !Number.isFinite(rhs)) {
+    return result;
+  }
+  throw new Error(message || `Arithmetic overflow: result is ${__fmt(result)}`);
+}
+
+function __add(lhs, rhs) { return __check(lhs + rhs, lhs, rhs); }
+function __sub(lhs, rhs) { return __check(lhs - rhs, lhs, rhs); }
+function __mul(lhs, rhs) { return __check(lhs * rhs, lhs, rhs); }
+function __div(lhs, rhs) {
+  return __check(lhs / rhs, lhs, rhs, rhs === 0 ? "Division by zero" : undefined);
+}
+function __pow(lhs, rhs) {
+  const result = lhs ** rhs;
+  return __check(result, lhs, rhs,
+    Number.isNaN(result) ? `Fractional power of negative number ${__fmt(lhs)}` : undefined);
+}
+
+// Formats a number the way the interpreter does: never in exponent notation.
+function __fmt(x) {
+  if (Number.isNaN(x)) return "NaN";
+  if (x === Infinity) return "inf";
+  if (x === -Infinity) return "-inf";
+  if (Object.is(x, -0)) return "-0";
+  const s = String(x);
+  const e = s.indexOf("e");
+  if (e < 0) return s;
+  const sign = s[0] === "-" ? "-" : "";
+  const mantissa = s.slice(sign.length, e);
+  const exponent = Number(s.slice(e + 1));
+  const point = mantissa.indexOf(".");
+  const digits = mantissa.replace(".", "");
+  const intLen = (point < 0 ? mantissa.length : point) + exponent;
+  if (intLen <= 0) return `${sign}0.${"0".repeat(-intLen)}${digits}`;
+  if (intLen >= digits.length) return sign + digits + "0".repeat(intLen - digits.length);
+  return `${sign}${digits.slice(0, intLen)}.${digits.slice(intLen)}`;
+}
+"#;
+
+struct Name {
+    name: String,
+    mangled: String,
+}
+
+struct Function {
+    name: Name,
+    arity: usize,
+}
+
+#[derive(Default)]
+struct Emitter {
+    vars: Vec<Name>,
+    fns: Vec<Function>,
+    /// Number of names declared so far, used to make them unique.
+    declared: usize,
+}
+
+/// Translates a program into JavaScript. When `module` is set, the output is
+/// an ES module exporting a `run()` function that returns the result (or
+/// throws) instead of a script printing it.
+pub fn transpile(program: &Expr, module: bool) -> Result<String, TranspileError> {
+    let mut emitter = Emitter::default();
+    let body = emitter.decl(program)?;
+
+    let mut out = String::from(RUNTIME);
+    if module {
+        write!(out, "\nexport function run() {{\n{body}}}\n").unwrap();
+    } else {
+        write!(
+            out,
+            "\nfunction __main() {{\n{body}}}\n\ntry {{\n  console.log(__fmt(__main()));\n}} catch (e) {{\n  console.log(`Evaluation error: ${{e.message}}`);\n  process.exitCode = 1;\n}}\n"
+        )
+        .unwrap();
+    }
+    Ok(out)
+}
+
+impl Emitter {
+    fn declare(&mut self, name: &str, prefix: char) -> Name {
+        let mangled = format!("{prefix}{}_{name}", self.declared);
+        self.declared += 1;
+        Name {
+            name: name.to_string(),
+            mangled,
+        }
+    }
+
+    /// Translates a declaration chain into the statements of a function body
+    /// returning its value.
+    fn decl(&mut self, expr: &Expr) -> Result<String, TranspileError> {
+        let indent = "  ";
+        let mut out = String::new();
+
+        let mut decl = expr;
+        loop {
+            match &decl.kind {
+                ExprKind::Let { lazy: true, .. } => {
+                    return Err(TranspileError::new(
+                        "lazy bindings are not supported",
+                        &decl.span,
+                    ))
+                }
+                ExprKind::Let {
+                    name, rhs, then, ..
+                } => {
+                    let rhs = self.expr(rhs)?;
+                    let var = self.declare(name, 'v');
+                    writeln!(out, "{indent}const {} = {rhs};", var.mangled).unwrap();
+                    self.vars.push(var);
+                    decl = then;
+                }
+                ExprKind::Fn {
+                    name,
+                    args,
+                    body,
+                    then,
+                    ..
+                } => {
+                    let name = self.declare(name, 'f');
+                    let mangled = name.mangled.clone();
+                    // Declared before the body is translated so that it can
+                    // recurse.
+                    self.fns.push(Function {
+                        name,
+                        arity: args.len(),
+                    });
+
+                    let params: Vec<_> = args.iter().map(|arg| self.declare(arg, 'v')).collect();
+                    let list = params
+                        .iter()
+                        .map(|p| p.mangled.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let vars = self.vars.len();
+                    self.vars.extend(params);
+                    let body = self.expr(body)?;
+                    self.vars.truncate(vars);
+
+                    writeln!(out, "{indent}function {mangled}({list}) {{").unwrap();
+                    writeln!(out, "{indent}  return {body};").unwrap();
+                    writeln!(out, "{indent}}}").unwrap();
+                    decl = then;
+                }
+                _ => {
+                    writeln!(out, "{indent}return {};", self.expr(decl)?).unwrap();
+                    break;
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn expr(&self, expr: &Expr) -> Result<String, TranspileError> {
+        let binary = |helper: &str, a: &Expr, b: &Expr| {
+            Ok(format!("{helper}({}, {})", self.expr(a)?, self.expr(b)?))
+        };
+
+        match &expr.kind {
+            ExprKind::Num(x) => Ok(format!("{x:?}")),
+            ExprKind::Var(name) => match self.vars.iter().rev().find(|v| v.name == *name) {
+                Some(var) => Ok(var.mangled.clone()),
+                None => Err(TranspileError::new(
+                    format!("variable `{name}` is not declared before this point"),
+                    &expr.span,
+                )),
+            },
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                operand,
+            } => Ok(format!("(-{})", self.expr(operand)?)),
+            ExprKind::Binary {
+                op: op @ (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow),
+                lhs,
+                rhs,
+            } => {
+                let helper = match op {
+                    BinOp::Add => "__add",
+                    BinOp::Sub => "__sub",
+                    BinOp::Mul => "__mul",
+                    BinOp::Div => "__div",
+                    _ => "__pow",
+                };
+                binary(helper, lhs, rhs)
+            }
+            ExprKind::Call(name, args) => {
+                let Some(f) = self.fns.iter().rev().find(|f| f.name.name == *name) else {
+                    return Err(TranspileError::new(
+                        format!("function `{name}` is not declared before this point"),
+                        &expr.span,
+                    ));
+                };
+                if let Some(arg) = args
+                    .iter()
+                    .find(|arg| matches!(arg.kind, ExprKind::Spread(_)))
+                {
+                    return Err(TranspileError::new(
+                        "spread arguments are not supported",
+                        &arg.span,
+                    ));
+                }
+                if f.arity != args.len() {
+                    return Err(TranspileError::new(
+                        format!(
+                            "function `{name}` takes {} arguments but {} were given",
+                            f.arity,
+                            args.len()
+                        ),
+                        &expr.span,
+                    ));
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("{}({})", f.name.mangled, args.join(", ")))
+            }
+            ExprKind::Error => Err(TranspileError::new(
+                "the program has syntax errors",
+                &expr.span,
+            )),
+            ExprKind::NamedArg(..) => Err(TranspileError::new(
+                "named arguments are not supported",
+                &expr.span,
+            )),
+            ExprKind::Spread(_) => Err(TranspileError::new(
+                "spread arguments are not supported",
+                &expr.span,
+            )),
+            ExprKind::Apply(..) => Err(TranspileError::new(
+                "only functions can be called, by name",
+                &expr.span,
+            )),
+            ExprKind::Block(_) => Err(TranspileError::new("blocks are not supported", &expr.span)),
+            ExprKind::Binary {
+                op: BinOp::Pipe, ..
+            } => Err(TranspileError::new(
+                "`|>` must be desugared first",
+                &expr.span,
+            )),
+            ExprKind::Comprehension { .. } => Err(TranspileError::new(
+                "list comprehensions must be desugared first",
+                &expr.span,
+            )),
+            ExprKind::For { .. } => Err(TranspileError::new("`for` is not supported", &expr.span)),
+            ExprKind::None | ExprKind::IfLet { .. } => Err(TranspileError::new(
+                "optional values are not supported",
+                &expr.span,
+            )),
+            ExprKind::Lambda { .. } => {
+                Err(TranspileError::new("lambdas are not supported", &expr.span))
+            }
+            ExprKind::Str(_) | ExprKind::InterpolatedStr(_) => {
+                Err(TranspileError::new("strings are not supported", &expr.span))
+            }
+            // The remaining operators: comparisons, `&&` and `||`.
+            ExprKind::Bool(_)
+            | ExprKind::Binary { .. }
+            | ExprKind::Unary {
+                op: UnaryOp::Not, ..
+            } => Err(TranspileError::new(
+                "booleans are not supported",
+                &expr.span,
+            )),
+            ExprKind::List(_)
+            | ExprKind::Map(_)
+            | ExprKind::Index(..)
+            | ExprKind::IndexAssign { .. } => Err(TranspileError::new(
+                "lists and maps are not supported",
+                &expr.span,
+            )),
+            ExprKind::Let { .. } | ExprKind::Fn { .. } => Err(TranspileError::new(
+                "declarations are only supported at the top level",
+                &expr.span,
+            )),
+        }
+    }
+}
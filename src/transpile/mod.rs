@@ -0,0 +1,28 @@
+//! Translation of programs into other languages.
+
+use crate::ast::Span;
+
+pub mod js;
+pub mod rust;
+
+/// A construct that has no equivalent in the target language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranspileError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl TranspileError {
+    fn new(message: impl Into<String>, span: &Span) -> Self {
+        Self {
+            message: message.into(),
+            span: span.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Cannot transpile: {}", self.message)
+    }
+}
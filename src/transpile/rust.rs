@@ -0,0 +1,254 @@
+//! Emits a standalone Rust `main.rs` computing the same value as a program.
+//!
+//! Every value is an `f64`, so only numeric programs can be translated.
+//! Functions become top-level Rust functions, which can't capture local
+//! variables: a function body may only use its own parameters. Calls are
+//! resolved to the functions visible where the call is written.
+//!
+//! Names are mangled so they can never clash with Rust keywords or with each
+//! other: variables become `v_name`, and the `N`th function declared becomes
+//! `fN_name`, which keeps redefinitions of the same name apart.
+//! Helpers of the generated runtime start with `__`, which mangled names never
+//! do.
+
+use super::TranspileError;
+use crate::ast::{BinOp, Expr, ExprKind, UnaryOp};
+use std::fmt::Write;
+
+/// Arithmetic helpers mirroring the interpreter, which treats non-finite
+/// results of finite operands as an error.
+const RUNTIME: &str = r#"#![allow(non_snake_case)]
+
+fn __check(result: f64, lhs: f64, rhs: f64, message: &str) -> f64 {
+    if result.is_finite() || !lhs.is_finite() || !rhs.is_finite() {
+        return result;
+    }
+    if message.is_empty() {
+        println!("Evaluation error: Arithmetic overflow: result is {result}");
+    } else {
+        println!("Evaluation error: {message}");
+    }
+    std::process::exit(1);
+}
+
+#[allow(dead_code)]
+fn __add(lhs: f64, rhs: f64) -> f64 {
+    __check(lhs + rhs, lhs, rhs, "")
+}
+
+#[allow(dead_code)]
+fn __sub(lhs: f64, rhs: f64) -> f64 {
+    __check(lhs - rhs, lhs, rhs, "")
+}
+
+#[allow(dead_code)]
+fn __mul(lhs: f64, rhs: f64) -> f64 {
+    __check(lhs * rhs, lhs, rhs, "")
+}
+
+#[allow(dead_code)]
+fn __div(lhs: f64, rhs: f64) -> f64 {
+    let message = if rhs == 0.0 { "Division by zero" } else { "" };
+    __check(lhs / rhs, lhs, rhs, message)
+}
+
+#[allow(dead_code)]
+fn __pow(lhs: f64, rhs: f64) -> f64 {
+    let result = lhs.powf(rhs);
+    if result.is_nan() && lhs.is_finite() && rhs.is_finite() {
+        println!("Evaluation error: Fractional power of negative number {lhs}");
+        std::process::exit(1);
+    }
+    __check(result, lhs, rhs, "")
+}
+"#;
+
+struct Function {
+    name: String,
+    mangled: String,
+    arity: usize,
+}
+
+#[derive(Default)]
+struct Emitter {
+    /// Functions in scope, innermost last.
+    fns: Vec<Function>,
+    /// Number of functions declared so far, used to make names unique.
+    declared: usize,
+    /// Rendered function items.
+    items: Vec<String>,
+}
+
+/// Translates a program into the source of a Rust program printing its
+/// result.
+pub fn transpile(program: &Expr) -> Result<String, TranspileError> {
+    let mut emitter = Emitter::default();
+    let mut main = String::new();
+
+    let mut decl = program;
+    loop {
+        match &decl.kind {
+            ExprKind::Let { lazy: true, .. } => {
+                return Err(TranspileError::new(
+                    "lazy bindings are not supported",
+                    &decl.span,
+                ))
+            }
+            ExprKind::Let {
+                name, rhs, then, ..
+            } => {
+                let rhs = emitter.expr(rhs, None)?;
+                writeln!(main, "    let v_{name}: f64 = {rhs};").unwrap();
+                decl = then;
+            }
+            ExprKind::Fn {
+                name,
+                args,
+                body,
+                then,
+                ..
+            } => {
+                emitter.function(name, args, body)?;
+                decl = then;
+            }
+            _ => {
+                let result = emitter.expr(decl, None)?;
+                writeln!(main, "    let result: f64 = {result};").unwrap();
+                break;
+            }
+        }
+    }
+
+    let mut out = String::from(RUNTIME);
+    for item in &emitter.items {
+        write!(out, "\n{item}").unwrap();
+    }
+    write!(
+        out,
+        "\nfn main() {{\n{main}    println!(\"{{result}}\");\n}}\n"
+    )
+    .unwrap();
+    Ok(out)
+}
+
+impl Emitter {
+    fn function(&mut self, name: &str, args: &[String], body: &Expr) -> Result<(), TranspileError> {
+        let mangled = format!("f{}_{name}", self.declared);
+        self.declared += 1;
+        // Declared before the body is translated so that it can recurse.
+        self.fns.push(Function {
+            name: name.to_string(),
+            mangled: mangled.clone(),
+            arity: args.len(),
+        });
+
+        let params = args
+            .iter()
+            .map(|arg| format!("v_{arg}: f64"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let body = self.expr(body, Some(args))?;
+        self.items.push(format!(
+            "fn {mangled}({params}) -> f64 {{\n    {body}\n}}\n"
+        ));
+        Ok(())
+    }
+
+    /// Translates an expression. Inside a function body, `params` lists the
+    /// only variables it may refer to.
+    fn expr(&self, expr: &Expr, params: Option<&[String]>) -> Result<String, TranspileError> {
+        let binary = |helper: &str, a: &Expr, b: &Expr| {
+            Ok(format!(
+                "{helper}({}, {})",
+                self.expr(a, params)?,
+                self.expr(b, params)?
+            ))
+        };
+
+        match &expr.kind {
+            ExprKind::Num(x) => Ok(format!("{x:?}")),
+            ExprKind::Var(name) => match params {
+                Some(params) if !params.contains(name) => Err(TranspileError::new(
+                    format!("function bodies can only use their parameters, but `{name}` is captured from the enclosing scope"),
+                    &expr.span,
+                )),
+                _ => Ok(format!("v_{name}")),
+            },
+            ExprKind::Unary { op: UnaryOp::Neg, operand } => Ok(format!("-({})", self.expr(operand, params)?)),
+            ExprKind::Binary {
+                op: op @ (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow),
+                lhs,
+                rhs,
+            } => {
+                let helper = match op {
+                    BinOp::Add => "__add",
+                    BinOp::Sub => "__sub",
+                    BinOp::Mul => "__mul",
+                    BinOp::Div => "__div",
+                    _ => "__pow",
+                };
+                binary(helper, lhs, rhs)
+            }
+            ExprKind::Call(name, args) => {
+                let Some(f) = self.fns.iter().rev().find(|f| f.name == *name) else {
+                    return Err(TranspileError::new(
+                        format!("function `{name}` is not declared before this point"),
+                        &expr.span,
+                    ));
+                };
+                if let Some(arg) = args.iter().find(|arg| matches!(arg.kind, ExprKind::Spread(_))) {
+                    return Err(TranspileError::new(
+                        "spread arguments are not supported",
+                        &arg.span,
+                    ));
+                }
+                if f.arity != args.len() {
+                    return Err(TranspileError::new(
+                        format!(
+                            "function `{name}` takes {} arguments but {} were given",
+                            f.arity,
+                            args.len()
+                        ),
+                        &expr.span,
+                    ));
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| self.expr(arg, params))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("{}({})", f.mangled, args.join(", ")))
+            }
+            ExprKind::Error => Err(TranspileError::new("the program has syntax errors", &expr.span)),
+            ExprKind::NamedArg(..) => Err(TranspileError::new(
+                "named arguments are not supported",
+                &expr.span,
+            )),
+            ExprKind::Spread(_) => Err(TranspileError::new(
+                "spread arguments are not supported",
+                &expr.span,
+            )),
+            ExprKind::Apply(..) => Err(TranspileError::new(
+                "only functions can be called, by name",
+                &expr.span,
+            )),
+            ExprKind::Block(_) => Err(TranspileError::new("blocks are not supported", &expr.span)),
+            ExprKind::Binary { op: BinOp::Pipe, .. } => Err(TranspileError::new("`|>` must be desugared first", &expr.span)),
+            ExprKind::Comprehension { .. } => Err(TranspileError::new("list comprehensions must be desugared first", &expr.span)),
+            ExprKind::For { .. } => Err(TranspileError::new("`for` is not supported", &expr.span)),
+            ExprKind::None | ExprKind::IfLet { .. } => Err(TranspileError::new("optional values are not supported", &expr.span)),
+            ExprKind::Lambda { .. } => Err(TranspileError::new("lambdas are not supported", &expr.span)),
+            ExprKind::Str(_) | ExprKind::InterpolatedStr(_) => Err(TranspileError::new("strings are not supported", &expr.span)),
+            // The remaining operators: comparisons, `&&` and `||`.
+            ExprKind::Bool(_)
+            | ExprKind::Binary { .. }
+            | ExprKind::Unary { op: UnaryOp::Not, .. } => Err(TranspileError::new("booleans are not supported", &expr.span)),
+            ExprKind::List(_) | ExprKind::Map(_) | ExprKind::Index(..) | ExprKind::IndexAssign { .. } => {
+                Err(TranspileError::new("lists and maps are not supported", &expr.span))
+            }
+            ExprKind::Let { .. } | ExprKind::Fn { .. } => Err(TranspileError::new(
+                "declarations are only supported at the top level",
+                &expr.span,
+            )),
+        }
+    }
+}
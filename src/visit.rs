@@ -0,0 +1,276 @@
+//! Traversal of syntax trees.
+//!
+//! A pass implements [`Visitor`] (or [`VisitorMut`] to rewrite the tree in
+//! place) and overrides only the hooks it cares about. The default hooks visit
+//! every child in source order, so a pass cannot forget an edge, and
+//! `walk_expr` matches every `ExprKind` variant explicitly, so adding one
+//! fails to compile until the traversal knows about it.
+
+use crate::ast::{Expr, ExprKind, StrPart};
+
+/// A read-only pass over a tree. The lifetime lets a pass keep references
+/// into the tree it visits.
+///
+/// ```
+/// use kyomusky::ast::{Expr, ExprKind};
+/// use kyomusky::parser::{parse_program, ParseOptions};
+/// use kyomusky::visit::Visitor;
+///
+/// struct CountNums(usize);
+///
+/// impl Visitor<'_> for CountNums {
+///     fn visit_expr(&mut self, expr: &Expr) {
+///         if matches!(expr.kind, ExprKind::Num(_)) {
+///             self.0 += 1;
+///         }
+///         self.walk_expr(expr);
+///     }
+/// }
+///
+/// let program = parse_program("1 + f(2, x) * 3", ParseOptions::default()).unwrap();
+/// let mut counter = CountNums(0);
+/// counter.visit_expr(&program[0]);
+/// assert_eq!(counter.0, 3);
+/// ```
+pub trait Visitor<'a> {
+    /// Called on every node. Overriding it without calling `walk_expr`
+    /// prunes the subtree.
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        self.walk_expr(expr);
+    }
+
+    fn visit_var(&mut self, _expr: &'a Expr, _name: &'a str) {}
+
+    fn visit_call(&mut self, _expr: &'a Expr, _name: &'a str, args: &'a [Expr]) {
+        for arg in args {
+            self.visit_expr(arg);
+        }
+    }
+
+    fn visit_let(&mut self, _expr: &'a Expr, _name: &'a str, rhs: &'a Expr, then: &'a Expr) {
+        self.visit_expr(rhs);
+        self.visit_expr(then);
+    }
+
+    fn visit_fn(
+        &mut self,
+        _expr: &'a Expr,
+        _name: &'a str,
+        _params: &'a [String],
+        body: &'a Expr,
+        then: &'a Expr,
+    ) {
+        self.visit_expr(body);
+        self.visit_expr(then);
+    }
+
+    /// `chain` is the declaration chain between the braces.
+    fn visit_block(&mut self, _expr: &'a Expr, chain: &'a Expr) {
+        self.visit_expr(chain);
+    }
+
+    fn visit_lambda(&mut self, _expr: &'a Expr, _params: &'a [String], body: &'a Expr) {
+        self.visit_expr(body);
+    }
+
+    fn visit_for(&mut self, _expr: &'a Expr, _var: &'a str, list: &'a Expr, body: &'a Expr) {
+        self.visit_expr(list);
+        self.visit_expr(body);
+    }
+
+    /// `name` is only in scope in `then`.
+    fn visit_if_let(
+        &mut self,
+        _expr: &'a Expr,
+        _name: &'a str,
+        value: &'a Expr,
+        then: &'a Expr,
+        otherwise: &'a Expr,
+    ) {
+        self.visit_expr(value);
+        self.visit_expr(then);
+        self.visit_expr(otherwise);
+    }
+
+    /// Visits the lists, then the filter, then the body, in the order their
+    /// variables come into scope.
+    fn visit_comprehension(
+        &mut self,
+        _expr: &'a Expr,
+        body: &'a Expr,
+        generators: &'a [(String, Expr)],
+        filter: Option<&'a Expr>,
+    ) {
+        for (_, list) in generators {
+            self.visit_expr(list);
+        }
+        if let Some(filter) = filter {
+            self.visit_expr(filter);
+        }
+        self.visit_expr(body);
+    }
+
+    /// Visits the children of `expr`, dispatching to the specific hooks.
+    fn walk_expr(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Error
+            | ExprKind::Num(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_)
+            | ExprKind::None => {}
+            ExprKind::Var(name) => self.visit_var(expr, name),
+            ExprKind::Unary { operand: a, .. } | ExprKind::NamedArg(_, a) | ExprKind::Spread(a) => {
+                self.visit_expr(a)
+            }
+            ExprKind::Block(chain) => self.visit_block(expr, chain),
+            ExprKind::Binary { lhs: a, rhs: b, .. } | ExprKind::Index(a, b) => {
+                self.visit_expr(a);
+                self.visit_expr(b);
+            }
+            ExprKind::List(items) => items.iter().for_each(|item| self.visit_expr(item)),
+            ExprKind::InterpolatedStr(parts) => {
+                for part in parts {
+                    if let StrPart::Expr(e) = part {
+                        self.visit_expr(e);
+                    }
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (k, v) in entries {
+                    self.visit_expr(k);
+                    self.visit_expr(v);
+                }
+            }
+            ExprKind::IndexAssign {
+                target,
+                index,
+                value,
+            } => {
+                self.visit_expr(target);
+                self.visit_expr(index);
+                self.visit_expr(value);
+            }
+            ExprKind::Call(name, args) => self.visit_call(expr, name, args),
+            ExprKind::Apply(callee, args) => {
+                self.visit_expr(callee);
+                args.iter().for_each(|arg| self.visit_expr(arg));
+            }
+            ExprKind::Let {
+                name, rhs, then, ..
+            } => self.visit_let(expr, name, rhs, then),
+            ExprKind::Fn {
+                name,
+                args,
+                body,
+                then,
+                ..
+            } => self.visit_fn(expr, name, args, body, then),
+            ExprKind::Lambda { args, body } => self.visit_lambda(expr, args, body),
+            ExprKind::For { var, list, body } => self.visit_for(expr, var, list, body),
+            ExprKind::IfLet {
+                name,
+                value,
+                then,
+                otherwise,
+            } => self.visit_if_let(expr, name, value, then, otherwise),
+            ExprKind::Comprehension {
+                body,
+                generators,
+                filter,
+            } => self.visit_comprehension(expr, body, generators, filter.as_deref()),
+        }
+    }
+}
+
+/// Like [`Visitor`], but with mutable access to every node, for passes that
+/// rewrite the tree in place.
+pub trait VisitorMut {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        self.walk_expr_mut(expr);
+    }
+
+    /// Visits the children of `expr` in source order.
+    fn walk_expr_mut(&mut self, expr: &mut Expr) {
+        match &mut expr.kind {
+            ExprKind::Error
+            | ExprKind::Num(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_)
+            | ExprKind::None
+            | ExprKind::Var(_) => {}
+            ExprKind::Unary { operand: a, .. }
+            | ExprKind::NamedArg(_, a)
+            | ExprKind::Spread(a)
+            | ExprKind::Block(a)
+            | ExprKind::Lambda { body: a, .. } => self.visit_expr_mut(a),
+            ExprKind::Binary { lhs: a, rhs: b, .. }
+            | ExprKind::Index(a, b)
+            | ExprKind::For {
+                list: a, body: b, ..
+            } => {
+                self.visit_expr_mut(a);
+                self.visit_expr_mut(b);
+            }
+            ExprKind::List(items) | ExprKind::Call(_, items) => {
+                items.iter_mut().for_each(|item| self.visit_expr_mut(item))
+            }
+            ExprKind::InterpolatedStr(parts) => {
+                for part in parts {
+                    if let StrPart::Expr(e) = part {
+                        self.visit_expr_mut(e);
+                    }
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (k, v) in entries {
+                    self.visit_expr_mut(k);
+                    self.visit_expr_mut(v);
+                }
+            }
+            ExprKind::IndexAssign {
+                target,
+                index,
+                value,
+            } => {
+                self.visit_expr_mut(target);
+                self.visit_expr_mut(index);
+                self.visit_expr_mut(value);
+            }
+            ExprKind::Apply(callee, args) => {
+                self.visit_expr_mut(callee);
+                args.iter_mut().for_each(|arg| self.visit_expr_mut(arg));
+            }
+            ExprKind::Let { rhs, then, .. } => {
+                self.visit_expr_mut(rhs);
+                self.visit_expr_mut(then);
+            }
+            ExprKind::Fn { body, then, .. } => {
+                self.visit_expr_mut(body);
+                self.visit_expr_mut(then);
+            }
+            ExprKind::IfLet {
+                value,
+                then,
+                otherwise,
+                ..
+            } => {
+                self.visit_expr_mut(value);
+                self.visit_expr_mut(then);
+                self.visit_expr_mut(otherwise);
+            }
+            ExprKind::Comprehension {
+                body,
+                generators,
+                filter,
+            } => {
+                self.visit_expr_mut(body);
+                for (_, list) in generators {
+                    self.visit_expr_mut(list);
+                }
+                if let Some(filter) = filter {
+                    self.visit_expr_mut(filter);
+                }
+            }
+        }
+    }
+}
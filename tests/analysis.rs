@@ -0,0 +1,143 @@
+//! Static analyses of parsed programs.
+
+use chumsky::Parser;
+use kyomusky::analysis::{call_graph, free_variables};
+use kyomusky::ast::Expr;
+use kyomusky::build::{call, fn_, let_, num, var};
+use kyomusky::parser::program;
+use kyomusky::stats::{ast_stats, Metrics};
+use std::collections::BTreeSet;
+
+#[test]
+fn ast_stats_of_a_small_program() {
+    let program = program().parse("let x = 1 + 2; x * 3").unwrap();
+    let stats = ast_stats(&program);
+    assert_eq!(stats.nodes, 7);
+    assert_eq!(stats.depth, 3);
+    let per_variant = [("Num", 3), ("Binary", 2), ("Let", 1), ("Var", 1)];
+    assert_eq!(stats.per_variant, per_variant);
+}
+
+#[test]
+fn ast_stats_add_up_over_expressions() {
+    let program = program().parse("1\n-(2 * 3)").unwrap();
+    let stats = ast_stats(&program);
+    assert_eq!(stats.nodes, 5);
+    assert_eq!(stats.depth, 3);
+}
+
+#[test]
+fn metrics_of_a_hand_built_tree() {
+    // let x = 1; fn f a b = a + b * x; f(x, 2)
+    let body = var("a") + var("b") * var("x");
+    let program = let_(
+        "x",
+        num(1.0),
+        fn_("f", ["a", "b"], body, call("f", [var("x"), num(2.0)])),
+    );
+    let metrics = Metrics::of(&program);
+    assert_eq!(metrics.nodes, 11);
+    assert_eq!(metrics.depth, 5);
+    assert_eq!(metrics.variables, 3);
+    assert_eq!(metrics.functions, 1);
+    assert_eq!(metrics.max_call_args, 2);
+    assert_eq!(metrics.max_let_depth, 1);
+    let per_variant = [
+        ("Var", 4),
+        ("Binary", 2),
+        ("Num", 2),
+        ("Call", 1),
+        ("Fn", 1),
+        ("Let", 1),
+    ];
+    assert_eq!(metrics.per_variant, per_variant);
+}
+
+fn parse(src: &str) -> Expr {
+    program().parse(src).unwrap().remove(0)
+}
+
+#[test]
+fn metrics_agree_with_the_iterator() {
+    for src in [
+        "1",
+        "let a = 1; let b = a * 2; [a, b, f(a, b, 3)]",
+        "fn f xs = xs[0] * 2; f([1, 2, 3])",
+        r#"let m = {"k": 4}; -m["k"]"#,
+    ] {
+        let program = parse(src);
+        let metrics = Metrics::of(&program);
+        assert_eq!(metrics.nodes, program.iter().count(), "{src}");
+        assert_eq!(metrics.nodes, program.node_count(), "{src}");
+        assert_eq!(metrics.depth, program.depth(), "{src}");
+    }
+    let program = parse("let a = 1; let b = 2; let c = 3; c");
+    assert_eq!(Metrics::of(&program).max_let_depth, 3);
+}
+
+fn names(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn free_variables_respect_let_and_parameters() {
+    let program = parse("let a = 1; fn f x = x + a + b; f(c)");
+    assert_eq!(free_variables(&program), names(&["b", "c"]));
+    // `y` is only bound after its own right-hand side.
+    assert_eq!(free_variables(&parse("let y = y + 1; y")), names(&["y"]));
+    let program = parse("[x * k for x in xs if x > 0]");
+    assert_eq!(free_variables(&program), names(&["k", "xs"]));
+}
+
+#[test]
+fn variable_free_in_a_body_but_bound_at_the_use_site() {
+    // Scoping is lexical: `n` in the body of `f` is not the `n` bound where
+    // `f` is called.
+    let program = parse("fn f = n; let n = 1; f()");
+    assert_eq!(free_variables(&program), names(&["n"]));
+    let body = parse("fn f x = x + n; 0");
+    assert_eq!(free_variables(&body), names(&["n"]));
+}
+
+#[test]
+fn recursion_is_a_self_edge() {
+    let graph = call_graph(&parse("fn down n = [n, down(n - 1)]; down(5)"));
+    assert_eq!(graph.calls["down"], names(&["down"]));
+    assert!(graph.unknown.is_empty());
+}
+
+#[test]
+fn calls_reach_functions_declared_later_and_built_ins() {
+    let graph = call_graph(&parse("fn f x = g(sqrt(x)); fn g y = y; f(4)"));
+    assert_eq!(graph.calls["f"], names(&["g", "sqrt"]));
+    assert_eq!(graph.calls["g"], names(&[]));
+    let graph = call_graph(&parse("fn f = nope(1); f()"));
+    assert_eq!(graph.unknown, names(&["nope"]));
+}
+
+#[test]
+fn calling_a_parameter_is_a_value_call() {
+    let graph = call_graph(&parse("fn twice g x = g(g(x)); twice(\\x -> x + 1, 0)"));
+    assert_eq!(graph.calls["twice"], names(&[]));
+    assert!(graph.unknown.is_empty());
+    // A variable holding a lambda is called the same way.
+    let graph = call_graph(&parse("let inc = \\x -> x + 1; fn f = 0; inc(1)"));
+    assert!(graph.unknown.is_empty());
+}
+
+#[test]
+fn a_parameter_does_not_shadow_a_function() {
+    // As in evaluation, `g(x)` calls the declared `g`, not the parameter.
+    let graph = call_graph(&parse("fn g x = x; fn f g x = g(x); f(1, 2)"));
+    assert_eq!(graph.calls["f"], names(&["g"]));
+}
+
+#[test]
+fn nested_functions_have_their_own_edges() {
+    let src = "fn outer x = { fn inner y = helper(y); inner(x) }; fn helper z = z; outer(1)";
+    let graph = call_graph(&parse(src));
+    assert_eq!(graph.calls["outer"], names(&["inner"]));
+    assert_eq!(graph.calls["inner"], names(&["helper"]));
+    assert_eq!(graph.calls["helper"], names(&[]));
+    assert!(graph.unknown.is_empty());
+}
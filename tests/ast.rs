@@ -0,0 +1,442 @@
+//! Compares syntax trees, and prints them back as source to check that
+//! parsing the result gives the same tree.
+
+use chumsky::Parser;
+use kyomusky::ast::{BinOp, Expr, ExprKind, UnaryOp};
+use kyomusky::fold::fold_constants;
+use kyomusky::parser::program;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+fn expr(kind: ExprKind) -> Expr {
+    Expr::new(kind, 0..0)
+}
+
+fn num(x: f64) -> Expr {
+    expr(ExprKind::Num(x))
+}
+
+fn var(name: &str) -> Expr {
+    expr(ExprKind::Var(name.to_string()))
+}
+
+fn neg(operand: Expr) -> Expr {
+    expr(ExprKind::Unary {
+        op: UnaryOp::Neg,
+        operand: Box::new(operand),
+    })
+}
+
+fn bin(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+    let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+    expr(ExprKind::Binary { op, lhs, rhs })
+}
+
+fn call(name: &str, args: Vec<Expr>) -> Expr {
+    expr(ExprKind::Call(name.to_string(), args))
+}
+
+/// Prints `expr`, checks that it parses back to the same tree, and returns
+/// the source.
+fn round_trip(expr: &Expr) -> String {
+    let src = expr.to_string();
+    let parsed = program()
+        .parse(src.as_str())
+        .unwrap_or_else(|errs| panic!("{src:?} does not parse: {errs:?}"));
+    assert_eq!(parsed.len(), 1, "{src:?}");
+    assert_eq!(&parsed[0], expr, "{src:?} parses to another tree");
+    src
+}
+
+#[test]
+fn parentheses_follow_precedence() {
+    use BinOp::{Add, Mul, Sub};
+    let (a, b, c) = (|| var("a"), || var("b"), || var("c"));
+    assert_eq!(
+        round_trip(&bin(Mul, bin(Add, a(), b()), c())),
+        "(a + b) * c"
+    );
+    assert_eq!(round_trip(&bin(Add, bin(Mul, a(), b()), c())), "a * b + c");
+    assert_eq!(
+        round_trip(&bin(Sub, a(), bin(Sub, b(), c()))),
+        "a - (b - c)"
+    );
+    assert_eq!(round_trip(&bin(Sub, bin(Sub, a(), b()), c())), "a - b - c");
+    assert_eq!(
+        round_trip(&call("f", vec![a(), bin(Add, b(), c())])),
+        "f(a, b + c)"
+    );
+}
+
+#[test]
+fn negation_round_trips() {
+    assert_eq!(round_trip(&neg(num(1.0))), "-1");
+    assert_eq!(round_trip(&neg(neg(num(1.0)))), "--1");
+    assert_eq!(
+        round_trip(&bin(BinOp::Sub, var("a"), neg(num(1.0)))),
+        "a - -1"
+    );
+    assert_eq!(
+        round_trip(&neg(bin(BinOp::Mul, var("a"), var("b")))),
+        "-(a * b)"
+    );
+}
+
+/// Prints `expr`, and checks that parsing and folding the result gives
+/// the same tree as folding `expr`. Negative numbers print as negations,
+/// which only folding turns back into literals.
+fn folded_round_trip(expr: &Expr) -> String {
+    let src = expr.to_string();
+    let parsed = program()
+        .parse(src.as_str())
+        .unwrap_or_else(|errs| panic!("{src:?} does not parse: {errs:?}"));
+    assert_eq!(parsed.len(), 1, "{src:?}");
+    assert_eq!(
+        fold_constants(parsed[0].clone()),
+        fold_constants(expr.clone()),
+        "{src:?} parses to another tree"
+    );
+    src
+}
+
+#[test]
+fn folded_negative_numbers_round_trip() {
+    for (before, after) in [
+        ("(0 - 2) ^ 0.5", "(-2) ^ 0.5"),
+        ("(1 - 3) * 4", "-8"),
+        ("x - (1 - 3)", "x - -2"),
+        ("((1 - 3) * 4)[0]", "(-8)[0]"),
+        ("-(0 - 2) ^ 2", "-4"),
+        ("2 ^ (0 - 1)", "0.5"),
+        ("x ^ (0 - 1)", "x ^ -1"),
+        ("-0", "-0"),
+    ] {
+        let folded = fold_constants(parse(before));
+        assert_eq!(folded_round_trip(&folded), after, "{before}");
+    }
+}
+
+#[test]
+fn negative_number_literals_round_trip() {
+    use BinOp::{Mul, Pow, Sub};
+    let index = |target: Expr| expr(ExprKind::Index(Box::new(target), Box::new(num(0.0))));
+    for (tree, src) in [
+        (bin(Pow, num(-2.0), num(0.5)), "(-2) ^ 0.5"),
+        (bin(Pow, num(2.0), num(-1.0)), "2 ^ -1"),
+        (bin(Mul, num(-1.5), var("x")), "-1.5 * x"),
+        (bin(Sub, var("x"), num(-8.0)), "x - -8"),
+        (neg(num(-1.0)), "--1"),
+        (index(num(-1.0)), "(-1)[0]"),
+    ] {
+        assert_eq!(folded_round_trip(&tree), src);
+    }
+}
+
+#[test]
+fn non_finite_numbers_print_as_divisions() {
+    use BinOp::{Div, Pow};
+    let div = |x: f64| bin(Div, num(x), num(0.0));
+    for (x, src, reparsed) in [
+        (f64::INFINITY, "(1 / 0) ^ 2", bin(Pow, div(1.0), num(2.0))),
+        (
+            f64::NEG_INFINITY,
+            "(-1 / 0) ^ 2",
+            bin(Pow, bin(Div, neg(num(1.0)), num(0.0)), num(2.0)),
+        ),
+        (f64::NAN, "(0 / 0) ^ 2", bin(Pow, div(0.0), num(2.0))),
+    ] {
+        let tree = bin(Pow, num(x), num(2.0));
+        assert_eq!(tree.to_string(), src);
+        assert_eq!(parse(src), reparsed, "{src}");
+    }
+}
+
+#[test]
+fn chains_round_trip() {
+    for src in [
+        "let a = 1;\nfn f x = x * a;\nf(2)",
+        "let s = \"a\";\n[s, {\"k\": true}][0]",
+    ] {
+        let parsed = program().parse(src).unwrap();
+        round_trip(&parsed[0]);
+    }
+}
+
+/// A xorshift generator, to build the same random trees on every run.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// A random arithmetic tree at most `depth` deep.
+fn random_tree(rng: &mut Rng, depth: usize) -> Expr {
+    const OPS: [BinOp; 10] = [
+        BinOp::Add,
+        BinOp::Sub,
+        BinOp::Mul,
+        BinOp::Div,
+        BinOp::Eq,
+        BinOp::NotEq,
+        BinOp::Lt,
+        BinOp::Gt,
+        BinOp::LtEq,
+        BinOp::GtEq,
+    ];
+    match rng.below(if depth == 0 { 2 } else { 5 }) {
+        0 => num(rng.below(20) as f64),
+        1 => var(["a", "b", "c"][rng.below(3)]),
+        2 => neg(random_tree(rng, depth - 1)),
+        3 => call(
+            "f",
+            (0..rng.below(3))
+                .map(|_| random_tree(rng, depth - 1))
+                .collect(),
+        ),
+        _ => bin(
+            OPS[rng.below(OPS.len())],
+            random_tree(rng, depth - 1),
+            random_tree(rng, depth - 1),
+        ),
+    }
+}
+
+#[test]
+fn random_trees_round_trip() {
+    let mut rng = Rng(158);
+    for _ in 0..2000 {
+        round_trip(&random_tree(&mut rng, 5));
+    }
+}
+
+fn parse(src: &str) -> Expr {
+    program().parse(src).unwrap().remove(0)
+}
+
+fn hash(expr: &Expr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    expr.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn equality_and_hashing_ignore_spans() {
+    let (a, b) = (parse("1+2*x"), parse("1  +  2 *\n  x"));
+    assert_ne!(a.span, b.span);
+    assert_eq!(a, b);
+    assert_eq!(hash(&a), hash(&b));
+    let expected = bin(BinOp::Add, num(1.0), bin(BinOp::Mul, num(2.0), var("x")));
+    assert_eq!(a, expected);
+    assert_ne!(a, parse("(1 + 2) * x"));
+    assert_ne!(parse("f(x)"), parse("g(x)"));
+}
+
+#[test]
+fn number_literals_compare_by_bits() {
+    assert_eq!(num(f64::NAN), num(f64::NAN));
+    assert_eq!(hash(&num(f64::NAN)), hash(&num(f64::NAN)));
+    assert_ne!(num(0.0), num(-0.0));
+}
+
+/// Counts each subtree of `expr` in `counts`.
+fn count_subtrees<'a>(expr: &'a Expr, counts: &mut HashMap<&'a Expr, usize>) {
+    *counts.entry(expr).or_insert(0) += 1;
+    for child in expr.children() {
+        count_subtrees(child, counts);
+    }
+}
+
+#[test]
+fn subtrees_can_be_map_keys() {
+    let program = parse("f(x + 1) * f(x + 1) + f(x + 2)");
+    let mut counts = HashMap::new();
+    count_subtrees(&program, &mut counts);
+    assert_eq!(counts[&parse("f(x + 1)")], 2);
+    assert_eq!(counts[&parse("x + 2")], 1);
+    assert_eq!(counts[&var("x")], 3);
+}
+
+#[test]
+fn transform_rewrites_children_first() {
+    let mut order = Vec::new();
+    parse("1 + 2 * f(3)").transform(&mut |e| {
+        order.push(e.to_string());
+        e
+    });
+    assert_eq!(order, ["1", "2", "3", "f(3)", "2 * f(3)", "1 + 2 * f(3)"]);
+}
+
+#[test]
+fn transform_keeps_the_spans_of_nodes_it_does_not_replace() {
+    let src = "a + b * 2";
+    let mut spans = Vec::new();
+    let doubled = parse(src).transform(&mut |e| match e.kind {
+        ExprKind::Num(x) => num(x * 2.0),
+        _ => {
+            spans.push(e.span.clone());
+            e
+        }
+    });
+    assert_eq!(doubled.to_string(), "a + b * 4");
+    let sources: Vec<_> = spans.into_iter().map(|span| &src[span]).collect();
+    assert_eq!(sources, ["a", "b", "b * 2", "a + b * 2"]);
+    let ExprKind::Binary { rhs: product, .. } = &doubled.kind else {
+        panic!("{doubled:?}");
+    };
+    let ExprKind::Binary { rhs: four, .. } = &product.kind else {
+        panic!("{product:?}");
+    };
+    assert_eq!(four.span, 0..0);
+}
+
+#[test]
+fn try_transform_stops_at_the_first_error() {
+    let mut seen = 0;
+    let result = parse("1 + x * y").try_transform(&mut |e| {
+        seen += 1;
+        match &e.kind {
+            ExprKind::Var(name) => Err(format!("unbound `{name}`")),
+            _ => Ok(e),
+        }
+    });
+    assert_eq!(result.unwrap_err(), "unbound `x`");
+    assert_eq!(seen, 2);
+}
+
+/// `expr` negated `depth` times.
+fn negated(expr: Expr, depth: usize) -> Expr {
+    (0..depth).fold(expr, |e, _| neg(e))
+}
+
+#[test]
+fn transform_handles_deep_trees() {
+    let depth = 100_000;
+    let deep = negated(var("x"), depth);
+    let renamed = deep.transform(&mut |e| match e.kind {
+        ExprKind::Var(_) => var("y"),
+        _ => e,
+    });
+    let (mut nodes, mut ys) = (0, 0);
+    let renamed = renamed.transform(&mut |e| {
+        nodes += 1;
+        if e.kind == ExprKind::Var("y".to_string()) {
+            ys += 1;
+        }
+        e
+    });
+    assert_eq!((nodes, ys), (depth + 1, 1));
+    // Dropping the tree recurses, unlike the traversals.
+    std::mem::forget(renamed);
+}
+
+#[test]
+fn constant_folding_before_and_after() {
+    for (before, after) in [
+        ("x * (2 + 3)", "x * 5"),
+        ("-(2 * 3) + y", "-6 + y"),
+        ("f(1 + 1, [2 * 3])", "f(2, [6])"),
+        // Errors are left for evaluation to report.
+        ("1 / 0 + 1", "1 / 0 + 1"),
+    ] {
+        assert_eq!(fold_constants(parse(before)).to_string(), after, "{before}");
+    }
+}
+
+#[test]
+fn iter_visits_nodes_in_pre_order() {
+    let program = parse("f(a + 1, [b])");
+    let order: Vec<_> = program.iter().map(|e| e.to_string()).collect();
+    assert_eq!(order, ["f(a + 1, [b])", "a + 1", "a", "1", "[b]", "b"]);
+    // Counted by hand: the call, the sum and its two operands, the list and
+    // its element.
+    assert_eq!(program.iter().count(), 6);
+    assert_eq!(program.node_count(), 6);
+}
+
+#[test]
+fn iter_with_spans_pairs_nodes_with_their_source() {
+    let src = "f(a + 1, [b])";
+    let program = parse(src);
+    let spans: Vec<_> = program
+        .iter_with_spans()
+        .map(|(_, span)| &src[span.clone()])
+        .collect();
+    assert_eq!(spans, ["f(a + 1, [b])", "a + 1", "a", "1", "[b]", "b"]);
+}
+
+#[test]
+fn find_and_any_query_the_tree() {
+    let program = parse("let x = input(); sqrt(x) + input()");
+    let is_input = |e: &Expr| matches!(&e.kind, ExprKind::Call(name, _) if name == "input");
+    assert!(program.any(is_input));
+    assert!(!program.any(|e| matches!(e.kind, ExprKind::Str(_))));
+    let first = program.find(is_input).unwrap();
+    assert_eq!(first.span, 8..15);
+    assert!(program
+        .find(|e| matches!(e.kind, ExprKind::Bool(_)))
+        .is_none());
+}
+
+#[test]
+fn iter_handles_deep_trees() {
+    let depth = 100_000;
+    let deep = negated(var("x"), depth);
+    assert_eq!(deep.iter().count(), depth + 1);
+    assert!(deep.any(|e| matches!(e.kind, ExprKind::Var(_))));
+    // Dropping the tree recurses, unlike the traversals.
+    std::mem::forget(deep);
+}
+
+/// The source of the node at `offset` in `src`, and the kinds of the nodes
+/// on the path down to it.
+fn at(src: &str, offset: usize) -> (Option<&str>, Vec<&'static str>) {
+    let program = parse(src);
+    let node = program.node_at(offset).map(|node| &src[node.span.clone()]);
+    let path = program
+        .path_to(offset)
+        .iter()
+        .map(|e| e.kind.name())
+        .collect();
+    (node, path)
+}
+
+#[test]
+fn node_at_finds_the_innermost_node() {
+    let src = "let total = a+b;  fn f x = x * 2; f(total)";
+    // Inside an identifier.
+    assert_eq!(
+        at(src, 38),
+        (Some("total"), vec!["Let", "Fn", "Call", "Var"])
+    );
+    assert_eq!(at(src, 12), (Some("a"), vec!["Let", "Binary", "Var"]));
+    // On an operator, between its operands.
+    assert_eq!(at(src, 13), (Some("a+b"), vec!["Let", "Binary"]));
+    assert_eq!(at(src, 29), (Some("x * 2"), vec!["Let", "Fn", "Binary"]));
+    // On the keyword and `;` of a declaration.
+    assert_eq!(at(src, 0), (Some("let total = a+b;"), vec!["Let"]));
+    assert_eq!(at(src, 15), (Some("let total = a+b;"), vec!["Let"]));
+}
+
+#[test]
+fn node_at_ties_go_to_the_node_starting_there() {
+    // Spans are half-open: `a` ends where `+` starts.
+    assert_eq!(at("a+b", 1), (Some("a+b"), vec!["Binary"]));
+    assert_eq!(at("a+b", 2), (Some("b"), vec!["Binary", "Var"]));
+}
+
+#[test]
+fn node_at_outside_any_node() {
+    let src = "let total = a+b;  fn f x = x * 2; f(total)";
+    // Between declarations.
+    assert_eq!(at(src, 16), (None, vec![]));
+    assert_eq!(at(src, 17), (None, vec![]));
+    // Past the end of the source.
+    assert_eq!(at(src, src.len()), (None, vec![]));
+    assert_eq!(at(src, 1000), (None, vec![]));
+}
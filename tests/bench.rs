@@ -0,0 +1,61 @@
+//! Statistics of synthetic timings.
+
+use kyomusky::bench::Timings;
+use std::time::Duration;
+
+fn ms(values: &[u64]) -> Vec<Duration> {
+    values.iter().map(|&v| Duration::from_millis(v)).collect()
+}
+
+#[test]
+fn no_samples_have_no_statistics() {
+    assert_eq!(Timings::of(&[]), None);
+}
+
+#[test]
+fn single_sample() {
+    let t = Timings::of(&ms(&[7])).unwrap();
+    assert_eq!(t.iterations, 1);
+    let seven = Duration::from_millis(7);
+    assert_eq!(
+        (t.min, t.median, t.max, t.mean),
+        (seven, seven, seven, seven)
+    );
+}
+
+#[test]
+fn odd_number_of_samples_in_any_order() {
+    let t = Timings::of(&ms(&[9, 1, 5, 3, 2])).unwrap();
+    assert_eq!(
+        t,
+        Timings {
+            iterations: 5,
+            min: Duration::from_millis(1),
+            median: Duration::from_millis(3),
+            max: Duration::from_millis(9),
+            mean: Duration::from_millis(4),
+        }
+    );
+}
+
+#[test]
+fn even_number_of_samples_average_the_middle_two() {
+    let t = Timings::of(&ms(&[4, 1, 10, 2])).unwrap();
+    assert_eq!(t.median, Duration::from_millis(3));
+    assert_eq!(t.mean, Duration::from_micros(4250));
+    let t = Timings::of(&ms(&[1, 2])).unwrap();
+    assert_eq!(t.median, Duration::from_micros(1500));
+}
+
+#[test]
+fn display_lists_each_statistic() {
+    let t = Timings::of(&ms(&[1, 2, 3])).unwrap();
+    assert_eq!(
+        t.to_string(),
+        "iterations: 3\n\
+         min:        1ms\n\
+         median:     2ms\n\
+         max:        3ms\n\
+         mean:       2ms\n"
+    );
+}
@@ -0,0 +1,41 @@
+//! Integer arithmetic with the `bigint` feature, run with
+//! `cargo test --features bigint`.
+#![cfg(feature = "bigint")]
+
+use chumsky::Parser;
+use kyomusky::eval::{eval, Env};
+use kyomusky::parser::parser;
+
+fn eval_str(src: &str) -> String {
+    let program = parser().parse(src).unwrap();
+    let value = eval(&program, &mut Env::new()).unwrap();
+    value.to_string()
+}
+
+#[test]
+fn factorial_is_exact() {
+    assert_eq!(
+        eval_str("factorial(30)"),
+        "265252859812191058636308480000000"
+    );
+    assert_eq!(eval_str("factorial(25)"), "15511210043330985984000000");
+}
+
+#[test]
+fn overflow_promotes_to_big_integers() {
+    assert_eq!(eval_str("9007199254740992 + 1"), "9007199254740993");
+    assert_eq!(
+        eval_str("4294967296 * 4294967296 * 64"),
+        "1180591620717411303424"
+    );
+    assert_eq!(
+        eval_str("-(4294967296 * 4294967296) * 3"),
+        "-55340232221128654848"
+    );
+}
+
+#[test]
+fn fractions_stay_floating_point() {
+    assert_eq!(eval_str("factorial(20) / 3"), "810967336058880000");
+    assert_eq!(eval_str("1 / 4"), "0.25");
+}
@@ -0,0 +1,646 @@
+//! Runs the `kyomusky` binary on small programs and checks what it prints.
+
+use kyomusky::json::Json;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Writes `src` to a fresh file and runs the binary on it with `args`
+/// before the path. Reports are printed without colors.
+fn kyomusky(args: &[&str], src: &str) -> Output {
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let n = FILES.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("kyomusky-cli-{}-{n}.ky", std::process::id()));
+    std::fs::write(&path, src).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_kyomusky"))
+        .args(["--theme", "monochrome"])
+        .args(args)
+        .arg(&path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+/// `bytes` printed by the binary, without the escape codes coloring reports.
+fn plain(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+fn stdout(output: &Output) -> String {
+    plain(&output.stdout)
+}
+
+fn stderr(output: &Output) -> String {
+    plain(&output.stderr)
+}
+
+/// A program with `n` syntax errors, one per `let`.
+fn many_syntax_errors(n: usize) -> String {
+    let lets: String = (0..n).map(|i| format!("let x{i} = ); ")).collect();
+    format!("{lets}1")
+}
+
+#[test]
+fn max_errors_caps_the_rendered_reports() {
+    let src = many_syntax_errors(5);
+    let out = stdout(&kyomusky(&["--max-errors", "2"], &src));
+    assert_eq!(out.matches("Error: Syntax error").count(), 2);
+    assert!(out.ends_with("…and 3 more errors (re-run with --max-errors 0 for all)\n"));
+
+    let out = stdout(&kyomusky(&["--max-errors", "0"], &src));
+    assert_eq!(out.matches("Error: Syntax error").count(), 5);
+    assert!(!out.contains("more errors"));
+
+    let out = stdout(&kyomusky(&[], &src));
+    assert_eq!(out.matches("Error: Syntax error").count(), 5);
+}
+
+#[test]
+fn max_errors_expects_a_number() {
+    let output = kyomusky(&["--max-errors", "two"], "1");
+    assert_eq!(output.status.code(), Some(2));
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        err.contains("Invalid value for `--max-errors`: `two`"),
+        "{err}"
+    );
+}
+
+/// The value the binary printed for `src`, or the message of its evaluation
+/// error.
+fn eval(src: &str) -> Result<String, String> {
+    eval_with(&[], src)
+}
+
+/// Like [`eval`], running the binary with `args`.
+fn eval_with(args: &[&str], src: &str) -> Result<String, String> {
+    let out = stdout(&kyomusky(args, src));
+    if let Some(message) = eval_error(&out) {
+        return Err(message);
+    }
+    for line in out.lines() {
+        if let Some(value) = line.strip_prefix("eval: ") {
+            return Ok(value.to_string());
+        }
+    }
+    panic!("no value or evaluation error in {out:?}");
+}
+
+/// The message of the first evaluation error reported in `out`: the label
+/// under the code, or the last one if the report points at several places.
+fn eval_error(out: &str) -> Option<String> {
+    let report = out.split("Error: Evaluation error\n").nth(1)?;
+    let report = report.split("───╯").next()?;
+    let label = report.lines().rev().find_map(|line| line.split_once('╰'))?;
+    Some(label.1.trim_start_matches('─').trim().to_string())
+}
+
+#[test]
+fn strings_compare_lexicographically() {
+    assert_eq!(eval(r#""apple" < "banana""#).unwrap(), "true");
+    assert_eq!(eval(r#""banana" <= "apple""#).unwrap(), "false");
+    assert_eq!(eval(r#""b" > "abc""#).unwrap(), "true");
+    assert_eq!(eval(r#""ab" >= "ab""#).unwrap(), "true");
+    assert_eq!(eval(r#""ab" == "ab""#).unwrap(), "true");
+    assert_eq!(eval(r#""ab" != "aB""#).unwrap(), "true");
+}
+
+#[test]
+fn comparing_a_string_with_a_number_is_a_type_error() {
+    assert_eq!(
+        eval(r#""1" < 2"#).unwrap_err(),
+        "Type error: cannot compare string with number"
+    );
+    assert_eq!(
+        eval(r#"3 >= "a""#).unwrap_err(),
+        "Type error: cannot compare number with string"
+    );
+}
+
+#[test]
+fn index_assignment_updates_a_list_in_place() {
+    assert_eq!(
+        eval("let xs = [1, 2]; let _ = xs[0] := 9; xs").unwrap(),
+        "[9, 2]"
+    );
+    // Every name bound to the list sees the update.
+    let src = "let xs = [1, 2]; let ys = xs; let _ = xs[1] := 5; ys";
+    assert_eq!(eval(src).unwrap(), "[1, 5]");
+    assert_eq!(
+        eval("let xs = [1, 2]; xs[2] := 9").unwrap_err(),
+        "Index 2 out of bounds for list of length 2"
+    );
+}
+
+#[test]
+fn index_assignment_inserts_into_a_map() {
+    let src = r#"let m = {"a": 1}; let _ = m["k"] := 2; let _ = m["a"] := 3; m"#;
+    assert_eq!(eval(src).unwrap(), r#"{"a": 3, "k": 2}"#);
+}
+
+#[test]
+fn downgraded_errors_warn_and_still_print_the_result() {
+    let output = kyomusky(&["--no-eval-errors-fatal"], "1 / 0");
+    assert!(stdout(&output).ends_with("eval: inf\n"));
+    let err = stderr(&output);
+    assert!(
+        err.contains("Warning: Evaluation error downgraded (`non-finite`)"),
+        "{err}"
+    );
+    assert!(err.contains("Division by zero"));
+
+    let output = kyomusky(&["--no-eval-errors-fatal"], "[1][3] + 1");
+    assert!(stdout(&output).ends_with("eval: NaN\n"));
+}
+
+#[test]
+fn only_the_given_kinds_of_errors_are_downgraded() {
+    let output = kyomusky(&["--no-eval-errors-fatal=non-finite"], "1 / 0");
+    assert!(stdout(&output).ends_with("eval: inf\n"));
+    assert_eq!(
+        eval_with(&["--no-eval-errors-fatal=non-finite"], "[1][3] + 1").unwrap_err(),
+        "Index 3 out of bounds for list of length 1"
+    );
+    assert_eq!(eval("1 / 0").unwrap_err(), "Division by zero");
+}
+
+/// The values printed for each top-level expression of `src`.
+fn values(src: &str) -> Vec<String> {
+    let out = stdout(&kyomusky(&[], src));
+    let values = out.lines().filter_map(|line| line.strip_prefix("eval: "));
+    values.map(str::to_string).collect()
+}
+
+#[test]
+fn each_top_level_expression_prints_its_result() {
+    assert_eq!(values("1 + 1\n2 * 3\n10 / 4\n"), ["2", "6", "2.5"]);
+    // A chain still spans lines.
+    assert_eq!(values("let a = 1;\na + 1\n3"), ["2", "3"]);
+    // An error in one expression doesn't stop the next.
+    let out = stdout(&kyomusky(&[], "1 / 0\n2"));
+    assert_eq!(eval_error(&out).unwrap(), "Division by zero");
+    assert!(out.ends_with("eval: 2\n"), "{out}");
+}
+
+#[test]
+fn equality_compares_structure() {
+    assert_eq!(eval("[[1, 2], [3]] == [[1, 2], [3]]").unwrap(), "true");
+    assert_eq!(eval(r#"{"a": 1} == {"a": 1}"#).unwrap(), "true");
+    // Maps are equal regardless of the order of their keys.
+    assert_eq!(
+        eval(r#"{"a": 1, "b": 2} == {"b": 2, "a": 1}"#).unwrap(),
+        "true"
+    );
+    assert_eq!(eval(r#"{"a": 1} == {"a": 1, "b": 2}"#).unwrap(), "false");
+    let src = r#"[{"a": [1, 2]}, [3]] == [{"a": [1, 3]}, [3]]"#;
+    assert_eq!(eval(src).unwrap(), "false");
+    assert_eq!(eval("[[1, 2], [3]] != [[1, 2], [3, 4]]").unwrap(), "true");
+}
+
+#[test]
+fn equality_does_not_compare_identity() {
+    // Two lists built separately are equal until one of them is updated.
+    let src =
+        "let xs = [1]; let ys = [1]; let same = xs == ys; let _ = xs[0] := 2; [same, xs == ys]";
+    assert_eq!(eval(src).unwrap(), "[true, false]");
+}
+
+#[test]
+fn ast_stats_counts_nodes_and_depth() {
+    let out = stdout(&kyomusky(&["--ast-stats"], "let x = 1 + 2; x * 3"));
+    let expected = "nodes: 7\ndepth: 3\n  Num         3\n  Binary      2\n  Let         1\n  Var         1\nast:  ";
+    assert!(out.starts_with(expected), "{out}");
+    assert!(out.ends_with("eval: 9\n"), "{out}");
+}
+
+#[test]
+fn input_reads_the_given_data() {
+    let src = "[input(), input(), input()]";
+    let value = eval_with(&["--input-data", "3 4\n5"], src);
+    assert_eq!(value.unwrap(), "[3, 4, 5]");
+
+    let data = std::env::temp_dir().join(format!("kyomusky-data-{}.txt", std::process::id()));
+    std::fs::write(&data, "1\n2 3\n").unwrap();
+    let value = eval_with(&["--input-file", data.to_str().unwrap()], src);
+    std::fs::remove_file(&data).unwrap();
+    assert_eq!(value.unwrap(), "[1, 2, 3]");
+}
+
+#[test]
+fn exhausted_input_data_is_an_error() {
+    let value = eval_with(&["--input-data", "3 4"], "[input(), input(), input()]");
+    assert_eq!(value.unwrap_err(), "Input exhausted after reading 2 values");
+}
+
+#[test]
+fn newline_terminates_splits_lines_into_expressions() {
+    assert_eq!(values("1\n- 2"), ["-1"]);
+    let out = stdout(&kyomusky(&["--newline-terminates"], "1\n- 2"));
+    assert!(
+        out.ends_with("eval: 1\nast:  Unary { op: Neg, operand: Num(2.0) }\neval: -2\n"),
+        "{out}"
+    );
+}
+
+#[test]
+fn graph_eval_annotates_nodes_with_their_values() {
+    let dot = stdout(&kyomusky(&["--no-prelude", "--graph-eval"], "1 + 2"));
+    assert!(dot.starts_with("digraph {\n"), "{dot}");
+    assert!(dot.contains(r#"n0 [label="Binary +\n= 3"];"#), "{dot}");
+    assert!(dot.contains(r#"n1 [label="Num 1\n= 1"];"#), "{dot}");
+    assert!(dot.contains("n0 -> n1;"), "{dot}");
+
+    let dot = stdout(&kyomusky(&["--no-prelude", "--graph-eval"], "1 / 0"));
+    let failed = r##"n0 [label="Binary /\nDivision by zero", style=filled, fillcolor="#ffb3b3"];"##;
+    assert!(dot.contains(failed), "{dot}");
+}
+
+#[test]
+fn no_std_builtins_leaves_only_declared_functions() {
+    assert_eq!(eval("sqrt(4)").unwrap(), "2");
+    let no_std = &["--no-std-builtins"];
+    assert_eq!(
+        eval_with(no_std, "sqrt(4)").unwrap_err(),
+        "Cannot find function `sqrt` in scope"
+    );
+    assert_eq!(
+        eval_with(no_std, "fn sqrt x = x * 10; sqrt(4)").unwrap(),
+        "40"
+    );
+}
+
+#[test]
+fn emit_metrics_prints_keys_and_values() {
+    let output = kyomusky(
+        &["--emit", "metrics"],
+        "let x = 1; fn f a b = a + b * x; f(x, 2)",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let expected = "nodes: 11\ndepth: 5\nvariables: 3\nfunctions: 1\nmax_call_args: 2\nmax_let_depth: 1\nnodes.Var: 4\nnodes.Binary: 2\nnodes.Num: 2\nnodes.Call: 1\nnodes.Fn: 1\nnodes.Let: 1\n";
+    assert_eq!(stdout(&output), expected);
+}
+
+#[test]
+fn bench_prints_timings_of_the_given_number_of_iterations() {
+    let path = std::env::temp_dir().join(format!("kyomusky-bench-{}.ky", std::process::id()));
+    std::fs::write(&path, "fn sq x = x * x; sq(12)").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_kyomusky"))
+        .args(["bench", "--iters", "3"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let keys: Vec<_> = stdout(&output)
+        .lines()
+        .map(|line| line.split(':').next().unwrap().to_owned())
+        .collect();
+    assert_eq!(keys, ["iterations", "min", "median", "max", "mean"]);
+    assert!(stdout(&output).starts_with("iterations: 3\n"));
+}
+
+#[test]
+fn float_eps_sets_the_tolerance_of_equality() {
+    let src = "1 / 10 + 2 / 10 == 3 / 10";
+    assert_eq!(eval(src).unwrap(), "false");
+    assert_eq!(eval_with(&["--float-eps", "1e-9"], src).unwrap(), "true");
+    for eps in ["-1", "inf", "abc"] {
+        let output = kyomusky(&["--float-eps", eps], "1");
+        assert_eq!(output.status.code(), Some(2));
+        assert!(
+            stderr(&output).contains(&format!("Invalid value for `--float-eps`: `{eps}`")),
+            "{}",
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn prelude_functions_are_available_and_can_be_shadowed() {
+    assert_eq!(eval("double(4) + square(3) + avg(1, 2)").unwrap(), "18.5");
+    assert_eq!(eval("fn double x = x * 3; double(4)").unwrap(), "12");
+}
+
+#[test]
+fn no_prelude_leaves_its_functions_undeclared() {
+    assert_eq!(
+        eval_with(&["--no-prelude"], "double(4)").unwrap_err(),
+        "Cannot find function `double` in scope"
+    );
+}
+
+/// Runs `program` with a prelude file containing `prelude`, and returns the
+/// output along with the path of that file.
+fn with_prelude(prelude: &str, program: &str) -> (Output, String) {
+    let path = std::env::temp_dir().join(format!("kyomusky-prelude-{}.ky", std::process::id()));
+    std::fs::write(&path, prelude).unwrap();
+    let path = path.to_str().unwrap().to_owned();
+    let output = kyomusky(&["--prelude", &path], program);
+    std::fs::remove_file(&path).unwrap();
+    (output, path)
+}
+
+#[test]
+fn errors_in_a_prelude_file_are_reported_against_it() {
+    let (output, path) = with_prelude("fn broken x = x +;\n", "1");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stdout(&output).contains(&format!("{path}:1:18")),
+        "{}",
+        stdout(&output)
+    );
+    assert!(!stdout(&output).contains("eval: "), "{}", stdout(&output));
+
+    let (output, path) = with_prelude("fn half x = x / 0;\n", "half(4)");
+    let report = stdout(&output);
+    assert!(report.contains(&format!("{path}:1:13")), "{report}");
+    assert!(report.contains("1 │ fn half x = x / 0;"), "{report}");
+    assert!(report.contains("Note: in `half` called at "), "{report}");
+}
+
+#[test]
+fn max_parse_errors_is_the_same_as_max_errors() {
+    let src = many_syntax_errors(6);
+    let output = kyomusky(&["--max-parse-errors", "2"], &src);
+    assert_eq!(output.status.code(), Some(2));
+    let out = stdout(&output);
+    assert_eq!(out.matches("Error: Syntax error").count(), 2);
+    assert!(out.ends_with("…and 4 more errors (re-run with --max-errors 0 for all)\n"));
+
+    let output = kyomusky(&["--max-parse-errors", "two"], &src);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Invalid value for `--max-parse-errors`: `two`"));
+}
+
+#[test]
+fn parse_only_does_not_evaluate_or_lint() {
+    let output = kyomusky(&["--parse-only"], "x + 1");
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert_eq!(stdout(&output), "");
+    let output = kyomusky(&["--parse-only"], "let y = 1; 2");
+    assert_eq!(
+        (stdout(&output), stderr(&output)),
+        (String::new(), String::new())
+    );
+}
+
+#[test]
+fn parse_only_reports_syntax_errors() {
+    let output = kyomusky(&["--parse-only"], "x +");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stdout(&output).contains("Syntax error"),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn quiet_prints_only_the_value() {
+    let output = kyomusky(&["--quiet"], "1 + 2");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n");
+    assert_eq!(stderr(&output), "");
+    let output = kyomusky(&["--quiet"], r#"[1, "b"]"#);
+    assert_eq!(stdout(&output), "[1, \"b\"]\n");
+
+    let output = kyomusky(&[], "1 + 2");
+    assert!(stdout(&output).starts_with("ast: "), "{}", stdout(&output));
+}
+
+#[test]
+fn quiet_leaves_the_tree_out_of_json_output() {
+    let output = kyomusky(&["--output", "json"], "1 + 2");
+    assert!(stderr(&output).starts_with("ast: "), "{}", stderr(&output));
+    let output = kyomusky(&["--quiet", "--output", "json"], "1 + 2");
+    assert_eq!(stderr(&output), "");
+    assert!(stdout(&output).starts_with(r#"{"ok":true,"value":3,"#));
+}
+
+#[test]
+fn nesting_just_under_the_limit_evaluates() {
+    let depth = 1000;
+    let src = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    let output = kyomusky(&["--quiet"], &src);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "1\n");
+}
+
+#[test]
+fn massive_nesting_is_a_syntax_error() {
+    let depth = 50_000;
+    let src = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    let output = kyomusky(&[], &src);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).contains("expression nesting exceeds the limit of 1024"));
+}
+
+#[test]
+fn deep_trees_without_brackets_are_syntax_errors() {
+    let sources = [
+        format!("{}1", "-".repeat(100_000)),
+        format!("{}1", "-".repeat(5_000)),
+        format!("{}true", "not ".repeat(50_000)),
+        format!("{}1", "\\-> ".repeat(20_000)),
+        format!("{}1", "2^".repeat(50_000)),
+        format!("{}a", "let a = 1; ".repeat(50_000)),
+        format!("{}f", "fn f = 1; ".repeat(50_000)),
+        format!("{}1", "1 + ".repeat(100_000)),
+        format!("[1]{}", "[0]".repeat(100_000)),
+    ];
+    for src in &sources {
+        let output = kyomusky(&[], src);
+        assert_eq!(output.status.code(), Some(2), "{}", &src[..20]);
+        assert!(stdout(&output).contains("expression nesting exceeds the limit of 1024"));
+    }
+}
+
+#[test]
+fn syntax_errors_exit_with_2() {
+    assert_eq!(kyomusky(&[], "1 +").status.code(), Some(2));
+    assert_eq!(kyomusky(&["--parse-only"], "1 +").status.code(), Some(2));
+    assert_eq!(
+        kyomusky(&["--output", "json"], "1 +").status.code(),
+        Some(2)
+    );
+}
+
+/// Recurses `n` levels deep, without tail calls.
+fn deep_recursion(n: usize) -> String {
+    format!("fn count n = if let some(k) = find(\\x -> x > 0, [n]) then 1 + count(k - 1) else 0; count({n})")
+}
+
+#[test]
+fn larger_stack_allows_deeper_recursion() {
+    let src = deep_recursion(5000);
+
+    let output = kyomusky(&["--quiet"], &src);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("Evaluation aborted: stack exhausted"));
+
+    let output = kyomusky(&["--quiet", "--stack-size", "1G"], &src);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert_eq!(stdout(&output), "5000\n");
+}
+
+#[test]
+fn evaluation_errors_exit_with_1() {
+    assert_eq!(kyomusky(&[], "1\nx + 1").status.code(), Some(1));
+    assert_eq!(kyomusky(&["--output", "json"], "x").status.code(), Some(1));
+    assert_eq!(kyomusky(&[], "1 + 1").status.code(), Some(0));
+}
+
+#[test]
+fn repeated_calls_are_collapsed_in_the_note() {
+    let output = kyomusky(&[], "fn f n = 1 + f(n - 1);\nf(1)");
+    assert_eq!(output.status.code(), Some(1));
+    let note = stdout(&output)
+        .lines()
+        .find_map(|line| line.split_once("Note: ").map(|(_, note)| note.to_string()))
+        .unwrap();
+    assert!(note.starts_with("in `f` called at "), "{note}");
+    assert!(note.contains(" (repeated "), "{note}");
+    assert_eq!(note.matches("called at").count(), 2, "{note}");
+}
+
+/// Runs the binary with `--output json` and parses what it printed.
+fn json_output(args: &[&str], src: &str) -> (Option<i32>, Json) {
+    let mut all = vec!["--output", "json"];
+    all.extend(args);
+    let output = kyomusky(&all, src);
+    let json = Json::parse(stdout(&output).trim()).unwrap();
+    (output.status.code(), json)
+}
+
+#[test]
+fn json_envelope_on_success() {
+    let (code, json) = json_output(&[], "1 + 2");
+    assert_eq!(code, Some(0));
+    assert_eq!(json.get("ok"), &Json::Bool(true));
+    assert_eq!(json.get("value").as_f64(), Some(3.0));
+    assert_eq!(json.get("diagnostics").as_array().map(<[_]>::len), Some(0));
+    let time_ms = json.get("time_ms").as_f64().unwrap();
+    assert_eq!((time_ms * 1e3).round() / 1e3, time_ms);
+}
+
+#[test]
+fn json_envelope_on_parse_error() {
+    let (code, json) = json_output(&[], "1 +");
+    assert_eq!(code, Some(2));
+    assert_eq!(json.get("ok"), &Json::Bool(false));
+    assert_eq!(json.get("error").get("kind").as_str(), Some("parse"));
+    let span = json.get("error").get("span").as_array().unwrap();
+    assert_eq!(span[0].as_f64(), Some(3.0));
+    let diagnostics = json.get("diagnostics").as_array().unwrap();
+    assert_eq!(diagnostics[0].get("severity").as_str(), Some("error"));
+}
+
+#[test]
+fn json_envelope_on_runtime_error() {
+    let (code, json) = json_output(&[], "1 + x");
+    assert_eq!(code, Some(1));
+    assert_eq!(json.get("ok"), &Json::Bool(false));
+    assert_eq!(json.get("error").get("kind").as_str(), Some("runtime"));
+    let span = json.get("error").get("span").as_array().unwrap();
+    assert_eq!((span[0].as_f64(), span[1].as_f64()), (Some(4.0), Some(5.0)));
+    assert_eq!(json.get("diagnostics").as_array().map(<[_]>::len), Some(0));
+}
+
+#[test]
+fn json_envelope_includes_runtime_warnings() {
+    let (code, json) = json_output(&["--no-eval-errors-fatal"], "1 / 0");
+    assert_eq!(code, Some(0));
+    assert_eq!(json.get("value"), &Json::Null);
+    let diagnostics = json.get("diagnostics").as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].get("severity").as_str(), Some("warning"));
+}
+
+#[test]
+fn empty_programs_are_reported() {
+    for src in ["", " \n\t\n", "// nothing to see\n  // here\n"] {
+        let output = kyomusky(&[], src);
+        assert_eq!(output.status.code(), Some(2), "{src:?}");
+        let out = stdout(&output);
+        assert!(
+            out.contains("empty program: nothing to evaluate"),
+            "{src:?}: {out}"
+        );
+    }
+}
+
+#[test]
+fn comments_are_ignored() {
+    let output = kyomusky(&["--quiet"], "// add\n1 + 2 // three\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn lint_levels_from_the_command_line() {
+    let unused = "let x = 1;\n2";
+
+    let output = kyomusky(&["--quiet"], unused);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).contains("`unused-variable` lint"));
+    assert!(stderr(&output).contains("Unused variable `x`"));
+    assert_eq!(stdout(&output), "2\n");
+
+    let output = kyomusky(&["--quiet", "-A", "unused-variable"], unused);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(&output), "");
+    assert_eq!(stdout(&output), "2\n");
+
+    for args in [["-D", "unused-variable"], ["-D", "warnings"]] {
+        let output = kyomusky(&args, unused);
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        assert!(stderr(&output).contains("Error: `unused-variable` lint"));
+        assert!(stderr(&output).contains("Unused variable `x`"));
+        assert_eq!(stdout(&output), "", "{args:?}");
+    }
+
+    let shadowed = "let x = 1;\nlet x = x + 1;\nx";
+    let output = kyomusky(&["--quiet"], shadowed);
+    assert_eq!(stderr(&output), "");
+    let output = kyomusky(&["--quiet", "-W", "shadowed-variable"], shadowed);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).contains("Warning: `shadowed-variable` lint"));
+    assert_eq!(stdout(&output), "2\n");
+}
+
+#[test]
+fn json_diagnostics_have_the_final_severity() {
+    let (code, json) = json_output(&["-D", "unused-variable"], "let x = 1;\n2");
+    assert_eq!(code, Some(1));
+    assert_eq!(json.get("error").get("kind").as_str(), Some("lint"));
+    let diagnostics = json.get("diagnostics").as_array().unwrap();
+    assert_eq!(diagnostics[0].get("lint").as_str(), Some("unused-variable"));
+    assert_eq!(diagnostics[0].get("severity").as_str(), Some("error"));
+}
+
+#[test]
+fn log_levels_need_the_trace_feature() {
+    let output = kyomusky(&["--quiet", "--log", "debug"], "sqrt(4)");
+    if cfg!(feature = "trace") {
+        assert_eq!(stdout(&output), "2\n");
+        let err = stderr(&output);
+        assert!(err.contains("INFO parse{len=7}: close"), "{err}");
+        assert!(!err.contains("TRACE"), "{err}");
+    } else {
+        assert_eq!(output.status.code(), Some(2));
+        assert!(stderr(&output).starts_with("`--log` requires building with the `trace` feature"));
+    }
+    let output = kyomusky(&["--log", "loud"], "1");
+    assert!(stderr(&output).starts_with("Unknown log level `loud`"));
+}
@@ -0,0 +1,239 @@
+//! Evaluates small programs through the library and checks their values and
+//! errors.
+
+use chumsky::Parser;
+use kyomusky::eval::{eval, Env, ErrorKind, EvalError};
+use kyomusky::input::DataInput;
+use kyomusky::parser::program;
+use kyomusky::run;
+
+/// Evaluates the first expression of `src` in an environment prepared by
+/// `setup`, and returns its value as printed.
+fn eval_with(src: &str, setup: impl FnOnce(&mut Env)) -> Result<String, EvalError> {
+    let program = program().parse(src).unwrap();
+    let mut env = Env::new();
+    setup(&mut env);
+    let value = eval(&program[0], &mut env)?;
+    Ok(value.to_string())
+}
+
+fn eval_str(src: &str) -> Result<String, EvalError> {
+    eval_with(src, |_| {})
+}
+
+#[test]
+fn input_comes_from_the_environment() {
+    let set_input = |env: &mut Env| env.input = Box::new(DataInput::new("3 4 5"));
+    let src = "input() + input() * input()";
+    assert_eq!(eval_with(src, set_input).unwrap(), "23");
+
+    let err = eval_with("[input(), input(), input(), input()]", set_input).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Input);
+    assert_eq!(err.message, "Input exhausted after reading 3 values");
+}
+
+#[test]
+fn named_arguments_bind_parameters_by_name() {
+    let src = "fn sub a b = a - b; [sub(b = 1, a = 5), sub(5, b = 1)]";
+    assert_eq!(eval_str(src).unwrap(), "[4, 4]");
+}
+
+#[test]
+fn built_ins_reject_named_arguments() {
+    let err = eval_str("sqrt(x = 4)").unwrap_err();
+    assert_eq!(
+        err.message,
+        "built-in `sqrt` does not support named arguments"
+    );
+    // Even when the arity is wrong too.
+    let err = eval_str("sqrt(4, y = 1)").unwrap_err();
+    assert_eq!(
+        err.message,
+        "built-in `sqrt` does not support named arguments"
+    );
+    assert_eq!(eval_str("sqrt(4)").unwrap(), "2");
+}
+
+#[test]
+fn min_and_max_take_numbers_or_a_list() {
+    assert_eq!(eval_str("max(3, 1, 4)").unwrap(), "4");
+    assert_eq!(eval_str("min(3, 1, 4)").unwrap(), "1");
+    assert_eq!(eval_str("min(2)").unwrap(), "2");
+    assert_eq!(eval_str("max([3, 1, 4])").unwrap(), "4");
+    assert_eq!(eval_str("min([3, 1, 4])").unwrap(), "1");
+    assert_eq!(eval_str("clamp([1, 5, -2], 0, 3)").unwrap(), "[1, 3, 0]");
+    // A list among other arguments is not unpacked.
+    assert_eq!(eval_str("max([1], 2)").unwrap_err().kind, ErrorKind::Type);
+}
+
+#[test]
+fn min_and_max_of_an_empty_list_fail() {
+    let err = eval_str("max([])").unwrap_err();
+    assert_eq!(err.message, "`max` of an empty list");
+    assert_eq!(
+        eval_str("min([])").unwrap_err().message,
+        "`min` of an empty list"
+    );
+}
+
+#[test]
+fn built_ins_can_be_disabled() {
+    let no_builtins = |env: &mut Env| env.builtins = false;
+    let err = eval_with("sqrt(4)", no_builtins).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnknownFunction);
+    assert_eq!(eval_str("sqrt(4)").unwrap(), "2");
+}
+
+#[test]
+fn functions_in_a_block_can_call_each_other() {
+    // `f` calls `g` before it is declared.
+    let src = "{
+        fn f x = g(x) + 1;
+        fn g x = x * 10;
+        [f(2), g(3)]
+    }";
+    assert_eq!(eval_str(src).unwrap(), "[21, 30]");
+    // Or even before any function is declared.
+    let src = "{ let a = f(2); fn f x = x * 10; a }";
+    assert_eq!(eval_str(src).unwrap(), "20");
+    // They are only in scope inside the block.
+    let err = eval_str("let a = { fn f = 1; f() }; f()").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnknownFunction);
+}
+
+#[test]
+fn number_equality_is_exact_by_default() {
+    assert_eq!(eval_str("1 / 10 + 2 / 10 == 3 / 10").unwrap(), "false");
+    assert_eq!(eval_str("1 / 10 + 2 / 10 != 3 / 10").unwrap(), "true");
+}
+
+#[test]
+fn float_eps_makes_close_numbers_equal() {
+    let tolerant = |src| eval_with(src, |env: &mut Env| env.float_eps = 1e-9).unwrap();
+    assert_eq!(tolerant("1 / 10 + 2 / 10 == 3 / 10"), "true");
+    assert_eq!(tolerant("1 / 10 + 2 / 10 != 3 / 10"), "false");
+    assert_eq!(tolerant("[1 / 10 + 2 / 10, 1] == [3 / 10, 1]"), "true");
+    assert_eq!(
+        tolerant(r#"{"a": 1 / 10 + 2 / 10} == {"a": 3 / 10}"#),
+        "true"
+    );
+    assert_eq!(tolerant("1 == 1001 / 1000"), "false");
+}
+
+#[test]
+fn callers_locals_do_not_leak_into_the_called_function() {
+    let err = eval_str("fn g x = x + local; fn f x = { let local = 10; g(x) }; f(1)").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnboundVariable);
+    assert_eq!(err.message, "Cannot find variable `local` in scope");
+    assert_eq!(err.span, 13..18);
+}
+
+#[test]
+fn function_bodies_see_the_variables_where_they_are_declared() {
+    let src = "let y = 1; fn f x = x + y; let y = 100; f(0)";
+    assert_eq!(eval_str(src).unwrap(), "1");
+    let src = "fn g x = x + local; let local = 1; fn f x = { let local = 10; g(x) }; f(1)";
+    assert_eq!(
+        eval_str(src).unwrap_err().message,
+        "Cannot find variable `local` in scope"
+    );
+}
+
+#[test]
+fn lists_inside_themselves_can_be_compared() {
+    let cycles = "let xs = [0]; let ys = [0]; let _ = xs[0] := xs; let _ = ys[0] := ys;";
+    let run = |src| eval_str(&format!("{cycles} {src}"));
+    assert_eq!(run("xs == xs").unwrap(), "true");
+    assert_eq!(run("xs == ys").unwrap(), "true");
+    assert_eq!(run("xs == [xs]").unwrap(), "true");
+    assert_eq!(run("xs == [ys, 1]").unwrap(), "false");
+    assert_eq!(run("xs").unwrap(), "[[…]]");
+    assert_eq!(run("len(xs)").unwrap(), "1");
+}
+
+#[test]
+fn lists_and_maps_inside_themselves_have_no_json_form() {
+    let err = eval_str("let xs = [0]; let _ = xs[0] := xs; to_json(xs)").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Type);
+    assert_eq!(
+        err.message,
+        "Type error: a list inside itself has no JSON form"
+    );
+    let src = r#"let m = {}; let _ = m["m"] := [m]; to_json([m])"#;
+    let err = eval_str(src).unwrap_err();
+    assert_eq!(
+        err.message,
+        "Type error: a map inside itself has no JSON form"
+    );
+}
+
+#[test]
+fn lists_too_large_for_memory_are_errors() {
+    for src in ["repeat(0, 2 ^ 40)", "len(range_list(0, 10 ^ 12, 1))"] {
+        let err = eval_str(src).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::MemoryLimit, "{src}");
+        assert!(
+            err.message.starts_with("Out of memory for a list of "),
+            "{err:?}"
+        );
+    }
+}
+
+#[test]
+fn panics_in_registered_functions_are_internal_errors() {
+    let setup = |env: &mut Env| env.register_fn("broken", 1, |_| panic!("oops"));
+    let err = run::eval_str_with("1 + broken(2)", setup).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Internal);
+    assert_eq!(err.message, "Internal error: oops");
+}
+
+#[test]
+fn map_lookups_and_find_return_optionals() {
+    let m = r#"let m = {"a": 1};"#;
+    let lookup = |src: &str| eval_str(&format!("{m} {src}"));
+    assert_eq!(lookup(r#"m["a"]"#).unwrap(), "some(1)");
+    assert_eq!(lookup(r#"m["b"]"#).unwrap(), "none");
+    let branch = |key| format!(r#"if let some(v) = m["{key}"] then v + 1 else 0"#);
+    assert_eq!(lookup(&branch("a")).unwrap(), "2");
+    assert_eq!(lookup(&branch("b")).unwrap(), "0");
+    // The value has to be taken out of the optional first.
+    let err = lookup(r#"m["a"] + 1"#).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Type);
+
+    let find = |xs| format!(r"if let some(x) = find(\x -> x > 1, {xs}) then x else -1");
+    assert_eq!(eval_str(&find("[1, 2, 3]")).unwrap(), "2");
+    assert_eq!(eval_str(&find("[1]")).unwrap(), "-1");
+}
+
+#[test]
+fn every_condition_reports_a_non_boolean_at_the_condition() {
+    for (src, condition) in [
+        ("not 1", "1"),
+        ("1 and true", "1"),
+        ("true and 1", "1"),
+        ("false or 1", "1"),
+        (r"filter(\x -> x, [1])", r"\x -> x"),
+        (r"find(\x -> x, [1])", r"\x -> x"),
+        (r"take_while(\x -> x, [1])", r"\x -> x"),
+        (r"let p = \x -> x; drop_while(p, [1])", "p"),
+        ("[x for x in [1] if x + 1]", "x + 1"),
+        ("[[x, y] for x in [1] for y in [2] if y]", "y"),
+        // A tail call on the right of `or` hands the check to the loop
+        // running the calls.
+        ("fn f n = n > 0 and n; f(1)", "n"),
+        (
+            "fn f n = if let some(m) = n then m else false or f(some(2)); f(none)",
+            "f(some(2))",
+        ),
+    ] {
+        let err = run::eval_str(src).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Type, "{src}");
+        assert_eq!(
+            err.message, "Type error: expected boolean, found number",
+            "{src}"
+        );
+        // The condition is the last occurrence of its text.
+        let start = src.rfind(condition).unwrap();
+        assert_eq!(err.span, start..start + condition.len(), "{src}");
+    }
+}
@@ -0,0 +1,121 @@
+use kyomusky::json::Json;
+use kyomusky::lsp;
+
+/// Frames each message with its `Content-Length` header.
+fn frame(bodies: &[&str]) -> String {
+    bodies
+        .iter()
+        .map(|body| format!("Content-Length: {}\r\n\r\n{body}", body.len()))
+        .collect()
+}
+
+/// Runs the server over `bodies` and returns the replies it sent.
+fn serve(bodies: &[&str]) -> Vec<Json> {
+    serve_input(&frame(bodies))
+}
+
+/// Runs the server over the raw `input` and returns the replies it sent.
+fn serve_input(input: &str) -> Vec<Json> {
+    let mut output = Vec::new();
+    lsp::run(input.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    output
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|msg| Json::parse(msg.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+#[test]
+fn request_response_round_trip() {
+    let replies = serve(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.ky","text":"fn double x = x * 2;\ndouble(2)"}}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.ky"},"position":{"line":1,"character":1}}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]);
+    assert_eq!(replies.len(), 4);
+
+    assert_eq!(replies[0].get("id").as_f64(), Some(1.0));
+    let capabilities = replies[0].get("result").get("capabilities");
+    assert_eq!(capabilities.get("definitionProvider"), &Json::Bool(true));
+
+    assert_eq!(
+        replies[1].get("method").as_str(),
+        Some("textDocument/publishDiagnostics")
+    );
+    let diagnostics = replies[1].get("params").get("diagnostics");
+    assert_eq!(diagnostics.as_array().map(<[_]>::len), Some(0));
+
+    assert_eq!(replies[2].get("id").as_f64(), Some(2.0));
+    let start = replies[2].get("result").get("range").get("start");
+    assert_eq!(start.get("line").as_f64(), Some(0.0));
+    assert_eq!(start.get("character").as_f64(), Some(0.0));
+
+    assert_eq!(replies[3].get("id").as_f64(), Some(3.0));
+    assert_eq!(replies[3].get("result"), &Json::Null);
+}
+
+#[test]
+fn diagnostics_have_ranges() {
+    let replies = serve(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.ky","text":"let x = 1;\n2 +"}}}"#,
+    ]);
+    let diagnostics = replies[0].get("params").get("diagnostics");
+    let first = &diagnostics.as_array().unwrap()[0];
+    assert_eq!(first.get("severity").as_f64(), Some(1.0));
+    let start = first.get("range").get("start");
+    assert_eq!(start.get("line").as_f64(), Some(1.0));
+}
+
+#[test]
+fn malformed_message_is_answered_with_a_parse_error() {
+    let replies = serve(&[
+        "{not json",
+        r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#,
+    ]);
+    assert_eq!(replies.len(), 2);
+    assert_eq!(replies[0].get("id"), &Json::Null);
+    assert_eq!(replies[0].get("error").get("code").as_f64(), Some(-32700.0));
+    // The server keeps serving after the malformed message.
+    assert_eq!(replies[1].get("id").as_f64(), Some(1.0));
+}
+
+#[test]
+fn headers_without_a_length_are_answered_with_a_parse_error() {
+    let shutdown = frame(&[r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#]);
+    for header in ["Content-Type: text/plain\r\n", "Content-Length: many\r\n"] {
+        let replies = serve_input(&format!("{header}\r\n{shutdown}"));
+        assert_eq!(replies.len(), 2, "{header}");
+        assert_eq!(replies[0].get("error").get("code").as_f64(), Some(-32700.0));
+        assert_eq!(
+            replies[0].get("error").get("message").as_str(),
+            Some("Missing or invalid Content-Length")
+        );
+        assert_eq!(replies[1].get("id").as_f64(), Some(1.0));
+    }
+}
+
+#[test]
+fn huge_lengths_are_skipped_rather_than_allocated() {
+    let replies = serve_input("Content-Length: 999999999999999\r\n\r\n{}");
+    assert_eq!(replies.len(), 0);
+
+    // A body over the limit is read past, and the next message served.
+    let body = " ".repeat((64 << 20) + 1);
+    let shutdown = r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#;
+    let replies = serve(&[&body, shutdown]);
+    assert_eq!(replies.len(), 2);
+    assert_eq!(
+        replies[0].get("error").get("message").as_str(),
+        Some("Message of 67108865 bytes exceeds the limit of 67108864")
+    );
+    assert_eq!(replies[1].get("id").as_f64(), Some(1.0));
+}
+
+#[test]
+fn bodies_cut_short_end_the_input() {
+    let replies = serve_input("Content-Length: 10\r\n\r\n{}");
+    assert_eq!(replies.len(), 0);
+}
@@ -0,0 +1,235 @@
+//! Parses programs through the library and checks the trees built.
+
+use chumsky::error::SimpleReason;
+use chumsky::Parser;
+use kyomusky::ast::{BinOp, Expr, ExprKind, UnaryOp};
+use kyomusky::build::*;
+use kyomusky::cache::{ParseCache, ParseResult};
+use kyomusky::eval::{eval, Env, EvalError};
+use kyomusky::parser::{parse_program, program_with, ParseOptions};
+
+/// The tree of each top-level expression of `src`.
+fn expressions(src: &str, options: ParseOptions) -> Vec<String> {
+    let program = program_with(options).parse(src).unwrap();
+    program.iter().map(|expr| format!("{expr:?}")).collect()
+}
+
+fn newline_terminates() -> ParseOptions {
+    ParseOptions {
+        newline_terminates: true,
+        ..ParseOptions::default()
+    }
+}
+
+#[test]
+fn newlines_are_insignificant_by_default() {
+    let options = ParseOptions::default();
+    assert!(!options.newline_terminates);
+    assert_eq!(
+        expressions("1\n- 2", options),
+        ["Binary { op: Sub, lhs: Num(1.0), rhs: Num(2.0) }"]
+    );
+    assert_eq!(expressions("f\n(1)", options), [r#"Call("f", [Num(1.0)])"#]);
+    assert_eq!(expressions("1\n2", options), ["Num(1.0)", "Num(2.0)"]);
+}
+
+#[test]
+fn newlines_can_terminate_expressions() {
+    let options = newline_terminates();
+    assert_eq!(
+        expressions("1\n- 2", options),
+        ["Num(1.0)", "Unary { op: Neg, operand: Num(2.0) }"]
+    );
+    assert_eq!(
+        expressions("x\n[1]", options),
+        [r#"Var("x")"#, "List([Num(1.0)])"]
+    );
+    // A line break is still allowed after an operator and inside brackets.
+    assert_eq!(
+        expressions("1 -\n2\n[1,\n2]", options),
+        [
+            "Binary { op: Sub, lhs: Num(1.0), rhs: Num(2.0) }",
+            "List([Num(1.0), Num(2.0)])"
+        ]
+    );
+    assert_eq!(
+        expressions("let a = 1;\na + 1", options),
+        [
+            r#"Let { name: "a", lazy: false, deprecated: None, rhs: Num(1.0), then: Binary { op: Add, lhs: Var("a"), rhs: Num(1.0) } }"#
+        ]
+    );
+}
+
+#[test]
+fn parse_cache_reuses_the_tree_of_identical_source() {
+    let mut cache = ParseCache::new();
+    let options = ParseOptions::default();
+
+    let (first, fresh) = cache.parse("let x = 1; x + 1", options);
+    assert!(fresh);
+    let first: *const ParseResult = first;
+    let (again, fresh) = cache.parse("let x = 1; x + 1", options);
+    assert!(!fresh);
+    assert!(std::ptr::eq(first, again), "the memoized tree is returned");
+
+    let (changed, fresh) = cache.parse("let x = 2; x + 1", options);
+    assert!(fresh);
+    assert_eq!(
+        changed.as_ref().unwrap()[0].to_string(),
+        "let x = 2;\nx + 1"
+    );
+
+    // Syntax errors are remembered too.
+    assert!(cache.parse("1 +", options).1);
+    let (errors, fresh) = cache.parse("1 +", options);
+    assert!(!fresh);
+    assert!(errors.is_err());
+}
+
+fn parse(src: &str) -> Expr {
+    program_with(ParseOptions::default())
+        .parse(src)
+        .unwrap()
+        .remove(0)
+}
+
+#[test]
+fn operators_parse_with_precedence_and_associativity() {
+    assert_eq!(parse("1 + 2 * x"), num(1.0) + num(2.0) * var("x"));
+    assert_eq!(parse("(1 + 2) * x"), (num(1.0) + num(2.0)) * var("x"));
+    assert_eq!(parse("a - b - c"), var("a") - var("b") - var("c"));
+    assert_eq!(parse("a / (b / c)"), var("a") / (var("b") / var("c")));
+    assert_eq!(parse("--a - -1"), -(-var("a")) - -num(1.0));
+}
+
+#[test]
+fn declarations_and_calls_parse_to_their_trees() {
+    let expected = let_(
+        "x",
+        num(2.0),
+        fn_(
+            "f",
+            ["a", "b"],
+            var("a") * var("b"),
+            call("f", [var("x"), named("b", num(3.0))]),
+        ),
+    );
+    assert_eq!(parse("let x = 2; fn f a b = a * b; f(x, b = 3)"), expected);
+
+    let expected = index(list([string("s"), boolean(true)]), num(0.0));
+    assert_eq!(parse(r#"["s", true][0]"#), expected);
+}
+
+fn apply(callee: Expr, args: Vec<Expr>) -> Expr {
+    Expr::new(ExprKind::Apply(Box::new(callee), args), 0..0)
+}
+
+#[test]
+fn postfix_operators_chain_in_any_order() {
+    let matrix = index(index(var("matrix"), num(0.0)), num(1.0));
+    assert_eq!(parse("matrix[0][1]"), matrix);
+    assert_eq!(parse("get()[0]"), index(call("get", []), num(0.0)));
+    // A method call passes the operand as the first argument.
+    assert_eq!(parse("obj.f().g()"), call("g", [call("f", [var("obj")])]));
+    assert_eq!(parse("obj.f(1)"), call("f", [var("obj"), num(1.0)]));
+    let chained = index(apply(index(var("a"), num(0.0)), vec![num(1.0)]), num(2.0));
+    assert_eq!(parse("a[0](1)[2]"), chained);
+    assert_eq!(
+        parse("f(1)(2)"),
+        apply(call("f", [num(1.0)]), vec![num(2.0)])
+    );
+}
+
+fn eval_str(src: &str) -> Result<String, EvalError> {
+    eval(&parse(src), &mut Env::new()).map(|value| value.to_string())
+}
+
+#[test]
+fn postfix_chains_evaluate() {
+    let src = "let m = [[1, 2], [3, 16]]; [m[1][0], m[1][1].sqrt()]";
+    assert_eq!(eval_str(src).unwrap(), "[3, 4]");
+    let err = eval_str("let m = [1]; m[0](2)").unwrap_err();
+    assert_eq!(err.message, "Type error: cannot call number");
+}
+
+fn and(lhs: Expr, rhs: Expr) -> Expr {
+    let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+    Expr::new(
+        ExprKind::Binary {
+            op: BinOp::And,
+            lhs,
+            rhs,
+        },
+        0..0,
+    )
+}
+
+fn or(lhs: Expr, rhs: Expr) -> Expr {
+    let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+    Expr::new(
+        ExprKind::Binary {
+            op: BinOp::Or,
+            lhs,
+            rhs,
+        },
+        0..0,
+    )
+}
+
+fn not(operand: Expr) -> Expr {
+    let operand = Box::new(operand);
+    Expr::new(
+        ExprKind::Unary {
+            op: UnaryOp::Not,
+            operand,
+        },
+        0..0,
+    )
+}
+
+#[test]
+fn keyword_logical_operators_are_aliases() {
+    let (t, f) = (boolean(true), boolean(false));
+    assert_eq!(parse("true and false"), and(t.clone(), f.clone()));
+    assert_eq!(parse("true and false"), parse("true && false"));
+    assert_eq!(parse("true or false"), parse("true || false"));
+    assert_eq!(parse("not true"), not(t.clone()));
+    assert_eq!(parse("not true"), parse("!true"));
+    // `and` binds tighter than `or`, and `not` tighter than both.
+    assert_eq!(
+        parse("not a or b and c"),
+        or(not(var("a")), and(var("b"), var("c")))
+    );
+    assert_eq!(parse("not not f"), not(not(var("f"))));
+    // Words merely starting with a keyword are identifiers.
+    assert_eq!(
+        parse("notice or android"),
+        or(var("notice"), var("android"))
+    );
+}
+
+#[test]
+fn keyword_logical_operators_evaluate() {
+    assert_eq!(eval_str("true and false").unwrap(), "false");
+    assert_eq!(eval_str("not true").unwrap(), "false");
+    assert_eq!(eval_str("false or not false and true").unwrap(), "true");
+    // The right operand is only evaluated when needed.
+    assert_eq!(eval_str("false and missing()").unwrap(), "false");
+    assert_eq!(eval_str("true or missing()").unwrap(), "true");
+}
+
+#[test]
+fn logical_keywords_are_not_identifiers() {
+    for word in ["and", "or", "not"] {
+        let src = format!("let {word} = 1; 2");
+        assert!(
+            parse_program(&src, ParseOptions::default()).is_err(),
+            "{src}"
+        );
+        let src = format!("fn {word} x = x; 2");
+        let errors = parse_program(&src, ParseOptions::default()).unwrap_err();
+        let message = format!("`{word}` is a keyword");
+        assert_eq!(errors[0].reason(), &SimpleReason::Custom(message), "{src}");
+        assert_eq!(errors[0].span(), 3..3 + word.len());
+    }
+}
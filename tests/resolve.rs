@@ -0,0 +1,119 @@
+//! Resolves variables to slots, and checks that evaluation reads the same
+//! variables through them as it does by name.
+
+use chumsky::Parser;
+use kyomusky::ast::{Expr, ExprKind};
+use kyomusky::desugar::desugar;
+use kyomusky::eval::{eval, Env};
+use kyomusky::parser::program;
+use kyomusky::resolve::resolve;
+
+fn parse(src: &str) -> Expr {
+    desugar(program().parse(src).unwrap().remove(0))
+}
+
+/// The variables of `src` in pre-order, with their slots.
+fn slots(src: &str) -> Vec<(String, Option<usize>)> {
+    let program = parse(src);
+    let slots = resolve(&program);
+    program
+        .iter()
+        .filter_map(|node| match &node.kind {
+            ExprKind::Var(name) => Some((name.clone(), slots.get(node))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn expected(vars: &[(&str, usize)]) -> Vec<(String, Option<usize>)> {
+    vars.iter()
+        .map(|(name, slot)| (name.to_string(), Some(*slot)))
+        .collect()
+}
+
+#[test]
+fn slots_count_the_variables_declared_since() {
+    let src = "let a = 1; let a = { let b = a; let a = b; a + b }; let c = a; [a, c]";
+    let vars = [
+        ("a", 0),
+        ("b", 0),
+        ("a", 0),
+        ("b", 1),
+        ("a", 0),
+        ("a", 1),
+        ("c", 0),
+    ];
+    assert_eq!(slots(src), expected(&vars));
+
+    let src = r"let x = 1; for y in [x] do \z -> [x, y, z]";
+    assert_eq!(
+        slots(src),
+        expected(&[("x", 0), ("x", 2), ("y", 1), ("z", 0)])
+    );
+
+    let src = "let x = 1; if let some(y) = some(x) then [x, y] else x";
+    assert_eq!(
+        slots(src),
+        expected(&[("x", 0), ("x", 1), ("y", 0), ("x", 0)])
+    );
+}
+
+/// Evaluates `src`, with its variables resolved if `resolved`.
+fn eval_str(src: &str, resolved: bool) -> String {
+    let program = parse(src);
+    let mut env = Env::new();
+    if resolved {
+        env.resolve(&program);
+    }
+    let value = eval(&program, &mut env).unwrap_or_else(|err| panic!("{src}: {err:?}"));
+    value.to_string()
+}
+
+#[test]
+fn resolved_variables_are_the_ones_looked_up_by_name() {
+    for (src, value) in [
+        (
+            "let a = 1; let b = 2; let c = a + b; let d = { let a = c; [a, b] }; [a, d]",
+            "[1, [3, 2]]",
+        ),
+        (
+            r"let a = 1; fn f x y = x + y + a; let g = \z -> [z, a, f(z, 2)]; g(5)",
+            "[5, 1, 8]",
+        ),
+        // Functions declared in a block, called before and after their
+        // declaration.
+        (
+            "let a = 1; { let b = 2; fn g x = { let y = x; [y, b, a] }; g(b) }",
+            "[2, 2, 1]",
+        ),
+        (
+            "{ let r = h(1); let a = 10; fn h x = { let y = x + 1; y }; [r, h(a)] }",
+            "[2, 11]",
+        ),
+        (
+            "fn sub a b = { let d = a - b; d }; [sub(b = 1, a = 5), sub(5, b = 1)]",
+            "[4, 4]",
+        ),
+        // Tail calls rebind the parameters in a loop.
+        (
+            "fn f n acc = n < 1 and acc > 0 or { let m = n - 1; f(m, acc + n) }; f(100, 0)",
+            "true",
+        ),
+        (
+            "let xs = [1, 2]; [for x in xs do { let y = x * 10; if let some(z) = some(y) then z + x else 0 }, [x + y for x in xs for y in [x, 3] if x < y]]",
+            "[[11, 22], [4, 5]]",
+        ),
+        (r"let lazy a = 1 + 1; let b = \x -> a + x; let a = 10; b(1)", "3"),
+        (
+            r"fn adder n = \x -> x + n; let add2 = adder(2); let n = 100; add2(1)",
+            "3",
+        ),
+        (
+            r"let k = 3; map(\x -> { let y = x; y * k }, filter(\x -> x > 1, [1, 2, 3]))",
+            "[6, 9]",
+        ),
+    ] {
+        assert_eq!(eval_str(src, false), value, "{src}");
+        assert_eq!(eval_str(src, true), value, "{src}");
+    }
+}
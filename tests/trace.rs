@@ -0,0 +1,114 @@
+//! Spans and events of the pipeline, run with `cargo test --features trace`.
+#![cfg(feature = "trace")]
+
+use chumsky::Parser;
+use kyomusky::analysis::analyze;
+use kyomusky::eval::{eval, Env, ErrorKind};
+use kyomusky::parser::parser;
+use kyomusky::stack::with_stack;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Collects what a subscriber writes.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Captured {
+    type Writer = Captured;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Runs `f` with every span and event on this thread formatted like
+/// `--log trace` does, without times or colors, and returns the lines. Spans
+/// report when they close, without how long they took.
+fn capture<R>(f: impl FnOnce() -> R) -> (R, Vec<String>) {
+    let captured = Captured::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(captured.clone())
+        .with_max_level(tracing::Level::TRACE)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false)
+        .with_target(false)
+        .without_time()
+        .finish();
+    let result = tracing::subscriber::with_default(subscriber, f);
+    let text = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    (result, text.lines().map(str::to_string).collect())
+}
+
+#[test]
+fn parsing_nests_its_events_in_spans() {
+    let (_, lines) = capture(|| analyze("let a = ); 1"));
+    assert_eq!(
+        lines,
+        [
+            "DEBUG parse{len=12}: recovered parse error span=8..9",
+            " INFO parse{len=12}: close",
+            "DEBUG parsed nodes=3",
+            " INFO resolve: close",
+        ]
+    );
+}
+
+#[test]
+fn recursive_calls_are_traced() {
+    // The recursion ends with dividing by zero.
+    let program = parser().parse("fn f n = 1 / n + f(n - 1); f(2)").unwrap();
+    let (result, lines) = capture(|| eval(&program, &mut Env::new()));
+    assert_eq!(result.unwrap_err().message, "Division by zero");
+    let calls: Vec<_> = lines
+        .iter()
+        .filter(|line| line.contains("call name=f "))
+        .collect();
+    assert_eq!(calls, ["TRACE call name=f args=1"; 3]);
+}
+
+#[test]
+fn cache_hits_are_traced() {
+    let src = "for i in [1, 2, 3] do sqrt(16)";
+    let program = parser().parse(src).unwrap();
+    let (result, lines) = capture(|| {
+        let mut env = Env::new();
+        env.memoize = true;
+        eval(&program, &mut env)
+    });
+    assert_eq!(result.unwrap().to_string(), "[4, 4, 4]");
+    let hits: Vec<_> = lines
+        .iter()
+        .filter(|line| line.contains("memoized"))
+        .collect();
+    assert_eq!(hits, ["DEBUG memoized span=22..30"; 2]);
+}
+
+#[test]
+fn running_out_of_stack_is_traced() {
+    let program = parser().parse("fn f n = 1 + f(n); f(1)").unwrap();
+    let (result, lines) = with_stack(1 << 20, || {
+        capture(|| {
+            let result = eval(&program, &mut Env::new());
+            result
+                .map(|value| value.to_string())
+                .map_err(|err| err.kind)
+        })
+    });
+    assert_eq!(result.unwrap_err(), ErrorKind::StackExhausted);
+    let warnings: Vec<_> = lines.iter().filter(|line| line.contains("WARN")).collect();
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert!(warnings[0].starts_with(" WARN stack exhausted span="));
+}
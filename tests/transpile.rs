@@ -0,0 +1,143 @@
+//! Translates numeric programs to other languages, runs the result when the
+//! toolchain is installed, and compares its output with the interpreter's.
+
+use chumsky::Parser;
+use kyomusky::ast::Expr;
+use kyomusky::eval::{eval, Env};
+use kyomusky::parser::parser;
+use kyomusky::transpile;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Programs which every target can translate.
+const CORPUS: &[&str] = &[
+    "1 + 2 * 3",
+    "1 / 10 + 2 / 10",
+    "-(-1) - -2",
+    "let x = 2; let y = x * x * x; y / 3",
+    "fn sq x = x * x; fn hyp a b = sq(a) + sq(b); hyp(3, 4) / 5",
+    // Names which are keywords in the targets.
+    "let type = 1; let self = 2; fn loop match = match + 1; loop(self) + type",
+    // A function redeclared under the same name.
+    "fn f x = x + 1; let a = f(1); fn f x = x * 10; a + f(2)",
+];
+
+fn parse(src: &str) -> Expr {
+    parser().parse(src).unwrap()
+}
+
+/// The value of `src` as printed by the interpreter.
+fn interpret(src: &str) -> String {
+    eval(&parse(src), &mut Env::new()).unwrap().to_string()
+}
+
+/// A fresh directory for the files of one test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kyomusky-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Whether `program --version` runs.
+fn installed(program: &str) -> bool {
+    Command::new(program).arg("--version").output().is_ok()
+}
+
+fn run(command: &mut Command) -> String {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn rust_output_matches_the_interpreter() {
+    if !installed("rustc") {
+        eprintln!("rustc is not installed, skipping");
+        return;
+    }
+    let dir = temp_dir("rust");
+    for (i, src) in CORPUS.iter().enumerate() {
+        let main = dir.join(format!("main{i}.rs"));
+        let binary = dir.join(format!("main{i}"));
+        std::fs::write(&main, transpile::rust::transpile(&parse(src)).unwrap()).unwrap();
+        run(Command::new("rustc").arg("-o").arg(&binary).arg(&main));
+        assert_eq!(
+            run(&mut Command::new(&binary)),
+            format!("{}\n", interpret(src)),
+            "{src}"
+        );
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rust_reports_what_it_cannot_translate() {
+    let src = r#"let s = "a"; 1"#;
+    let err = transpile::rust::transpile(&parse(src)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Cannot transpile: strings are not supported"
+    );
+    assert_eq!(err.span, 8..11);
+    let src = "let a = 1; fn f x = x + a; f(1)";
+    let err = transpile::rust::transpile(&parse(src)).unwrap_err();
+    assert!(err.to_string().contains("`a` is captured"), "{err}");
+}
+
+#[test]
+fn js_output_matches_the_interpreter() {
+    for src in CORPUS {
+        let js = transpile::js::transpile(&parse(src), false).unwrap();
+        assert!(js.contains("console.log"), "{js}");
+    }
+    if !installed("node") {
+        eprintln!("node is not installed, skipping");
+        return;
+    }
+    let dir = temp_dir("js");
+    for (i, src) in CORPUS.iter().enumerate() {
+        let script = dir.join(format!("main{i}.js"));
+        std::fs::write(
+            &script,
+            transpile::js::transpile(&parse(src), false).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            run(Command::new("node").arg(&script)),
+            format!("{}\n", interpret(src)),
+            "{src}"
+        );
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn js_checks_division_by_zero_like_the_interpreter() {
+    let src = "fn f x = x / 0; f(1)";
+    let err = eval(&parse(src), &mut Env::new()).unwrap_err();
+    assert_eq!(err.message, "Division by zero");
+    let js = transpile::js::transpile(&parse(src), false).unwrap();
+    if !installed("node") {
+        return;
+    }
+    let dir = temp_dir("js-errors");
+    let script = dir.join("main.js");
+    std::fs::write(&script, js).unwrap();
+    let output = Command::new("node").arg(&script).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "Evaluation error: Division by zero\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn js_module_exports_the_program_as_a_function() {
+    let js = transpile::js::transpile(&parse("fn f x = x * 2; f(21)"), true).unwrap();
+    assert!(js.contains("export function run() {"), "{js}");
+    assert!(js.contains("return f0_f(21.0);"), "{js}");
+    assert!(!js.contains("console.log"), "{js}");
+}
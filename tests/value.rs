@@ -0,0 +1,109 @@
+//! How values are shown to users (`Display`) and when debugging (`Debug`).
+
+use kyomusky::eval::{eval, Env, Value};
+use kyomusky::parser::{parse_program, ParseOptions};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn shown(value: &Value) -> (String, String) {
+    (format!("{value}"), format!("{value:?}"))
+}
+
+fn list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+#[test]
+fn numbers_show_their_full_precision_in_debug() {
+    assert_eq!(shown(&Value::Num(3.0)), ("3".into(), "Num(3.0)".into()));
+    let sum = Value::Num(0.1 + 0.2);
+    assert_eq!(
+        shown(&sum),
+        (
+            "0.30000000000000004".into(),
+            "Num(0.30000000000000004)".into()
+        )
+    );
+    assert_eq!(
+        shown(&Value::Num(1e21)),
+        ("1000000000000000000000".into(), "Num(1e21)".into())
+    );
+    assert_eq!(
+        shown(&Value::Num(f64::NAN)),
+        ("NaN".into(), "Num(NaN)".into())
+    );
+}
+
+#[test]
+fn strings_are_told_apart_from_numbers_in_debug() {
+    let (three, text) = (Value::Num(3.0), Value::Str("3".into()));
+    assert_eq!(format!("{three}"), format!("{text}"));
+    assert_eq!(shown(&text), ("3".into(), r#"Str("3")"#.into()));
+    assert_eq!(
+        shown(&Value::Bool(true)),
+        ("true".into(), "Bool(true)".into())
+    );
+}
+
+#[test]
+fn compound_values() {
+    let xs = list(vec![Value::Num(1.0), Value::Str("a".into())]);
+    assert_eq!(
+        shown(&xs),
+        (r#"[1, "a"]"#.into(), r#"List([Num(1.0), Str("a")])"#.into())
+    );
+    let m = Value::Map(Rc::new(RefCell::new(vec![("k".into(), Value::Num(1.0))])));
+    assert_eq!(
+        shown(&m),
+        (r#"{"k": 1}"#.into(), r#"Map({"k": Num(1.0)})"#.into())
+    );
+    assert_eq!(
+        shown(&Value::Optional(None)),
+        ("none".into(), "Optional(None)".into())
+    );
+    let some = Value::Optional(Some(Box::new(Value::Str("x".into()))));
+    assert_eq!(
+        shown(&some),
+        (r#"some("x")"#.into(), r#"Optional(Some(Str("x")))"#.into())
+    );
+}
+
+#[test]
+fn functions() {
+    let program = parse_program("sqrt", ParseOptions::default()).unwrap();
+    let sqrt = eval(&program[0], &mut Env::new()).unwrap();
+    assert_eq!(
+        shown(&sqrt),
+        ("<fn sqrt>".into(), r#"Function("sqrt")"#.into())
+    );
+}
+
+#[test]
+fn lists_and_maps_inside_themselves() {
+    let xs = list(vec![Value::Num(1.0)]);
+    let Value::List(items) = &xs else {
+        unreachable!()
+    };
+    items.borrow_mut().push(xs.clone());
+    assert_eq!(
+        shown(&xs),
+        ("[1, […]]".into(), "List([Num(1.0), List(…)])".into())
+    );
+    // Only a list inside itself is cut short, not one appearing twice.
+    let twice = list(vec![xs.clone(), xs.clone()]);
+    assert_eq!(twice.to_string(), "[[1, […]], [1, […]]]");
+    assert_eq!(xs.to_json().to_string(), "[1,null]");
+
+    let m = Value::Map(Rc::new(RefCell::new(Vec::new())));
+    let Value::Map(entries) = &m else {
+        unreachable!()
+    };
+    entries.borrow_mut().push(("m".into(), m.clone()));
+    assert_eq!(
+        shown(&m),
+        (r#"{"m": {…}}"#.into(), r#"Map({"m": Map(…)})"#.into())
+    );
+    // Break the cycles so that the values are freed.
+    items.borrow_mut().clear();
+    entries.borrow_mut().clear();
+}
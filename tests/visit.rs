@@ -0,0 +1,103 @@
+//! Walks trees with the default traversals of `Visitor` and `VisitorMut`.
+
+use chumsky::Parser;
+use kyomusky::ast::{Expr, ExprKind};
+use kyomusky::parser::program;
+use kyomusky::visit::{Visitor, VisitorMut};
+use std::collections::BTreeSet;
+
+/// A program with every kind of node.
+const EVERY_KIND: &str = r#"
+let m = {"k": [1, -2, true]};
+fn f a b = a * b;
+let _ = m["k"][0] := "s";
+[f(1, b = 2) + 1 - 2 / 3, 1 == 1, 1 != 2, 1 < 2, 1 > 2, 1 <= 2, 1 >= 2, m["k"]]
+"#;
+
+fn parse(src: &str) -> Expr {
+    program().parse(src).unwrap().remove(0)
+}
+
+/// Every node of `expr` in pre-order, following `Expr::children`.
+fn nodes(expr: &Expr) -> Vec<&Expr> {
+    let mut nodes = vec![expr];
+    for child in expr.children() {
+        nodes.extend(self::nodes(child));
+    }
+    nodes
+}
+
+/// Records the address of every node visited.
+#[derive(Default)]
+struct Visited(Vec<*const Expr>);
+
+impl Visitor<'_> for Visited {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.0.push(expr);
+        self.walk_expr(expr);
+    }
+}
+
+#[test]
+fn the_program_has_every_kind_of_node() {
+    let program = parse(EVERY_KIND);
+    let kinds: BTreeSet<_> = nodes(&program).iter().map(|e| e.kind.name()).collect();
+    // All but `Error`, only built when recovering from syntax errors.
+    let missing: Vec<_> = [
+        "Num",
+        "Str",
+        "Bool",
+        "Var",
+        "Unary",
+        "Binary",
+        "List",
+        "Map",
+        "Index",
+        "IndexAssign",
+        "Call",
+        "NamedArg",
+        "Let",
+        "Fn",
+    ]
+    .into_iter()
+    .filter(|name| !kinds.contains(name))
+    .collect();
+    assert!(missing.is_empty(), "{missing:?}");
+}
+
+#[test]
+fn default_walk_visits_every_node_once() {
+    let program = parse(EVERY_KIND);
+    let mut visited = Visited::default();
+    visited.visit_expr(&program);
+    let expected: Vec<*const Expr> = nodes(&program)
+        .into_iter()
+        .map(|e| e as *const Expr)
+        .collect();
+    assert_eq!(visited.0.len(), program.node_count());
+    let unique: BTreeSet<_> = visited.0.iter().collect();
+    assert_eq!(unique.len(), visited.0.len(), "a node was visited twice");
+    assert_eq!(unique, expected.iter().collect());
+}
+
+/// Renames every variable, function and call.
+struct Rename;
+
+impl VisitorMut for Rename {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let ExprKind::Var(name) | ExprKind::Call(name, _) = &mut expr.kind {
+            name.push('_');
+        }
+        self.walk_expr_mut(expr);
+    }
+}
+
+#[test]
+fn mutable_walk_visits_every_node() {
+    let mut program = parse("let a = 1; f(a, [a, g(a)], {\"k\": a})");
+    Rename.visit_expr_mut(&mut program);
+    assert_eq!(
+        program,
+        parse("let a = 1; f_(a_, [a_, g_(a_)], {\"k\": a_})")
+    );
+}
@@ -0,0 +1,14 @@
+//! The built-ins left out by the `wasm` feature, run with
+//! `cargo test --features wasm`.
+#![cfg(feature = "wasm")]
+
+use kyomusky::builtins::BUILTINS;
+use kyomusky::eval::ErrorKind;
+use kyomusky::run::eval_str;
+
+#[test]
+fn env_is_not_a_builtin() {
+    assert!(BUILTINS.iter().all(|builtin| builtin.name != "env"));
+    let err = eval_str(r#"env("HOME")"#).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnknownFunction);
+}